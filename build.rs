@@ -0,0 +1,25 @@
+use tonic_build::manual::{Builder, Method, Service};
+
+fn main() {
+    // The gRPC service is defined manually so that building doesn't require protoc,
+    // the messages are defined in src/grpc.rs and match proto/convert.proto
+    let convert_service = Service::builder()
+        .name("ConvertService")
+        .package("convert")
+        .method(
+            Method::builder()
+                .name("convert")
+                .route_name("Convert")
+                .input_type("crate::grpc::ConvertRequest")
+                .output_type("crate::grpc::ConvertResponse")
+                .codec_path("tonic::codec::ProstCodec")
+                .client_streaming()
+                .server_streaming()
+                .build(),
+        )
+        .build();
+
+    Builder::new()
+        .build_client(false)
+        .compile(&[convert_service]);
+}
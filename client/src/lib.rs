@@ -51,19 +51,88 @@ impl RequestError {
             RequestError::RequestFailed(_)
                 | RequestError::InvalidResponse(_)
                 | RequestError::ServerConnectTimeout
+        ) || self.is_busy()
+    }
+
+    /// Whether the server rejected the request because its conversion queue
+    /// is full, the request should be sent to another server or retried later
+    pub fn is_busy(&self) -> bool {
+        matches!(
+            self,
+            RequestError::ErrorResponse(ErrorResponse {
+                kind: ErrorKind::Busy,
+                ..
+            })
         )
     }
 }
 
+/// Category of an error reported by the server
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// Request was malformed or had invalid options
+    InvalidRequest,
+    /// Request is missing valid credentials
+    Unauthorized,
+    /// Client isn't allowed to make the request
+    Forbidden,
+    /// Requested resource doesn't exist
+    NotFound,
+    /// Resource is in a state that doesn't allow the request
+    Conflict,
+    /// File is encrypted and the password is missing or incorrect
+    Encrypted,
+    /// File is corrupted and can't be read
+    Corrupted,
+    /// Format of the file or the requested output format isn't supported
+    UnsupportedFormat,
+    /// Conversion took too long
+    Timeout,
+    /// File or request is too large
+    TooLarge,
+    /// Conversion exceeded one of the server resource limits
+    ResourceLimit,
+    /// File was rejected by the server virus scanner
+    Infected,
+    /// File repeatedly crashed the server converter and is quarantined
+    PoisonDocument,
+    /// Conversion queue of the server is full, the request can be retried
+    /// later or on another server
+    Busy,
+    /// Server is unable to handle the request or is shutting down
+    Unavailable,
+    /// Server failed to convert the file for an unknown reason
+    ConversionFailed,
+    /// Unexpected server failure
+    Internal,
+    /// Kind not known to this version of the client
+    #[default]
+    #[serde(other)]
+    Unknown,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ErrorResponse {
     /// Error code from x2t if available
     pub code: Option<i32>,
+    /// Category of the error
+    #[serde(default)]
+    pub kind: ErrorKind,
     /// Server reason for the error
+    #[serde(alias = "message")]
     pub reason: String,
     /// Server backtrace if available
     pub backtrace: Option<String>,
+    /// Number of conversions waiting on the server, only present when the
+    /// queue is full
+    #[serde(default)]
+    pub queued: Option<usize>,
+    /// Maximum number of conversions allowed to wait on the server, only
+    /// present when the queue is full
+    #[serde(default, rename = "max_queued")]
+    pub max_queued: Option<usize>,
 }
 
 impl Display for ErrorResponse {
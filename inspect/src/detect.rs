@@ -0,0 +1,176 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+use crate::{
+    encrypted::{EncryptionCheck, find_needle, ole_encryption, to_utf16_le},
+    ole::{CompoundFile, OLE_MAGIC},
+};
+
+/// Extensions sharing the container of each format detected from the
+/// structure of a file, an uploaded file name with an extension outside of
+/// these is treated as mislabelled
+const COMPATIBLE_EXTENSIONS: &[(&str, &[&str])] = &[
+    ("docx", &["docx", "docm", "dotx", "dotm", "docxf", "oform"]),
+    ("xlsx", &["xlsx", "xlsm", "xltx", "xltm", "xlsb"]),
+    ("pptx", &["pptx", "pptm", "ppsx", "ppsm", "potx", "potm"]),
+    ("odt", &["odt", "ott"]),
+    ("ods", &["ods", "ots"]),
+    ("odp", &["odp", "otp"]),
+    ("doc", &["doc", "dot", "wps"]),
+    ("xls", &["xls", "xlt", "et"]),
+    ("ppt", &["ppt", "pps", "pot", "dps"]),
+    ("pdf", &["pdf"]),
+    ("rtf", &["rtf"]),
+];
+
+/// Details of an OLE compound file read from its directory
+#[derive(Debug, Clone)]
+pub struct OleDetails {
+    /// File extension of the detected format
+    pub format: &'static str,
+    /// Whether the streams of the file mark it as encrypted
+    pub encryption: EncryptionCheck,
+}
+
+/// Detect the format of a file from the magic bytes and content at the start
+/// of the file, returns the file extension of the detected format
+///
+/// ## Arguments
+/// * `header` - The start of the file
+pub fn detect_format(header: &[u8]) -> Option<&'static str> {
+    if header.starts_with(b"PK\x03\x04") {
+        return Some(detect_zip_format(header));
+    }
+
+    if header.starts_with(OLE_MAGIC) {
+        return Some(detect_ole_format(header));
+    }
+
+    let format = if header.starts_with(b"%PDF-") {
+        "pdf"
+    } else if header.starts_with(b"{\\rtf") {
+        "rtf"
+    } else if header.starts_with(b"\x89PNG\r\n\x1a\n") {
+        "png"
+    } else if header.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        "jpg"
+    } else if header.starts_with(b"GIF87a") || header.starts_with(b"GIF89a") {
+        "gif"
+    } else if is_html(header) {
+        "html"
+    } else if is_text(header) {
+        "txt"
+    } else {
+        return None;
+    };
+
+    Some(format)
+}
+
+/// Detect the format of a ZIP based file from the names of the entries
+/// near the start of the archive
+fn detect_zip_format(header: &[u8]) -> &'static str {
+    // ODF files store their mimetype uncompressed as the first entry
+    if find_needle(header, b"mimetypeapplication/vnd.oasis.opendocument.text") {
+        return "odt";
+    }
+    if find_needle(
+        header,
+        b"mimetypeapplication/vnd.oasis.opendocument.spreadsheet",
+    ) {
+        return "ods";
+    }
+    if find_needle(
+        header,
+        b"mimetypeapplication/vnd.oasis.opendocument.presentation",
+    ) {
+        return "odp";
+    }
+
+    if find_needle(header, b"word/") {
+        return "docx";
+    }
+    if find_needle(header, b"xl/") {
+        return "xlsx";
+    }
+    if find_needle(header, b"ppt/") {
+        return "pptx";
+    }
+
+    "zip"
+}
+
+/// Detect the format of an OLE compound file from the stream names found
+/// in the header, used when the directory can't be read
+fn detect_ole_format(header: &[u8]) -> &'static str {
+    ole_format(|name| find_needle(header, &to_utf16_le(name.as_bytes())))
+}
+
+/// Detect the format of an OLE compound file from the streams it contains
+fn ole_format(has_stream: impl Fn(&str) -> bool) -> &'static str {
+    if has_stream("WordDocument") {
+        "doc"
+    } else if has_stream("Workbook") || has_stream("Book") {
+        "xls"
+    } else if has_stream("PowerPoint Document") {
+        "ppt"
+    } else {
+        "cfb"
+    }
+}
+
+/// Read the format and encryption of an OLE compound file from its
+/// directory, the directory is often at the end of large files so isn't
+/// covered by the header. None when the file isn't a readable compound file
+pub fn inspect_ole_file(path: &Path) -> std::io::Result<Option<OleDetails>> {
+    let Some(mut file) = CompoundFile::open(BufReader::new(File::open(path)?))? else {
+        return Ok(None);
+    };
+
+    let format = ole_format(|name| file.has_stream(name));
+    let encryption = ole_encryption(&mut file)?;
+
+    Ok(Some(OleDetails { format, encryption }))
+}
+
+/// Whether the detected format of a file conflicts with the extension it
+/// was uploaded with, only formats detected from the structure of the file
+/// are trusted over the extension
+///
+/// ## Arguments
+/// * `detected` - Extension of the detected format
+/// * `extension` - Extension the file was uploaded with
+pub fn conflicts_with_extension(detected: &str, extension: &str) -> bool {
+    COMPATIBLE_EXTENSIONS
+        .iter()
+        .find(|(format, _)| *format == detected)
+        .is_some_and(|(_, extensions)| !extensions.contains(&extension))
+}
+
+/// Check if the header looks like the start of an HTML document
+fn is_html(header: &[u8]) -> bool {
+    let start = header
+        .iter()
+        .position(|byte| !byte.is_ascii_whitespace())
+        .map(|index| &header[index..])
+        .unwrap_or_default();
+    let start = start[..start.len().min(15)].to_ascii_lowercase();
+
+    start.starts_with(b"<!doctype html") || start.starts_with(b"<html")
+}
+
+/// Check if the header looks like plain text (valid UTF-8 without control characters)
+fn is_text(header: &[u8]) -> bool {
+    let text = match std::str::from_utf8(header) {
+        Ok(text) => text,
+        // The header may have been cut off part way through a character
+        Err(err) if err.error_len().is_none() => {
+            std::str::from_utf8(&header[..err.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return false,
+    };
+
+    !text.is_empty()
+        && !text
+            .chars()
+            .any(|value| value.is_control() && !matches!(value, '\n' | '\r' | '\t' | '\u{c}'))
+}
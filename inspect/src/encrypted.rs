@@ -0,0 +1,409 @@
+use aho_corasick::AhoCorasick;
+use serde::Serialize;
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{Read, Seek},
+    path::Path,
+    sync::LazyLock,
+};
+
+use crate::ole::CompoundFile;
+
+/// Header token of the PowerPoint "Current User" stream for encrypted presentations
+const PPT_ENCRYPTED_TOKEN: u32 = 0xF3D1_C4DF;
+
+/// Identifier at the start of the Word FIB
+const WORD_FIB_IDENT: u16 = 0xA5EC;
+
+/// fEncrypted bit of the Word FIB flags
+const WORD_ENCRYPTED_FLAG: u16 = 0x0100;
+
+/// BIFF record types checked in Excel workbook streams
+const BIFF_FILEPASS: u16 = 0x002F;
+const BIFF_EOF: u16 = 0x000A;
+
+/// Number of bytes from the end of a PDF searched for the trailer
+pub const PDF_TRAILER_LENGTH: usize = 1024 * 32;
+
+/// Name of the ODF manifest listing the entries of the package
+const ODF_MANIFEST: &str = "META-INF/manifest.xml";
+
+/// Names of ZIP entries that only exist in encrypted packages
+const ZIP_ENCRYPTED_ENTRIES: &[&str] = &["EncryptionInfo", "EncryptedPackage"];
+
+/// Maximum number of bytes of the ODF manifest that are read
+const ODF_MANIFEST_MAX_LENGTH: u64 = 1024 * 1024;
+
+/// Number of bytes of the workbook stream searched for the FILEPASS record,
+/// it follows the first BOF record so is always near the start
+const BIFF_SEARCH_LENGTH: u64 = 64 * 1024;
+
+const ENCRYPTED_SIGNATURES: &[&str] = &[
+    "EncryptedPackage",
+    "Microsoft_Container_",
+    "DRMContent",
+    "EncryptionInfo",
+    "EncryptedData",
+    "EncryptedDocument",
+    "ECMA-376 Encryption",
+    "msoffice",
+    "encrypt",
+];
+
+/// Matcher for every [ENCRYPTED_SIGNATURES] signature along with their UTF-16
+/// LE and BE versions, built once so the header is only scanned a single time.
+/// Each signature has 3 consecutive patterns
+static ENCRYPTED_SIGNATURE_MATCHER: LazyLock<AhoCorasick> = LazyLock::new(|| {
+    let patterns = ENCRYPTED_SIGNATURES.iter().flat_map(|signature| {
+        [
+            signature.as_bytes().to_vec(),
+            to_utf16_le(signature.as_bytes()),
+            to_utf16_be(signature.as_bytes()),
+        ]
+    });
+
+    AhoCorasick::new(patterns).expect("encrypted signature patterns are valid")
+});
+
+/// Verdict of a file condition check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileVerdict {
+    Normal,
+    LikelyCorrupted,
+    LikelyEncrypted,
+}
+
+/// How much the verdict of a file condition check can be relied on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Confidence {
+    /// Verdict is based on heuristics that can also match normal files
+    Low,
+    /// Verdict is based on the structure of the file
+    High,
+}
+
+/// Signal that triggered a file condition verdict
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConditionReason {
+    /// File has no contents
+    Empty,
+    /// File is too short to be a document
+    TooShort,
+    /// Start of the file contains a password protection signature
+    EncryptedSignature(&'static str),
+    /// Compound file contains an encrypted package stream
+    EncryptedPackage,
+    /// Word document FIB has the encrypted flag set
+    WordEncryptedFlag,
+    /// Excel workbook globals contain a FILEPASS record
+    ExcelFilePass,
+    /// PowerPoint "Current User" stream has the encrypted token
+    PowerPointEncryptedToken,
+    /// PDF trailer references an encryption dictionary
+    PdfEncryptDictionary,
+    /// ZIP entry is encrypted with ZIP encryption
+    ZipEncryptedEntry(String),
+    /// ZIP contains an entry that only exists in encrypted packages
+    ZipEncryptionEntry(&'static str),
+    /// ODF manifest lists encryption data for its entries
+    OdfEncryptionData,
+    /// File is too small to contain a ZIP end record
+    ZipTooSmall,
+    /// ZIP end record isn't at the end of the file
+    MissingEndRecord,
+}
+
+impl Display for ConditionReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => f.write_str("file is empty"),
+            Self::TooShort => f.write_str("file is too short to be a document"),
+            Self::EncryptedSignature(signature) => {
+                write!(f, "found encryption signature \"{signature}\"")
+            }
+            Self::EncryptedPackage => f.write_str("compound file contains an encrypted package"),
+            Self::WordEncryptedFlag => f.write_str("word document is flagged as encrypted"),
+            Self::ExcelFilePass => f.write_str("workbook contains a FILEPASS record"),
+            Self::PowerPointEncryptedToken => f.write_str("presentation is flagged as encrypted"),
+            Self::PdfEncryptDictionary => f.write_str("PDF references an encryption dictionary"),
+            Self::ZipEncryptedEntry(name) => write!(f, "archive entry \"{name}\" is encrypted"),
+            Self::ZipEncryptionEntry(name) => write!(f, "archive contains an \"{name}\" entry"),
+            Self::OdfEncryptionData => f.write_str("ODF manifest lists encryption data"),
+            Self::ZipTooSmall => f.write_str("file is too small to be a ZIP archive"),
+            Self::MissingEndRecord => f.write_str("ZIP end of central directory record is missing"),
+        }
+    }
+}
+
+/// Result of checking the structure of a file for encryption
+#[derive(Debug, Clone)]
+pub enum EncryptionCheck {
+    Unencrypted,
+    Encrypted(ConditionReason),
+}
+
+/// Condition of a file along with the signal that determined it
+#[derive(Debug, Clone)]
+pub struct FileCondition {
+    pub verdict: FileVerdict,
+    pub confidence: Confidence,
+    /// Signal that triggered the verdict, None for normal files
+    pub reason: Option<ConditionReason>,
+}
+
+impl FileCondition {
+    pub fn normal(confidence: Confidence) -> Self {
+        Self {
+            verdict: FileVerdict::Normal,
+            confidence,
+            reason: None,
+        }
+    }
+
+    fn corrupted(reason: ConditionReason, confidence: Confidence) -> Self {
+        Self {
+            verdict: FileVerdict::LikelyCorrupted,
+            confidence,
+            reason: Some(reason),
+        }
+    }
+
+    fn encrypted(reason: ConditionReason, confidence: Confidence) -> Self {
+        Self {
+            verdict: FileVerdict::LikelyEncrypted,
+            confidence,
+            reason: Some(reason),
+        }
+    }
+}
+
+/// Number of bytes from the start of the file that are checked for signatures
+pub const HEADER_LENGTH: usize = 1024 * 32;
+
+/// Length of a ZIP end of central directory record (without a comment)
+pub const END_RECORD_LENGTH: usize = 22;
+
+/// Helper to check the condition of a file for better corruption and encryption error
+/// checking
+///
+/// ## Arguments
+/// * `header` - The start of the file (Not really header, just up to the first [HEADER_LENGTH] bytes of the file)
+/// * `end_record` - The first 4 bytes of where the ZIP end record would be located ([END_RECORD_LENGTH] bytes from the end)
+/// * `size` - The total size of the file
+/// * `encryption` - Whether the file is encrypted according to its structure (OLE streams,
+///   PDF trailer, ZIP central directory), None when the structure wasn't checked
+pub fn get_file_condition(
+    header: &[u8],
+    end_record: &[u8],
+    size: u64,
+    encryption: Option<&EncryptionCheck>,
+) -> FileCondition {
+    // File is empty, probably corrupted
+    if size == 0 {
+        return FileCondition::corrupted(ConditionReason::Empty, Confidence::High);
+    }
+
+    if header.len() < 4 {
+        return FileCondition::corrupted(ConditionReason::TooShort, Confidence::Low);
+    }
+
+    // Structure of the file is authoritative, the signatures below can
+    // appear in the content of normal files
+    match encryption {
+        Some(EncryptionCheck::Encrypted(reason)) => {
+            return FileCondition::encrypted(reason.clone(), Confidence::High);
+        }
+        Some(EncryptionCheck::Unencrypted) => {}
+        None => {
+            if let Some(signature) = find_encrypted_signature(header) {
+                return FileCondition::encrypted(
+                    ConditionReason::EncryptedSignature(signature),
+                    Confidence::Low,
+                );
+            }
+        }
+    }
+
+    // Check for common corruption signs (ZIP-based file)
+    if header.first() == Some(&b'P') && header.get(1) == Some(&b'K') {
+        // Too small for valid ZIP (File is probably corrupted)
+        if size < END_RECORD_LENGTH as u64 {
+            return FileCondition::corrupted(ConditionReason::ZipTooSmall, Confidence::High);
+        }
+
+        // Invalid ZIP end record, can also be caused by an archive comment
+        if end_record != [0x50, 0x4b, 0x05, 0x06] {
+            return FileCondition::corrupted(ConditionReason::MissingEndRecord, Confidence::Low);
+        }
+    }
+
+    // Normal files can only be trusted when their structure was checked
+    match encryption {
+        Some(_) => FileCondition::normal(Confidence::High),
+        None => FileCondition::normal(Confidence::Low),
+    }
+}
+
+/// Find the first password protection signature at the start of a file, used
+/// when the structure of the file can't be checked
+fn find_encrypted_signature(header: &[u8]) -> Option<&'static str> {
+    ENCRYPTED_SIGNATURE_MATCHER
+        .find(header)
+        .map(|found| ENCRYPTED_SIGNATURES[found.pattern().as_usize() / 3])
+}
+
+/// Whether a PDF references an encryption dictionary from its trailer, the
+/// first page trailer of linearized files is at the start of the file
+///
+/// ## Arguments
+/// * `header` - The start of the file
+/// * `trailer` - Up to the last [PDF_TRAILER_LENGTH] bytes of the file
+pub fn pdf_encryption(header: &[u8], trailer: &[u8]) -> EncryptionCheck {
+    if find_needle(header, b"/Encrypt") || find_needle(trailer, b"/Encrypt") {
+        EncryptionCheck::Encrypted(ConditionReason::PdfEncryptDictionary)
+    } else {
+        EncryptionCheck::Unencrypted
+    }
+}
+
+/// Whether a ZIP based file (OOXML, ODF) is password protected, checked using
+/// every entry in the central directory rather than just the start of the file.
+/// None when the file isn't a readable ZIP archive
+pub fn zip_encryption(path: &Path) -> std::io::Result<Option<EncryptionCheck>> {
+    let Ok(mut archive) = zip::ZipArchive::new(File::open(path)?) else {
+        return Ok(None);
+    };
+
+    for index in 0..archive.len() {
+        let Ok(entry) = archive.by_index_raw(index) else {
+            return Ok(None);
+        };
+
+        // Entries encrypted with ZIP encryption
+        if entry.encrypted() {
+            let reason = ConditionReason::ZipEncryptedEntry(entry.name().to_string());
+            return Ok(Some(EncryptionCheck::Encrypted(reason)));
+        }
+
+        // Entries containing an encrypted package
+        let name = entry.name().rsplit('/').next().unwrap_or_default();
+        if let Some(name) = ZIP_ENCRYPTED_ENTRIES.iter().find(|entry| **entry == name) {
+            let reason = ConditionReason::ZipEncryptionEntry(name);
+            return Ok(Some(EncryptionCheck::Encrypted(reason)));
+        }
+    }
+
+    // ODF manifest lists encryption data for each encrypted entry
+    let manifest = match archive.by_name(ODF_MANIFEST) {
+        Ok(manifest) => manifest,
+        Err(_) => return Ok(Some(EncryptionCheck::Unencrypted)),
+    };
+
+    let mut contents = Vec::new();
+    manifest
+        .take(ODF_MANIFEST_MAX_LENGTH)
+        .read_to_end(&mut contents)?;
+
+    if find_needle(&contents, b"encryption-data") {
+        let reason = ConditionReason::OdfEncryptionData;
+        return Ok(Some(EncryptionCheck::Encrypted(reason)));
+    }
+
+    Ok(Some(EncryptionCheck::Unencrypted))
+}
+
+/// Check whether a legacy office file (DOC, XLS, PPT) or encrypted OOXML file
+/// stored as an OLE compound file is encrypted by reading its streams
+pub fn ole_encryption<R: Read + Seek>(
+    file: &mut CompoundFile<R>,
+) -> std::io::Result<EncryptionCheck> {
+    let encrypted = |is_encrypted: bool, reason: ConditionReason| {
+        if is_encrypted {
+            Ok(EncryptionCheck::Encrypted(reason))
+        } else {
+            Ok(EncryptionCheck::Unencrypted)
+        }
+    };
+
+    // Encrypted OOXML (and IRM protected) files are a compound file
+    // containing the encrypted package
+    if file.has_stream("EncryptedPackage") {
+        return encrypted(true, ConditionReason::EncryptedPackage);
+    }
+
+    if let Some(fib) = file.read_stream("WordDocument", 12)? {
+        let is_encrypted = fib.len() >= 12
+            && u16::from_le_bytes([fib[0], fib[1]]) == WORD_FIB_IDENT
+            && u16::from_le_bytes([fib[10], fib[11]]) & WORD_ENCRYPTED_FLAG != 0;
+        return encrypted(is_encrypted, ConditionReason::WordEncryptedFlag);
+    }
+
+    let workbook = match file.read_stream("Workbook", BIFF_SEARCH_LENGTH)? {
+        Some(workbook) => Some(workbook),
+        None => file.read_stream("Book", BIFF_SEARCH_LENGTH)?,
+    };
+    if let Some(workbook) = workbook {
+        return encrypted(has_biff_filepass(&workbook), ConditionReason::ExcelFilePass);
+    }
+
+    if let Some(current_user) = file.read_stream("Current User", 16)? {
+        let is_encrypted = current_user.len() >= 16
+            && u32::from_le_bytes([
+                current_user[12],
+                current_user[13],
+                current_user[14],
+                current_user[15],
+            ]) == PPT_ENCRYPTED_TOKEN;
+        return encrypted(is_encrypted, ConditionReason::PowerPointEncryptedToken);
+    }
+
+    Ok(EncryptionCheck::Unencrypted)
+}
+
+/// Whether the globals substream of an Excel workbook stream contains the
+/// FILEPASS record marking the workbook as encrypted
+fn has_biff_filepass(workbook: &[u8]) -> bool {
+    let mut offset = 0;
+
+    while offset + 4 <= workbook.len() {
+        let record_type = u16::from_le_bytes([workbook[offset], workbook[offset + 1]]);
+        let length = u16::from_le_bytes([workbook[offset + 2], workbook[offset + 3]]);
+
+        match record_type {
+            BIFF_FILEPASS => return true,
+            BIFF_EOF => return false,
+            _ => offset += 4 + usize::from(length),
+        }
+    }
+
+    false
+}
+
+pub fn find_needle(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+/// Convert ASCII bytes to UTF-16 Little Endian
+pub fn to_utf16_le(ascii: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(ascii.len() * 2);
+    for &byte in ascii {
+        result.push(byte);
+        result.push(0);
+    }
+    result
+}
+
+/// Convert ASCII bytes to UTF-16 Big Endian
+fn to_utf16_be(ascii: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(ascii.len() * 2);
+    for &byte in ascii {
+        result.push(0);
+        result.push(byte);
+    }
+    result
+}
@@ -0,0 +1,29 @@
+//! Detection of the format and condition of office files, checks whether a
+//! file is likely encrypted or corrupted before it is converted. This is the
+//! same logic used by onlyoffice-convert-server to screen uploaded files so
+//! uploads can be pre-screened before they are sent to the server
+//!
+//! ```no_run
+//! use office_file_inspect::{FileSample, FileVerdict};
+//!
+//! let sample = FileSample::read("document.docx".as_ref())?;
+//! let condition = sample.condition();
+//!
+//! if condition.verdict == FileVerdict::LikelyEncrypted {
+//!     println!("file is encrypted: {:?}", condition.reason);
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+mod detect;
+mod encrypted;
+mod ole;
+mod sample;
+
+pub use detect::{OleDetails, conflicts_with_extension, detect_format, inspect_ole_file};
+pub use encrypted::{
+    ConditionReason, Confidence, END_RECORD_LENGTH, EncryptionCheck, FileCondition, FileVerdict,
+    HEADER_LENGTH, PDF_TRAILER_LENGTH, get_file_condition, pdf_encryption, zip_encryption,
+};
+pub use ole::OLE_MAGIC;
+pub use sample::FileSample;
@@ -0,0 +1,619 @@
+use std::io::{Read, Seek, SeekFrom};
+
+/// Magic bytes at the start of an OLE compound file (DOC, XLS, PPT, encrypted OOXML)
+pub const OLE_MAGIC: &[u8] = &[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1];
+
+/// Length of the compound file header
+const HEADER_LENGTH: usize = 512;
+
+/// Number of FAT sector IDs stored in the header
+const HEADER_DIFAT_LENGTH: usize = 109;
+
+/// Marks the end of a sector chain
+const END_OF_CHAIN: u32 = 0xFFFF_FFFE;
+
+/// Sector IDs at and above this value are special markers rather than sectors
+const MAX_SECTOR_ID: u32 = 0xFFFF_FFFA;
+
+/// Length of a directory entry
+const DIRECTORY_ENTRY_LENGTH: usize = 128;
+
+/// Directory entry object types
+const STREAM_OBJECT: u8 = 2;
+const ROOT_STORAGE_OBJECT: u8 = 5;
+
+/// Entry in the compound file directory
+struct DirectoryEntry {
+    name: String,
+    object_type: u8,
+    start_sector: u32,
+    size: u64,
+}
+
+/// Minimal reader for OLE compound files (MS-CFB), only supports finding and
+/// reading streams by name which is enough to inspect legacy office files
+pub struct CompoundFile<R> {
+    reader: R,
+    sector_shift: u32,
+    mini_sector_shift: u32,
+    mini_stream_cutoff: u64,
+    /// Sector allocation table
+    fat: Vec<u32>,
+    /// Mini sector allocation table
+    mini_fat: Vec<u32>,
+    /// Sectors making up the mini stream, in order
+    mini_stream_sectors: Vec<u32>,
+    directory: Vec<DirectoryEntry>,
+}
+
+impl<R: Read + Seek> CompoundFile<R> {
+    /// Parse the structure of a compound file, None when the file isn't a
+    /// valid compound file (i.e it is truncated)
+    pub fn open(mut reader: R) -> std::io::Result<Option<Self>> {
+        let file_size = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut header = [0; HEADER_LENGTH];
+        if reader.read_exact(&mut header).is_err() || !header.starts_with(OLE_MAGIC) {
+            return Ok(None);
+        }
+
+        let sector_shift = u32::from(read_u16(&header, 0x1E));
+        let mini_sector_shift = u32::from(read_u16(&header, 0x20));
+        if !matches!(sector_shift, 9 | 12) || mini_sector_shift != 6 {
+            return Ok(None);
+        }
+
+        let mut file = Self {
+            reader,
+            sector_shift,
+            mini_sector_shift,
+            mini_stream_cutoff: u64::from(read_u32(&header, 0x38)),
+            fat: Vec::new(),
+            mini_fat: Vec::new(),
+            mini_stream_sectors: Vec::new(),
+            directory: Vec::new(),
+        };
+
+        // Sector IDs can't reference past the end of the file
+        let max_sectors = (file_size >> sector_shift) as usize;
+
+        let Some(fat_sectors) = file.read_difat(&header, max_sectors)? else {
+            return Ok(None);
+        };
+
+        for sector in fat_sectors {
+            let Some(data) = file.read_sector(sector)? else {
+                return Ok(None);
+            };
+            file.fat
+                .extend(data.chunks_exact(4).map(|value| read_u32(value, 0)));
+        }
+
+        let Some(directory) = file.read_chain(read_u32(&header, 0x30), u64::MAX)? else {
+            return Ok(None);
+        };
+        file.directory = directory
+            .chunks_exact(DIRECTORY_ENTRY_LENGTH)
+            .map(parse_directory_entry)
+            .collect();
+
+        let Some(mini_fat) = file.read_chain(read_u32(&header, 0x3C), u64::MAX)? else {
+            return Ok(None);
+        };
+        file.mini_fat = mini_fat
+            .chunks_exact(4)
+            .map(|value| read_u32(value, 0))
+            .collect();
+
+        // Mini stream is stored in the sectors of the root entry
+        if let Some(root) = file
+            .directory
+            .iter()
+            .find(|entry| entry.object_type == ROOT_STORAGE_OBJECT)
+        {
+            let Some(sectors) = file.chain_sectors(root.start_sector) else {
+                return Ok(None);
+            };
+            file.mini_stream_sectors = sectors;
+        }
+
+        Ok(Some(file))
+    }
+
+    /// Whether the compound file contains a stream with the provided name
+    pub fn has_stream(&self, name: &str) -> bool {
+        self.find_stream(name).is_some()
+    }
+
+    /// Read up to `max_length` bytes from the start of the stream with the
+    /// provided name, None when there is no such stream
+    pub fn read_stream(&mut self, name: &str, max_length: u64) -> std::io::Result<Option<Vec<u8>>> {
+        let Some(entry) = self.find_stream(name) else {
+            return Ok(None);
+        };
+
+        // Only version 4 files (4096 byte sectors) use the high bits of the size
+        let size = match self.sector_shift {
+            9 => entry.size & 0xFFFF_FFFF,
+            _ => entry.size,
+        };
+        let start_sector = entry.start_sector;
+        let length = size.min(max_length);
+
+        let data = if size < self.mini_stream_cutoff {
+            self.read_mini_chain(start_sector, length)?
+        } else {
+            self.read_chain(start_sector, length)?
+        };
+
+        Ok(data.map(|mut data| {
+            data.truncate(length as usize);
+            data
+        }))
+    }
+
+    fn find_stream(&self, name: &str) -> Option<&DirectoryEntry> {
+        self.directory.iter().find(|entry| {
+            entry.object_type == STREAM_OBJECT && entry.name.eq_ignore_ascii_case(name)
+        })
+    }
+
+    fn sector_size(&self) -> usize {
+        1 << self.sector_shift
+    }
+
+    /// Collect the IDs of the FAT sectors from the header and DIFAT sectors
+    fn read_difat(
+        &mut self,
+        header: &[u8],
+        max_sectors: usize,
+    ) -> std::io::Result<Option<Vec<u32>>> {
+        let fat_sector_count = read_u32(header, 0x2C) as usize;
+        if fat_sector_count > max_sectors {
+            return Ok(None);
+        }
+
+        let mut fat_sectors: Vec<u32> = (0..HEADER_DIFAT_LENGTH)
+            .map(|index| read_u32(header, 0x4C + index * 4))
+            .take(fat_sector_count)
+            .collect();
+
+        let mut difat_sector = read_u32(header, 0x44);
+        let mut remaining = read_u32(header, 0x48);
+
+        while fat_sectors.len() < fat_sector_count && remaining > 0 {
+            let Some(data) = self.read_sector(difat_sector)? else {
+                return Ok(None);
+            };
+
+            let values: Vec<u32> = data
+                .chunks_exact(4)
+                .map(|value| read_u32(value, 0))
+                .collect();
+            let Some((next, entries)) = values.split_last() else {
+                return Ok(None);
+            };
+
+            fat_sectors.extend(entries);
+            difat_sector = *next;
+            remaining -= 1;
+        }
+
+        fat_sectors.truncate(fat_sector_count);
+        Ok(Some(fat_sectors))
+    }
+
+    /// Read the contents of a sector, None when the sector ID is invalid or
+    /// past the end of the file
+    fn read_sector(&mut self, sector: u32) -> std::io::Result<Option<Vec<u8>>> {
+        if sector >= MAX_SECTOR_ID {
+            return Ok(None);
+        }
+
+        let offset = (u64::from(sector) + 1) << self.sector_shift;
+        self.reader.seek(SeekFrom::Start(offset))?;
+
+        let mut data = vec![0; self.sector_size()];
+        if self.reader.read_exact(&mut data).is_err() {
+            return Ok(None);
+        }
+
+        Ok(Some(data))
+    }
+
+    /// IDs of the sectors in the chain starting at the provided sector, None
+    /// when the chain is broken or loops
+    fn chain_sectors(&self, start_sector: u32) -> Option<Vec<u32>> {
+        follow_chain(&self.fat, start_sector)
+    }
+
+    /// Read up to `max_length` bytes of the sector chain starting at the
+    /// provided sector
+    fn read_chain(
+        &mut self,
+        start_sector: u32,
+        max_length: u64,
+    ) -> std::io::Result<Option<Vec<u8>>> {
+        let Some(sectors) = self.chain_sectors(start_sector) else {
+            return Ok(None);
+        };
+
+        let mut data = Vec::new();
+        for sector in sectors {
+            if data.len() as u64 >= max_length {
+                break;
+            }
+
+            let Some(sector_data) = self.read_sector(sector)? else {
+                return Ok(None);
+            };
+            data.extend_from_slice(&sector_data);
+        }
+
+        Ok(Some(data))
+    }
+
+    /// Read up to `max_length` bytes of the mini sector chain starting at the
+    /// provided mini sector
+    fn read_mini_chain(
+        &mut self,
+        start_sector: u32,
+        max_length: u64,
+    ) -> std::io::Result<Option<Vec<u8>>> {
+        let Some(mini_sectors) = follow_chain(&self.mini_fat, start_sector) else {
+            return Ok(None);
+        };
+
+        let mini_sector_size = 1usize << self.mini_sector_shift;
+        let mut data = Vec::new();
+
+        for mini_sector in mini_sectors {
+            if data.len() as u64 >= max_length {
+                break;
+            }
+
+            // Locate the mini sector within the sectors of the mini stream
+            let offset = (mini_sector as usize) << self.mini_sector_shift;
+            let Some(&sector) = self.mini_stream_sectors.get(offset >> self.sector_shift) else {
+                return Ok(None);
+            };
+            let Some(sector_data) = self.read_sector(sector)? else {
+                return Ok(None);
+            };
+
+            let start = offset & (self.sector_size() - 1);
+            data.extend_from_slice(&sector_data[start..start + mini_sector_size]);
+        }
+
+        Ok(Some(data))
+    }
+}
+
+/// Follow a chain through an allocation table, None when the chain is
+/// broken or loops
+fn follow_chain(table: &[u32], start_sector: u32) -> Option<Vec<u32>> {
+    let mut sectors = Vec::new();
+    let mut sector = start_sector;
+
+    while sector != END_OF_CHAIN {
+        // A chain can't be longer than the table without looping
+        if sector >= MAX_SECTOR_ID || sectors.len() >= table.len() {
+            return None;
+        }
+
+        sectors.push(sector);
+        sector = *table.get(sector as usize)?;
+    }
+
+    Some(sectors)
+}
+
+fn parse_directory_entry(data: &[u8]) -> DirectoryEntry {
+    // Name length is in bytes and includes the null terminator
+    let name_length = (usize::from(read_u16(data, 0x40)) / 2)
+        .saturating_sub(1)
+        .min(31);
+    let name: Vec<u16> = (0..name_length)
+        .map(|index| read_u16(data, index * 2))
+        .collect();
+
+    DirectoryEntry {
+        name: String::from_utf16_lossy(&name),
+        object_type: data[0x42],
+        start_sector: read_u32(data, 0x74),
+        size: read_u32(data, 0x78) as u64 | (u64::from(read_u32(data, 0x7C)) << 32),
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Marks a free sector in an allocation table
+    const FREE_SECTOR: u32 = 0xFFFF_FFFF;
+
+    /// Marks a sector used by the FAT
+    const FAT_SECTOR: u32 = 0xFFFF_FFFD;
+
+    const SECTOR_SIZE: usize = 512;
+
+    /// Size of the large stream, stored in regular sectors
+    const DOCUMENT_SIZE: usize = 4096;
+
+    /// Size of the small stream, stored in the mini stream
+    const SMALL_SIZE: usize = 100;
+
+    fn write_u16(data: &mut [u8], offset: usize, value: u16) {
+        data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+        data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn sector_offset(sector: usize) -> usize {
+        (sector + 1) * SECTOR_SIZE
+    }
+
+    fn write_directory_entry(
+        data: &mut [u8],
+        offset: usize,
+        name: &str,
+        object_type: u8,
+        start_sector: u32,
+        size: u32,
+    ) {
+        let name: Vec<u16> = name.encode_utf16().collect();
+        for (index, value) in name.iter().enumerate() {
+            write_u16(data, offset + index * 2, *value);
+        }
+
+        write_u16(data, offset + 0x40, ((name.len() + 1) * 2) as u16);
+        data[offset + 0x42] = object_type;
+        write_u32(data, offset + 0x74, start_sector);
+        write_u32(data, offset + 0x78, size);
+    }
+
+    fn document_contents() -> Vec<u8> {
+        (0..DOCUMENT_SIZE)
+            .map(|index| (index % 251) as u8)
+            .collect()
+    }
+
+    fn small_contents() -> Vec<u8> {
+        vec![0xAB; SMALL_SIZE]
+    }
+
+    /// Create a version 3 compound file with a "WordDocument" stream stored
+    /// in regular sectors and a "Small" stream stored in the mini stream
+    ///
+    /// Sector 0 is the FAT, 1 the directory, 2 the mini FAT, 3 the mini
+    /// stream and 4-11 the contents of "WordDocument"
+    fn compound_file() -> Vec<u8> {
+        let mut data = vec![0; sector_offset(12)];
+
+        data[..8].copy_from_slice(OLE_MAGIC);
+        write_u16(&mut data, 0x18, 0x3E);
+        write_u16(&mut data, 0x1A, 3);
+        write_u16(&mut data, 0x1C, 0xFFFE);
+        write_u16(&mut data, 0x1E, 9);
+        write_u16(&mut data, 0x20, 6);
+        write_u32(&mut data, 0x2C, 1);
+        write_u32(&mut data, 0x30, 1);
+        write_u32(&mut data, 0x38, 4096);
+        write_u32(&mut data, 0x3C, 2);
+        write_u32(&mut data, 0x40, 1);
+        write_u32(&mut data, 0x44, END_OF_CHAIN);
+        write_u32(&mut data, 0x48, 0);
+        for index in 0..HEADER_DIFAT_LENGTH {
+            write_u32(&mut data, 0x4C + index * 4, FREE_SECTOR);
+        }
+        write_u32(&mut data, 0x4C, 0);
+
+        let fat = [
+            FAT_SECTOR,
+            END_OF_CHAIN,
+            END_OF_CHAIN,
+            END_OF_CHAIN,
+            5,
+            6,
+            7,
+            8,
+            9,
+            10,
+            11,
+            END_OF_CHAIN,
+        ];
+        for index in 0..SECTOR_SIZE / 4 {
+            let value = fat.get(index).copied().unwrap_or(FREE_SECTOR);
+            write_u32(&mut data, sector_offset(0) + index * 4, value);
+        }
+
+        let directory = sector_offset(1);
+        write_directory_entry(
+            &mut data,
+            directory,
+            "Root Entry",
+            ROOT_STORAGE_OBJECT,
+            3,
+            SECTOR_SIZE as u32,
+        );
+        write_directory_entry(
+            &mut data,
+            directory + DIRECTORY_ENTRY_LENGTH,
+            "WordDocument",
+            STREAM_OBJECT,
+            4,
+            DOCUMENT_SIZE as u32,
+        );
+        write_directory_entry(
+            &mut data,
+            directory + DIRECTORY_ENTRY_LENGTH * 2,
+            "Small",
+            STREAM_OBJECT,
+            0,
+            SMALL_SIZE as u32,
+        );
+
+        let mini_fat = [1, END_OF_CHAIN];
+        for index in 0..SECTOR_SIZE / 4 {
+            let value = mini_fat.get(index).copied().unwrap_or(FREE_SECTOR);
+            write_u32(&mut data, sector_offset(2) + index * 4, value);
+        }
+
+        let small = small_contents();
+        data[sector_offset(3)..sector_offset(3) + SMALL_SIZE].copy_from_slice(&small);
+
+        let document = document_contents();
+        data[sector_offset(4)..sector_offset(4) + DOCUMENT_SIZE].copy_from_slice(&document);
+
+        data
+    }
+
+    fn open(data: Vec<u8>) -> Option<CompoundFile<Cursor<Vec<u8>>>> {
+        CompoundFile::open(Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn test_read_streams() {
+        let mut file = open(compound_file()).unwrap();
+
+        assert!(file.has_stream("WordDocument"));
+        assert!(file.has_stream("worddocument"));
+        assert!(!file.has_stream("Root Entry"));
+        assert!(!file.has_stream("Workbook"));
+
+        assert_eq!(
+            file.read_stream("WordDocument", u64::MAX).unwrap(),
+            Some(document_contents())
+        );
+        assert_eq!(
+            file.read_stream("WordDocument", 600).unwrap(),
+            Some(document_contents()[..600].to_vec())
+        );
+        assert_eq!(
+            file.read_stream("Small", u64::MAX).unwrap(),
+            Some(small_contents())
+        );
+        assert_eq!(file.read_stream("Workbook", u64::MAX).unwrap(), None);
+    }
+
+    #[test]
+    fn test_empty_file() {
+        assert!(open(Vec::new()).is_none());
+    }
+
+    #[test]
+    fn test_truncated_header() {
+        let mut data = compound_file();
+        data.truncate(HEADER_LENGTH - 1);
+
+        assert!(open(data).is_none());
+    }
+
+    #[test]
+    fn test_invalid_magic() {
+        let mut data = compound_file();
+        data[0] = 0;
+
+        assert!(open(data).is_none());
+    }
+
+    #[test]
+    fn test_invalid_sector_shift() {
+        let mut data = compound_file();
+        write_u16(&mut data, 0x1E, 10);
+        assert!(open(data).is_none());
+
+        let mut data = compound_file();
+        write_u16(&mut data, 0x20, 7);
+        assert!(open(data).is_none());
+    }
+
+    #[test]
+    fn test_truncated_sectors() {
+        // FAT is present but the directory is missing
+        let mut data = compound_file();
+        data.truncate(sector_offset(1));
+        assert!(open(data).is_none());
+
+        // Only part of the FAT sector is present
+        let mut data = compound_file();
+        data.truncate(sector_offset(0) + 100);
+        assert!(open(data).is_none());
+    }
+
+    #[test]
+    fn test_truncated_stream() {
+        // Structure is intact but the stream contents are cut off
+        let mut data = compound_file();
+        data.truncate(sector_offset(6));
+
+        let mut file = open(data).unwrap();
+        assert!(file.has_stream("WordDocument"));
+        assert_eq!(file.read_stream("WordDocument", u64::MAX).unwrap(), None);
+    }
+
+    #[test]
+    fn test_invalid_fat_sectors() {
+        // More FAT sectors than the file could contain
+        let mut data = compound_file();
+        write_u32(&mut data, 0x2C, 1000);
+        assert!(open(data).is_none());
+
+        // FAT sector past the end of the file
+        let mut data = compound_file();
+        write_u32(&mut data, 0x4C, 100);
+        assert!(open(data).is_none());
+    }
+
+    #[test]
+    fn test_looping_chain() {
+        // Directory chain points back to itself
+        let mut data = compound_file();
+        write_u32(&mut data, sector_offset(0) + 4, 1);
+        assert!(open(data).is_none());
+
+        // Stream chain loops back to its start
+        let mut data = compound_file();
+        write_u32(&mut data, sector_offset(0) + 11 * 4, 4);
+
+        let mut file = open(data).unwrap();
+        assert_eq!(file.read_stream("WordDocument", u64::MAX).unwrap(), None);
+    }
+
+    #[test]
+    fn test_follow_chain() {
+        let table = [1, 2, END_OF_CHAIN, 0];
+
+        assert_eq!(follow_chain(&table, 0), Some(vec![0, 1, 2]));
+        assert_eq!(follow_chain(&table, END_OF_CHAIN), Some(Vec::new()));
+
+        assert_eq!(follow_chain(&table, 3), Some(vec![3, 0, 1, 2]));
+
+        // Loops back to the start
+        assert_eq!(follow_chain(&[1, 0], 0), None);
+        assert_eq!(follow_chain(&[0], 0), None);
+
+        // Points past the end of the table
+        assert_eq!(follow_chain(&[5], 0), None);
+
+        // Special sector IDs aren't part of a chain
+        assert_eq!(follow_chain(&[FREE_SECTOR], 0), None);
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::{
+    detect::{OleDetails, detect_format, inspect_ole_file},
+    encrypted::{
+        END_RECORD_LENGTH, EncryptionCheck, FileCondition, HEADER_LENGTH, PDF_TRAILER_LENGTH,
+        get_file_condition, pdf_encryption, zip_encryption,
+    },
+    ole::OLE_MAGIC,
+};
+
+/// Parts of a file used to check its condition and format
+pub struct FileSample {
+    /// Up to the first [HEADER_LENGTH] bytes of the file
+    pub header: Vec<u8>,
+    /// First 4 bytes of where the ZIP end record would be located
+    pub end_record: [u8; 4],
+    /// Total size of the file
+    pub size: u64,
+    /// Details read from the directory of OLE compound files, None for
+    /// other files or when the directory can't be read
+    pub ole: Option<OleDetails>,
+    /// Whether the file is encrypted according to its structure, None when
+    /// the structure of the file wasn't checked
+    pub encryption: Option<EncryptionCheck>,
+}
+
+impl FileSample {
+    /// Read the parts of the file needed to check its condition and format
+    pub fn read(path: &Path) -> std::io::Result<Self> {
+        let mut file = File::open(path)?;
+        let size = file.metadata()?.len();
+
+        let mut header = vec![0; size.min(HEADER_LENGTH as u64) as usize];
+        file.read_exact(&mut header)?;
+
+        let mut end_record = [0; 4];
+        if size >= END_RECORD_LENGTH as u64 {
+            file.seek(SeekFrom::End(-(END_RECORD_LENGTH as i64)))?;
+            file.read_exact(&mut end_record)?;
+        }
+
+        // Compound files are parsed for an accurate format and encryption check
+        let ole = if header.starts_with(OLE_MAGIC) {
+            inspect_ole_file(path)?
+        } else {
+            None
+        };
+
+        let encryption = if let Some(ole) = &ole {
+            Some(ole.encryption.clone())
+        } else if header.starts_with(b"%PDF-") {
+            let trailer_length = size.min(PDF_TRAILER_LENGTH as u64);
+            let mut trailer = vec![0; trailer_length as usize];
+            file.seek(SeekFrom::End(-(trailer_length as i64)))?;
+            file.read_exact(&mut trailer)?;
+
+            Some(pdf_encryption(&header, &trailer))
+        } else if header.starts_with(b"PK\x03\x04") {
+            zip_encryption(path)?
+        } else {
+            None
+        };
+
+        Ok(Self {
+            header,
+            end_record,
+            size,
+            ole,
+            encryption,
+        })
+    }
+
+    /// Check the condition of the sampled file
+    pub fn condition(&self) -> FileCondition {
+        get_file_condition(
+            &self.header,
+            &self.end_record,
+            self.size,
+            self.encryption.as_ref(),
+        )
+    }
+
+    /// File extension of the format detected from the content of the file
+    pub fn format(&self) -> Option<&'static str> {
+        match &self.ole {
+            Some(ole) => Some(ole.format),
+            None => detect_format(&self.header),
+        }
+    }
+}
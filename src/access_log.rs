@@ -0,0 +1,176 @@
+use anyhow::Context;
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use clap::ValueEnum;
+use futures_util::TryStreamExt;
+use serde::Serialize;
+use std::{
+    fs::OpenOptions,
+    io::{LineWriter, Write},
+    net::IpAddr,
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+use crate::client_ip::ClientIp;
+
+/// Destination writing the access log to stdout instead of a file
+const STDOUT_DESTINATION: &str = "-";
+
+/// Format to write access log entries in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum AccessLogFormat {
+    /// Single line of space separated fields per request
+    #[default]
+    Text,
+    /// JSON object per request, one object per line
+    Json,
+}
+
+/// Unique ID of a request, shared by the access log and the request span
+#[derive(Debug, Clone, Copy)]
+pub struct RequestId(pub Uuid);
+
+/// Log of every request handled by the server, written separately from the
+/// tracing logs so it can be consumed on its own
+pub struct AccessLog {
+    format: AccessLogFormat,
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+#[derive(Serialize)]
+struct AccessLogEntry<'a> {
+    /// Time the request was received in milliseconds since the unix epoch
+    timestamp_ms: u64,
+    request_id: Uuid,
+    method: &'a str,
+    /// Path of the request, the query is excluded as it can contain tokens
+    path: &'a str,
+    status: u16,
+    /// Address of the client, None for connections without an address
+    client_ip: Option<IpAddr>,
+    /// Number of bytes read from the request body
+    input_size: u64,
+    /// Time taken to produce the response in milliseconds
+    duration_ms: u64,
+}
+
+impl AccessLog {
+    /// Open the access log at the destination, "-" writes to stdout and any
+    /// other value is a file path entries are appended to
+    pub fn open(destination: &str, format: AccessLogFormat) -> anyhow::Result<Self> {
+        let writer: Box<dyn Write + Send> = if destination == STDOUT_DESTINATION {
+            Box::new(std::io::stdout())
+        } else {
+            let path = Path::new(destination);
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("failed to open access log {}", path.display()))?;
+
+            Box::new(LineWriter::new(file))
+        };
+
+        Ok(Self {
+            format,
+            writer: Mutex::new(writer),
+        })
+    }
+
+    fn write(&self, entry: &AccessLogEntry<'_>) {
+        let line = match self.format {
+            AccessLogFormat::Text => format!(
+                "{} {} {} \"{} {}\" {} {} {}ms\n",
+                entry.timestamp_ms,
+                entry
+                    .client_ip
+                    .map(|client_ip| client_ip.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                entry.request_id,
+                entry.method,
+                entry.path,
+                entry.status,
+                entry.input_size,
+                entry.duration_ms,
+            ),
+            AccessLogFormat::Json => match serde_json::to_string(entry) {
+                Ok(value) => value + "\n",
+                Err(err) => {
+                    tracing::warn!(?err, "failed to serialize access log entry");
+                    return;
+                }
+            },
+        };
+
+        let mut writer = match self.writer.lock() {
+            Ok(value) => value,
+            Err(err) => err.into_inner(),
+        };
+
+        if let Err(err) = writer.write_all(line.as_bytes()) {
+            tracing::warn!(?err, "failed to write access log entry");
+        }
+    }
+}
+
+/// Middleware writing an entry to the access log for every request, assigns
+/// the ID of the request and counts the bytes of the request body read by
+/// the handler
+pub async fn log_access(
+    State(access_log): State<Option<Arc<AccessLog>>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let request_id = Uuid::new_v4();
+    request.extensions_mut().insert(RequestId(request_id));
+
+    let Some(access_log) = access_log else {
+        return next.run(request).await;
+    };
+
+    let start = Instant::now();
+    let timestamp_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|value| value.as_millis() as u64)
+        .unwrap_or_default();
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let client_ip = request
+        .extensions()
+        .get::<ClientIp>()
+        .and_then(|ClientIp(client_ip)| *client_ip);
+
+    let input_size = Arc::new(AtomicU64::new(0));
+    let request = request.map(|body| {
+        let input_size = input_size.clone();
+        Body::from_stream(body.into_data_stream().inspect_ok(move |chunk| {
+            input_size.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        }))
+    });
+
+    let response = next.run(request).await;
+
+    access_log.write(&AccessLogEntry {
+        timestamp_ms,
+        request_id,
+        method: method.as_str(),
+        path: &path,
+        status: response.status().as_u16(),
+        client_ip,
+        input_size: input_size.load(Ordering::Relaxed),
+        duration_ms: start.elapsed().as_millis() as u64,
+    });
+
+    response
+}
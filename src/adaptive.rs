@@ -0,0 +1,161 @@
+use anyhow::Context;
+use std::{sync::Arc, time::Duration};
+
+use crate::limiter::ConversionLimiter;
+
+/// Interval between samples of the system load
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Fraction of memory that must remain available, the limit is halved when
+/// less memory than this is available
+const MIN_AVAILABLE_MEMORY: f64 = 0.1;
+
+/// CPU utilization above which the CPU is considered saturated, the limit
+/// is lowered by one while saturated
+const CPU_SATURATED: f64 = 0.95;
+
+/// CPU utilization below which the limit is raised by one when all of the
+/// current slots are in use
+const CPU_HEADROOM: f64 = 0.8;
+
+/// Adjusts the number of conversions allowed to run at once based on the
+/// memory pressure and CPU saturation of the system, between one and the
+/// configured maximum. The limit is halved under memory pressure, lowered
+/// while the CPU is saturated and raised while there is spare capacity
+pub struct AdaptiveConcurrency {
+    limiter: Arc<ConversionLimiter>,
+}
+
+/// Memory usage read from /proc/meminfo
+struct MemorySample {
+    total: u64,
+    available: u64,
+}
+
+/// Cumulative CPU time read from /proc/stat
+#[derive(Clone, Copy)]
+struct CpuSample {
+    busy: u64,
+    total: u64,
+}
+
+impl AdaptiveConcurrency {
+    /// Create the controller, fails when the system load can't be read
+    pub fn new(limiter: Arc<ConversionLimiter>) -> anyhow::Result<Self> {
+        read_memory()
+            .and(read_cpu())
+            .context("adaptive concurrency requires /proc/meminfo and /proc/stat")?;
+
+        Ok(Self { limiter })
+    }
+
+    /// Sample the system load and adjust the limit until the server stops
+    pub async fn run(self) {
+        let mut limit = self.limiter.max_concurrent();
+        let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+        let mut previous_cpu = read_cpu().ok();
+
+        loop {
+            interval.tick().await;
+
+            let (memory, cpu) = match (read_memory(), read_cpu()) {
+                (Ok(memory), Ok(cpu)) => (memory, cpu),
+                (Err(err), _) | (_, Err(err)) => {
+                    tracing::warn!(?err, "failed to read system load");
+                    continue;
+                }
+            };
+
+            let cpu_utilization = previous_cpu
+                .map(|previous| utilization(previous, cpu))
+                .unwrap_or_default();
+            previous_cpu = Some(cpu);
+
+            let available_memory = memory.available as f64 / memory.total.max(1) as f64;
+            let max_concurrent = self.limiter.max_concurrent();
+
+            let next_limit = if available_memory < MIN_AVAILABLE_MEMORY {
+                limit / 2
+            } else if cpu_utilization > CPU_SATURATED {
+                limit.saturating_sub(1)
+            } else if cpu_utilization < CPU_HEADROOM && self.limiter.running() >= limit {
+                limit + 1
+            } else {
+                limit
+            }
+            .clamp(1, max_concurrent.max(1));
+
+            if next_limit != limit {
+                tracing::debug!(
+                    limit = next_limit,
+                    available_memory,
+                    cpu_utilization,
+                    "adjusted adaptive concurrency limit"
+                );
+            }
+
+            limit = next_limit;
+            self.limiter.set_adaptive_limit(limit);
+        }
+    }
+}
+
+/// Fraction of CPU time spent busy between two samples
+fn utilization(previous: CpuSample, current: CpuSample) -> f64 {
+    let total = current.total.saturating_sub(previous.total);
+    if total == 0 {
+        return 0.0;
+    }
+
+    current.busy.saturating_sub(previous.busy) as f64 / total as f64
+}
+
+fn read_memory() -> std::io::Result<MemorySample> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo")?;
+
+    let field = |name: &str| {
+        meminfo
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|value| value.split_whitespace().next()?.parse::<u64>().ok())
+    };
+
+    match (field("MemTotal"), field("MemAvailable")) {
+        (Some(total), Some(available)) => Ok(MemorySample { total, available }),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "missing MemTotal or MemAvailable",
+        )),
+    }
+}
+
+fn read_cpu() -> std::io::Result<CpuSample> {
+    let stat = std::fs::read_to_string("/proc/stat")?;
+
+    // Aggregate line: cpu user nice system idle iowait irq softirq steal ...
+    let values: Vec<u64> = stat
+        .lines()
+        .find_map(|line| line.strip_prefix("cpu "))
+        .map(|line| {
+            line.split_whitespace()
+                .filter_map(|value| value.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if values.len() < 5 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "missing aggregate cpu line",
+        ));
+    }
+
+    // Guest time is already included in user time
+    let total: u64 = values.iter().take(8).sum();
+    let idle = values[3] + values[4];
+
+    Ok(CpuSample {
+        busy: total - idle,
+        total,
+    })
+}
@@ -0,0 +1,170 @@
+use anyhow::Context;
+use std::{path::Path, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::{ErrorKind, ErrorResponse};
+
+/// Size of the chunks the file is streamed to clamd in
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Maximum time to wait for a scan to complete
+const SCAN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Address of a clamd daemon
+#[derive(Debug, Clone)]
+pub enum ClamdAddress {
+    /// TCP address (i.e 127.0.0.1:3310)
+    Tcp(String),
+    /// Path to a unix domain socket (i.e unix:/run/clamav/clamd.ctl)
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
+
+impl ClamdAddress {
+    /// Parse a clamd address, addresses prefixed with "unix:" are unix
+    /// domain socket paths, anything else is a TCP address
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        if let Some(path) = value.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return Ok(ClamdAddress::Unix(path.into()));
+
+            #[cfg(not(unix))]
+            anyhow::bail!("unix sockets are not supported on this platform ({path})");
+        }
+
+        if value.is_empty() {
+            anyhow::bail!("clamd address is empty");
+        }
+
+        Ok(ClamdAddress::Tcp(value.to_string()))
+    }
+}
+
+/// Scans uploaded files for viruses using clamd before they are converted
+#[derive(Debug)]
+pub struct VirusScanner {
+    address: ClamdAddress,
+}
+
+/// Outcome of a successful scan
+enum ScanResult {
+    Clean,
+    /// Name of the detected signature
+    Infected(String),
+}
+
+impl VirusScanner {
+    pub fn new(address: ClamdAddress) -> Self {
+        Self { address }
+    }
+
+    /// Scan the file, infected files are rejected. Files are rejected as
+    /// well when the scan fails so that unscanned files are never converted
+    ///
+    /// ## Arguments
+    /// * `path` - Path to the file to scan
+    pub async fn check(&self, path: &Path) -> Result<(), ErrorResponse> {
+        let result = tokio::time::timeout(SCAN_TIMEOUT, self.scan(path))
+            .await
+            .context("virus scan timed out")
+            .and_then(|result| result);
+
+        match result {
+            Ok(ScanResult::Clean) => Ok(()),
+            Ok(ScanResult::Infected(signature)) => {
+                tracing::warn!(signature, "rejected infected file");
+
+                Err(ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::Infected,
+                    message: format!("file is infected ({signature})"),
+                    backtrace: None,
+                })
+            }
+            Err(err) => {
+                tracing::error!(?err, "failed to scan file for viruses");
+
+                Err(ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::Unavailable,
+                    message: "failed to scan file for viruses".to_string(),
+                    backtrace: None,
+                })
+            }
+        }
+    }
+
+    async fn scan(&self, path: &Path) -> anyhow::Result<ScanResult> {
+        let file = tokio::fs::File::open(path)
+            .await
+            .context("failed to open file")?;
+
+        match &self.address {
+            ClamdAddress::Tcp(address) => {
+                let stream = TcpStream::connect(address)
+                    .await
+                    .with_context(|| format!("failed to connect to clamd at {address}"))?;
+                instream(stream, file).await
+            }
+            #[cfg(unix)]
+            ClamdAddress::Unix(path) => {
+                let stream = tokio::net::UnixStream::connect(path)
+                    .await
+                    .with_context(|| format!("failed to connect to clamd at {}", path.display()))?;
+                instream(stream, file).await
+            }
+        }
+    }
+}
+
+/// Stream the file to clamd using the INSTREAM command and parse the reply
+async fn instream<S, R>(mut stream: S, mut file: R) -> anyhow::Result<ScanResult>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    R: AsyncRead + Unpin,
+{
+    stream.write_all(b"zINSTREAM\0").await?;
+
+    // Each chunk is prefixed with its length, a zero length chunk ends the stream
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let length = file
+            .read(&mut buffer)
+            .await
+            .context("failed to read file")?;
+        stream.write_all(&(length as u32).to_be_bytes()).await?;
+
+        if length == 0 {
+            break;
+        }
+
+        stream.write_all(&buffer[..length]).await?;
+    }
+
+    stream.flush().await?;
+
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await?;
+
+    parse_reply(&reply)
+}
+
+/// Parse an INSTREAM reply (i.e "stream: OK" or "stream: Eicar-Signature FOUND")
+fn parse_reply(reply: &[u8]) -> anyhow::Result<ScanResult> {
+    let reply = String::from_utf8_lossy(reply);
+    let reply = reply.trim_end_matches(['\0', '\n']);
+    let result = reply.strip_prefix("stream: ").unwrap_or(reply);
+
+    if result == "OK" {
+        return Ok(ScanResult::Clean);
+    }
+
+    if let Some(signature) = result.strip_suffix(" FOUND") {
+        return Ok(ScanResult::Infected(signature.to_string()));
+    }
+
+    anyhow::bail!("unexpected clamd reply: {reply}")
+}
@@ -0,0 +1,391 @@
+use axum::{
+    Extension,
+    body::Body,
+    extract::{Multipart, RawQuery},
+    http::{HeaderValue, Response, header},
+};
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{Read, Write},
+    path::Path,
+    sync::Arc,
+    time::Instant,
+};
+use zip::{ZipArchive, write::SimpleFileOptions};
+
+use crate::{
+    ErrorKind, ErrorResponse, RuntimeConfig,
+    auth::AdminAccess,
+    batch::{add_archive_file, archive_error, create_archive},
+    convert::{OutputFile, convert_file, create_convert_temp_paths, create_temp_dir},
+    deadline::{RequestDeadline, deadline_exceeded},
+    error_backtrace,
+    limiter::QueueTicket,
+    upload::read_convert_upload,
+};
+
+/// Name of the manifest within the output archive listing the outcome of
+/// every converted entry
+const MANIFEST_NAME: &str = "manifest.json";
+
+/// Maximum number of entries an uploaded archive can contain
+const MAX_ARCHIVE_ENTRIES: usize = 1000;
+
+/// Entry of the uploaded archive that will be converted
+struct ArchiveEntry {
+    /// Index of the entry within the archive
+    index: usize,
+    /// Path of the entry within the archive, using "/" separators
+    name: String,
+    /// Uncompressed size declared by the archive
+    size: u64,
+}
+
+/// Outcome of converting an entry of the uploaded archive
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ArchiveEntryStatus {
+    Converted,
+    Failed,
+}
+
+/// Entry in the manifest describing the outcome of converting an entry
+#[derive(Serialize)]
+struct ManifestEntry {
+    /// Path of the entry within the uploaded archive
+    file_name: String,
+    status: ArchiveEntryStatus,
+    /// Path of the converted file within the output archive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output_name: Option<String>,
+    /// Reason the conversion failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ErrorResponse>,
+}
+
+/// POST /convert/archive
+///
+/// Converts every document within an uploaded ZIP archive, responding with a
+/// ZIP archive of the converted files mirroring the directory structure of
+/// the upload. The outcome of every entry is listed in a manifest within the
+/// archive
+pub async fn convert_archive(
+    Extension(runtime_config): Extension<Arc<RuntimeConfig>>,
+    admin_access: AdminAccess,
+    RequestDeadline(deadline): RequestDeadline,
+    queue_ticket: QueueTicket,
+    RawQuery(query): RawQuery,
+    multipart: Multipart,
+) -> Result<Response<Body>, ErrorResponse> {
+    let temp_paths = create_convert_temp_paths(&runtime_config).await?;
+    let upload = read_convert_upload(query, multipart, &temp_paths).await?;
+
+    queue_ticket.set_input_size(upload.size);
+
+    let mut options = upload.fields.into_options(runtime_config.default_pdfa)?;
+    admin_access.authorize(&options)?;
+    options.deadline = deadline;
+
+    let max_uncompressed_size = runtime_config.input_limits.max_uncompressed_size;
+    let input_path = temp_paths.input_path.clone();
+    let (mut input, entries) = tokio::task::spawn_blocking(move || {
+        read_archive_entries(&input_path, max_uncompressed_size)
+    })
+    .await
+    .map_err(|err| archive_error(std::io::Error::other(err)))??;
+
+    // Archive is deleted when dropped, even if the conversion fails part way
+    let archive_dir = Arc::new(create_temp_dir(&runtime_config).await?);
+    let archive_file = OutputFile::temporary(archive_dir.path().join("archive.zip"), archive_dir);
+    let mut archive = create_archive(archive_file.path()).map_err(archive_error)?;
+
+    // Wait for a free conversion slot, the entries are converted one at a time
+    let _permit = queue_ticket
+        .acquire_before(options.priority, options.deadline)
+        .await?;
+
+    let extension = options.output_format.extension();
+    let mut output_names: HashSet<String> = HashSet::from([MANIFEST_NAME.to_string()]);
+    let mut manifest: Vec<ManifestEntry> = Vec::with_capacity(entries.len());
+
+    for entry in entries {
+        // Deadline applies to the whole archive, the slot isn't held past it
+        if options
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(deadline_exceeded(
+                "archive didn't finish before the request deadline",
+            ));
+        }
+
+        tracing::debug!(
+            file_name = entry.name,
+            size = entry.size,
+            "converting archive entry"
+        );
+
+        let mut entry_paths = create_convert_temp_paths(&runtime_config).await?;
+
+        let input_path = entry_paths.input_path.clone();
+        let (index, size) = (entry.index, entry.size);
+        let extracted;
+        (input, extracted) = tokio::task::spawn_blocking(move || {
+            let result = extract_entry(&mut input, index, size, &input_path);
+            (input, result)
+        })
+        .await
+        .map_err(|err| archive_error(std::io::Error::other(err)))?;
+
+        let result = match extracted {
+            Ok(()) => {
+                entry_paths
+                    .resolve_input_extension(
+                        &runtime_config,
+                        options.input_format.as_deref(),
+                        Some(&entry.name),
+                    )
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+
+        let result = match result {
+            Ok(()) => convert_file(&runtime_config, &entry_paths, &options).await,
+            Err(err) => Err(err),
+        };
+
+        let output_file = match result {
+            Ok(output_file) => output_file,
+            Err(err) => {
+                manifest.push(ManifestEntry {
+                    file_name: entry.name,
+                    status: ArchiveEntryStatus::Failed,
+                    output_name: None,
+                    error: Some(err),
+                });
+                continue;
+            }
+        };
+
+        let output_name = unique_output_name(&mut output_names, &entry.name, extension);
+
+        let entry_name = output_name.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            add_archive_file(&mut archive, &entry_name, output_file.path())?;
+            Ok(archive)
+        })
+        .await
+        .map_err(std::io::Error::other)
+        .and_then(|result| result);
+
+        archive = result.map_err(archive_error)?;
+
+        manifest.push(ManifestEntry {
+            file_name: entry.name,
+            status: ArchiveEntryStatus::Converted,
+            output_name: Some(output_name),
+            error: None,
+        });
+    }
+
+    tokio::task::spawn_blocking(move || {
+        let manifest = serde_json::to_vec_pretty(&manifest)?;
+        archive.start_file(MANIFEST_NAME, SimpleFileOptions::default())?;
+        archive.write_all(&manifest)?;
+        archive.finish()?;
+        Ok(())
+    })
+    .await
+    .map_err(std::io::Error::other)
+    .and_then(|result: std::io::Result<()>| result)
+    .map_err(archive_error)?;
+
+    let body = archive_file.into_body().await.map_err(|err| {
+        tracing::error!(?err, "failed to open output file");
+        ErrorResponse {
+            code: None,
+            kind: ErrorKind::Internal,
+            message: "failed to read output".to_string(),
+            backtrace: error_backtrace(&err),
+        }
+    })?;
+
+    Response::builder()
+        .header(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/zip"),
+        )
+        .body(body)
+        .map_err(|err| {
+            tracing::error!(?err, "failed to make response");
+            ErrorResponse {
+                code: None,
+                kind: ErrorKind::Internal,
+                message: "failed to make response".to_string(),
+                backtrace: error_backtrace(&err),
+            }
+        })
+}
+
+/// Open the uploaded archive and list the entries to convert. Directories,
+/// hidden files and entries with unsafe paths are skipped. Archives with
+/// too many entries or exceeding the uncompressed size limit are rejected
+fn read_archive_entries(
+    path: &Path,
+    max_uncompressed_size: Option<u64>,
+) -> Result<(ZipArchive<File>, Vec<ArchiveEntry>), ErrorResponse> {
+    let file = File::open(path).map_err(archive_error)?;
+    let mut archive = ZipArchive::new(file).map_err(|err| {
+        tracing::debug!(?err, "uploaded archive is not a valid zip");
+        ErrorResponse {
+            code: None,
+            kind: ErrorKind::UnsupportedFormat,
+            message: "upload is not a valid zip archive".to_string(),
+            backtrace: None,
+        }
+    })?;
+
+    if archive.len() > MAX_ARCHIVE_ENTRIES {
+        return Err(ErrorResponse {
+            code: None,
+            kind: ErrorKind::TooLarge,
+            message: format!("archive contains more than {MAX_ARCHIVE_ENTRIES} entries"),
+            backtrace: None,
+        });
+    }
+
+    let mut entries = Vec::new();
+    let mut uncompressed_size: u64 = 0;
+
+    for index in 0..archive.len() {
+        let entry = archive.by_index_raw(index).map_err(|err| {
+            tracing::debug!(?err, "failed to read archive entry");
+            ErrorResponse {
+                code: None,
+                kind: ErrorKind::Corrupted,
+                message: "archive is corrupted".to_string(),
+                backtrace: None,
+            }
+        })?;
+
+        if entry.is_dir() || entry.enclosed_name().is_none() {
+            continue;
+        }
+
+        let name = entry.name().replace('\\', "/");
+
+        // Skip metadata added by archiving tools (i.e "__MACOSX/" and ".DS_Store")
+        if name
+            .split('/')
+            .any(|component| component.starts_with('.') || component == "__MACOSX")
+        {
+            continue;
+        }
+
+        uncompressed_size = uncompressed_size.saturating_add(entry.size());
+        entries.push(ArchiveEntry {
+            index,
+            name,
+            size: entry.size(),
+        });
+    }
+
+    if let Some(max_uncompressed_size) = max_uncompressed_size
+        && uncompressed_size > max_uncompressed_size
+    {
+        return Err(ErrorResponse {
+            code: None,
+            kind: ErrorKind::ResourceLimit,
+            message: format!(
+                "archive exceeds the uncompressed size limit of {} MB",
+                max_uncompressed_size / (1024 * 1024)
+            ),
+            backtrace: None,
+        });
+    }
+
+    if entries.is_empty() {
+        return Err(ErrorResponse {
+            code: None,
+            kind: ErrorKind::InvalidRequest,
+            message: "archive doesn't contain any files to convert".to_string(),
+            backtrace: None,
+        });
+    }
+
+    Ok((archive, entries))
+}
+
+/// Extract an entry of the archive to the provided path, entries
+/// decompressing to more than their declared size are rejected
+fn extract_entry(
+    archive: &mut ZipArchive<File>,
+    index: usize,
+    size: u64,
+    path: &Path,
+) -> Result<(), ErrorResponse> {
+    let entry = archive.by_index(index).map_err(|err| {
+        tracing::debug!(?err, "failed to read archive entry");
+        ErrorResponse {
+            code: None,
+            kind: ErrorKind::Corrupted,
+            message: "archive is corrupted".to_string(),
+            backtrace: None,
+        }
+    })?;
+
+    let mut file = File::create(path).map_err(archive_error)?;
+    let written =
+        std::io::copy(&mut entry.take(size.saturating_add(1)), &mut file).map_err(|err| {
+            tracing::debug!(?err, "failed to extract archive entry");
+            ErrorResponse {
+                code: None,
+                kind: ErrorKind::Corrupted,
+                message: "archive is corrupted".to_string(),
+                backtrace: None,
+            }
+        })?;
+
+    if written > size {
+        return Err(ErrorResponse {
+            code: None,
+            kind: ErrorKind::ResourceLimit,
+            message: "archive entry is larger than its declared size".to_string(),
+            backtrace: None,
+        });
+    }
+
+    Ok(())
+}
+
+/// Create a unique path for a converted file within the output archive,
+/// keeping the directories of the entry and replacing its extension.
+/// Numbered suffixes are added to paths that have already been used
+fn unique_output_name(used: &mut HashSet<String>, entry_name: &str, extension: &str) -> String {
+    let (dir, file_name) = match entry_name.rsplit_once('/') {
+        Some((dir, file_name)) => (Some(dir), file_name),
+        None => (None, entry_name),
+    };
+
+    let stem = Path::new(file_name)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .filter(|stem| !stem.is_empty())
+        .unwrap_or("file");
+
+    let prefix = dir.map(|dir| format!("{dir}/")).unwrap_or_default();
+
+    let mut name = format!("{prefix}{stem}.{extension}");
+    let mut index = 1;
+
+    while used.contains(&name) {
+        index += 1;
+        name = format!("{prefix}{stem}_{index}.{extension}");
+    }
+
+    used.insert(name.clone());
+    name
+}
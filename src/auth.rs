@@ -0,0 +1,213 @@
+use axum::{
+    Json, async_trait,
+    extract::{FromRequestParts, Request, State},
+    http::{HeaderMap, HeaderName, StatusCode, header, request::Parts},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode};
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+};
+
+use crate::{ErrorKind, ErrorResponse, convert::ConvertOptions};
+
+/// Default header the token is read from, matches the ONLYOFFICE DocumentServer default
+pub const DEFAULT_JWT_HEADER: &str = "Authorization";
+
+/// Header the admin token is read from on non-admin endpoints, the
+/// Authorization header may already be in use for the JWT
+const ADMIN_TOKEN_HEADER: &str = "x-admin-token";
+
+/// JWT authentication using a shared secret, compatible with the tokens
+/// issued for ONLYOFFICE DocumentServer (HS256 signed using `JWT_SECRET`)
+pub struct JwtAuth {
+    /// Key derived from the secret, replaced when the secret is reloaded
+    decoding_key: RwLock<DecodingKey>,
+    validation: Validation,
+    /// Header the token is read from
+    header: HeaderName,
+}
+
+impl JwtAuth {
+    pub fn new(secret: &str, header: HeaderName) -> Self {
+        let mut validation = Validation::new(Algorithm::HS256);
+
+        // DocumentServer tokens are not required to have an expiry, it is
+        // still validated when present
+        validation.required_spec_claims = HashSet::new();
+
+        Self {
+            decoding_key: RwLock::new(DecodingKey::from_secret(secret.as_bytes())),
+            validation,
+            header,
+        }
+    }
+
+    /// Header the token is read from
+    pub fn header(&self) -> &HeaderName {
+        &self.header
+    }
+
+    /// Replace the shared secret tokens are validated with
+    pub fn set_secret(&self, secret: &str) {
+        *self.decoding_key.write().expect("jwt key lock poisoned") =
+            DecodingKey::from_secret(secret.as_bytes());
+    }
+
+    /// Check if the provided token is valid
+    pub fn is_valid(&self, token: &str) -> bool {
+        self.claims(token).is_some()
+    }
+
+    /// Claims of the provided token, None if the token is invalid
+    pub fn claims(&self, token: &str) -> Option<serde_json::Value> {
+        let decoding_key = self.decoding_key.read().expect("jwt key lock poisoned");
+
+        decode::<serde_json::Value>(token, &decoding_key, &self.validation)
+            .inspect_err(|err| tracing::debug!(?err, "rejected invalid token"))
+            .ok()
+            .map(|data| data.claims)
+    }
+
+    /// Token provided in the header of the request
+    pub fn header_token<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+        headers
+            .get(&self.header)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.strip_prefix("Bearer ").unwrap_or(value).trim())
+    }
+}
+
+/// Middleware rejecting requests that don't provide a valid token
+pub async fn require_jwt(
+    State(auth): State<Arc<JwtAuth>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match auth.header_token(request.headers()) {
+        Some(token) if auth.is_valid(token) => next.run(request).await,
+        _ => (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                code: None,
+                kind: ErrorKind::Unauthorized,
+                message: "missing or invalid token".to_string(),
+                backtrace: None,
+            }),
+        )
+            .into_response(),
+    }
+}
+
+/// Bearer token authentication for the admin endpoints
+pub struct AdminAuth {
+    /// Expected token, replaced when the token is reloaded
+    token: RwLock<String>,
+}
+
+impl AdminAuth {
+    pub fn new(token: String) -> Self {
+        Self {
+            token: RwLock::new(token),
+        }
+    }
+
+    /// Replace the expected admin token
+    pub fn set_token(&self, token: String) {
+        *self.token.write().expect("admin token lock poisoned") = token;
+    }
+
+    /// Check if the provided token matches, compares the full token
+    /// regardless of where the first difference is
+    fn is_valid(&self, token: &str) -> bool {
+        let expected = self.token.read().expect("admin token lock poisoned");
+        let expected = expected.as_bytes();
+        let token = token.as_bytes();
+
+        expected.len() == token.len()
+            && expected
+                .iter()
+                .zip(token)
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+/// Middleware rejecting requests that don't provide the admin token
+pub async fn require_admin(
+    State(auth): State<Arc<AdminAuth>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim);
+
+    match token {
+        Some(token) if auth.is_valid(token) => next.run(request).await,
+        _ => (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                code: None,
+                kind: ErrorKind::Unauthorized,
+                message: "missing or invalid admin token".to_string(),
+                backtrace: None,
+            }),
+        )
+            .into_response(),
+    }
+}
+
+/// Whether the request provided the admin token in the [ADMIN_TOKEN_HEADER]
+/// header, used to gate admin only features on the regular endpoints
+pub struct AdminAccess(bool);
+
+impl AdminAccess {
+    /// Whether the admin token was provided
+    pub fn is_admin(&self) -> bool {
+        self.0
+    }
+
+    /// Ensure the admin token was provided if the conversion requested
+    /// debug diagnostics
+    pub fn authorize(&self, options: &ConvertOptions) -> Result<(), ErrorResponse> {
+        if options.debug && !self.0 {
+            return Err(ErrorResponse {
+                code: None,
+                kind: ErrorKind::Unauthorized,
+                message: "debug mode requires the admin token".to_string(),
+                backtrace: None,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AdminAccess
+where
+    S: Send + Sync,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        // Admin auth is only available when an admin token is configured
+        let Some(Some(auth)) = parts.extensions.get::<Option<Arc<AdminAuth>>>() else {
+            return Ok(AdminAccess(false));
+        };
+
+        let is_admin = parts
+            .headers
+            .get(ADMIN_TOKEN_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|token| auth.is_valid(token.trim()));
+
+        Ok(AdminAccess(is_admin))
+    }
+}
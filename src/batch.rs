@@ -0,0 +1,224 @@
+use axum::{
+    Extension,
+    body::Body,
+    extract::{Multipart, RawQuery},
+    http::{HeaderValue, Response, header},
+};
+use serde::Serialize;
+use std::{collections::HashSet, io::Write, path::Path, sync::Arc, time::Instant};
+use zip::{ZipWriter, write::SimpleFileOptions};
+
+use crate::{
+    ErrorKind, ErrorResponse, RuntimeConfig,
+    auth::AdminAccess,
+    convert::{OutputFile, convert_file, create_temp_dir},
+    deadline::{RequestDeadline, deadline_exceeded},
+    disposition::sanitize_file_name,
+    error_backtrace,
+    limiter::QueueTicket,
+    upload::read_batch_upload,
+};
+
+/// Name of the manifest within the archive listing the files that failed to convert
+const ERROR_MANIFEST_NAME: &str = "errors.json";
+
+/// Entry in the error manifest for a file that failed to convert
+#[derive(Serialize)]
+struct BatchError {
+    /// Original name of the uploaded file
+    file_name: String,
+    /// Error code from x2t if available
+    code: Option<i32>,
+    /// Category of the error
+    kind: ErrorKind,
+    /// Reason the conversion failed
+    message: String,
+    /// x2t diagnostics when debug mode was requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    backtrace: Option<String>,
+}
+
+/// POST /convert/batch
+///
+/// Converts multiple files, responding with a ZIP archive of the converted files.
+/// Files that fail to convert are listed in an error manifest within the archive
+pub async fn convert_batch(
+    Extension(runtime_config): Extension<Arc<RuntimeConfig>>,
+    admin_access: AdminAccess,
+    RequestDeadline(deadline): RequestDeadline,
+    queue_ticket: QueueTicket,
+    RawQuery(query): RawQuery,
+    multipart: Multipart,
+) -> Result<Response<Body>, ErrorResponse> {
+    let upload = read_batch_upload(&runtime_config, query, multipart).await?;
+
+    queue_ticket.set_input_size(upload.files.iter().map(|file| file.size).sum());
+
+    let mut options = upload.fields.into_options(runtime_config.default_pdfa)?;
+    admin_access.authorize(&options)?;
+    options.deadline = deadline;
+
+    // Archive is deleted when dropped, even if the batch fails part way
+    let archive_dir = Arc::new(create_temp_dir(&runtime_config).await?);
+    let archive_file = OutputFile::temporary(archive_dir.path().join("batch.zip"), archive_dir);
+    let mut archive = create_archive(archive_file.path()).map_err(archive_error)?;
+
+    // Wait for a free conversion slot, the files are converted one at a time
+    let _permit = queue_ticket
+        .acquire_before(options.priority, options.deadline)
+        .await?;
+
+    let extension = options.output_format.extension();
+    let mut entry_names: HashSet<String> = HashSet::new();
+    let mut errors: Vec<BatchError> = Vec::new();
+
+    for mut file in upload.files {
+        // Deadline applies to the whole batch, the slot isn't held past it
+        if options
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(deadline_exceeded(
+                "batch didn't finish before the request deadline",
+            ));
+        }
+
+        tracing::debug!(
+            file_name = file.file_name,
+            size = file.size,
+            "converting batch file"
+        );
+
+        let result = match file
+            .temp_paths
+            .resolve_input_extension(
+                &runtime_config,
+                options.input_format.as_deref(),
+                Some(&file.file_name),
+            )
+            .await
+        {
+            Ok(()) => convert_file(&runtime_config, &file.temp_paths, &options).await,
+            Err(err) => Err(err),
+        };
+
+        let output_file = match result {
+            Ok(output_file) => output_file,
+            Err(err) => {
+                errors.push(BatchError {
+                    file_name: file.file_name,
+                    code: err.code,
+                    kind: err.kind,
+                    message: err.message,
+                    backtrace: err.backtrace,
+                });
+                continue;
+            }
+        };
+
+        let entry_name = unique_entry_name(&mut entry_names, &file.file_name, extension);
+
+        let result = tokio::task::spawn_blocking(move || {
+            add_archive_file(&mut archive, &entry_name, output_file.path())?;
+            Ok(archive)
+        })
+        .await
+        .map_err(std::io::Error::other)
+        .and_then(|result| result);
+
+        archive = result.map_err(archive_error)?;
+    }
+
+    tokio::task::spawn_blocking(move || {
+        if !errors.is_empty() {
+            let manifest = serde_json::to_vec_pretty(&errors)?;
+            archive.start_file(ERROR_MANIFEST_NAME, SimpleFileOptions::default())?;
+            archive.write_all(&manifest)?;
+        }
+
+        archive.finish()?;
+        Ok(())
+    })
+    .await
+    .map_err(std::io::Error::other)
+    .and_then(|result: std::io::Result<()>| result)
+    .map_err(archive_error)?;
+
+    let body = archive_file.into_body().await.map_err(|err| {
+        tracing::error!(?err, "failed to open output file");
+        ErrorResponse {
+            code: None,
+            kind: ErrorKind::Internal,
+            message: "failed to read output".to_string(),
+            backtrace: error_backtrace(&err),
+        }
+    })?;
+
+    Response::builder()
+        .header(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/zip"),
+        )
+        .body(body)
+        .map_err(|err| {
+            tracing::error!(?err, "failed to make response");
+            ErrorResponse {
+                code: None,
+                kind: ErrorKind::Internal,
+                message: "failed to make response".to_string(),
+                backtrace: error_backtrace(&err),
+            }
+        })
+}
+
+/// Create a new ZIP archive at the provided path
+pub fn create_archive(path: &Path) -> std::io::Result<ZipWriter<std::fs::File>> {
+    let file = std::fs::File::create(path)?;
+    Ok(ZipWriter::new(file))
+}
+
+/// Copy the file at `path` into the archive under the provided entry name
+pub fn add_archive_file(
+    archive: &mut ZipWriter<std::fs::File>,
+    entry_name: &str,
+    path: &Path,
+) -> std::io::Result<()> {
+    let mut file = std::fs::File::open(path)?;
+    archive.start_file(entry_name, SimpleFileOptions::default())?;
+    std::io::copy(&mut file, archive)?;
+    Ok(())
+}
+
+/// Create a unique name for an archive entry from the original file name,
+/// numbered suffixes are added to names that have already been used
+fn unique_entry_name(used: &mut HashSet<String>, file_name: &str, extension: &str) -> String {
+    // Only the final component of the name is used, clients may send full
+    // paths using either Windows or Unix separators
+    let file_name = sanitize_file_name(file_name).unwrap_or_default();
+    let stem = Path::new(&file_name)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .filter(|stem| !stem.is_empty())
+        .unwrap_or("file");
+
+    let mut name = format!("{stem}.{extension}");
+    let mut index = 1;
+
+    while used.contains(&name) {
+        index += 1;
+        name = format!("{stem}_{index}.{extension}");
+    }
+
+    used.insert(name.clone());
+    name
+}
+
+pub fn archive_error(err: std::io::Error) -> ErrorResponse {
+    tracing::error!(?err, "failed to write output archive");
+    ErrorResponse {
+        code: None,
+        kind: ErrorKind::Internal,
+        message: "failed to write output archive".to_string(),
+        backtrace: error_backtrace(&err),
+    }
+}
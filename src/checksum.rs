@@ -0,0 +1,88 @@
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+
+use crate::{ErrorKind, ErrorResponse, error_backtrace};
+
+/// Header containing the hex encoded SHA-256 of the converted output
+pub const OUTPUT_CHECKSUM_HEADER: &str = "x-output-sha256";
+
+/// Feed the contents of a file into the hasher
+pub async fn hash_file(hasher: &mut Sha256, path: &Path) -> std::io::Result<()> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        let count = file.read(&mut buffer).await?;
+        if count == 0 {
+            break;
+        }
+
+        hasher.update(&buffer[..count]);
+    }
+
+    Ok(())
+}
+
+/// Hex encoded SHA-256 of the contents of a file
+pub async fn file_checksum(path: &Path) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    hash_file(&mut hasher, path).await?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Checksum of a converted output file for the [OUTPUT_CHECKSUM_HEADER]
+pub async fn output_checksum(path: &Path) -> Result<String, ErrorResponse> {
+    file_checksum(path).await.map_err(|err| {
+        tracing::error!(?err, "failed to hash output file");
+        ErrorResponse {
+            code: None,
+            kind: ErrorKind::Internal,
+            message: "failed to read output".to_string(),
+            backtrace: error_backtrace(&err),
+        }
+    })
+}
+
+/// Ensure the uploaded input file matches the checksum the client expected,
+/// catches uploads that were truncated or corrupted on the way
+///
+/// ## Arguments
+/// * `path` - Path to the uploaded input file
+/// * `expected` - Hex encoded SHA-256 the input is expected to have
+pub async fn verify_input_checksum(path: &Path, expected: &str) -> Result<(), ErrorResponse> {
+    let expected = expected.trim().to_ascii_lowercase();
+
+    if expected.len() != 64 || !expected.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(ErrorResponse {
+            code: None,
+            kind: ErrorKind::InvalidRequest,
+            message: "input_checksum must be a hex encoded SHA-256".to_string(),
+            backtrace: None,
+        });
+    }
+
+    let actual = file_checksum(path).await.map_err(|err| {
+        tracing::error!(?err, "failed to hash input file");
+        ErrorResponse {
+            code: None,
+            kind: ErrorKind::Internal,
+            message: "failed to read uploaded file".to_string(),
+            backtrace: error_backtrace(&err),
+        }
+    })?;
+
+    if actual != expected {
+        tracing::debug!(%expected, %actual, "uploaded file checksum mismatch");
+
+        return Err(ErrorResponse {
+            code: None,
+            kind: ErrorKind::InvalidRequest,
+            message: "uploaded file doesn't match input_checksum, it may have been truncated"
+                .to_string(),
+            backtrace: None,
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use anyhow::Context;
+use std::path::Path;
+
+use crate::{
+    ErrorResponse, RuntimeConfig,
+    convert::{convert_file, create_convert_temp_paths},
+    upload::ConvertFields,
+};
+
+/// Convert a local file using the same pipeline as the HTTP API without
+/// starting the server
+///
+/// ## Arguments
+/// * `runtime_config` - Runtime configuration
+/// * `input` - Path to the file to convert
+/// * `output` - Path to write the converted file to
+/// * `fields` - Conversion options, the output format defaults to the
+///   extension of the output path
+pub async fn convert_local(
+    runtime_config: &RuntimeConfig,
+    input: &Path,
+    output: &Path,
+    mut fields: ConvertFields,
+) -> anyhow::Result<()> {
+    if fields.target_format.is_none() {
+        fields.target_format = output
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_string);
+    }
+
+    let options = fields
+        .into_options(runtime_config.default_pdfa)
+        .map_err(cli_error)?;
+
+    let mut temp_paths = create_convert_temp_paths(runtime_config)
+        .await
+        .map_err(cli_error)?;
+
+    tokio::fs::copy(input, &temp_paths.input_path)
+        .await
+        .with_context(|| format!("failed to read {}", input.display()))?;
+
+    let file_name = input.file_name().and_then(|file_name| file_name.to_str());
+    temp_paths
+        .resolve_input_extension(runtime_config, options.input_format.as_deref(), file_name)
+        .await
+        .map_err(cli_error)?;
+
+    let output_file = convert_file(runtime_config, &temp_paths, &options)
+        .await
+        .map_err(cli_error)?;
+
+    // The output is copied as the temporary directory may be on another filesystem
+    tokio::fs::copy(output_file.path(), output)
+        .await
+        .with_context(|| format!("failed to write {}", output.display()))?;
+
+    Ok(())
+}
+
+/// Convert an error response into an error for the command line
+fn cli_error(err: ErrorResponse) -> anyhow::Error {
+    match err.code {
+        Some(code) => anyhow::anyhow!("{} (x2t error code {code:#06x})", err.message),
+        None => anyhow::anyhow!(err.message),
+    }
+}
@@ -0,0 +1,452 @@
+use anyhow::Context;
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use ipnet::IpNet;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use crate::{ErrorKind, ErrorResponse};
+
+/// Header set by most proxies with the chain of client addresses
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Proxies whose forwarding headers are trusted to contain the address of
+/// the client that made the request
+#[derive(Debug, Default)]
+pub struct TrustedProxies {
+    networks: Vec<IpNet>,
+}
+
+impl TrustedProxies {
+    /// Parse a comma separated list of CIDRs (i.e "10.0.0.0/8"), plain
+    /// addresses are treated as a single address network
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            networks: parse_networks(value)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+
+    fn is_trusted(&self, address: IpAddr) -> bool {
+        contains_address(&self.networks, address)
+    }
+
+    /// Resolve the address of the client from the address of the peer that
+    /// connected and the forwarding headers. Forwarded addresses are walked
+    /// from the nearest proxy outwards, the first address that isn't a
+    /// trusted proxy is the client
+    fn resolve(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+
+        let forwarded = forwarded_addresses(headers);
+        let mut client = peer;
+
+        for address in forwarded.into_iter().rev() {
+            client = address;
+            if !self.is_trusted(address) {
+                break;
+            }
+        }
+
+        client
+    }
+}
+
+/// Networks clients are allowed or denied from making requests from, denied
+/// networks take precedence. When the allow list is empty only the denied
+/// networks are rejected
+#[derive(Debug, Default)]
+pub struct IpAccessList {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl IpAccessList {
+    /// Create the access list from comma separated lists of CIDRs
+    pub fn parse(allow: Option<&str>, deny: Option<&str>) -> anyhow::Result<Self> {
+        Ok(Self {
+            allow: allow.map(parse_networks).transpose()?.unwrap_or_default(),
+            deny: deny.map(parse_networks).transpose()?.unwrap_or_default(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Whether a client with the provided address can make requests
+    pub fn is_allowed(&self, address: IpAddr) -> bool {
+        if contains_address(&self.deny, address) {
+            return false;
+        }
+
+        self.allow.is_empty() || contains_address(&self.allow, address)
+    }
+}
+
+/// Parse a comma separated list of CIDRs, plain addresses are treated as a
+/// single address network
+pub fn parse_networks(value: &str) -> anyhow::Result<Vec<IpNet>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            value
+                .parse::<IpNet>()
+                .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+                .with_context(|| format!("invalid network \"{value}\""))
+        })
+        .collect()
+}
+
+/// Whether any of the networks contain the address, IPv4 mapped IPv6
+/// addresses are matched against IPv4 networks
+pub fn contains_address(networks: &[IpNet], address: IpAddr) -> bool {
+    let address = address.to_canonical();
+    networks.iter().any(|network| network.contains(&address))
+}
+
+/// Addresses listed in the Forwarded header, or the X-Forwarded-For header
+/// when there is no Forwarded header, ordered from the original client to
+/// the nearest proxy. Unparsable entries end the list as nothing before them
+/// can be trusted
+fn forwarded_addresses(headers: &HeaderMap) -> Vec<IpAddr> {
+    let forwarded: Vec<&str> = headers
+        .get_all(header::FORWARDED)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+
+    let entries: Vec<Option<IpAddr>> = if !forwarded.is_empty() {
+        forwarded
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, value)| parse_forwarded_node(value))
+            })
+            .collect()
+    } else {
+        headers
+            .get_all(X_FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(parse_forwarded_node)
+            .collect()
+    };
+
+    // Only the addresses after the last invalid entry can be relied on
+    let start = entries
+        .iter()
+        .rposition(Option::is_none)
+        .map(|index| index + 1)
+        .unwrap_or(0);
+
+    entries[start..].iter().flatten().copied().collect()
+}
+
+/// Parse a forwarded node, the value can be quoted and include a port
+/// (i.e "192.0.2.1:4711" or "[2001:db8::1]:4711")
+fn parse_forwarded_node(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+
+    if let Ok(address) = value.parse::<IpAddr>() {
+        return Some(address);
+    }
+
+    if let Ok(address) = value.parse::<SocketAddr>() {
+        return Some(address.ip());
+    }
+
+    // Bracketed IPv6 address without a port
+    value
+        .strip_prefix('[')
+        .and_then(|value| value.strip_suffix(']'))
+        .and_then(|value| value.parse().ok())
+}
+
+/// Address of the client that made the request, None when the connection
+/// has no address (i.e over a unix socket)
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+/// Middleware resolving the address of the client, honoring the forwarding
+/// headers of trusted proxies
+pub async fn resolve_client_ip(
+    State(trusted_proxies): State<Arc<TrustedProxies>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(address)| address.ip());
+
+    let client_ip = peer.map(|peer| trusted_proxies.resolve(peer, request.headers()));
+    request.extensions_mut().insert(ClientIp(client_ip));
+
+    next.run(request).await
+}
+
+/// Middleware rejecting requests from clients outside of the access list,
+/// runs before the request body is read. Connections without an address
+/// (i.e over a unix socket) are always allowed
+pub async fn enforce_ip_access(
+    State(access_list): State<Arc<IpAccessList>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let client_ip = request
+        .extensions()
+        .get::<ClientIp>()
+        .and_then(|ClientIp(client_ip)| *client_ip);
+
+    if let Some(client_ip) = client_ip
+        && !access_list.is_allowed(client_ip)
+    {
+        tracing::warn!(%client_ip, "rejected request from a client outside the access list");
+
+        return ErrorResponse {
+            code: None,
+            kind: ErrorKind::Forbidden,
+            message: "requests from this address are not allowed".to_string(),
+            backtrace: None,
+        }
+        .into_response();
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn proxies(value: &str) -> TrustedProxies {
+        TrustedProxies::parse(value).unwrap()
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn headers(name: &str, values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(
+                header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn test_untrusted_peer_ignores_headers() {
+        let proxies = proxies("10.0.0.0/8");
+        let headers = headers(X_FORWARDED_FOR, &["203.0.113.7"]);
+
+        assert_eq!(
+            proxies.resolve(ip("198.51.100.1"), &headers),
+            ip("198.51.100.1")
+        );
+    }
+
+    #[test]
+    fn test_no_trusted_proxies() {
+        let proxies = TrustedProxies::default();
+        let headers = headers(X_FORWARDED_FOR, &["203.0.113.7"]);
+
+        assert_eq!(proxies.resolve(ip("10.0.0.1"), &headers), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn test_x_forwarded_for_from_trusted_proxy() {
+        let proxies = proxies("10.0.0.0/8");
+        let headers = headers(X_FORWARDED_FOR, &["203.0.113.7"]);
+
+        assert_eq!(proxies.resolve(ip("10.0.0.1"), &headers), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn test_spoofed_leftmost_entry_ignored() {
+        let proxies = proxies("10.0.0.0/8");
+        // Client sent its own X-Forwarded-For which the proxy appended to
+        let headers = headers(X_FORWARDED_FOR, &["1.2.3.4, 203.0.113.7"]);
+
+        assert_eq!(proxies.resolve(ip("10.0.0.1"), &headers), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn test_chain_of_trusted_proxies() {
+        let proxies = proxies("10.0.0.0/8, 192.168.1.1");
+        let headers = headers(
+            X_FORWARDED_FOR,
+            &["1.2.3.4, 203.0.113.7, 192.168.1.1, 10.0.0.2"],
+        );
+
+        assert_eq!(proxies.resolve(ip("10.0.0.1"), &headers), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn test_untrusted_hop_is_client() {
+        let proxies = proxies("10.0.0.0/8");
+        // 198.51.100.9 isn't trusted so anything it claims can't be relied on
+        let headers = headers(X_FORWARDED_FOR, &["203.0.113.7, 198.51.100.9, 10.0.0.2"]);
+
+        assert_eq!(
+            proxies.resolve(ip("10.0.0.1"), &headers),
+            ip("198.51.100.9")
+        );
+    }
+
+    #[test]
+    fn test_multiple_x_forwarded_for_headers() {
+        let proxies = proxies("10.0.0.0/8");
+        let headers = headers(X_FORWARDED_FOR, &["1.2.3.4", "203.0.113.7, 10.0.0.2"]);
+
+        assert_eq!(proxies.resolve(ip("10.0.0.1"), &headers), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn test_forwarded_header() {
+        let proxies = proxies("10.0.0.0/8");
+        let headers = headers(
+            "forwarded",
+            &[r#"for=1.2.3.4, for="[2001:db8::7]:4711";proto=https, for=10.0.0.2"#],
+        );
+
+        assert_eq!(proxies.resolve(ip("10.0.0.1"), &headers), ip("2001:db8::7"));
+    }
+
+    #[test]
+    fn test_forwarded_takes_precedence() {
+        let proxies = proxies("10.0.0.0/8");
+        let mut headers = headers("forwarded", &["for=203.0.113.7"]);
+        headers.insert(X_FORWARDED_FOR, HeaderValue::from_static("1.2.3.4"));
+
+        assert_eq!(proxies.resolve(ip("10.0.0.1"), &headers), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn test_malformed_forwarded_entry_ends_chain() {
+        let proxies = proxies("10.0.0.0/8");
+        // Entries before the malformed one can't be trusted
+        let headers = headers("forwarded", &["for=1.2.3.4, for=unknown, for=203.0.113.7"]);
+
+        assert_eq!(proxies.resolve(ip("10.0.0.1"), &headers), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn test_malformed_nearest_entry_uses_peer() {
+        let proxies = proxies("10.0.0.0/8");
+        let forwarded = headers("forwarded", &["for=203.0.113.7, for=_hidden"]);
+
+        assert_eq!(proxies.resolve(ip("10.0.0.1"), &forwarded), ip("10.0.0.1"));
+
+        let x_forwarded_for = headers(X_FORWARDED_FOR, &["203.0.113.7, not-an-address"]);
+
+        assert_eq!(
+            proxies.resolve(ip("10.0.0.1"), &x_forwarded_for),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn test_forwarded_without_for() {
+        let proxies = proxies("10.0.0.0/8");
+        let headers = headers("forwarded", &["proto=https;by=10.0.0.2"]);
+
+        assert_eq!(proxies.resolve(ip("10.0.0.1"), &headers), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn test_parse_forwarded_node() {
+        assert_eq!(parse_forwarded_node("192.0.2.1"), Some(ip("192.0.2.1")));
+        assert_eq!(
+            parse_forwarded_node(" 192.0.2.1:4711 "),
+            Some(ip("192.0.2.1"))
+        );
+        assert_eq!(
+            parse_forwarded_node(r#""[2001:db8::1]""#),
+            Some(ip("2001:db8::1"))
+        );
+        assert_eq!(
+            parse_forwarded_node("[2001:db8::1]:4711"),
+            Some(ip("2001:db8::1"))
+        );
+        assert_eq!(parse_forwarded_node("unknown"), None);
+        assert_eq!(parse_forwarded_node(""), None);
+    }
+
+    #[test]
+    fn test_ipv4_mapped_peer_trusted() {
+        let proxies = proxies("10.0.0.0/8");
+        let headers = headers(X_FORWARDED_FOR, &["203.0.113.7"]);
+
+        assert_eq!(
+            proxies.resolve(ip("::ffff:10.0.0.1"), &headers),
+            ip("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_network() {
+        assert!(TrustedProxies::parse("10.0.0.0/8, nonsense").is_err());
+        assert!(IpAccessList::parse(Some("10.0.0.0/33"), None).is_err());
+    }
+
+    #[test]
+    fn test_access_list_empty_allows_all() {
+        let access_list = IpAccessList::parse(None, None).unwrap();
+
+        assert!(access_list.is_empty());
+        assert!(access_list.is_allowed(ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn test_access_list_allow() {
+        let access_list = IpAccessList::parse(Some("10.0.0.0/8"), None).unwrap();
+
+        assert!(access_list.is_allowed(ip("10.1.2.3")));
+        assert!(access_list.is_allowed(ip("::ffff:10.1.2.3")));
+        assert!(!access_list.is_allowed(ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn test_access_list_deny_only() {
+        let access_list = IpAccessList::parse(None, Some("203.0.113.0/24")).unwrap();
+
+        assert!(!access_list.is_allowed(ip("203.0.113.7")));
+        assert!(access_list.is_allowed(ip("198.51.100.1")));
+    }
+
+    #[test]
+    fn test_access_list_deny_takes_precedence() {
+        let access_list =
+            IpAccessList::parse(Some("10.0.0.0/8"), Some("10.0.5.0/24, 10.0.6.1")).unwrap();
+
+        assert!(access_list.is_allowed(ip("10.0.4.1")));
+        assert!(!access_list.is_allowed(ip("10.0.5.1")));
+        assert!(!access_list.is_allowed(ip("10.0.6.1")));
+        assert!(!access_list.is_allowed(ip("::ffff:10.0.5.1")));
+        assert!(access_list.is_allowed(ip("10.0.6.2")));
+    }
+}
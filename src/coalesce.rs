@@ -0,0 +1,141 @@
+use futures_util::{
+    FutureExt,
+    future::{BoxFuture, Shared},
+};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    ErrorKind, ErrorResponse,
+    checksum::hash_file,
+    convert::{ConvertOptions, OutputFile},
+    error_backtrace,
+    forms::FormData,
+};
+
+/// Result of a conversion shared between all the requests waiting on it
+pub type SharedConversion = Shared<BoxFuture<'static, Result<Arc<OutputFile>, ErrorResponse>>>;
+
+/// Coalesces concurrent conversions of identical inputs so that x2t only runs
+/// once, with the result fanned out to every request waiting on it
+#[derive(Default)]
+pub struct ConversionCoalescer {
+    /// Conversions currently running, keyed by the hash of their input and options
+    in_flight: Mutex<HashMap<String, SharedConversion>>,
+}
+
+impl ConversionCoalescer {
+    /// Join an in-flight conversion with the same key if one is running
+    pub fn join(&self, key: &str) -> Option<SharedConversion> {
+        self.in_flight
+            .lock()
+            .expect("coalescer lock poisoned")
+            .get(key)
+            .cloned()
+    }
+
+    /// Start a conversion that other requests with the same key can join, the
+    /// conversion runs in its own task so it completes even if the request that
+    /// started it is cancelled
+    pub fn start<F>(self: &Arc<Self>, key: String, conversion: F) -> SharedConversion
+    where
+        F: Future<Output = Result<OutputFile, ErrorResponse>> + Send + 'static,
+    {
+        // Lock is held until the conversion is stored so that the task can't
+        // remove it before it has been added
+        let mut in_flight = self.in_flight.lock().expect("coalescer lock poisoned");
+
+        let handle = tokio::spawn({
+            let coalescer = self.clone();
+            let key = key.clone();
+
+            async move {
+                let result = conversion.await.map(Arc::new);
+
+                // Requests arriving from now on start a new conversion
+                coalescer
+                    .in_flight
+                    .lock()
+                    .expect("coalescer lock poisoned")
+                    .remove(&key);
+
+                result
+            }
+        });
+
+        let shared = async move {
+            handle.await.unwrap_or_else(|err| {
+                tracing::error!(?err, "conversion task failed");
+                Err(ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::Internal,
+                    message: "conversion task failed".to_string(),
+                    backtrace: error_backtrace(&err),
+                })
+            })
+        }
+        .boxed()
+        .shared();
+
+        in_flight.insert(key, shared.clone());
+
+        shared
+    }
+}
+
+/// Create the key identifying a conversion from the tenant, the hash of the
+/// input file contents and the options that affect the output. The deadline
+/// and priority only affect when the conversion runs, requests that differ
+/// in them still share a conversion
+///
+/// ## Arguments
+/// * `input_path` - Path to the input file
+/// * `options` - Options for the conversion
+/// * `tenant` - Tenant the conversion runs for, tenants have their own fonts
+///   and limits so they never share conversions
+pub async fn conversion_key(
+    input_path: &Path,
+    options: &ConvertOptions,
+    tenant: Option<&str>,
+) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    hash_file(&mut hasher, input_path).await?;
+
+    let layout = &options.spreadsheet_layout;
+    let fields = json!({
+        "tenant": tenant,
+        // x2t infers the input format from the extension of the input file
+        "input_extension": input_path.extension().map(|extension| extension.to_string_lossy()),
+        "input_format": options.input_format,
+        "output_format": options.output_format.name(),
+        "password": options.password,
+        "watermark": options.watermark.as_ref().map(|watermark| json!({
+            "text": watermark.text,
+            "opacity": watermark.opacity,
+            "angle": watermark.angle,
+            "font_size": watermark.font_size,
+        })),
+        "csv": options.csv.config(None),
+        "spreadsheet_layout": {
+            "fit_to_width": layout.fit_to_width,
+            "fit_to_height": layout.fit_to_height,
+            "ignore_print_area": layout.ignore_print_area,
+            "sheets": layout.sheets,
+        },
+        "speaker_notes": options.speaker_notes,
+        "pdf_version": options.pdf_version.map(|version| version.as_str()),
+        "debug": options.debug,
+        "x2t_params": options.x2t_params.config(),
+        "form_data": options.form_data.as_ref().map(FormData::script),
+    });
+
+    hasher.update(fields.to_string().as_bytes());
+
+    Ok(hex::encode(hasher.finalize()))
+}
@@ -0,0 +1,1352 @@
+use axum::body::Body;
+use futures_util::StreamExt;
+use office_file_inspect::{
+    Confidence, FileCondition, FileSample, FileVerdict, conflicts_with_extension,
+};
+use std::process::Stdio;
+use std::{
+    path::{Path, PathBuf, absolute},
+    process::ExitStatus,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::process::Command;
+use tokio_util::{io::ReaderStream, sync::CancellationToken};
+use uuid::Uuid;
+
+use crate::{
+    ErrorKind, ErrorResponse, RuntimeConfig,
+    checksum::file_checksum,
+    csv::CsvOptions,
+    deadline::deadline_exceeded,
+    docbuilder::{is_document, is_presentation},
+    encoding::detect_codepage,
+    error_backtrace,
+    format::OutputFormat,
+    forms::FormData,
+    limiter::Priority,
+    limits::{ProcessLimits, is_crash, prepend_library_path},
+    params::X2tParams,
+    pdf_version::{PdfVersion, set_pdf_version},
+    planner::plan_conversion,
+    repair::repair_zip,
+    retry::{RETRY_DELAY, RetryPolicy},
+    scratch::ScratchUsage,
+    sheets::convert_sheets,
+    spreadsheet::SpreadsheetLayout,
+    usage::{ConversionUsage, ProcessMonitor, ResourceUsage},
+    watermark::Watermark,
+};
+
+/// Options controlling how a file is converted
+#[derive(Debug)]
+pub struct ConvertOptions {
+    /// Format to convert the file to
+    pub output_format: OutputFormat,
+    /// Password to open the file with if its encrypted
+    pub password: Option<String>,
+    /// Watermark to stamp onto the converted document
+    pub watermark: Option<Watermark>,
+    /// Options for reading CSV/TXT input files
+    pub csv: CsvOptions,
+    /// Page layout for spreadsheet inputs
+    pub spreadsheet_layout: SpreadsheetLayout,
+    /// Whether slide notes pages are included for presentation inputs
+    pub speaker_notes: bool,
+    /// Version of the PDF specification the PDF output declares
+    pub pdf_version: Option<PdfVersion>,
+    /// Whether x2t diagnostics should be included in errors
+    pub debug: bool,
+    /// Priority of the conversion in the queue
+    pub priority: Priority,
+    /// Extension of the input format, overrides the extension of the
+    /// uploaded file name
+    pub input_format: Option<String>,
+    /// Additional x2t config elements provided by the client
+    pub x2t_params: X2tParams,
+    /// Values to fill the forms of the document with before converting it
+    pub form_data: Option<FormData>,
+    /// Point in time x2t is killed at if it is still running, set from the
+    /// deadline of the request
+    pub deadline: Option<Instant>,
+}
+
+/// Maximum length of an input file extension
+const MAX_EXTENSION_LENGTH: usize = 10;
+
+/// Normalize a file extension (i.e ".DOCX" to "docx"), returns None when
+/// the extension isn't a short alphanumeric value
+pub fn normalize_extension(extension: &str) -> Option<String> {
+    let extension = extension.trim().trim_start_matches('.');
+
+    if extension.is_empty()
+        || extension.len() > MAX_EXTENSION_LENGTH
+        || !extension.chars().all(|value| value.is_ascii_alphanumeric())
+    {
+        return None;
+    }
+
+    Some(extension.to_ascii_lowercase())
+}
+
+/// Determine the output format from the requested target format name, PDF/A
+/// preference, whether all pages should be rendered for image formats and
+/// whether sheets should be split into separate PDFs, falling back to the
+/// server default for PDF/A
+pub fn resolve_output_format(
+    target_format: Option<String>,
+    pdfa: Option<bool>,
+    all_pages: Option<bool>,
+    split_sheets: Option<bool>,
+    default_pdfa: bool,
+) -> Result<OutputFormat, ErrorResponse> {
+    let output_format = match target_format {
+        Some(value) => OutputFormat::from_name(&value).ok_or_else(|| ErrorResponse {
+            code: None,
+            kind: ErrorKind::UnsupportedFormat,
+            message: format!("unsupported target format \"{value}\""),
+            backtrace: None,
+        })?,
+        None => OutputFormat::Pdf,
+    };
+
+    // Sheets are split before resolving PDF/A so that the server default
+    // doesn't apply to them
+    let output_format = match (output_format, split_sheets) {
+        (OutputFormat::Pdf, Some(true)) => OutputFormat::PdfSheets,
+        (_, Some(true)) => {
+            return Err(ErrorResponse {
+                code: None,
+                kind: ErrorKind::InvalidRequest,
+                message: "split_sheets is only supported when converting to pdf".to_string(),
+                backtrace: None,
+            });
+        }
+        (_, _) => output_format,
+    };
+
+    let output_format = match (output_format, pdfa) {
+        // Explicitly requested PDF/A
+        (OutputFormat::Pdf, Some(true)) => OutputFormat::PdfA,
+        // Explicitly requested plain PDF
+        (OutputFormat::Pdf, Some(false)) => OutputFormat::Pdf,
+        // Use the server default
+        (OutputFormat::Pdf, None) if default_pdfa => OutputFormat::PdfA,
+        (OutputFormat::Pdf | OutputFormat::PdfA, _) => output_format,
+        (_, Some(true)) => {
+            return Err(ErrorResponse {
+                code: None,
+                kind: ErrorKind::InvalidRequest,
+                message: "pdfa is only supported when converting to pdf".to_string(),
+                backtrace: None,
+            });
+        }
+        (_, _) => output_format,
+    };
+
+    Ok(match (output_format, all_pages) {
+        (OutputFormat::Png, Some(true)) => OutputFormat::PngPages,
+        (OutputFormat::Jpg, Some(true)) => OutputFormat::JpgPages,
+        (_, Some(true)) => {
+            return Err(ErrorResponse {
+                code: None,
+                kind: ErrorKind::InvalidRequest,
+                message: "all_pages is only supported when converting to images".to_string(),
+                backtrace: None,
+            });
+        }
+        (_, _) => output_format,
+    })
+}
+
+/// Temporary directory that is removed along with its contents when dropped,
+/// this happens even if the task using it is cancelled or panics
+pub struct TempDir {
+    path: PathBuf,
+    /// Disk space used by the files in the directory
+    scratch: ScratchUsage,
+    /// Resources used by the x2t processes run in the directory
+    usage: ConversionUsage,
+}
+
+impl TempDir {
+    /// Path to the directory
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Disk space used by the files in the directory
+    pub fn scratch(&self) -> &ScratchUsage {
+        &self.scratch
+    }
+
+    /// Resources used by the x2t processes run in the directory
+    pub fn usage(&self) -> &ConversionUsage {
+        &self.usage
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_dir_all(&self.path)
+            && err.kind() != std::io::ErrorKind::NotFound
+        {
+            tracing::error!(?err, "failed to delete temporary directory");
+        }
+    }
+}
+
+/// Creates a uniquely named temporary directory within the server
+/// temporary directory, fails when there isn't enough scratch space left
+/// to start a conversion
+pub async fn create_temp_dir(runtime_config: &RuntimeConfig) -> Result<TempDir, ErrorResponse> {
+    runtime_config.scratch_space.preflight()?;
+
+    // Generate random unique ID
+    let random_id = Uuid::new_v4().simple();
+    let path = runtime_config
+        .temp_path
+        .join(format!("tmp_native_{random_id}"));
+
+    // Make path absolute
+    let path = absolute(path).map_err(|err| {
+        tracing::error!(?err, "failed to make file path absolute");
+        ErrorResponse {
+            code: None,
+            kind: ErrorKind::Internal,
+            message: "failed to setup temporary paths".to_string(),
+            backtrace: error_backtrace(&err),
+        }
+    })?;
+
+    // Creates the server temporary directory as well if it doesn't exist
+    tokio::fs::create_dir_all(&path).await.map_err(|err| {
+        tracing::error!(?err, "failed to create temporary directory");
+        ErrorResponse {
+            code: None,
+            kind: ErrorKind::Internal,
+            message: "failed to create temporary directory".to_string(),
+            backtrace: error_backtrace(&err),
+        }
+    })?;
+
+    Ok(TempDir {
+        path,
+        scratch: ScratchUsage::new(runtime_config.scratch_space.clone()),
+        usage: ConversionUsage::default(),
+    })
+}
+
+/// Temporary files used while converting a file, the files are stored in
+/// their own directory which is removed once the paths and any output file
+/// created from them have been dropped
+pub struct ConvertTempPaths {
+    /// Directory containing the files
+    dir: Arc<TempDir>,
+    /// Path to the x2t config file
+    pub config_path: PathBuf,
+    /// Path the uploaded input file is written to
+    pub input_path: PathBuf,
+    /// x2t codepage identifier detected for TXT/CSV input files
+    pub detected_codepage: Option<u32>,
+}
+
+impl ConvertTempPaths {
+    /// Give the uploaded input file an extension so that x2t can infer its
+    /// format, the extension is resolved from the explicit input format, the
+    /// uploaded file name or the content of the file in that order, the
+    /// extension of the file name is replaced when the content is clearly
+    /// another format. Inputs with a format rejected by the input format
+    /// filter, exceeding the input limits or rejected by the virus scanner
+    /// are rejected
+    ///
+    /// ## Arguments
+    /// * `runtime_config` - Runtime configuration
+    /// * `input_format` - Explicit input format extension
+    /// * `file_name` - Original name of the uploaded file
+    pub async fn resolve_input_extension(
+        &mut self,
+        runtime_config: &RuntimeConfig,
+        input_format: Option<&str>,
+        file_name: Option<&str>,
+    ) -> Result<(), ErrorResponse> {
+        let input_filter = &runtime_config.input_filter;
+        let mut extension = input_format
+            .or_else(|| {
+                file_name
+                    .and_then(|file_name| file_name.rsplit_once('.'))
+                    .map(|(_, extension)| extension)
+            })
+            .and_then(normalize_extension);
+
+        let sample = read_file_sample(&self.input_path)
+            .await
+            .map_err(input_error)?;
+        let detected = sample.format();
+
+        if extension.is_none() {
+            extension = detected.map(str::to_string);
+        } else if input_format.is_none()
+            && let (Some(named), Some(detected)) = (&extension, detected)
+            && conflicts_with_extension(detected, named)
+        {
+            // x2t picks the format from the extension, mislabelled uploads
+            // are converted as the format of their content
+            tracing::debug!(
+                extension = named,
+                detected,
+                "uploaded file content doesn't match its extension"
+            );
+            extension = Some(detected.to_string());
+        }
+
+        input_filter.check(extension.as_deref(), detected)?;
+
+        runtime_config
+            .input_limits
+            .check(&self.input_path, &sample.header)
+            .await?;
+
+        if let Some(virus_scanner) = &runtime_config.virus_scanner {
+            virus_scanner.check(&self.input_path).await?;
+        }
+
+        let Some(extension) = extension else {
+            return Ok(());
+        };
+
+        let input_path = self.input_path.with_extension(&extension);
+        tokio::fs::rename(&self.input_path, &input_path)
+            .await
+            .map_err(input_error)?;
+        self.input_path = input_path;
+        self.detected_codepage = detect_codepage(&self.input_path).await;
+
+        Ok(())
+    }
+
+    /// Disk space used by the temporary files
+    pub fn scratch(&self) -> &ScratchUsage {
+        self.dir.scratch()
+    }
+
+    /// Resources used by the x2t processes of the conversion
+    pub fn usage(&self) -> &ConversionUsage {
+        self.dir.usage()
+    }
+
+    /// Path to a file within the temporary directory
+    pub fn file_path(&self, file_name: &str) -> PathBuf {
+        self.dir.path().join(file_name)
+    }
+
+    /// Path to the output file for the provided format
+    pub fn output_path(&self, output_format: OutputFormat) -> PathBuf {
+        self.dir
+            .path()
+            .join(format!("output.{}", output_format.extension()))
+    }
+}
+
+fn input_error(err: std::io::Error) -> ErrorResponse {
+    tracing::error!(?err, "failed to prepare input file");
+    ErrorResponse {
+        code: None,
+        kind: ErrorKind::Internal,
+        message: "failed to read uploaded file".to_string(),
+        backtrace: error_backtrace(&err),
+    }
+}
+
+/// Creates unique temporary paths for a conversion, ensuring the
+/// temporary directory exists
+pub async fn create_convert_temp_paths(
+    runtime_config: &RuntimeConfig,
+) -> Result<ConvertTempPaths, ErrorResponse> {
+    let dir = create_temp_dir(runtime_config).await?;
+
+    Ok(ConvertTempPaths {
+        config_path: dir.path().join("config.xml"),
+        input_path: dir.path().join("input"),
+        detected_codepage: None,
+        dir: Arc::new(dir),
+    })
+}
+
+/// Converts the input file at the temporary input path using x2t, the caller is
+/// responsible for acquiring a conversion slot before calling this
+pub async fn convert_file(
+    runtime_config: &RuntimeConfig,
+    temp_paths: &ConvertTempPaths,
+    options: &ConvertOptions,
+) -> Result<OutputFile, ErrorResponse> {
+    let output_format = options.output_format;
+    let output_path = temp_paths.output_path(output_format);
+
+    // Output file keeps the temporary directory alive until it is dropped
+    let mut output_file = OutputFile::temporary(output_path, temp_paths.dir.clone());
+
+    let input_format = temp_paths
+        .input_path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("unknown");
+    let input_size = tokio::fs::metadata(&temp_paths.input_path)
+        .await
+        .map(|metadata| metadata.len())
+        .unwrap_or_default();
+
+    // Forms are filled by docbuilder, the filled document is converted instead
+    let mut input_path = match &options.form_data {
+        Some(form_data) => fill_forms(runtime_config, temp_paths, form_data).await?,
+        None => temp_paths.input_path.clone(),
+    };
+
+    if options.speaker_notes && !is_presentation(&input_path) {
+        return Err(ErrorResponse {
+            code: None,
+            kind: ErrorKind::UnsupportedFormat,
+            message: "speaker notes can only be included for presentations".to_string(),
+            backtrace: None,
+        });
+    }
+
+    let plan_input_format = input_path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("unknown");
+    let intermediates = plan_conversion(plan_input_format, output_format);
+    if !intermediates.is_empty() {
+        tracing::debug!(
+            input_format,
+            output_format = output_format.extension(),
+            ?intermediates,
+            "converting through intermediate formats"
+        );
+    }
+
+    let start = Instant::now();
+    let mut result = convert_input(
+        runtime_config,
+        temp_paths,
+        options,
+        &input_path,
+        &intermediates,
+        output_file.path(),
+    )
+    .await;
+
+    if let Err(err) = &result
+        && err.kind == ErrorKind::Corrupted
+        && runtime_config.repair_corrupted_inputs
+        && let Some(repaired_path) = repair_input(runtime_config, temp_paths, &input_path).await
+    {
+        tracing::info!(input_format, "retrying conversion with repaired input");
+
+        // Failures of the repaired input are reported instead as the input
+        // is known to be corrupted
+        input_path = repaired_path;
+        result = convert_input(
+            runtime_config,
+            temp_paths,
+            options,
+            &input_path,
+            &intermediates,
+            output_file.path(),
+        )
+        .await;
+    }
+
+    if let Err(err) = &result
+        && let Some(libreoffice) = &runtime_config.libreoffice
+        && libreoffice.should_fallback(err.code, output_format)
+    {
+        tracing::info!(
+            input_format,
+            code = err.code,
+            "x2t rejected input, converting with libreoffice"
+        );
+
+        let limits: ProcessLimits = *runtime_config
+            .x2t_limits
+            .read()
+            .expect("x2t limits lock poisoned");
+
+        match libreoffice
+            .convert(
+                temp_paths.dir.path(),
+                &input_path,
+                output_file.path(),
+                &limits,
+            )
+            .await
+        {
+            Ok(()) => {
+                output_file.backend = ConversionBackend::LibreOffice;
+                result = Ok(());
+            }
+            // The x2t error is reported as it describes why the input was rejected
+            Err(err) => tracing::warn!(?err, "libreoffice fallback failed"),
+        }
+    }
+
+    let duration = start.elapsed();
+
+    let output_size = match &result {
+        Ok(()) => tokio::fs::metadata(output_file.path())
+            .await
+            .map(|metadata| metadata.len())
+            .ok(),
+        Err(_) => None,
+    };
+
+    if let Some(output_size) = output_size {
+        temp_paths.scratch().record(output_size);
+    }
+
+    runtime_config
+        .metrics
+        .record_conversion(input_format, duration, input_size, output_size);
+
+    let usage = temp_paths.usage().total();
+    if !usage.wall_time.is_zero() {
+        runtime_config
+            .metrics
+            .record_x2t_usage(input_format, &usage);
+
+        tracing::info!(
+            tenant = runtime_config.tenant.as_deref(),
+            input_format,
+            output_format = output_format.extension(),
+            wall_time_ms = usage.wall_time.as_millis() as u64,
+            cpu_time_ms = usage.cpu_time.as_millis() as u64,
+            peak_rss = usage.peak_rss,
+            success = result.is_ok(),
+            "x2t resource usage"
+        );
+    }
+
+    if runtime_config
+        .slow_conversion_threshold
+        .is_some_and(|threshold| duration >= threshold)
+    {
+        tracing::warn!(
+            input_format,
+            output_format = output_format.extension(),
+            duration_ms = duration.as_millis() as u64,
+            input_size,
+            output_size,
+            success = result.is_ok(),
+            "slow conversion"
+        );
+    }
+
+    result?;
+
+    if let Some(pdf_version) = options.pdf_version {
+        set_pdf_version(output_file.path(), pdf_version).await?;
+    }
+
+    Ok(output_file)
+}
+
+/// Fill the forms of the input file using docbuilder, returns the path to
+/// the filled document
+async fn fill_forms(
+    runtime_config: &RuntimeConfig,
+    temp_paths: &ConvertTempPaths,
+    form_data: &FormData,
+) -> Result<PathBuf, ErrorResponse> {
+    let docbuilder = runtime_config
+        .docbuilder
+        .as_ref()
+        .ok_or_else(|| ErrorResponse {
+            code: None,
+            kind: ErrorKind::InvalidRequest,
+            message: "form filling requires docbuilder to be configured".to_string(),
+            backtrace: None,
+        })?;
+
+    if !is_document(&temp_paths.input_path) {
+        return Err(ErrorResponse {
+            code: None,
+            kind: ErrorKind::UnsupportedFormat,
+            message: "forms can only be filled in text documents".to_string(),
+            backtrace: None,
+        });
+    }
+
+    let limits: ProcessLimits = *runtime_config
+        .x2t_limits
+        .read()
+        .expect("x2t limits lock poisoned");
+
+    let filled_path = docbuilder
+        .run(&temp_paths.input_path, &form_data.script(), &limits)
+        .await?;
+
+    let size = tokio::fs::metadata(&filled_path)
+        .await
+        .map(|metadata| metadata.len())
+        .unwrap_or_default();
+    temp_paths.scratch().record(size);
+
+    Ok(filled_path)
+}
+
+/// Run x2t for each step of a conversion plan, each intermediate file is
+/// deleted once the next step has converted it
+///
+/// ## Arguments
+/// * `runtime_config` - Runtime configuration
+/// * `temp_paths` - Temporary paths of the conversion
+/// * `options` - Options for the conversion
+/// * `input_path` - Path to the file to convert
+/// * `intermediates` - Formats converted through before the output format
+/// * `output_path` - Path to write the output file to
+async fn run_conversion_plan(
+    runtime_config: &RuntimeConfig,
+    temp_paths: &ConvertTempPaths,
+    options: &ConvertOptions,
+    input_path: &Path,
+    intermediates: &[OutputFormat],
+    output_path: &Path,
+) -> Result<(), ErrorResponse> {
+    let steps = intermediates
+        .iter()
+        .copied()
+        .chain(std::iter::once(options.output_format));
+
+    // Intermediate file produced by the previous step
+    let mut intermediate: Option<(PathBuf, u64)> = None;
+
+    for (index, step_format) in steps.enumerate() {
+        let is_first = index == 0;
+        let is_last = index == intermediates.len();
+
+        let step_input = match &intermediate {
+            Some((path, _)) => path.clone(),
+            None => input_path.to_path_buf(),
+        };
+        let step_output = if is_last {
+            output_path.to_path_buf()
+        } else {
+            temp_paths
+                .dir
+                .path()
+                .join(format!("intermediate_{index}.{}", step_format.extension()))
+        };
+
+        let step = ConvertStep {
+            input_path: &step_input,
+            output_path: &step_output,
+            output_format: step_format,
+            is_first,
+            is_last,
+            sheet: None,
+        };
+
+        let result = run_step(runtime_config, temp_paths, options, &step).await;
+
+        if let Some((path, size)) = intermediate.take() {
+            if let Err(err) = tokio::fs::remove_file(&path).await {
+                tracing::warn!(?err, "failed to delete intermediate file");
+            } else {
+                temp_paths.scratch().release(size);
+            }
+        }
+
+        result?;
+
+        if !is_last {
+            let size = tokio::fs::metadata(&step_output)
+                .await
+                .map(|metadata| metadata.len())
+                .unwrap_or_default();
+            temp_paths.scratch().record(size);
+            intermediate = Some((step_output, size));
+        }
+    }
+
+    Ok(())
+}
+
+/// Single x2t pass of a conversion
+pub struct ConvertStep<'a> {
+    /// Path to the input of the step
+    pub input_path: &'a Path,
+    /// Path to write the output of the step to
+    pub output_path: &'a Path,
+    /// Format to convert to in this step
+    pub output_format: OutputFormat,
+    /// Whether the step converts the uploaded file, options for reading the
+    /// input only apply to the first step
+    pub is_first: bool,
+    /// Whether the step produces the output, options for the output only
+    /// apply to the last step
+    pub is_last: bool,
+    /// Only convert the spreadsheet sheet at this index
+    pub sheet: Option<u32>,
+}
+
+/// Run x2t for a single step of a conversion
+pub async fn run_step(
+    runtime_config: &RuntimeConfig,
+    temp_paths: &ConvertTempPaths,
+    options: &ConvertOptions,
+    step: &ConvertStep<'_>,
+) -> Result<(), ErrorResponse> {
+    let config = x2t_config(runtime_config, temp_paths, options, step);
+
+    x2t(
+        runtime_config,
+        step.input_path,
+        &temp_paths.config_path,
+        config.as_bytes(),
+        step.is_first && options.password.is_some(),
+        options.debug,
+        options.deadline,
+        &runtime_config.cancel_conversions,
+        temp_paths.usage(),
+    )
+    .await
+}
+
+/// Create the x2t config for a step of a conversion
+fn x2t_config(
+    runtime_config: &RuntimeConfig,
+    temp_paths: &ConvertTempPaths,
+    options: &ConvertOptions,
+    step: &ConvertStep<'_>,
+) -> String {
+    let mut input_config = String::new();
+    if step.is_first {
+        if let Some(password) = options.password.as_deref() {
+            input_config.push_str(&format!(
+                "<m_sPassword>{}</m_sPassword>",
+                escape_xml(password)
+            ));
+        }
+
+        input_config.push_str(&options.csv.config(temp_paths.detected_codepage));
+        input_config.push_str(&runtime_config.input_limits.config());
+    }
+
+    let mut output_config = String::new();
+    if step.is_last {
+        if let Some(json_params) = json_params(options, step.sheet) {
+            output_config.push_str(&format!(
+                "<m_sJsonParams>{}</m_sJsonParams>",
+                escape_xml(&json_params)
+            ));
+        }
+
+        output_config.push_str(&options.x2t_params.config());
+    }
+
+    format!(
+        r#"
+        <?xml version="1.0" encoding="utf-8"?>
+        <TaskQueueDataConvert xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance"
+                              xmlns:xsd="http://www.w3.org/2001/XMLSchema">
+          <m_sFileFrom>{}</m_sFileFrom>
+          <m_sFileTo>{}</m_sFileTo>
+          <m_sFontDir>{}</m_sFontDir>
+          <m_nFormatTo>{}</m_nFormatTo>
+          {}
+          {}
+          {}
+        </TaskQueueDataConvert>
+        "#,
+        escape_xml(&step.input_path.display().to_string()),
+        escape_xml(&step.output_path.display().to_string()),
+        escape_xml(&runtime_config.fonts_path.display().to_string()),
+        step.output_format.x2t_code(),
+        step.output_format.extra_config(),
+        input_config,
+        output_config,
+    )
+}
+
+/// Create the JSON params (m_sJsonParams) for the conversion options,
+/// returns None when no options need them
+fn json_params(options: &ConvertOptions, sheet: Option<u32>) -> Option<String> {
+    let mut params = serde_json::Map::new();
+
+    if let Some(watermark) = &options.watermark {
+        params.insert("watermark_on_draw".to_string(), watermark.json_param());
+    }
+
+    if let Some(layout) = options.spreadsheet_layout.json_param(sheet) {
+        params.insert("spreadsheetLayout".to_string(), layout);
+    }
+
+    // Renders each slide on a notes page layout with its notes below it
+    if options.speaker_notes {
+        params.insert(
+            "slidesLayout".to_string(),
+            serde_json::json!({ "printNotes": true }),
+        );
+    }
+
+    if params.is_empty() {
+        return None;
+    }
+
+    Some(serde_json::Value::Object(params).to_string())
+}
+
+/// Converted output file on disk, the file is deleted along with its
+/// temporary directory when this is dropped unless it has been persisted
+/// elsewhere
+pub struct OutputFile {
+    path: PathBuf,
+    /// Backend that produced the file
+    backend: ConversionBackend,
+    /// Directory containing the file, removed once the file is dropped
+    temp_dir: Arc<TempDir>,
+}
+
+/// Backend used to convert a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConversionBackend {
+    X2t,
+    /// LibreOffice fallback for inputs x2t rejects
+    LibreOffice,
+}
+
+impl ConversionBackend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConversionBackend::X2t => "x2t",
+            ConversionBackend::LibreOffice => "libreoffice",
+        }
+    }
+}
+
+impl OutputFile {
+    /// Create an output file for a file at the provided path within a
+    /// temporary directory
+    pub fn temporary(path: PathBuf, temp_dir: Arc<TempDir>) -> Self {
+        Self {
+            path,
+            backend: ConversionBackend::X2t,
+            temp_dir,
+        }
+    }
+
+    /// Path to the output file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Backend that produced the output file
+    pub fn backend(&self) -> ConversionBackend {
+        self.backend
+    }
+
+    /// Resources used by the x2t processes that produced the output file
+    pub fn usage(&self) -> ResourceUsage {
+        self.temp_dir.usage().total()
+    }
+
+    /// Move the output file to the provided path, it will no longer be
+    /// deleted when dropped
+    pub async fn persist(self, path: &Path) -> std::io::Result<()> {
+        tokio::fs::rename(&self.path, path).await
+    }
+
+    /// Create a response body that streams the contents of the output file,
+    /// the file is deleted once the body stream is dropped
+    pub async fn into_body(self) -> std::io::Result<Body> {
+        Arc::new(self).into_shared_body().await
+    }
+
+    /// Create a response body that streams the contents of a shared output
+    /// file, the file is deleted once every reference has been dropped
+    pub async fn into_shared_body(self: Arc<Self>) -> std::io::Result<Body> {
+        let file = tokio::fs::File::open(&self.path).await?;
+        let stream = ReaderStream::new(file).map(move |chunk| {
+            // Hold onto the output file until the stream is dropped
+            let _output_file = &self;
+            chunk
+        });
+
+        Ok(Body::from_stream(stream))
+    }
+}
+
+/// Convert the input file to the output path, running each step of the
+/// conversion plan or converting each sheet separately
+async fn convert_input(
+    runtime_config: &RuntimeConfig,
+    temp_paths: &ConvertTempPaths,
+    options: &ConvertOptions,
+    input_path: &Path,
+    intermediates: &[OutputFormat],
+    output_path: &Path,
+) -> Result<(), ErrorResponse> {
+    if options.output_format == OutputFormat::PdfSheets {
+        convert_sheets(runtime_config, temp_paths, options, input_path, output_path).await
+    } else {
+        run_conversion_plan(
+            runtime_config,
+            temp_paths,
+            options,
+            input_path,
+            intermediates,
+            output_path,
+        )
+        .await
+    }
+}
+
+/// Attempt to repair a corrupted ZIP based input, returns the path to the
+/// repaired input when it could be salvaged
+async fn repair_input(
+    runtime_config: &RuntimeConfig,
+    temp_paths: &ConvertTempPaths,
+    input_path: &Path,
+) -> Option<PathBuf> {
+    let sample = read_file_sample(input_path).await.ok()?;
+    if !sample.header.starts_with(b"PK\x03\x04") {
+        return None;
+    }
+
+    let extension = input_path.extension()?.to_str()?;
+    let repaired_path = temp_paths.file_path(&format!("repaired.{extension}"));
+
+    let repaired = repair_zip(
+        input_path,
+        &repaired_path,
+        runtime_config.input_limits.max_uncompressed_size,
+    )
+    .await;
+
+    if !repaired {
+        tracing::debug!("corrupted input could not be repaired");
+        return None;
+    }
+
+    if let Ok(metadata) = tokio::fs::metadata(&repaired_path).await {
+        temp_paths.scratch().record(metadata.len());
+    }
+
+    Some(repaired_path)
+}
+
+/// Read the parts of the file needed to check its condition and format
+pub async fn read_file_sample(path: &Path) -> std::io::Result<FileSample> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || FileSample::read(&path))
+        .await
+        .map_err(std::io::Error::other)?
+}
+
+/// Error code used when x2t exceeds a resource limit, matches the x2t
+/// AVS_FILEUTILS_ERROR_CONVERT_LIMITS error code
+const RESOURCE_LIMIT_ERROR_CODE: i32 = 0x005d;
+
+/// Error code used when x2t exceeds the x2t timeout, matches the x2t
+/// AVS_FILEUTILS_ERROR_CONVERT_TIMEOUT error code
+const TIMEOUT_ERROR_CODE: i32 = 0x0053;
+
+#[cfg(not(windows))]
+pub const X2T_BIN: &str = "x2t";
+#[cfg(windows)]
+pub const X2T_BIN: &str = "x2t.exe";
+
+#[allow(clippy::too_many_arguments)]
+async fn x2t(
+    runtime_config: &RuntimeConfig,
+    input_path: &Path,
+    config_path: &Path,
+    config_bytes: &[u8],
+    has_password: bool,
+    debug: bool,
+    deadline: Option<Instant>,
+    cancel: &CancellationToken,
+    usage: &ConversionUsage,
+) -> Result<(), ErrorResponse> {
+    let x2t_path = &runtime_config.x2t_path;
+    // Settings are copied so that reloading the configuration doesn't affect
+    // running conversions
+    let limits: ProcessLimits = *runtime_config
+        .x2t_limits
+        .read()
+        .expect("x2t limits lock poisoned");
+    let timeout: Option<Duration> = *runtime_config
+        .x2t_timeout
+        .read()
+        .expect("x2t timeout lock poisoned");
+    let retry_policy: RetryPolicy = runtime_config
+        .x2t_retry_policy
+        .read()
+        .expect("x2t retry policy lock poisoned")
+        .clone();
+
+    let x2t = x2t_path.join(X2T_BIN);
+    let x2t = x2t.to_string_lossy();
+
+    tokio::fs::write(config_path, config_bytes)
+        .await
+        .map_err(|err| {
+            tracing::error!(?err, "failed to write config file");
+            ErrorResponse {
+                code: None,
+                kind: ErrorKind::Internal,
+                message: "failed to write config file".to_string(),
+                backtrace: error_backtrace(&err),
+            }
+        })?;
+
+    // Documents that keep crashing x2t are rejected without running it again
+    let document_hash = match &runtime_config.poison_documents {
+        Some(poison_documents) => {
+            let hash = file_checksum(input_path)
+                .await
+                .inspect_err(|err| tracing::warn!(?err, "failed to hash x2t input"))
+                .ok();
+
+            if let Some(hash) = &hash
+                && let Err(err) = poison_documents.check(hash)
+            {
+                runtime_config.metrics.record_poison_rejection();
+                return Err(err);
+            }
+
+            hash
+        }
+        None => None,
+    };
+
+    let run_error = |err: std::io::Error| {
+        tracing::error!(?err, "failed to run x2t");
+        ErrorResponse {
+            code: None,
+            kind: ErrorKind::Internal,
+            message: "failed to run x2t".to_string(),
+            backtrace: error_backtrace(&err),
+        }
+    };
+
+    let mut attempt = 1;
+
+    let output = loop {
+        let mut command = Command::new(x2t.as_ref());
+        command
+            .arg(config_path.display().to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // Ensure x2t doesn't outlive the conversion if it's abandoned
+            .kill_on_drop(true);
+
+        // Update the library path to include the x2t bin directory, fixes a bug where some of the
+        // required shared libraries aren't loaded when they need to be
+        prepend_library_path(&mut command, x2t_path);
+        limits.apply(&mut command);
+
+        let started = Instant::now();
+
+        // Time left until the request deadline, x2t is killed at whichever
+        // of the deadline and the x2t timeout is reached first
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(started));
+        if remaining.is_some_and(|remaining| remaining.is_zero()) {
+            return Err(deadline_exceeded(
+                "conversion didn't finish before the request deadline",
+            ));
+        }
+
+        let limit = match (timeout, remaining) {
+            (Some(timeout), Some(remaining)) if remaining < timeout => Some(remaining),
+            (Some(timeout), _) => Some(timeout),
+            (None, remaining) => remaining,
+        };
+
+        let child = command.spawn().map_err(run_error)?;
+        let monitor = ProcessMonitor::start(child.id());
+
+        // Killing the job also kills any processes x2t started, which would
+        // otherwise outlive a timed out conversion
+        #[cfg(windows)]
+        let _job = crate::windows::JobObject::assign(&child, &limits)
+            .inspect_err(|err| tracing::warn!(?err, "failed to assign x2t to a job object"))
+            .ok();
+
+        let wait = async {
+            match limit {
+                Some(limit) => tokio::time::timeout(limit, child.wait_with_output()).await,
+                None => Ok(child.wait_with_output().await),
+            }
+        };
+
+        // Dropping the output future kills the process
+        let output = tokio::select! {
+            output = wait => output,
+            _ = cancel.cancelled() => {
+                usage.record(monitor.finish(started.elapsed()));
+                tracing::warn!("x2t was still running at shutdown and was killed");
+
+                return Err(conversion_cancelled());
+            }
+        };
+
+        let output = match output {
+            Ok(output) => output,
+            Err(_) => {
+                usage.record(monitor.finish(started.elapsed()));
+
+                if let Some(timeout) = timeout.filter(|timeout| limit == Some(*timeout)) {
+                    tracing::warn!(?timeout, "x2t exceeded the timeout and was killed");
+                    runtime_config.metrics.record_x2t_timeout();
+
+                    return Err(ErrorResponse {
+                        code: Some(TIMEOUT_ERROR_CODE),
+                        kind: ErrorKind::Timeout,
+                        message: format!(
+                            "conversion exceeded the x2t timeout of {} seconds",
+                            timeout.as_secs()
+                        ),
+                        backtrace: None,
+                    });
+                }
+
+                tracing::warn!("x2t was still running at the request deadline and was killed");
+
+                return Err(deadline_exceeded(
+                    "conversion didn't finish before the request deadline",
+                ));
+            }
+        }
+        .map_err(run_error)?;
+
+        usage.record(monitor.finish(started.elapsed()));
+
+        if output.status.success() || !retry_policy.should_retry(attempt, output.status.code()) {
+            if attempt > 1 && !output.status.success() {
+                runtime_config.metrics.record_x2t_retry_failure();
+            }
+
+            break output;
+        }
+
+        tracing::warn!(
+            attempt,
+            exit_code = ?output.status.code(),
+            "x2t failed with a transient error, retrying"
+        );
+        runtime_config.metrics.record_x2t_retry();
+
+        attempt += 1;
+
+        // Retries aren't started once the server is shutting down
+        tokio::select! {
+            _ = tokio::time::sleep(RETRY_DELAY) => {}
+            _ = cancel.cancelled() => return Err(conversion_cancelled()),
+        }
+    };
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        // Aborts from exceeding the memory limit aren't crashes of the document
+        if is_crash(&output.status) && limits.exceeded_limit(&output.status, &stderr).is_none() {
+            runtime_config.metrics.record_x2t_crash();
+
+            if let (Some(poison_documents), Some(hash)) =
+                (&runtime_config.poison_documents, document_hash)
+            {
+                poison_documents.record_crash(hash);
+            }
+
+            if let Some(crash_reports) = &runtime_config.crash_reports {
+                let config = redact_password(&String::from_utf8_lossy(config_bytes));
+                crash_reports
+                    .capture(input_path, &config, &output.status, &stderr)
+                    .await;
+            }
+        }
+
+        let file_condition = read_file_sample(input_path)
+            .await
+            .map(|sample| sample.condition())
+            .inspect_err(|err| tracing::error!(?err, "failed to check input file condition"))
+            .unwrap_or_else(|_| FileCondition::normal(Confidence::Low));
+
+        tracing::error!(
+            "error processing file (stderr = {stderr}, exit status = {}, file_condition = {file_condition:?})",
+            output.status
+        );
+
+        let mut error = x2t_error(
+            &limits,
+            &output.status,
+            &stderr,
+            file_condition,
+            has_password,
+        );
+
+        // Include the raw x2t output when diagnostics were requested
+        if debug {
+            error.backtrace = Some(format!(
+                "{}\n\nstdout:\n{}\n\nstderr:\n{stderr}\n\nconfig:\n{}",
+                output.status,
+                String::from_utf8_lossy(&output.stdout),
+                redact_password(&String::from_utf8_lossy(config_bytes)),
+            ));
+        }
+
+        return Err(error);
+    }
+
+    Ok(())
+}
+
+/// Error for conversions killed because the server is shutting down
+fn conversion_cancelled() -> ErrorResponse {
+    ErrorResponse {
+        code: None,
+        kind: ErrorKind::Unavailable,
+        message: "conversion was cancelled because the server is shutting down".to_string(),
+        backtrace: None,
+    }
+}
+
+/// Create the error response for a failed x2t run
+fn x2t_error(
+    limits: &ProcessLimits,
+    status: &ExitStatus,
+    stderr: &str,
+    file_condition: FileCondition,
+    has_password: bool,
+) -> ErrorResponse {
+    let error_code = status.code();
+
+    // Process was stopped for exceeding one of its resource limits
+    if let Some(limit) = limits.exceeded_limit(status, stderr) {
+        return ErrorResponse {
+            code: Some(RESOURCE_LIMIT_ERROR_CODE),
+            kind: ErrorKind::ResourceLimit,
+            message: limit.message().to_string(),
+            backtrace: None,
+        };
+    }
+
+    // Password was provided but x2t could not open the file with it
+    if has_password && error_code == Some(0x005b) {
+        return ErrorResponse {
+            code: error_code,
+            kind: ErrorKind::Encrypted,
+            message: "incorrect file password".to_string(),
+            backtrace: None,
+        };
+    }
+
+    // Assume encryption for out of range crashes
+    if stderr.contains("std::out_of_range") {
+        return ErrorResponse {
+            code: error_code,
+            kind: ErrorKind::Encrypted,
+            message: "file is encrypted".to_string(),
+            backtrace: None,
+        };
+    }
+
+    let (kind, message) = match file_condition.verdict {
+        FileVerdict::LikelyCorrupted => (ErrorKind::Corrupted, "file is corrupted"),
+        FileVerdict::LikelyEncrypted => (ErrorKind::Encrypted, "file is encrypted"),
+        FileVerdict::Normal => (
+            error_code.map_or(ErrorKind::ConversionFailed, get_error_code_kind),
+            error_code
+                .and_then(get_error_code_message)
+                .unwrap_or("unknown error occurred"),
+        ),
+    };
+
+    // Include the signal that determined the condition of the file
+    let message = match &file_condition.reason {
+        Some(reason) => format!("{message} ({reason})"),
+        None => message.to_string(),
+    };
+
+    ErrorResponse {
+        code: error_code,
+        kind,
+        message,
+        backtrace: None,
+    }
+}
+
+/// Replace the password in a x2t config so it can be shown in diagnostics
+fn redact_password(config: &str) -> String {
+    const START: &str = "<m_sPassword>";
+    const END: &str = "</m_sPassword>";
+
+    match (config.find(START), config.find(END)) {
+        (Some(start), Some(end)) if start < end => {
+            format!("{}{START}[redacted]{}", &config[..start], &config[end..])
+        }
+        _ => config.to_string(),
+    }
+}
+
+/// Escape a value for use as XML element text
+pub fn escape_xml(value: &str) -> String {
+    let mut output = String::with_capacity(value.len());
+    for char in value.chars() {
+        match char {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&apos;"),
+            char => output.push(char),
+        }
+    }
+    output
+}
+
+/// Determine the kind of error from a x2t error code
+fn get_error_code_kind(code: i32) -> ErrorKind {
+    match code {
+        // AVS_FILEUTILS_ERROR_CONVERT_UNKNOWN_FORMAT, AVS_FILEUTILS_ERROR_CONVERT_DETECT
+        0x0052 | 0x005f => ErrorKind::UnsupportedFormat,
+        // AVS_FILEUTILS_ERROR_CONVERT_TIMEOUT
+        0x0053 => ErrorKind::Timeout,
+        // AVS_FILEUTILS_ERROR_CONVERT_READ_FILE, AVS_FILEUTILS_ERROR_CONVERT_CORRUPTED
+        0x0054 | 0x0056 => ErrorKind::Corrupted,
+        // AVS_FILEUTILS_ERROR_CONVERT_DRM_UNSUPPORTED, AVS_FILEUTILS_ERROR_CONVERT_DRM,
+        // AVS_FILEUTILS_ERROR_CONVERT_PASSWORD
+        0x0055 | 0x005a | 0x005b => ErrorKind::Encrypted,
+        // AVS_FILEUTILS_ERROR_CONVERT_LIMITS, AVS_FILEUTILS_ERROR_CONVERT_ROWLIMITS,
+        // AVS_FILEUTILS_ERROR_CONVERT_CELLLIMITS
+        0x005d | 0x005e | 0x0060 => ErrorKind::TooLarge,
+        _ => ErrorKind::ConversionFailed,
+    }
+}
+
+/// Translate a x2t error code to the common x2t error messages
+fn get_error_code_message(code: i32) -> Option<&'static str> {
+    Some(match code {
+        0x0001 => "AVS_FILEUTILS_ERROR_UNKNOWN",
+        0x0050 => "AVS_FILEUTILS_ERROR_CONVERT",
+        0x0051 => "AVS_FILEUTILS_ERROR_CONVERT_DOWNLOAD",
+        0x0052 => "AVS_FILEUTILS_ERROR_CONVERT_UNKNOWN_FORMAT",
+        0x0053 => "AVS_FILEUTILS_ERROR_CONVERT_TIMEOUT",
+        0x0054 => "AVS_FILEUTILS_ERROR_CONVERT_READ_FILE",
+        0x0055 => "AVS_FILEUTILS_ERROR_CONVERT_DRM_UNSUPPORTED",
+        0x0056 => "AVS_FILEUTILS_ERROR_CONVERT_CORRUPTED",
+        0x0057 => "AVS_FILEUTILS_ERROR_CONVERT_LIBREOFFICE",
+        0x0058 => "AVS_FILEUTILS_ERROR_CONVERT_PARAMS",
+        0x0059 => "AVS_FILEUTILS_ERROR_CONVERT_NEED_PARAMS",
+        0x005a => "AVS_FILEUTILS_ERROR_CONVERT_DRM",
+        0x005b => "AVS_FILEUTILS_ERROR_CONVERT_PASSWORD",
+        0x005c => "AVS_FILEUTILS_ERROR_CONVERT_ICU",
+        0x005d => "AVS_FILEUTILS_ERROR_CONVERT_LIMITS",
+        0x005e => "AVS_FILEUTILS_ERROR_CONVERT_ROWLIMITS",
+        0x005f => "AVS_FILEUTILS_ERROR_CONVERT_DETECT",
+        0x0060 => "AVS_FILEUTILS_ERROR_CONVERT_CELLLIMITS",
+        _ => return None,
+    })
+}
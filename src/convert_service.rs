@@ -0,0 +1,746 @@
+use axum::{
+    Extension, Json,
+    body::Body,
+    extract::{Path, Request, State},
+    http::{HeaderMap, HeaderValue, Response, StatusCode, header},
+    middleware::Next,
+    response::IntoResponse,
+};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncWriteExt, BufWriter},
+    sync::watch,
+};
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+use crate::{
+    ErrorKind, ErrorResponse, RuntimeConfig,
+    auth::JwtAuth,
+    convert::{ConvertOptions, convert_file, create_convert_temp_paths, escape_xml},
+    deadline::RequestDeadline,
+    disposition::{attachment, output_file_name},
+    error_backtrace,
+    format::OutputFormat,
+    limiter::QueueTicket,
+    scratch::WriteReservation,
+    upload::ConvertFields,
+    url_filter::UrlFilter,
+};
+
+/// Interval between checks for expired conversion results
+const RESULT_CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Maximum time to spend downloading the input file
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Maximum size of a downloaded input file, matches the upload body limit
+const MAX_DOWNLOAD_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Maximum size of a request body read when checking for a token
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+/// DocumentServer conversion API error codes
+const ERROR_UNKNOWN: i32 = -1;
+const ERROR_TIMEOUT: i32 = -2;
+const ERROR_CONVERT: i32 = -3;
+const ERROR_DOWNLOAD: i32 = -4;
+const ERROR_PASSWORD: i32 = -5;
+const ERROR_INPUT: i32 = -7;
+const ERROR_TOKEN: i32 = -8;
+const ERROR_SIZE_LIMIT: i32 = -10;
+
+/// Request body of the DocumentServer conversion API. When JWT
+/// authentication is enabled the token can be provided in the JWT header or
+/// in the `token` field of the body, the claims of a body token replace the
+/// rest of the body
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConvertServiceRequest {
+    /// Whether to respond immediately with the progress instead of waiting
+    /// for the conversion to finish
+    #[serde(default, rename = "async")]
+    is_async: bool,
+    /// x2t codepage identifier for the encoding of CSV/TXT input files,
+    /// detected from the content of the file when not provided
+    code_page: Option<u32>,
+    /// Delimiter of CSV/TXT input files (0 = none, 1 = tab, 2 = semicolon,
+    /// 3 = colon, 4 = comma, 5 = space)
+    delimiter: Option<u32>,
+    /// Extension of the input format
+    filetype: Option<String>,
+    /// Unique key of the conversion, repeated requests with the same key
+    /// respond with the same conversion
+    key: String,
+    /// Extension of the format to convert to
+    outputtype: String,
+    /// Password to open the file with if its encrypted
+    password: Option<String>,
+    /// Name of the file, used to name the result
+    title: Option<String>,
+    /// URL to download the file to convert from
+    url: String,
+}
+
+impl ConvertServiceRequest {
+    /// Resolve the request into the options for the conversion
+    fn options(&self, default_pdfa: bool) -> Result<ConvertOptions, ErrorResponse> {
+        let csv_delimiter = match self.delimiter {
+            None | Some(0) => None,
+            Some(1) => Some("tab"),
+            Some(2) => Some("semicolon"),
+            Some(3) => Some("colon"),
+            Some(4) => Some("comma"),
+            Some(5) => Some("space"),
+            Some(value) => {
+                return Err(ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::InvalidRequest,
+                    message: format!("invalid delimiter {value}"),
+                    backtrace: None,
+                });
+            }
+        };
+
+        ConvertFields {
+            target_format: Some(self.outputtype.clone()),
+            password: self.password.clone(),
+            csv_delimiter: csv_delimiter.map(str::to_string),
+            codepage: self.code_page,
+            input_format: self.filetype.clone(),
+            ..Default::default()
+        }
+        .into_options(default_pdfa)
+    }
+
+    /// Name of the input file, the title or the last segment of the URL
+    fn file_name(&self) -> Option<String> {
+        if let Some(title) = &self.title {
+            return Some(title.clone());
+        }
+
+        let url = reqwest::Url::parse(&self.url).ok()?;
+        url.path_segments()?
+            .next_back()
+            .filter(|segment| !segment.is_empty())
+            .map(str::to_string)
+    }
+}
+
+/// Progress of a conversion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConvertServiceState {
+    Converting,
+    Completed,
+    /// Conversion failed with the provided DocumentServer error code
+    Failed(i32),
+}
+
+struct ConvertServiceEntry {
+    /// ID the result is downloaded with
+    id: Uuid,
+    state: watch::Sender<ConvertServiceState>,
+    output_format: OutputFormat,
+    /// Name the result is downloaded as
+    output_name: String,
+    finished_at: Option<Instant>,
+    /// Path to the converted file once completed
+    result_path: Option<PathBuf>,
+}
+
+/// Conversions are keyed by the temporary directory of the runtime
+/// configuration (unique per tenant) and the request key
+type EntryKey = (PathBuf, String);
+
+/// Store for conversions made through the DocumentServer conversion API
+pub struct ConvertServiceStore {
+    entries: Mutex<HashMap<EntryKey, ConvertServiceEntry>>,
+    http: reqwest::Client,
+    /// Restricts the URLs files are downloaded from
+    url_filter: Arc<UrlFilter>,
+    /// Public base URL of the server used to build result URLs
+    public_url: String,
+    /// Time to keep finished conversion results around for
+    result_ttl: Duration,
+}
+
+impl ConvertServiceStore {
+    pub fn new(
+        public_url: String,
+        url_filter: UrlFilter,
+        result_ttl: Duration,
+    ) -> reqwest::Result<Self> {
+        let url_filter = Arc::new(url_filter);
+        let http = url_filter
+            .clone()
+            .apply(reqwest::Client::builder())
+            .timeout(DOWNLOAD_TIMEOUT)
+            .build()?;
+
+        Ok(Self {
+            entries: Default::default(),
+            http,
+            url_filter,
+            public_url: public_url.trim_end_matches('/').to_string(),
+            result_ttl,
+        })
+    }
+
+    fn update(&self, key: &EntryKey, action: impl FnOnce(&mut ConvertServiceEntry)) {
+        if let Some(entry) = self
+            .entries
+            .lock()
+            .expect("convert service lock poisoned")
+            .get_mut(key)
+        {
+            action(entry)
+        }
+    }
+
+    fn set_completed(&self, key: &EntryKey, result_path: PathBuf) {
+        self.update(key, |entry| {
+            entry.state.send_replace(ConvertServiceState::Completed);
+            entry.finished_at = Some(Instant::now());
+            entry.result_path = Some(result_path);
+        });
+    }
+
+    fn set_failed(&self, key: &EntryKey, error_code: i32) {
+        self.update(key, |entry| {
+            entry
+                .state
+                .send_replace(ConvertServiceState::Failed(error_code));
+            entry.finished_at = Some(Instant::now());
+        });
+    }
+
+    /// Removes finished conversions that are older than the result TTL,
+    /// returns the result files that should be deleted
+    fn remove_expired(&self) -> Vec<PathBuf> {
+        let mut entries = self.entries.lock().expect("convert service lock poisoned");
+        let mut expired_paths = Vec::new();
+
+        entries.retain(|_, entry| {
+            let expired = entry
+                .finished_at
+                .is_some_and(|finished_at| finished_at.elapsed() >= self.result_ttl);
+
+            if expired && let Some(path) = entry.result_path.take() {
+                expired_paths.push(path);
+            }
+
+            !expired
+        });
+
+        expired_paths
+    }
+
+    /// Background task that periodically removes expired conversion results
+    pub async fn run_cleanup(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(RESULT_CLEANUP_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            for path in self.remove_expired() {
+                if let Err(err) = tokio::fs::remove_file(&path).await {
+                    tracing::error!(?err, "failed to delete expired conversion result");
+                }
+            }
+        }
+    }
+}
+
+/// DocumentServer error code for an error
+fn error_code(error: &ErrorResponse) -> i32 {
+    match error.kind {
+        ErrorKind::InvalidRequest | ErrorKind::Infected => ERROR_INPUT,
+        ErrorKind::Unauthorized | ErrorKind::Forbidden => ERROR_TOKEN,
+        ErrorKind::Encrypted => ERROR_PASSWORD,
+        ErrorKind::Corrupted
+        | ErrorKind::UnsupportedFormat
+        | ErrorKind::PoisonDocument
+        | ErrorKind::ConversionFailed => ERROR_CONVERT,
+        ErrorKind::Timeout => ERROR_TIMEOUT,
+        ErrorKind::TooLarge | ErrorKind::ResourceLimit => ERROR_SIZE_LIMIT,
+        ErrorKind::NotFound
+        | ErrorKind::Conflict
+        | ErrorKind::Busy
+        | ErrorKind::Unavailable
+        | ErrorKind::Internal => ERROR_UNKNOWN,
+    }
+}
+
+/// Response body of the DocumentServer conversion API
+#[derive(Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ConvertServiceResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    end_convert: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    percent: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<i32>,
+}
+
+impl ConvertServiceResponse {
+    fn error(error_code: i32) -> Self {
+        Self {
+            error: Some(error_code),
+            ..Default::default()
+        }
+    }
+
+    /// Create the response, DocumentServer responds with XML unless JSON
+    /// is accepted
+    fn into_response(self, json: bool) -> Response<Body> {
+        if json {
+            return Json(self).into_response();
+        }
+
+        let mut xml = String::from(r#"<?xml version="1.0" encoding="utf-8"?><FileResult>"#);
+
+        if let Some(error) = self.error {
+            xml.push_str(&format!("<Error>{error}</Error>"));
+        }
+        if let Some(file_url) = &self.file_url {
+            xml.push_str(&format!("<FileUrl>{}</FileUrl>", escape_xml(file_url)));
+        }
+        if let Some(file_type) = self.file_type {
+            xml.push_str(&format!("<FileType>{file_type}</FileType>"));
+        }
+        if let Some(percent) = self.percent {
+            xml.push_str(&format!("<Percent>{percent}</Percent>"));
+        }
+        if let Some(end_convert) = self.end_convert {
+            let end_convert = if end_convert { "True" } else { "False" };
+            xml.push_str(&format!("<EndConvert>{end_convert}</EndConvert>"));
+        }
+
+        xml.push_str("</FileResult>");
+
+        (
+            [(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/xml"),
+            )],
+            xml,
+        )
+            .into_response()
+    }
+}
+
+/// Request body containing a token, used by DocumentServer integrations that
+/// send the token in the body instead of the JWT header
+#[derive(Deserialize)]
+struct BodyToken {
+    token: Option<String>,
+}
+
+/// Whether the request accepts a JSON response instead of XML
+fn accepts_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("application/json"))
+}
+
+/// Middleware authenticating conversion API requests with a token in either
+/// the JWT header or the body. Like DocumentServer, the claims of a body
+/// token are used as the request instead of the rest of the body so the
+/// signed parameters can't be altered
+pub async fn require_convert_service_jwt(
+    State(auth): State<Arc<JwtAuth>>,
+    request: Request,
+    next: Next,
+) -> Response<Body> {
+    if auth
+        .header_token(request.headers())
+        .is_some_and(|token| auth.is_valid(token))
+    {
+        return next.run(request).await;
+    }
+
+    let json = accepts_json(request.headers());
+    let (mut parts, body) = request.into_parts();
+
+    let claims = axum::body::to_bytes(body, MAX_REQUEST_SIZE)
+        .await
+        .ok()
+        .and_then(|body| serde_json::from_slice::<BodyToken>(&body).ok())
+        .and_then(|body| body.token)
+        .and_then(|token| auth.claims(&token));
+
+    let Some(claims) = claims else {
+        tracing::debug!("rejected conversion request without a valid token");
+        return ConvertServiceResponse::error(ERROR_TOKEN).into_response(json);
+    };
+
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let body = Body::from(claims.to_string());
+
+    next.run(Request::from_parts(parts, body)).await
+}
+
+/// POST /ConvertService.ashx
+/// POST /converter
+///
+/// Emulates the DocumentServer conversion API, the file at the provided URL
+/// is converted and a URL to download the result from is responded with.
+/// Errors are reported with DocumentServer error codes
+pub async fn convert_service(
+    Extension(runtime_config): Extension<Arc<RuntimeConfig>>,
+    Extension(store): Extension<Arc<ConvertServiceStore>>,
+    RequestDeadline(deadline): RequestDeadline,
+    queue_ticket: QueueTicket,
+    headers: HeaderMap,
+    Json(request): Json<ConvertServiceRequest>,
+) -> Response<Body> {
+    let json = accepts_json(&headers);
+
+    let is_async = request.is_async;
+    let key = (runtime_config.temp_path.clone(), request.key.clone());
+
+    let mut state = match start_conversion(
+        &runtime_config,
+        &store,
+        queue_ticket,
+        key.clone(),
+        request,
+        deadline,
+    ) {
+        Ok(state) => state,
+        Err(err) => {
+            tracing::debug!(message = err.message, "rejected conversion request");
+            return ConvertServiceResponse::error(error_code(&err)).into_response(json);
+        }
+    };
+
+    if !is_async {
+        // Sender is only dropped when the result expires
+        _ = state
+            .wait_for(|state| *state != ConvertServiceState::Converting)
+            .await;
+    }
+
+    let current_state = *state.borrow();
+    let response = match current_state {
+        ConvertServiceState::Converting => ConvertServiceResponse {
+            end_convert: Some(false),
+            percent: Some(0),
+            ..Default::default()
+        },
+        ConvertServiceState::Completed => {
+            let entries = store.entries.lock().expect("convert service lock poisoned");
+            match entries.get(&key) {
+                Some(entry) => ConvertServiceResponse {
+                    end_convert: Some(true),
+                    file_type: Some(entry.output_format.extension()),
+                    file_url: Some(format!(
+                        "{}/converter/results/{}",
+                        store.public_url,
+                        entry.id.simple()
+                    )),
+                    percent: Some(100),
+                    error: None,
+                },
+                None => ConvertServiceResponse::error(ERROR_UNKNOWN),
+            }
+        }
+        ConvertServiceState::Failed(error_code) => ConvertServiceResponse::error(error_code),
+    };
+
+    response.into_response(json)
+}
+
+/// Start converting the file for a request, requests with the key of an
+/// existing conversion subscribe to that conversion instead. The download
+/// and conversion fail with a timeout once the deadline is reached
+fn start_conversion(
+    runtime_config: &Arc<RuntimeConfig>,
+    store: &Arc<ConvertServiceStore>,
+    queue_ticket: QueueTicket,
+    key: EntryKey,
+    request: ConvertServiceRequest,
+    deadline: Option<Instant>,
+) -> Result<watch::Receiver<ConvertServiceState>, ErrorResponse> {
+    if let Some(entry) = store
+        .entries
+        .lock()
+        .expect("convert service lock poisoned")
+        .get(&key)
+    {
+        return Ok(entry.state.subscribe());
+    }
+
+    let mut options = request.options(runtime_config.default_pdfa)?;
+    options.deadline = deadline;
+
+    let url = reqwest::Url::parse(&request.url).map_err(|_| ErrorResponse {
+        code: None,
+        kind: ErrorKind::InvalidRequest,
+        message: "url must be an absolute http or https url".to_string(),
+        backtrace: None,
+    })?;
+
+    store
+        .url_filter
+        .check_url(&url)
+        .map_err(|message| ErrorResponse {
+            code: None,
+            kind: ErrorKind::InvalidRequest,
+            message,
+            backtrace: None,
+        })?;
+
+    let id = queue_ticket.id();
+    let file_name = request.file_name();
+    let output_format = options.output_format;
+    let output_name = output_file_name(file_name.as_deref(), output_format.extension());
+
+    let state = {
+        let mut entries = store.entries.lock().expect("convert service lock poisoned");
+
+        // Another request with the same key may have started in the meantime
+        if let Some(entry) = entries.get(&key) {
+            return Ok(entry.state.subscribe());
+        }
+
+        let state = watch::Sender::new(ConvertServiceState::Converting);
+        let receiver = state.subscribe();
+
+        entries.insert(
+            key.clone(),
+            ConvertServiceEntry {
+                id,
+                state,
+                output_format,
+                output_name,
+                finished_at: None,
+                result_path: None,
+            },
+        );
+
+        receiver
+    };
+
+    tokio::spawn({
+        let runtime_config = runtime_config.clone();
+        let store = store.clone();
+
+        async move {
+            let conversion = run_conversion(
+                &runtime_config,
+                &store,
+                queue_ticket,
+                &request.url,
+                file_name.as_deref(),
+                &options,
+            );
+
+            // Deadline also covers downloading the file
+            let result = match options.deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline.into(), conversion)
+                    .await
+                    .unwrap_or(Err(ERROR_TIMEOUT)),
+                None => conversion.await,
+            };
+
+            match result {
+                Ok(result_path) => store.set_completed(&key, result_path),
+                Err(error_code) => store.set_failed(&key, error_code),
+            }
+        }
+    });
+
+    Ok(state)
+}
+
+/// Download and convert the file, persisting the result until it expires.
+/// Fails with the DocumentServer error code
+async fn run_conversion(
+    runtime_config: &RuntimeConfig,
+    store: &ConvertServiceStore,
+    queue_ticket: QueueTicket,
+    url: &str,
+    file_name: Option<&str>,
+    options: &ConvertOptions,
+) -> Result<PathBuf, i32> {
+    let id = queue_ticket.id();
+    let mut temp_paths = create_convert_temp_paths(runtime_config)
+        .await
+        .map_err(|err| error_code(&err))?;
+
+    let size = {
+        let file = tokio::fs::File::create(&temp_paths.input_path)
+            .await
+            .map_err(|err| {
+                tracing::error!(?err, "failed to create input file");
+                ERROR_UNKNOWN
+            })?;
+
+        let mut reservation = WriteReservation::new(temp_paths.scratch());
+        let mut writer = BufWriter::new(file);
+        let mut size: u64 = 0;
+
+        let response = store
+            .http
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| {
+                tracing::warn!(?err, "failed to download file for conversion");
+                ERROR_DOWNLOAD
+            })?;
+
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|err| {
+                tracing::warn!(?err, "failed to download file for conversion");
+                ERROR_DOWNLOAD
+            })?;
+
+            size += chunk.len() as u64;
+            if size > MAX_DOWNLOAD_SIZE {
+                return Err(ERROR_SIZE_LIMIT);
+            }
+
+            reservation
+                .write(chunk.len() as u64)
+                .map_err(|err| error_code(&err))?;
+
+            writer.write_all(&chunk).await.map_err(|err| {
+                tracing::error!(?err, "failed to write input file");
+                ERROR_UNKNOWN
+            })?;
+        }
+
+        writer.flush().await.map_err(|err| {
+            tracing::error!(?err, "failed to write input file");
+            ERROR_UNKNOWN
+        })?;
+
+        size
+    };
+
+    tracing::debug!(size, "downloaded file for conversion");
+    queue_ticket.set_input_size(size);
+
+    let result = async {
+        temp_paths
+            .resolve_input_extension(runtime_config, options.input_format.as_deref(), file_name)
+            .await?;
+
+        // Wait for a free conversion slot
+        let _permit = queue_ticket
+            .acquire_before(options.priority, options.deadline)
+            .await?;
+
+        let output_file = convert_file(runtime_config, &temp_paths, options).await?;
+
+        let result_path = runtime_config.temp_path.join(format!(
+            "convert_service_result_{}.{}",
+            id.simple(),
+            options.output_format.extension()
+        ));
+
+        output_file.persist(&result_path).await.map_err(|err| {
+            tracing::error!(?err, "failed to write conversion result");
+            ErrorResponse {
+                code: None,
+                kind: ErrorKind::Internal,
+                message: "failed to write conversion result".to_string(),
+                backtrace: error_backtrace(&err),
+            }
+        })?;
+
+        Ok::<_, ErrorResponse>(result_path)
+    }
+    .await;
+
+    result.map_err(|err| error_code(&err))
+}
+
+/// GET /converter/results/:id
+///
+/// Responds with the converted file of a completed conversion, the
+/// unguessable ID of the result is the only credential required
+pub async fn get_convert_service_result(
+    Extension(store): Extension<Arc<ConvertServiceStore>>,
+    Path(id): Path<Uuid>,
+) -> Result<Response<Body>, (StatusCode, ErrorResponse)> {
+    let (result_path, output_format, output_name) = {
+        let entries = store.entries.lock().expect("convert service lock poisoned");
+        entries
+            .values()
+            .find(|entry| entry.id == id)
+            .and_then(|entry| {
+                let result_path = entry.result_path.clone()?;
+                Some((result_path, entry.output_format, entry.output_name.clone()))
+            })
+            .ok_or_else(|| {
+                (
+                    StatusCode::NOT_FOUND,
+                    ErrorResponse {
+                        code: None,
+                        kind: ErrorKind::NotFound,
+                        message: "conversion result not found".to_string(),
+                        backtrace: None,
+                    },
+                )
+            })?
+    };
+
+    let file = tokio::fs::File::open(&result_path).await.map_err(|err| {
+        tracing::error!(?err, "failed to open conversion result");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorResponse {
+                code: None,
+                kind: ErrorKind::Internal,
+                message: "failed to read conversion result".to_string(),
+                backtrace: error_backtrace(&err),
+            },
+        )
+    })?;
+
+    let mut response = Response::builder().header(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(output_format.content_type()),
+    );
+
+    if let Some(policy) = output_format.content_security_policy() {
+        response = response.header(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static(policy),
+        );
+    }
+
+    response
+        .header(header::CONTENT_DISPOSITION, attachment(&output_name))
+        .body(Body::from_stream(ReaderStream::new(file)))
+        .map_err(|err| {
+            tracing::error!(?err, "failed to make response");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::Internal,
+                    message: "failed to make response".to_string(),
+                    backtrace: error_backtrace(&err),
+                },
+            )
+        })
+}
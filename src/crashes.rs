@@ -0,0 +1,230 @@
+use axum::{Extension, Json};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+use crate::{ErrorKind, ErrorResponse, RuntimeConfig, error_backtrace};
+
+/// Default number of seconds crash reports are kept for, 7 days
+pub const DEFAULT_CRASH_RETENTION: u64 = 60 * 60 * 24 * 7;
+
+/// Default maximum number of crash reports that are kept
+pub const DEFAULT_MAX_CRASH_REPORTS: usize = 50;
+
+/// Name of the file describing a crash within its report directory
+const REPORT_FILE_NAME: &str = "report.json";
+
+/// Preserves the artifacts of x2t runs that crashed (input file, config XML
+/// and stderr) so converter bugs can be reproduced. Each crash is stored in
+/// its own directory, the oldest reports are removed once they are older than
+/// the retention period or there are more than the maximum number of reports
+pub struct CrashReports {
+    /// Directory the reports are stored in
+    path: PathBuf,
+    /// Duration reports are kept for
+    retention: Duration,
+    /// Maximum number of reports that are kept
+    max_reports: usize,
+}
+
+/// Details of a preserved crash
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CrashReport {
+    /// Unique ID of the report, the name of its directory
+    id: String,
+    /// Unix timestamp of when the crash happened
+    created_at: u64,
+    /// Exit status of the crashed x2t process
+    exit_status: String,
+    /// Name of the preserved input file
+    input_file: String,
+    /// Size of the input file in bytes
+    input_size: u64,
+    /// Path to the directory containing the artifacts
+    path: String,
+}
+
+impl CrashReports {
+    pub fn new(path: PathBuf, retention: Duration, max_reports: usize) -> Self {
+        Self {
+            path,
+            retention,
+            max_reports,
+        }
+    }
+
+    /// Preserve the artifacts of a crashed x2t run, failures are logged as
+    /// the conversion has already failed
+    ///
+    /// ## Arguments
+    /// * `input_path` - Path to the input file x2t was run with
+    /// * `config` - Config XML x2t was run with, passwords must already be redacted
+    /// * `status` - Exit status of the crashed x2t process
+    /// * `stderr` - Output x2t wrote to stderr
+    pub async fn capture(
+        &self,
+        input_path: &Path,
+        config: &str,
+        status: &ExitStatus,
+        stderr: &str,
+    ) {
+        match self.write_report(input_path, config, status, stderr).await {
+            Ok(report) => tracing::info!(id = report.id, "preserved x2t crash artifacts"),
+            Err(err) => tracing::error!(?err, "failed to preserve x2t crash artifacts"),
+        }
+
+        if let Err(err) = self.prune().await {
+            tracing::error!(?err, "failed to remove old crash reports");
+        }
+    }
+
+    async fn write_report(
+        &self,
+        input_path: &Path,
+        config: &str,
+        status: &ExitStatus,
+        stderr: &str,
+    ) -> std::io::Result<CrashReport> {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let id = format!("{}-{}", created_at.as_secs(), Uuid::new_v4().simple());
+
+        let report_path = self.path.join(&id);
+        tokio::fs::create_dir_all(&report_path).await?;
+
+        let input_file = input_path
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "input".to_string());
+        let input_size = tokio::fs::copy(input_path, report_path.join(&input_file)).await?;
+
+        tokio::fs::write(report_path.join("config.xml"), config).await?;
+        tokio::fs::write(report_path.join("stderr.txt"), stderr).await?;
+
+        let report = CrashReport {
+            id,
+            created_at: created_at.as_secs(),
+            exit_status: status.to_string(),
+            input_file,
+            input_size,
+            path: report_path.display().to_string(),
+        };
+
+        // Report is written last, directories without one are incomplete
+        let report_json = serde_json::to_vec_pretty(&report).map_err(std::io::Error::other)?;
+        tokio::fs::write(report_path.join(REPORT_FILE_NAME), report_json).await?;
+
+        Ok(report)
+    }
+
+    /// List the preserved crashes, newest first
+    pub async fn list(&self) -> std::io::Result<Vec<CrashReport>> {
+        let mut reports = Vec::new();
+
+        let mut entries = match tokio::fs::read_dir(&self.path).await {
+            Ok(entries) => entries,
+            // Directory is created by the first crash
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(reports),
+            Err(err) => return Err(err),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            let report = match tokio::fs::read(entry.path().join(REPORT_FILE_NAME)).await {
+                Ok(report) => report,
+                // Report is still being written or the entry isn't a report
+                Err(_) => continue,
+            };
+
+            match serde_json::from_slice::<CrashReport>(&report) {
+                Ok(report) => reports.push(report),
+                Err(err) => tracing::warn!(?err, path = ?entry.path(), "invalid crash report"),
+            }
+        }
+
+        reports.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(b.id.cmp(&a.id)));
+
+        Ok(reports)
+    }
+
+    /// Remove reports older than the retention period and the oldest reports
+    /// beyond the maximum number of reports
+    async fn prune(&self) -> std::io::Result<()> {
+        let mut entries = tokio::fs::read_dir(&self.path).await?;
+        let now = SystemTime::now();
+        let mut reports = Vec::new();
+
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = match entry.metadata().await {
+                Ok(metadata) => metadata,
+                // Entry was removed while pruning
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+
+            if !metadata.is_dir() {
+                continue;
+            }
+
+            let age = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .unwrap_or_default();
+
+            reports.push((age, entry.path()));
+        }
+
+        // Newest reports first
+        reports.sort_by_key(|(age, _)| *age);
+
+        for (index, (age, path)) in reports.into_iter().enumerate() {
+            if index < self.max_reports && age < self.retention {
+                continue;
+            }
+
+            if let Err(err) = tokio::fs::remove_dir_all(&path).await
+                && err.kind() != std::io::ErrorKind::NotFound
+            {
+                tracing::warn!(?err, ?path, "failed to remove crash report");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+pub struct CrashesResponse {
+    crashes: Vec<CrashReport>,
+}
+
+/// GET /admin/crashes
+///
+/// List the preserved artifacts of x2t crashes, newest first
+pub async fn list_crashes(
+    Extension(runtime_config): Extension<Arc<RuntimeConfig>>,
+) -> Result<Json<CrashesResponse>, ErrorResponse> {
+    let Some(crash_reports) = &runtime_config.crash_reports else {
+        return Ok(Json(CrashesResponse {
+            crashes: Vec::new(),
+        }));
+    };
+
+    let crashes = crash_reports.list().await.map_err(|err| {
+        tracing::error!(?err, "failed to list crash reports");
+        ErrorResponse {
+            code: None,
+            kind: ErrorKind::Internal,
+            message: "failed to list crash reports".to_string(),
+            backtrace: error_backtrace(&err),
+        }
+    })?;
+
+    Ok(Json(CrashesResponse { crashes }))
+}
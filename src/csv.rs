@@ -0,0 +1,111 @@
+use crate::{ErrorKind, ErrorResponse, convert::escape_xml};
+
+/// Delimiter used when reading CSV/TXT input files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CsvDelimiter {
+    Tab,
+    Semicolon,
+    Colon,
+    Comma,
+    Space,
+    /// Any other single character delimiter
+    Custom(char),
+}
+
+impl CsvDelimiter {
+    /// Parse a delimiter from its name (i.e "semicolon") or the delimiter
+    /// character itself (i.e ";")
+    pub fn from_name(name: &str) -> Option<CsvDelimiter> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "tab" | "\t" => CsvDelimiter::Tab,
+            "semicolon" | ";" => CsvDelimiter::Semicolon,
+            "colon" | ":" => CsvDelimiter::Colon,
+            "comma" | "," => CsvDelimiter::Comma,
+            "space" | " " => CsvDelimiter::Space,
+            _ => {
+                let mut chars = name.chars();
+                let value = chars.next()?;
+                if chars.next().is_some() {
+                    return None;
+                }
+
+                CsvDelimiter::Custom(value)
+            }
+        })
+    }
+
+    /// The x2t delimiter code (m_nCsvDelimiter) for this delimiter
+    fn x2t_code(&self) -> u32 {
+        match self {
+            CsvDelimiter::Tab => 1,
+            CsvDelimiter::Semicolon => 2,
+            CsvDelimiter::Colon => 3,
+            CsvDelimiter::Comma => 4,
+            CsvDelimiter::Space => 5,
+            // Custom delimiters use "none" with the character provided separately
+            CsvDelimiter::Custom(_) => 0,
+        }
+    }
+}
+
+/// Options for reading CSV/TXT input files
+#[derive(Debug, Default)]
+pub struct CsvOptions {
+    /// Delimiter separating values, x2t detects it when not provided
+    pub delimiter: Option<CsvDelimiter>,
+    /// x2t codepage identifier for the input encoding (i.e 46 for UTF-8)
+    pub codepage: Option<u32>,
+}
+
+impl CsvOptions {
+    /// Create the CSV options from the requested parameters
+    pub fn from_params(
+        delimiter: Option<String>,
+        codepage: Option<u32>,
+    ) -> Result<CsvOptions, ErrorResponse> {
+        let delimiter = match delimiter {
+            Some(value) => Some(
+                CsvDelimiter::from_name(&value).ok_or_else(|| ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::InvalidRequest,
+                    message: format!("unsupported csv delimiter \"{value}\""),
+                    backtrace: None,
+                })?,
+            ),
+            None => None,
+        };
+
+        Ok(CsvOptions {
+            delimiter,
+            codepage,
+        })
+    }
+
+    /// Additional x2t config elements for these options, the detected
+    /// codepage is used when no codepage was requested
+    pub fn config(&self, detected_codepage: Option<u32>) -> String {
+        let mut config = String::new();
+
+        if let Some(delimiter) = &self.delimiter {
+            config.push_str(&format!(
+                "<m_nCsvDelimiter>{}</m_nCsvDelimiter>",
+                delimiter.x2t_code()
+            ));
+
+            if let CsvDelimiter::Custom(value) = delimiter {
+                config.push_str(&format!(
+                    "<m_nCsvDelimiterChar>{}</m_nCsvDelimiterChar>",
+                    escape_xml(&value.to_string())
+                ));
+            }
+        }
+
+        if let Some(codepage) = self.codepage.or(detected_codepage) {
+            config.push_str(&format!(
+                "<m_nCsvTxtEncoding>{codepage}</m_nCsvTxtEncoding>"
+            ));
+        }
+
+        config
+    }
+}
@@ -0,0 +1,86 @@
+use axum::{
+    Json, async_trait,
+    extract::FromRequestParts,
+    http::{HeaderMap, StatusCode, request::Parts},
+};
+use std::time::{Duration, Instant};
+
+use crate::{ErrorKind, ErrorResponse};
+
+/// Header containing the number of milliseconds the caller is willing to
+/// wait for the conversion
+const DEADLINE_HEADER: &str = "x-deadline-ms";
+
+/// Standard header containing the number of seconds the caller is willing to
+/// wait for the conversion, used when [DEADLINE_HEADER] isn't provided
+const REQUEST_TIMEOUT_HEADER: &str = "request-timeout";
+
+/// Point in time the caller needs the conversion to have finished by,
+/// conversions that can't start before the deadline are rejected and x2t is
+/// killed if it is still running once the deadline is reached
+#[derive(Debug, Clone, Copy)]
+pub struct RequestDeadline(pub Option<Instant>);
+
+impl RequestDeadline {
+    /// Read the deadline from the headers of a request received at the
+    /// provided point in time
+    pub fn from_headers(headers: &HeaderMap, received_at: Instant) -> Result<Self, ErrorResponse> {
+        let Some((name, value)) = [DEADLINE_HEADER, REQUEST_TIMEOUT_HEADER]
+            .into_iter()
+            .find_map(|name| headers.get(name).map(|value| (name, value)))
+        else {
+            return Ok(RequestDeadline(None));
+        };
+
+        let remaining = value
+            .to_str()
+            .ok()
+            .and_then(|value| parse_remaining(name, value))
+            .ok_or_else(|| ErrorResponse {
+                code: None,
+                kind: ErrorKind::InvalidRequest,
+                message: format!("invalid {name} header"),
+                backtrace: None,
+            })?;
+
+        Ok(RequestDeadline(received_at.checked_add(remaining)))
+    }
+}
+
+/// Error responded with when a conversion misses the deadline of the request
+pub fn deadline_exceeded(message: &str) -> ErrorResponse {
+    ErrorResponse {
+        code: None,
+        kind: ErrorKind::Timeout,
+        message: message.to_string(),
+        backtrace: None,
+    }
+}
+
+/// Parse the time remaining until the deadline from the value of the
+/// [DEADLINE_HEADER] (milliseconds) or [REQUEST_TIMEOUT_HEADER] (seconds)
+fn parse_remaining(name: &str, value: &str) -> Option<Duration> {
+    let value = value.trim();
+
+    if name == DEADLINE_HEADER {
+        return value.parse::<u64>().ok().map(Duration::from_millis);
+    }
+
+    value
+        .parse::<f64>()
+        .ok()
+        .and_then(|value| Duration::try_from_secs_f64(value).ok())
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RequestDeadline
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, Json<ErrorResponse>);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        RequestDeadline::from_headers(&parts.headers, Instant::now())
+            .map_err(|err| (StatusCode::BAD_REQUEST, Json(err)))
+    }
+}
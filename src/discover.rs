@@ -0,0 +1,93 @@
+use std::path::{Path, PathBuf};
+
+use crate::convert::X2T_BIN;
+
+/// Default DocumentServer x2t install on Linux
+const LINUX_X2T_PATH: &str = "/var/www/onlyoffice/documentserver/server/FileConverter/bin";
+
+/// Default DocumentServer fonts directory on Linux, also used when no fonts
+/// directory could be found
+const LINUX_FONTS_PATH: &str = "/var/www/onlyoffice/documentserver/fonts";
+
+/// ONLYOFFICE Desktop Editors converter directory on macOS
+const MACOS_X2T_PATH: &str = "/Applications/ONLYOFFICE.app/Contents/Resources/converter";
+
+/// DocumentServer install directory relative to the Windows program files directory
+const WINDOWS_DOCUMENT_SERVER_PATH: &str = r"ONLYOFFICE\DocumentServer";
+
+/// Program files directory used when the environment doesn't provide one
+const WINDOWS_PROGRAM_FILES: &str = r"C:\Program Files";
+
+/// Find the x2t install directory, checking the default install locations
+/// for the current platform and then the directories on the PATH
+pub fn discover_x2t_path() -> Option<PathBuf> {
+    let path_dirs = std::env::var_os("PATH")
+        .map(|value| std::env::split_paths(&value).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    x2t_install_dirs()
+        .into_iter()
+        .chain(path_dirs)
+        .find(|path| path.join(X2T_BIN).is_file())
+}
+
+/// Find the fonts directory for the x2t install at `x2t_path`, falls back
+/// to the default Linux DocumentServer fonts directory
+pub fn discover_fonts_path(x2t_path: Option<&Path>) -> PathBuf {
+    // DocumentServer keeps its fonts three levels above the converter
+    // (documentserver/server/FileConverter/bin)
+    let install_fonts = x2t_path
+        .and_then(|path| path.ancestors().nth(3))
+        .map(|path| path.join("fonts"));
+
+    install_fonts
+        .into_iter()
+        .chain(fonts_dirs())
+        .find(|path| path.is_dir())
+        .unwrap_or_else(|| PathBuf::from(LINUX_FONTS_PATH))
+}
+
+/// Directories x2t is installed to by default on the current platform
+fn x2t_install_dirs() -> Vec<PathBuf> {
+    if cfg!(windows) {
+        windows_document_server_dirs()
+            .into_iter()
+            .map(|path| path.join(r"server\FileConverter\bin"))
+            .collect()
+    } else if cfg!(target_os = "macos") {
+        vec![PathBuf::from(MACOS_X2T_PATH)]
+    } else {
+        vec![PathBuf::from(LINUX_X2T_PATH)]
+    }
+}
+
+/// Directories fonts are installed to by default on the current platform
+fn fonts_dirs() -> Vec<PathBuf> {
+    if cfg!(windows) {
+        windows_document_server_dirs()
+            .into_iter()
+            .map(|path| path.join("fonts"))
+            .collect()
+    } else {
+        vec![PathBuf::from(LINUX_FONTS_PATH)]
+    }
+}
+
+/// DocumentServer install directories within the Windows program files directories
+fn windows_document_server_dirs() -> Vec<PathBuf> {
+    let program_files = ["ProgramFiles", "ProgramW6432", "ProgramFiles(x86)"]
+        .into_iter()
+        .filter_map(std::env::var_os)
+        .map(PathBuf::from);
+
+    let mut dirs: Vec<PathBuf> = Vec::new();
+
+    for path in program_files.chain([PathBuf::from(WINDOWS_PROGRAM_FILES)]) {
+        let path = path.join(WINDOWS_DOCUMENT_SERVER_PATH);
+        if !dirs.contains(&path) {
+            dirs.push(path);
+        }
+    }
+
+    dirs
+}
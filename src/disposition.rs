@@ -0,0 +1,74 @@
+use axum::http::HeaderValue;
+use std::fmt::Write;
+
+/// Name used for converted files when the upload didn't provide a usable name
+const DEFAULT_FILE_STEM: &str = "converted";
+
+/// Maximum number of characters kept from an uploaded file name
+const MAX_FILE_NAME_LENGTH: usize = 200;
+
+/// Sanitize a file name provided by a client, only the final path component
+/// is kept and control characters and path separators are removed. Returns
+/// [None] when nothing usable remains
+pub fn sanitize_file_name(file_name: &str) -> Option<String> {
+    // Clients may send full paths from either Windows or Unix systems
+    let file_name = file_name.rsplit(['/', '\\']).next().unwrap_or(file_name);
+
+    let sanitized: String = file_name
+        .chars()
+        .filter(|value| !value.is_control())
+        .take(MAX_FILE_NAME_LENGTH)
+        .collect();
+    let sanitized = sanitized.trim().trim_start_matches('.');
+
+    if sanitized.is_empty() {
+        return None;
+    }
+
+    Some(sanitized.to_string())
+}
+
+/// Name for a converted file, the extension of the original file name is
+/// replaced with the extension of the output format
+pub fn output_file_name(file_name: Option<&str>, extension: &str) -> String {
+    let stem = file_name
+        .map(|file_name| match file_name.rsplit_once('.') {
+            Some((stem, _)) if !stem.is_empty() => stem,
+            _ => file_name,
+        })
+        .unwrap_or(DEFAULT_FILE_STEM);
+
+    format!("{stem}.{extension}")
+}
+
+/// Create a Content-Disposition header value for downloading a converted
+/// file as an attachment
+///
+/// Non ASCII names are provided through the RFC 5987 `filename*` parameter
+/// with an ASCII fallback for clients that don't support it
+pub fn attachment(file_name: &str) -> HeaderValue {
+    let fallback: String = file_name
+        .chars()
+        .map(|value| match value {
+            '"' | '\\' => '_',
+            value if value.is_ascii() && !value.is_ascii_control() => value,
+            _ => '_',
+        })
+        .collect();
+
+    let mut value = format!("attachment; filename=\"{fallback}\"");
+
+    if !file_name.is_ascii() {
+        value.push_str("; filename*=UTF-8''");
+        for byte in file_name.bytes() {
+            if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+                value.push(byte as char);
+            } else {
+                _ = write!(value, "%{byte:02X}");
+            }
+        }
+    }
+
+    HeaderValue::from_str(&value)
+        .unwrap_or_else(|_| HeaderValue::from_static("attachment; filename=\"converted\""))
+}
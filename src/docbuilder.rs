@@ -0,0 +1,374 @@
+use axum::{
+    Extension,
+    body::Body,
+    extract::{Multipart, RawQuery},
+    http::{HeaderValue, Response, header},
+};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::process::Command;
+
+use crate::{
+    ErrorKind, ErrorResponse, RuntimeConfig,
+    auth::AdminAccess,
+    convert::{convert_file, create_convert_temp_paths},
+    deadline::{RequestDeadline, deadline_exceeded},
+    disposition::{attachment, output_file_name},
+    error_backtrace,
+    limiter::QueueTicket,
+    limits::ProcessLimits,
+    upload::read_raw_convert_upload,
+};
+
+/// Maximum time docbuilder can take to run a script
+const DOCBUILDER_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Name of the field containing a raw docbuilder script
+const SCRIPT_FIELD: &str = "script";
+
+/// Name of the field containing a JSON transform
+const TRANSFORM_FIELD: &str = "transform";
+
+/// Input formats docbuilder opens as text documents
+const DOCUMENT_FORMATS: &[&str] = &[
+    "doc", "docx", "docm", "dot", "dotx", "dotm", "docxf", "oform", "odt", "ott", "rtf", "txt",
+    "html", "htm", "epub", "fb2", "mht",
+];
+
+/// Input formats docbuilder opens as spreadsheets
+const SPREADSHEET_FORMATS: &[&str] = &[
+    "xls", "xlsx", "xlsm", "xlt", "xltx", "xltm", "ods", "ots", "csv",
+];
+
+/// Input formats docbuilder opens as presentations
+const PRESENTATION_FORMATS: &[&str] = &[
+    "ppt", "pptx", "pptm", "pps", "ppsx", "ppsm", "pot", "potx", "potm", "odp", "otp",
+];
+
+/// Constrained description of changes to make to a text document, allows
+/// templating without the admin token required for raw scripts
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DocBuilderTransform {
+    /// Text to replace mapped to its replacement (i.e "{{name}}" to "Jane")
+    #[serde(default)]
+    replace: BTreeMap<String, String>,
+    /// Paragraphs of text to append to the end of the document
+    #[serde(default)]
+    append_paragraphs: Vec<String>,
+}
+
+impl DocBuilderTransform {
+    /// Create the docbuilder script that applies the transform
+    fn script(&self) -> String {
+        let mut script = String::from("var oDocument = Api.GetDocument();\n");
+
+        for (search, replace) in &self.replace {
+            _ = writeln!(
+                script,
+                "oDocument.SearchAndReplace({{\"searchString\": {}, \"replaceString\": {}, \"matchCase\": true}});",
+                js_string(search),
+                js_string(replace)
+            );
+        }
+
+        for text in &self.append_paragraphs {
+            _ = writeln!(
+                script,
+                "var oParagraph = Api.CreateParagraph();\noParagraph.AddText({});\noDocument.Push(oParagraph);",
+                js_string(text)
+            );
+        }
+
+        script
+    }
+}
+
+/// Runs ONLYOFFICE docbuilder scripts against uploaded documents
+#[derive(Debug)]
+pub struct DocBuilder {
+    /// Path to the docbuilder binary
+    docbuilder_path: PathBuf,
+}
+
+impl DocBuilder {
+    pub fn new(docbuilder_path: PathBuf) -> Self {
+        Self { docbuilder_path }
+    }
+
+    /// Run the script against the input file, the modified document is saved
+    /// next to the input as an OOXML file whose path is returned
+    ///
+    /// ## Arguments
+    /// * `input_path` - Path to the document to open
+    /// * `script` - Script run with the document open
+    /// * `limits` - Resource limits applied to the docbuilder process
+    pub async fn run(
+        &self,
+        input_path: &Path,
+        script: &str,
+        limits: &ProcessLimits,
+    ) -> Result<PathBuf, ErrorResponse> {
+        let input_format = input_path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+        let save_format = save_format(input_format).ok_or_else(|| ErrorResponse {
+            code: None,
+            kind: ErrorKind::UnsupportedFormat,
+            message: format!("docbuilder can't open {input_format} files"),
+            backtrace: None,
+        })?;
+
+        let script_path = input_path.with_file_name("script.docbuilder");
+        let output_path = input_path.with_file_name(format!("built.{save_format}"));
+
+        let script = format!(
+            "builder.OpenFile({}, \"\");\n{script}\nbuilder.SaveFile({}, {});\nbuilder.CloseFile();\n",
+            js_string(&input_path.to_string_lossy()),
+            js_string(save_format),
+            js_string(&output_path.to_string_lossy()),
+        );
+
+        tokio::fs::write(&script_path, script)
+            .await
+            .map_err(|err| {
+                tracing::error!(?err, "failed to write docbuilder script");
+                ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::Internal,
+                    message: "failed to write docbuilder script".to_string(),
+                    backtrace: error_backtrace(&err),
+                }
+            })?;
+
+        let mut command = Command::new(&self.docbuilder_path);
+        command.arg(&script_path).kill_on_drop(true);
+        limits.apply(&mut command);
+
+        let output = tokio::time::timeout(DOCBUILDER_TIMEOUT, command.output())
+            .await
+            .map_err(|_| ErrorResponse {
+                code: None,
+                kind: ErrorKind::Timeout,
+                message: "docbuilder script took too long".to_string(),
+                backtrace: None,
+            })?
+            .map_err(|err| {
+                tracing::error!(?err, "failed to run docbuilder");
+                ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::Internal,
+                    message: "failed to run docbuilder".to_string(),
+                    backtrace: error_backtrace(&err),
+                }
+            })?;
+
+        // docbuilder reports script errors on stderr, a missing output file is
+        // the only reliable sign that the script failed
+        if !output.status.success() || !tokio::fs::try_exists(&output_path).await.unwrap_or(false) {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            tracing::warn!(status = %output.status, stderr = %stderr.trim(), "docbuilder script failed");
+
+            return Err(ErrorResponse {
+                code: None,
+                kind: ErrorKind::ConversionFailed,
+                message: "docbuilder failed to run the script".to_string(),
+                backtrace: None,
+            });
+        }
+
+        Ok(output_path)
+    }
+}
+
+/// OOXML format docbuilder saves a document of the provided input format as
+fn save_format(input_format: &str) -> Option<&'static str> {
+    if DOCUMENT_FORMATS.contains(&input_format) {
+        Some("docx")
+    } else if SPREADSHEET_FORMATS.contains(&input_format) {
+        Some("xlsx")
+    } else if PRESENTATION_FORMATS.contains(&input_format) {
+        Some("pptx")
+    } else {
+        None
+    }
+}
+
+/// Whether docbuilder opens the file as a text document
+pub fn is_document(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| DOCUMENT_FORMATS.contains(&extension))
+}
+
+/// Whether docbuilder opens the file as a presentation
+pub fn is_presentation(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| PRESENTATION_FORMATS.contains(&extension))
+}
+
+/// Encode a value as a JavaScript string literal
+fn js_string(value: &str) -> String {
+    serde_json::to_string(value).unwrap_or_else(|_| "\"\"".to_string())
+}
+
+/// POST /docbuilder
+///
+/// Runs a docbuilder script against the uploaded document before converting
+/// it. Either a raw script (requires the admin token) or a JSON transform of
+/// text replacements can be provided
+pub async fn convert_docbuilder(
+    Extension(runtime_config): Extension<Arc<RuntimeConfig>>,
+    admin_access: AdminAccess,
+    RequestDeadline(deadline): RequestDeadline,
+    queue_ticket: QueueTicket,
+    RawQuery(query): RawQuery,
+    multipart: Multipart,
+) -> Result<Response<Body>, ErrorResponse> {
+    let docbuilder = runtime_config
+        .docbuilder
+        .clone()
+        .ok_or_else(|| ErrorResponse {
+            code: None,
+            kind: ErrorKind::Unavailable,
+            message: "docbuilder is not configured".to_string(),
+            backtrace: None,
+        })?;
+
+    let mut temp_paths = create_convert_temp_paths(&runtime_config).await?;
+
+    let mut upload = read_raw_convert_upload(query, multipart, &temp_paths).await?;
+    let script = upload.take_param(SCRIPT_FIELD);
+    let transform = upload.take_param(TRANSFORM_FIELD);
+    let upload = upload.into_upload()?;
+
+    tracing::debug!(size = upload.size, "received file for docbuilder");
+    queue_ticket.set_input_size(upload.size);
+
+    let mut options = upload.fields.into_options(runtime_config.default_pdfa)?;
+    admin_access.authorize(&options)?;
+    options.deadline = deadline;
+
+    // Raw scripts can read and write any file docbuilder has access to
+    let (script, is_transform) = match (script, transform) {
+        (Some(script), None) => {
+            if !admin_access.is_admin() {
+                return Err(ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::Unauthorized,
+                    message: "docbuilder scripts require the admin token".to_string(),
+                    backtrace: None,
+                });
+            }
+
+            (script, false)
+        }
+        (None, Some(transform)) => {
+            let transform: DocBuilderTransform =
+                serde_json::from_str(&transform).map_err(|err| ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::InvalidRequest,
+                    message: format!("invalid transform: {err}"),
+                    backtrace: None,
+                })?;
+
+            (transform.script(), true)
+        }
+        _ => {
+            return Err(ErrorResponse {
+                code: None,
+                kind: ErrorKind::InvalidRequest,
+                message: "either a script or a transform must be provided".to_string(),
+                backtrace: None,
+            });
+        }
+    };
+
+    temp_paths
+        .resolve_input_extension(
+            &runtime_config,
+            options.input_format.as_deref(),
+            upload.file_name.as_deref(),
+        )
+        .await?;
+
+    // Transforms use the text document API
+    if is_transform && !is_document(&temp_paths.input_path) {
+        return Err(ErrorResponse {
+            code: None,
+            kind: ErrorKind::UnsupportedFormat,
+            message: "transforms can only be applied to text documents".to_string(),
+            backtrace: None,
+        });
+    }
+
+    let output_format = options.output_format;
+    let output_name = output_file_name(upload.file_name.as_deref(), output_format.extension());
+
+    // Wait for a free conversion slot, docbuilder and x2t run within the same slot
+    let _permit = queue_ticket
+        .acquire_before(options.priority, options.deadline)
+        .await?;
+
+    let limits: ProcessLimits = *runtime_config
+        .x2t_limits
+        .read()
+        .expect("x2t limits lock poisoned");
+
+    let run = docbuilder.run(&temp_paths.input_path, &script, &limits);
+
+    // Dropping the run kills docbuilder when the deadline is reached
+    temp_paths.input_path = match options.deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline.into(), run)
+            .await
+            .map_err(|_| {
+                deadline_exceeded("docbuilder didn't finish before the request deadline")
+            })??,
+        None => run.await?,
+    };
+
+    let output_file = convert_file(&runtime_config, &temp_paths, &options).await?;
+
+    let body = output_file.into_body().await.map_err(|err| {
+        tracing::error!(?err, "failed to open output file");
+        ErrorResponse {
+            code: None,
+            kind: ErrorKind::Internal,
+            message: "failed to read output".to_string(),
+            backtrace: error_backtrace(&err),
+        }
+    })?;
+
+    let mut response = Response::builder().header(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(output_format.content_type()),
+    );
+
+    if let Some(policy) = output_format.content_security_policy() {
+        response = response.header(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static(policy),
+        );
+    }
+
+    response
+        .header(header::CONTENT_DISPOSITION, attachment(&output_name))
+        .body(body)
+        .map_err(|err| {
+            tracing::error!(?err, "failed to make response");
+            ErrorResponse {
+                code: None,
+                kind: ErrorKind::Internal,
+                message: "failed to make response".to_string(),
+                backtrace: error_backtrace(&err),
+            }
+        })
+}
@@ -0,0 +1,261 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    convert::X2T_BIN,
+    limits::prepend_library_path,
+    readiness::{check_fonts, check_x2t},
+    startup::check_temp_dir,
+};
+
+/// Files generated by allfontsgen that x2t reads the available fonts from,
+/// stored in the x2t install directory
+const FONT_CACHE_FILES: &[&str] = &["AllFonts.js", "font_selection.bin"];
+
+/// Outcome of a single diagnostic check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CheckStatus {
+    Ok,
+    /// Check couldn't be completed or found a likely problem
+    Warn,
+    Fail,
+}
+
+impl CheckStatus {
+    fn label(&self) -> &'static str {
+        match self {
+            CheckStatus::Ok => "ok",
+            CheckStatus::Warn => "warn",
+            CheckStatus::Fail => "fail",
+        }
+    }
+}
+
+struct DoctorCheck {
+    name: &'static str,
+    status: CheckStatus,
+    /// Details about the outcome of the check
+    message: String,
+    /// Suggested fix when the check didn't pass
+    fix: Option<String>,
+}
+
+impl DoctorCheck {
+    fn ok(name: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Ok,
+            message: message.into(),
+            fix: None,
+        }
+    }
+
+    fn problem(
+        name: &'static str,
+        status: CheckStatus,
+        message: impl Into<String>,
+        fix: impl Into<String>,
+    ) -> Self {
+        Self {
+            name,
+            status,
+            message: message.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+/// Check the x2t install, fonts and temporary directory for common problems,
+/// printing the result of each check along with suggested fixes. Returns
+/// whether every check passed
+///
+/// ## Arguments
+/// * `x2t_path` - Path to the x2t install, None when no install was found
+/// * `fonts_path` - Path to the fonts directory
+/// * `temp_path` - Path to the temporary directory
+pub async fn run_doctor(x2t_path: Option<&Path>, fonts_path: &Path, temp_path: &Path) -> bool {
+    let mut checks = Vec::new();
+
+    match x2t_path {
+        Some(x2t_path) => {
+            let x2t_ok = match check_x2t(x2t_path) {
+                Ok(()) => {
+                    checks.push(DoctorCheck::ok(
+                        "x2t",
+                        x2t_path.join(X2T_BIN).display().to_string(),
+                    ));
+                    true
+                }
+                Err(message) => {
+                    checks.push(DoctorCheck::problem(
+                        "x2t",
+                        CheckStatus::Fail,
+                        message,
+                        "point --x2t-path (X2T_PATH) at the directory containing x2t and ensure \
+                         the binary is executable (chmod +x)",
+                    ));
+                    false
+                }
+            };
+
+            if x2t_ok {
+                checks.push(check_shared_libraries(x2t_path).await);
+            }
+
+            checks.push(check_font_cache(x2t_path));
+        }
+        None => checks.push(DoctorCheck::problem(
+            "x2t",
+            CheckStatus::Fail,
+            "no x2t install found",
+            "install the ONLYOFFICE DocumentServer converter or provide its location with \
+             --x2t-path (X2T_PATH)",
+        )),
+    }
+
+    checks.push(check_fonts_dir(fonts_path));
+
+    checks.push(match check_temp_dir(temp_path) {
+        Ok(()) => DoctorCheck::ok("temp dir", temp_path.display().to_string()),
+        Err(message) => DoctorCheck::problem(
+            "temp dir",
+            CheckStatus::Fail,
+            message,
+            format!(
+                "ensure the user running the server can create and write to {}",
+                temp_path.display()
+            ),
+        ),
+    });
+
+    for check in &checks {
+        println!(
+            "[{:<4}] {}: {}",
+            check.status.label(),
+            check.name,
+            check.message
+        );
+
+        if let Some(fix) = &check.fix {
+            println!("       fix: {fix}");
+        }
+    }
+
+    checks.iter().all(|check| check.status != CheckStatus::Fail)
+}
+
+/// Check that the shared libraries x2t depends on can be found, x2t is run
+/// with its install directory on the library path
+async fn check_shared_libraries(x2t_path: &Path) -> DoctorCheck {
+    const NAME: &str = "shared libraries";
+
+    if !cfg!(target_os = "linux") {
+        return DoctorCheck::ok(NAME, "skipped, only checked on linux");
+    }
+
+    let mut command = tokio::process::Command::new("ldd");
+    command.arg(x2t_path.join(X2T_BIN));
+    prepend_library_path(&mut command, x2t_path);
+
+    let output = command.output().await;
+
+    let output = match output {
+        Ok(output) => output,
+        Err(err) => {
+            return DoctorCheck::problem(
+                NAME,
+                CheckStatus::Warn,
+                format!("failed to run ldd: {err}"),
+                "install ldd (libc-bin) to check the shared libraries",
+            );
+        }
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let missing: Vec<&str> = stdout
+        .lines()
+        .filter(|line| line.contains("not found"))
+        .filter_map(|line| line.split_whitespace().next())
+        .collect();
+
+    if !missing.is_empty() {
+        return DoctorCheck::problem(
+            NAME,
+            CheckStatus::Fail,
+            format!("missing {}", missing.join(", ")),
+            "install the packages providing the missing libraries or add the directory \
+             containing them to LD_LIBRARY_PATH",
+        );
+    }
+
+    if !output.status.success() {
+        return DoctorCheck::problem(
+            NAME,
+            CheckStatus::Warn,
+            format!(
+                "ldd failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            "ensure x2t was built for this platform",
+        );
+    }
+
+    DoctorCheck::ok(NAME, "all libraries found")
+}
+
+/// Check the fonts cache generated by allfontsgen is present
+fn check_font_cache(x2t_path: &Path) -> DoctorCheck {
+    let missing: Vec<PathBuf> = FONT_CACHE_FILES
+        .iter()
+        .map(|file_name| x2t_path.join(file_name))
+        .filter(|path| !path.is_file())
+        .collect();
+
+    if missing.is_empty() {
+        return DoctorCheck::ok("fonts cache", "present");
+    }
+
+    let missing: Vec<String> = missing
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+
+    DoctorCheck::problem(
+        "fonts cache",
+        CheckStatus::Fail,
+        format!("missing {}", missing.join(", ")),
+        "generate the fonts cache by starting the server with --regenerate-fonts",
+    )
+}
+
+/// Check the fonts directory exists and contains fonts
+fn check_fonts_dir(fonts_path: &Path) -> DoctorCheck {
+    const NAME: &str = "fonts dir";
+    const FIX: &str = "point --fonts-path (X2T_FONTS_PATH) at the DocumentServer fonts directory";
+
+    if let Err(message) = check_fonts(fonts_path) {
+        return DoctorCheck::problem(NAME, CheckStatus::Fail, message, FIX);
+    }
+
+    let count = match std::fs::read_dir(fonts_path) {
+        Ok(entries) => entries.filter_map(Result::ok).count(),
+        Err(err) => {
+            return DoctorCheck::problem(
+                NAME,
+                CheckStatus::Fail,
+                format!("failed to read {}: {err}", fonts_path.display()),
+                "ensure the user running the server can read the fonts directory",
+            );
+        }
+    };
+
+    if count == 0 {
+        return DoctorCheck::problem(
+            NAME,
+            CheckStatus::Fail,
+            format!("{} is empty", fonts_path.display()),
+            FIX,
+        );
+    }
+
+    DoctorCheck::ok(NAME, format!("{} ({count} files)", fonts_path.display()))
+}
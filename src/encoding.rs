@@ -0,0 +1,86 @@
+use chardetng::EncodingDetector;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+
+/// Input formats whose character encoding is detected
+const TEXT_FORMATS: &[&str] = &["txt", "csv"];
+
+/// Maximum number of bytes read from the start of the file for detection
+const SAMPLE_LENGTH: u64 = 64 * 1024;
+
+/// x2t codepage identifiers for the encodings that can be detected, keyed
+/// by the WHATWG encoding name
+const X2T_CODEPAGES: &[(&str, u32)] = &[
+    ("ISO-8859-6", 0),
+    ("windows-1256", 2),
+    ("ISO-8859-4", 3),
+    ("ISO-8859-13", 4),
+    ("windows-1257", 6),
+    ("ISO-8859-5", 8),
+    ("KOI8-R", 9),
+    ("KOI8-U", 10),
+    ("x-mac-cyrillic", 11),
+    ("IBM866", 13),
+    ("windows-1251", 14),
+    ("windows-1250", 16),
+    ("Big5", 17),
+    ("GBK", 18),
+    ("gb18030", 18),
+    ("ISO-8859-2", 19),
+    ("ISO-8859-7", 20),
+    ("windows-1253", 23),
+    ("ISO-8859-8", 24),
+    ("windows-1255", 26),
+    ("Shift_JIS", 27),
+    ("EUC-KR", 28),
+    ("windows-874", 32),
+    ("windows-1254", 35),
+    ("windows-1252", 43),
+    ("windows-1258", 44),
+    ("UTF-8", 46),
+    ("UTF-16LE", 47),
+    ("UTF-16BE", 48),
+];
+
+/// Detect the character encoding of a TXT/CSV input file, returns the x2t
+/// codepage identifier for the encoding. None is returned for other formats
+/// and for encodings x2t has no identifier for, leaving x2t to decide
+pub async fn detect_codepage(path: &Path) -> Option<u32> {
+    let extension = path.extension().and_then(|extension| extension.to_str())?;
+    if !TEXT_FORMATS.contains(&extension) {
+        return None;
+    }
+
+    let file = tokio::fs::File::open(path).await.ok()?;
+    let size = file.metadata().await.ok()?.len();
+
+    let mut sample = Vec::new();
+    file.take(SAMPLE_LENGTH)
+        .read_to_end(&mut sample)
+        .await
+        .ok()?;
+
+    let encoding_name = match sample.as_slice() {
+        [0xEF, 0xBB, 0xBF, ..] => "UTF-8",
+        [0xFF, 0xFE, ..] => "UTF-16LE",
+        [0xFE, 0xFF, ..] => "UTF-16BE",
+        _ => {
+            let mut detector = EncodingDetector::new();
+            detector.feed(&sample, size <= SAMPLE_LENGTH);
+            detector.guess(None, true).name()
+        }
+    };
+
+    let codepage = X2T_CODEPAGES
+        .iter()
+        .find(|(name, _)| *name == encoding_name)
+        .map(|(_, codepage)| *codepage);
+
+    tracing::debug!(
+        encoding = encoding_name,
+        ?codepage,
+        "detected input encoding"
+    );
+
+    codepage
+}
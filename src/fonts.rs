@@ -0,0 +1,310 @@
+use anyhow::Context;
+use axum::{
+    Extension, Json,
+    extract::{Multipart, multipart::Field},
+    http::StatusCode,
+};
+use serde::Serialize;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
+use tokio::{process::Command, sync::Mutex};
+
+use crate::{
+    ErrorKind, ErrorResponse, error_backtrace, limits::prepend_library_path,
+    upload::write_field_to_file,
+};
+
+/// Default directory uploaded fonts are stored in
+pub const DEFAULT_CUSTOM_FONTS_PATH: &str = "/usr/share/fonts/truetype/custom";
+
+/// Path to the allfontsgen tool relative to the x2t install directory
+const ALLFONTSGEN_RELATIVE_PATH: &str = "../../tools/allfontsgen";
+
+/// File extensions accepted for uploaded fonts
+const FONT_EXTENSIONS: &[&str] = &["ttf", "otf", "ttc"];
+
+/// Manages the custom fonts directory and the x2t fonts cache
+pub struct FontManager {
+    /// Directory uploaded fonts are stored in
+    custom_fonts_path: PathBuf,
+    /// Path to the x2t install, the fonts cache used by x2t is written here
+    x2t_path: PathBuf,
+    /// Path to the fonts folder generated for the converter
+    fonts_path: PathBuf,
+    /// Lock ensuring only one regeneration runs at a time
+    regenerate_lock: Mutex<()>,
+}
+
+/// Font file within the custom fonts directory
+#[derive(Serialize)]
+pub struct FontFile {
+    /// File name of the font
+    name: String,
+    /// Size of the font file in bytes
+    size: u64,
+}
+
+#[derive(Serialize)]
+pub struct FontsResponse {
+    fonts: Vec<FontFile>,
+}
+
+#[derive(Serialize)]
+pub struct RegenerateFontsResponse {
+    /// Time taken to regenerate the fonts cache in milliseconds
+    duration_ms: u64,
+}
+
+impl FontManager {
+    pub fn new(custom_fonts_path: PathBuf, x2t_path: PathBuf, fonts_path: PathBuf) -> Self {
+        Self {
+            custom_fonts_path,
+            x2t_path,
+            fonts_path,
+            regenerate_lock: Mutex::new(()),
+        }
+    }
+
+    /// List the font files in the custom fonts directory
+    pub async fn list(&self) -> std::io::Result<Vec<FontFile>> {
+        let mut fonts = Vec::new();
+
+        let mut entries = match tokio::fs::read_dir(&self.custom_fonts_path).await {
+            Ok(entries) => entries,
+            // Directory is created when the first font is uploaded
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(fonts),
+            Err(err) => return Err(err),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if !metadata.is_file() {
+                continue;
+            }
+
+            fonts.push(FontFile {
+                name: entry.file_name().to_string_lossy().to_string(),
+                size: metadata.len(),
+            });
+        }
+
+        fonts.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(fonts)
+    }
+
+    /// Regenerate the fonts cache used by x2t so that it includes
+    /// newly installed fonts
+    pub async fn regenerate(&self) -> anyhow::Result<()> {
+        let _guard = self.regenerate_lock.lock().await;
+
+        let allfontsgen = self.x2t_path.join(ALLFONTSGEN_RELATIVE_PATH);
+
+        tokio::fs::create_dir_all(&self.custom_fonts_path)
+            .await
+            .context("failed to create custom fonts directory")?;
+
+        let mut command = Command::new(&allfontsgen);
+        command
+            .arg(format!("--input={}", self.custom_fonts_path.display()))
+            .arg(format!(
+                "--allfonts={}",
+                self.x2t_path.join("AllFonts.js").display()
+            ))
+            .arg(format!(
+                "--selection={}",
+                self.x2t_path.join("font_selection.bin").display()
+            ))
+            .arg(format!("--output-web={}", self.fonts_path.display()))
+            .arg("--use-system=true")
+            .kill_on_drop(true);
+        prepend_library_path(&mut command, &self.x2t_path);
+
+        let output = command
+            .output()
+            .await
+            .with_context(|| format!("failed to run {}", allfontsgen.display()))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!(
+                "allfontsgen failed (exit code = {:?}): {stderr}",
+                output.status.code()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// GET /admin/fonts
+///
+/// List the fonts that have been uploaded
+pub async fn list_fonts(
+    Extension(font_manager): Extension<Arc<FontManager>>,
+) -> Result<Json<FontsResponse>, ErrorResponse> {
+    let fonts = font_manager.list().await.map_err(|err| {
+        tracing::error!(?err, "failed to list fonts");
+        ErrorResponse {
+            code: None,
+            kind: ErrorKind::Internal,
+            message: "failed to list fonts".to_string(),
+            backtrace: error_backtrace(&err),
+        }
+    })?;
+
+    Ok(Json(FontsResponse { fonts }))
+}
+
+/// POST /admin/fonts
+///
+/// Upload one or more TTF/OTF fonts, the fonts cache must be regenerated
+/// before the fonts are used by conversions
+pub async fn upload_fonts(
+    Extension(font_manager): Extension<Arc<FontManager>>,
+    mut multipart: Multipart,
+) -> Result<(StatusCode, Json<FontsResponse>), (StatusCode, ErrorResponse)> {
+    tokio::fs::create_dir_all(&font_manager.custom_fonts_path)
+        .await
+        .map_err(|err| {
+            tracing::error!(?err, "failed to create custom fonts directory");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::Internal,
+                    message: "failed to create fonts directory".to_string(),
+                    backtrace: error_backtrace(&err),
+                },
+            )
+        })?;
+
+    let mut fonts = Vec::new();
+
+    while let Some(field) = multipart.next_field().await.map_err(|err| {
+        tracing::error!(?err, "failed to read multipart field");
+        bad_request("failed to read multipart body")
+    })? {
+        let name = font_file_name(&field)?;
+        let path = font_manager.custom_fonts_path.join(&name);
+
+        // Write to a temporary name first so a partial upload never replaces a working font
+        let temp_path = path.with_extension("upload");
+        let size = write_field_to_file(field, &temp_path, None)
+            .await
+            .map_err(|err| (err.kind.status_code(), err))?;
+
+        if let Err(err) = validate_font_file(&temp_path).await {
+            _ = tokio::fs::remove_file(&temp_path).await;
+            return Err(err);
+        }
+
+        tokio::fs::rename(&temp_path, &path).await.map_err(|err| {
+            tracing::error!(?err, "failed to store font file");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::Internal,
+                    message: "failed to store font file".to_string(),
+                    backtrace: error_backtrace(&err),
+                },
+            )
+        })?;
+
+        tracing::info!(name, size, "font uploaded");
+        fonts.push(FontFile { name, size });
+    }
+
+    if fonts.is_empty() {
+        return Err(bad_request("missing font files"));
+    }
+
+    Ok((StatusCode::CREATED, Json(FontsResponse { fonts })))
+}
+
+/// POST /admin/fonts/regenerate
+///
+/// Regenerate the fonts cache, responds once regeneration is complete
+pub async fn regenerate_fonts(
+    Extension(font_manager): Extension<Arc<FontManager>>,
+) -> Result<Json<RegenerateFontsResponse>, ErrorResponse> {
+    let start = Instant::now();
+
+    font_manager.regenerate().await.map_err(|err| {
+        tracing::error!(?err, "failed to regenerate fonts cache");
+        ErrorResponse {
+            code: None,
+            kind: ErrorKind::Internal,
+            message: "failed to regenerate fonts cache".to_string(),
+            backtrace: error_backtrace(&*err),
+        }
+    })?;
+
+    let duration_ms = start.elapsed().as_millis() as u64;
+    tracing::info!(duration_ms, "regenerated fonts cache");
+
+    Ok(Json(RegenerateFontsResponse { duration_ms }))
+}
+
+/// Get the file name to store an uploaded font as, only the final component
+/// of the provided name is used and it must have a font extension
+fn font_file_name(field: &Field<'_>) -> Result<String, (StatusCode, ErrorResponse)> {
+    let name = field
+        .file_name()
+        .and_then(|name| Path::new(name).file_name())
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| bad_request("font files must have a file name"))?;
+
+    let has_font_extension = Path::new(name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            FONT_EXTENSIONS
+                .iter()
+                .any(|allowed| extension.eq_ignore_ascii_case(allowed))
+        });
+
+    if !has_font_extension {
+        return Err(bad_request(&format!(
+            "unsupported font file \"{name}\", expected a ttf, otf or ttc file"
+        )));
+    }
+
+    Ok(name.to_string())
+}
+
+/// Check the uploaded file starts with a TrueType/OpenType signature
+async fn validate_font_file(path: &Path) -> Result<(), (StatusCode, ErrorResponse)> {
+    use tokio::io::AsyncReadExt;
+
+    let mut signature = [0; 4];
+    let is_font = match tokio::fs::File::open(path).await {
+        Ok(mut file) => file.read_exact(&mut signature).await.is_ok(),
+        Err(_) => false,
+    } && matches!(
+        &signature,
+        [0x00, 0x01, 0x00, 0x00] | b"true" | b"OTTO" | b"ttcf"
+    );
+
+    if !is_font {
+        return Err(bad_request("uploaded file is not a valid font"));
+    }
+
+    Ok(())
+}
+
+fn bad_request(message: &str) -> (StatusCode, ErrorResponse) {
+    (
+        StatusCode::BAD_REQUEST,
+        ErrorResponse {
+            code: None,
+            kind: ErrorKind::InvalidRequest,
+            message: message.to_string(),
+            backtrace: None,
+        },
+    )
+}
@@ -0,0 +1,287 @@
+use axum::{
+    Json, async_trait,
+    extract::FromRequestParts,
+    http::{StatusCode, header, request::Parts},
+};
+
+use crate::{ErrorKind, ErrorResponse};
+
+/// Formats that can be chosen through the Accept header, in order of preference
+/// when a wildcard range like `image/*` is accepted
+const NEGOTIABLE_FORMATS: &[OutputFormat] = &[
+    OutputFormat::Pdf,
+    OutputFormat::Docx,
+    OutputFormat::Xlsx,
+    OutputFormat::Pptx,
+    OutputFormat::Odt,
+    OutputFormat::Html,
+    OutputFormat::Txt,
+    OutputFormat::Png,
+    OutputFormat::Jpg,
+];
+
+/// Output formats that x2t can be asked to produce
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Pdf,
+    PdfA,
+    Docx,
+    Xlsx,
+    Pptx,
+    Odt,
+    Txt,
+    /// Single HTML file with resources embedded
+    Html,
+    /// HTML file with its extracted resources (images, styles) archived as a ZIP
+    HtmlZip,
+    Png,
+    Jpg,
+    /// Every page rendered to a PNG image, archived as a ZIP
+    PngPages,
+    /// Every page rendered to a JPEG image, archived as a ZIP
+    JpgPages,
+    /// Every sheet of a spreadsheet converted to its own PDF, archived as a ZIP
+    PdfSheets,
+}
+
+impl OutputFormat {
+    /// Parse an output format from its name or file extension (i.e "pdf", "docx")
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        let name = name.trim().trim_start_matches('.').to_ascii_lowercase();
+
+        Some(match name.as_str() {
+            "pdf" => OutputFormat::Pdf,
+            "pdfa" => OutputFormat::PdfA,
+            "docx" => OutputFormat::Docx,
+            "xlsx" => OutputFormat::Xlsx,
+            "pptx" => OutputFormat::Pptx,
+            "odt" => OutputFormat::Odt,
+            "txt" => OutputFormat::Txt,
+            "html" | "htm" => OutputFormat::Html,
+            "htmlzip" | "html.zip" => OutputFormat::HtmlZip,
+            "png" => OutputFormat::Png,
+            "jpg" | "jpeg" => OutputFormat::Jpg,
+            _ => return None,
+        })
+    }
+
+    /// Find the output format for a media type (i.e "image/png"), only formats
+    /// with a media type unique to them can be found
+    pub fn from_media_type(media_type: &str) -> Option<OutputFormat> {
+        NEGOTIABLE_FORMATS
+            .iter()
+            .copied()
+            .find(|format| format.media_type().eq_ignore_ascii_case(media_type))
+    }
+
+    /// MIME type of the format without any parameters
+    fn media_type(&self) -> &'static str {
+        let content_type = self.content_type();
+        content_type
+            .split_once(';')
+            .map(|(media_type, _)| media_type)
+            .unwrap_or(content_type)
+    }
+
+    /// Unique name of the format
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Pdf => "pdf",
+            OutputFormat::PdfA => "pdfa",
+            OutputFormat::Docx => "docx",
+            OutputFormat::Xlsx => "xlsx",
+            OutputFormat::Pptx => "pptx",
+            OutputFormat::Odt => "odt",
+            OutputFormat::Txt => "txt",
+            OutputFormat::Html => "html",
+            OutputFormat::HtmlZip => "htmlzip",
+            OutputFormat::Png => "png",
+            OutputFormat::Jpg => "jpg",
+            OutputFormat::PngPages => "png-pages",
+            OutputFormat::JpgPages => "jpg-pages",
+            OutputFormat::PdfSheets => "pdf-sheets",
+        }
+    }
+
+    /// The x2t format code (m_nFormatTo) for this format
+    pub fn x2t_code(&self) -> u32 {
+        match self {
+            // AVS_OFFICESTUDIO_FILE_CROSSPLATFORM_PDF
+            OutputFormat::Pdf | OutputFormat::PdfSheets => 0x0201,
+            // AVS_OFFICESTUDIO_FILE_CROSSPLATFORM_PDFA
+            OutputFormat::PdfA => 0x0209,
+            // AVS_OFFICESTUDIO_FILE_DOCUMENT_DOCX
+            OutputFormat::Docx => 0x0041,
+            // AVS_OFFICESTUDIO_FILE_SPREADSHEET_XLSX
+            OutputFormat::Xlsx => 0x0101,
+            // AVS_OFFICESTUDIO_FILE_PRESENTATION_PPTX
+            OutputFormat::Pptx => 0x0081,
+            // AVS_OFFICESTUDIO_FILE_DOCUMENT_ODT
+            OutputFormat::Odt => 0x0043,
+            // AVS_OFFICESTUDIO_FILE_DOCUMENT_TXT
+            OutputFormat::Txt => 0x0045,
+            // AVS_OFFICESTUDIO_FILE_DOCUMENT_HTML
+            OutputFormat::Html => 0x0046,
+            // AVS_OFFICESTUDIO_FILE_OTHER_HTMLZIP
+            OutputFormat::HtmlZip => 0x0803,
+            // AVS_OFFICESTUDIO_FILE_IMAGE_PNG
+            OutputFormat::Png | OutputFormat::PngPages => 0x0405,
+            // AVS_OFFICESTUDIO_FILE_IMAGE_JPG
+            OutputFormat::Jpg | OutputFormat::JpgPages => 0x0401,
+        }
+    }
+
+    /// File extension used for the output file
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Pdf | OutputFormat::PdfA => "pdf",
+            OutputFormat::Docx => "docx",
+            OutputFormat::Xlsx => "xlsx",
+            OutputFormat::Pptx => "pptx",
+            OutputFormat::Odt => "odt",
+            OutputFormat::Txt => "txt",
+            OutputFormat::Html => "html",
+            OutputFormat::Png => "png",
+            OutputFormat::Jpg => "jpg",
+            OutputFormat::HtmlZip
+            | OutputFormat::PngPages
+            | OutputFormat::JpgPages
+            | OutputFormat::PdfSheets => "zip",
+        }
+    }
+
+    /// MIME type to respond with for this format
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Pdf | OutputFormat::PdfA => "application/pdf",
+            OutputFormat::Docx => {
+                "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+            }
+            OutputFormat::Xlsx => {
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+            }
+            OutputFormat::Pptx => {
+                "application/vnd.openxmlformats-officedocument.presentationml.presentation"
+            }
+            OutputFormat::Odt => "application/vnd.oasis.opendocument.text",
+            OutputFormat::Txt => "text/plain; charset=utf-8",
+            OutputFormat::Html => "text/html; charset=utf-8",
+            OutputFormat::Png => "image/png",
+            OutputFormat::Jpg => "image/jpeg",
+            OutputFormat::HtmlZip
+            | OutputFormat::PngPages
+            | OutputFormat::JpgPages
+            | OutputFormat::PdfSheets => "application/zip",
+        }
+    }
+
+    /// Content security policy to respond with for this format, HTML output is
+    /// sandboxed so previewing it in a browser can't run scripts
+    pub fn content_security_policy(&self) -> Option<&'static str> {
+        match self {
+            OutputFormat::Html => Some("sandbox"),
+            _ => None,
+        }
+    }
+
+    /// Additional x2t config elements required for this format
+    pub fn extra_config(&self) -> &'static str {
+        match self {
+            // Image outputs need thumbnail options, render only the first page
+            OutputFormat::Png => {
+                "<m_oThumbnail><format>4</format><aspect>1</aspect><first>true</first></m_oThumbnail>"
+            }
+            OutputFormat::Jpg => {
+                "<m_oThumbnail><format>3</format><aspect>1</aspect><first>true</first></m_oThumbnail>"
+            }
+            // Rendering every page makes x2t output a ZIP of the page images
+            OutputFormat::PngPages => {
+                "<m_oThumbnail><format>4</format><aspect>1</aspect><first>false</first></m_oThumbnail>"
+            }
+            OutputFormat::JpgPages => {
+                "<m_oThumbnail><format>3</format><aspect>1</aspect><first>false</first></m_oThumbnail>"
+            }
+            _ => "",
+        }
+    }
+}
+
+/// Output format negotiated from the Accept header, None when the header
+/// is missing or accepts any format
+pub struct AcceptedFormat(pub Option<OutputFormat>);
+
+/// Choose the output format from the value of an Accept header, media ranges
+/// are tried in order of their quality. Returns [None] when none of the
+/// accepted media types can be produced
+fn negotiate_output_format(accept: &str) -> Option<AcceptedFormat> {
+    let mut ranges: Vec<(&str, f32)> = accept
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';').map(str::trim);
+            let media_type = parts.next().filter(|value| !value.is_empty())?;
+            let quality = parts
+                .filter_map(|param| param.strip_prefix("q="))
+                .find_map(|value| value.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            Some((media_type, quality))
+        })
+        // Quality of zero marks the media type as not acceptable
+        .filter(|(_, quality)| *quality > 0.0)
+        .collect();
+
+    // Stable sort keeps the order of the header for equal qualities
+    ranges.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+    ranges.into_iter().find_map(|(media_type, _)| {
+        if media_type == "*/*" {
+            return Some(AcceptedFormat(None));
+        }
+
+        let format = match media_type.strip_suffix("/*") {
+            Some(top_level) => NEGOTIABLE_FORMATS.iter().copied().find(|format| {
+                format
+                    .media_type()
+                    .split_once('/')
+                    .is_some_and(|(value, _)| value.eq_ignore_ascii_case(top_level))
+            }),
+            None => OutputFormat::from_media_type(media_type),
+        };
+
+        format.map(|format| AcceptedFormat(Some(format)))
+    })
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AcceptedFormat
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, Json<ErrorResponse>);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let accept = parts
+            .headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        if accept.trim().is_empty() {
+            return Ok(AcceptedFormat(None));
+        }
+
+        negotiate_output_format(&accept).ok_or_else(|| {
+            (
+                StatusCode::NOT_ACCEPTABLE,
+                Json(ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::UnsupportedFormat,
+                    message: "none of the accepted media types can be produced".to_string(),
+                    backtrace: None,
+                }),
+            )
+        })
+    }
+}
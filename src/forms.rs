@@ -0,0 +1,94 @@
+use serde_json::{Map, Value};
+
+use crate::{ErrorKind, ErrorResponse};
+
+/// Script filling the forms and content controls of the open document with
+/// the values in `oValues`, form fields are matched by their key and content
+/// controls by their tag. Every control is then removed keeping its content
+/// so that the converted output is flattened
+const FILL_FORMS_SCRIPT: &str = r#"
+var oDocument = Api.GetDocument();
+
+oDocument.GetAllForms().forEach(function (oForm) {
+    var sKey = oForm.GetFormKey();
+    if (!Object.prototype.hasOwnProperty.call(oValues, sKey)) return;
+
+    var value = oValues[sKey];
+    switch (oForm.GetFormType()) {
+        case "checkBoxForm":
+            oForm.SetChecked(value === true || value === "true");
+            break;
+        case "comboBoxForm":
+        case "dropDownForm":
+            oForm.SelectListValue(String(value));
+            break;
+        default:
+            oForm.SetText(String(value));
+            break;
+    }
+});
+
+oDocument.GetAllContentControls().forEach(function (oControl) {
+    var sTag = oControl.GetTag();
+    if (sTag && Object.prototype.hasOwnProperty.call(oValues, sTag)) {
+        var sText = String(oValues[sTag]);
+        oControl.RemoveAllElements();
+
+        if (oControl.GetClassType() === "blockLvlSdt") {
+            var oParagraph = Api.CreateParagraph();
+            oParagraph.AddText(sText);
+            oControl.AddElement(oParagraph, 0);
+        } else {
+            oControl.AddText(sText);
+        }
+    }
+});
+
+oDocument.GetAllContentControls().forEach(function (oControl) {
+    oControl.Delete(true);
+});
+"#;
+
+/// Values to fill the form fields of a document with before converting it
+#[derive(Debug)]
+pub struct FormData {
+    /// Field keys (or content control tags) mapped to their values
+    values: Map<String, Value>,
+}
+
+impl FormData {
+    /// Parse the form data from a JSON object mapping field keys to values,
+    /// only scalar values are accepted
+    pub fn from_param(value: Option<String>) -> Result<Option<FormData>, ErrorResponse> {
+        let Some(value) = value else {
+            return Ok(None);
+        };
+
+        let values: Map<String, Value> =
+            serde_json::from_str(&value).map_err(|err| ErrorResponse {
+                code: None,
+                kind: ErrorKind::InvalidRequest,
+                message: format!("form_data must be a JSON object: {err}"),
+                backtrace: None,
+            })?;
+
+        if let Some((name, _)) = values.iter().find(|(_, value)| {
+            !matches!(value, Value::String(_) | Value::Number(_) | Value::Bool(_))
+        }) {
+            return Err(ErrorResponse {
+                code: None,
+                kind: ErrorKind::InvalidRequest,
+                message: format!("form field \"{name}\" must be a string, number or boolean"),
+                backtrace: None,
+            });
+        }
+
+        Ok(Some(FormData { values }))
+    }
+
+    /// Create the docbuilder script that fills the forms of the document
+    pub fn script(&self) -> String {
+        let values = Value::Object(self.values.clone()).to_string();
+        format!("var oValues = {values};\n{FILL_FORMS_SCRIPT}")
+    }
+}
@@ -0,0 +1,399 @@
+use futures_util::{Stream, StreamExt, stream};
+use std::{
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio_util::io::ReaderStream;
+use tonic::{Code, Request, Response, Status, Streaming, service::Interceptor};
+
+use crate::{
+    ErrorKind, ErrorResponse, RuntimeConfig,
+    auth::JwtAuth,
+    client_ip::IpAccessList,
+    convert::{convert_file, create_convert_temp_paths},
+    deadline::RequestDeadline,
+    disposition::sanitize_file_name,
+    limiter::{ConversionLimiter, EnqueueError},
+    scratch::WriteReservation,
+    upload::ConvertFields,
+};
+
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/convert.ConvertService.rs"));
+}
+
+use generated::convert_service_server::ConvertService;
+pub use generated::convert_service_server::ConvertServiceServer;
+
+/// Size of the chunks the converted file is streamed back in
+const RESPONSE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Metadata containing the time the client is willing to wait for the call
+const GRPC_TIMEOUT_METADATA: &str = "grpc-timeout";
+
+// Messages are defined manually to avoid requiring protoc at build time,
+// these must be kept in sync with proto/convert.proto
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ConvertRequest {
+    #[prost(oneof = "convert_request::Payload", tags = "1, 2")]
+    pub payload: Option<convert_request::Payload>,
+}
+
+pub mod convert_request {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Payload {
+        /// Options for the conversion, must be the first message
+        #[prost(message, tag = "1")]
+        Options(super::GrpcConvertOptions),
+        /// Chunk of the file to convert
+        #[prost(bytes = "vec", tag = "2")]
+        Chunk(Vec<u8>),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GrpcConvertOptions {
+    /// Format to convert the file to (Defaults to PDF)
+    #[prost(string, optional, tag = "1")]
+    pub target_format: Option<String>,
+    /// Whether PDF output should be archival PDF/A
+    #[prost(bool, optional, tag = "2")]
+    pub pdfa: Option<bool>,
+    /// Password to open the file with if its encrypted
+    #[prost(string, optional, tag = "3")]
+    pub password: Option<String>,
+    /// Extension of the input format (i.e "docx")
+    #[prost(string, optional, tag = "4")]
+    pub input_format: Option<String>,
+    /// Original name of the file, its extension is used when the input
+    /// format isn't provided
+    #[prost(string, optional, tag = "5")]
+    pub file_name: Option<String>,
+    /// Version of the PDF specification the PDF output declares ("1.5",
+    /// "1.6" or "1.7")
+    #[prost(string, optional, tag = "6")]
+    pub pdf_version: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ConvertResponse {
+    #[prost(oneof = "convert_response::Payload", tags = "1, 2")]
+    pub payload: Option<convert_response::Payload>,
+}
+
+pub mod convert_response {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Payload {
+        /// Metadata of the converted file, always the first message
+        #[prost(message, tag = "1")]
+        Metadata(super::ConvertMetadata),
+        /// Chunk of the converted file
+        #[prost(bytes = "vec", tag = "2")]
+        Chunk(Vec<u8>),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ConvertMetadata {
+    /// MIME type of the converted file
+    #[prost(string, tag = "1")]
+    pub content_type: String,
+    /// File extension of the converted file
+    #[prost(string, tag = "2")]
+    pub extension: String,
+    /// Size of the converted file in bytes
+    #[prost(uint64, tag = "3")]
+    pub size: u64,
+}
+
+/// gRPC conversion service, shares the conversion pipeline and limiter
+/// with the HTTP API
+pub struct GrpcConvertService {
+    runtime_config: Arc<RuntimeConfig>,
+    limiter: Arc<ConversionLimiter>,
+}
+
+impl GrpcConvertService {
+    pub fn new(runtime_config: Arc<RuntimeConfig>, limiter: Arc<ConversionLimiter>) -> Self {
+        Self {
+            runtime_config,
+            limiter,
+        }
+    }
+}
+
+type ConvertResponseStream = Pin<Box<dyn Stream<Item = Result<ConvertResponse, Status>> + Send>>;
+
+// Status is the error type required by tonic and can't be made smaller
+#[allow(clippy::result_large_err)]
+#[tonic::async_trait]
+impl ConvertService for GrpcConvertService {
+    type ConvertStream = ConvertResponseStream;
+
+    async fn convert(
+        &self,
+        request: Request<Streaming<ConvertRequest>>,
+    ) -> Result<Response<Self::ConvertStream>, Status> {
+        let deadline = request_deadline(&request).map_err(error_status)?;
+
+        let queue_ticket = self.limiter.try_enqueue().map_err(|err| match err {
+            EnqueueError::QueueFull => {
+                Status::resource_exhausted("server is at capacity, try again later")
+            }
+            EnqueueError::Draining => Status::unavailable("server is shutting down"),
+        })?;
+
+        let mut temp_paths = create_convert_temp_paths(&self.runtime_config)
+            .await
+            .map_err(error_status)?;
+
+        let result = async {
+            let mut messages = request.into_inner();
+
+            let options = match messages
+                .message()
+                .await?
+                .and_then(|message| message.payload)
+            {
+                Some(convert_request::Payload::Options(options)) => options,
+                _ => {
+                    return Err(Status::invalid_argument(
+                        "first message must contain the conversion options",
+                    ));
+                }
+            };
+
+            let file_name = options.file_name.as_deref().and_then(sanitize_file_name);
+
+            let mut options = ConvertFields {
+                target_format: options.target_format,
+                pdfa: options.pdfa,
+                pdf_version: options.pdf_version,
+                password: options.password,
+                input_format: options.input_format,
+                ..Default::default()
+            }
+            .into_options(self.runtime_config.default_pdfa)
+            .map_err(error_status)?;
+            options.deadline = deadline;
+
+            let write_error = |err: std::io::Error| {
+                tracing::error!(?err, "failed to write uploaded file");
+                Status::internal("failed to write uploaded file")
+            };
+
+            let file = tokio::fs::File::create(&temp_paths.input_path)
+                .await
+                .map_err(write_error)?;
+            let mut writer = BufWriter::new(file);
+            let mut size: u64 = 0;
+            let mut reservation = WriteReservation::new(temp_paths.scratch());
+
+            while let Some(message) = messages.message().await? {
+                match message.payload {
+                    Some(convert_request::Payload::Chunk(chunk)) => {
+                        reservation
+                            .write(chunk.len() as u64)
+                            .map_err(error_status)?;
+                        size += chunk.len() as u64;
+                        writer.write_all(&chunk).await.map_err(write_error)?
+                    }
+                    Some(convert_request::Payload::Options(_)) => {
+                        return Err(Status::invalid_argument(
+                            "conversion options can only be sent once",
+                        ));
+                    }
+                    None => {}
+                }
+            }
+
+            writer.flush().await.map_err(write_error)?;
+            drop(reservation);
+            queue_ticket.set_input_size(size);
+
+            temp_paths
+                .resolve_input_extension(
+                    &self.runtime_config,
+                    options.input_format.as_deref(),
+                    file_name.as_deref(),
+                )
+                .await
+                .map_err(error_status)?;
+
+            // Wait for a free conversion slot
+            let _permit = queue_ticket
+                .acquire_before(options.priority, options.deadline)
+                .await
+                .map_err(error_status)?;
+
+            let output_file = convert_file(&self.runtime_config, &temp_paths, &options)
+                .await
+                .map_err(error_status)?;
+
+            Ok((output_file, options.output_format))
+        }
+        .await;
+
+        drop(temp_paths);
+
+        let (output_file, output_format) = result?;
+
+        let read_error = |err: std::io::Error| {
+            tracing::error!(?err, "failed to read output file");
+            Status::internal("failed to read output")
+        };
+
+        let file = tokio::fs::File::open(output_file.path())
+            .await
+            .map_err(read_error)?;
+        let size = file.metadata().await.map_err(read_error)?.len();
+
+        let metadata = ConvertResponse {
+            payload: Some(convert_response::Payload::Metadata(ConvertMetadata {
+                content_type: output_format.content_type().to_string(),
+                extension: output_format.extension().to_string(),
+                size,
+            })),
+        };
+
+        let chunks = ReaderStream::with_capacity(file, RESPONSE_CHUNK_SIZE).map(move |chunk| {
+            // Hold onto the output file until the stream is dropped
+            let _output_file = &output_file;
+
+            chunk
+                .map(|chunk| ConvertResponse {
+                    payload: Some(convert_response::Payload::Chunk(chunk.to_vec())),
+                })
+                .map_err(read_error)
+        });
+
+        let stream = stream::once(async move { Ok(metadata) }).chain(chunks);
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+/// Interceptor requiring a valid JWT in the request metadata when
+/// JWT authentication is enabled
+#[derive(Clone)]
+pub struct GrpcAuthInterceptor {
+    jwt_auth: Option<Arc<JwtAuth>>,
+    ip_access_list: Arc<IpAccessList>,
+}
+
+impl GrpcAuthInterceptor {
+    pub fn new(jwt_auth: Option<Arc<JwtAuth>>, ip_access_list: Arc<IpAccessList>) -> Self {
+        Self {
+            jwt_auth,
+            ip_access_list,
+        }
+    }
+}
+
+impl Interceptor for GrpcAuthInterceptor {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(remote_addr) = request.remote_addr()
+            && !self.ip_access_list.is_allowed(remote_addr.ip())
+        {
+            return Err(Status::permission_denied(
+                "requests from this address are not allowed",
+            ));
+        }
+
+        let Some(jwt_auth) = &self.jwt_auth else {
+            return Ok(request);
+        };
+
+        let token = request
+            .metadata()
+            .get(jwt_auth.header().as_str())
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.strip_prefix("Bearer ").unwrap_or(value).trim());
+
+        match token {
+            Some(token) if jwt_auth.is_valid(token) => Ok(request),
+            _ => Err(Status::unauthenticated("missing or invalid token")),
+        }
+    }
+}
+
+/// Convert an error into the gRPC status matching its kind
+/// Deadline of a call, the earliest of the `grpc-timeout` sent by the client
+/// and the deadline headers accepted by the HTTP API
+fn request_deadline<T>(request: &Request<T>) -> Result<Option<Instant>, ErrorResponse> {
+    let received_at = Instant::now();
+    let metadata = request.metadata();
+
+    let RequestDeadline(deadline) =
+        RequestDeadline::from_headers(&metadata.clone().into_headers(), received_at)?;
+
+    let grpc_deadline = match metadata.get(GRPC_TIMEOUT_METADATA) {
+        Some(value) => {
+            let timeout = value
+                .to_str()
+                .ok()
+                .and_then(parse_grpc_timeout)
+                .ok_or_else(|| ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::InvalidRequest,
+                    message: "invalid grpc-timeout".to_string(),
+                    backtrace: None,
+                })?;
+            received_at.checked_add(timeout)
+        }
+        None => None,
+    };
+
+    Ok(match (deadline, grpc_deadline) {
+        (Some(deadline), Some(grpc_deadline)) => Some(deadline.min(grpc_deadline)),
+        (deadline, grpc_deadline) => deadline.or(grpc_deadline),
+    })
+}
+
+/// Parse a `grpc-timeout` value, up to 8 digits followed by the unit
+fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    if value.len() < 2 || !value.is_ascii() {
+        return None;
+    }
+
+    let (amount, unit) = value.split_at(value.len() - 1);
+    if amount.len() > 8 {
+        return None;
+    }
+    let amount: u64 = amount.parse().ok()?;
+
+    Some(match unit {
+        "H" => Duration::from_secs(amount * 60 * 60),
+        "M" => Duration::from_secs(amount * 60),
+        "S" => Duration::from_secs(amount),
+        "m" => Duration::from_millis(amount),
+        "u" => Duration::from_micros(amount),
+        "n" => Duration::from_nanos(amount),
+        _ => return None,
+    })
+}
+
+fn error_status(err: ErrorResponse) -> Status {
+    let code = match err.kind {
+        ErrorKind::InvalidRequest => Code::InvalidArgument,
+        ErrorKind::Unauthorized => Code::Unauthenticated,
+        ErrorKind::Forbidden => Code::PermissionDenied,
+        ErrorKind::NotFound => Code::NotFound,
+        ErrorKind::Conflict => Code::FailedPrecondition,
+        ErrorKind::Encrypted
+        | ErrorKind::Corrupted
+        | ErrorKind::UnsupportedFormat
+        | ErrorKind::Infected
+        | ErrorKind::PoisonDocument => Code::InvalidArgument,
+        ErrorKind::TooLarge | ErrorKind::ResourceLimit => Code::ResourceExhausted,
+        ErrorKind::Timeout => Code::DeadlineExceeded,
+        ErrorKind::Busy => Code::ResourceExhausted,
+        ErrorKind::Unavailable => Code::Unavailable,
+        ErrorKind::ConversionFailed | ErrorKind::Internal => Code::Internal,
+    };
+
+    Status::new(code, err.message)
+}
@@ -0,0 +1,53 @@
+use anyhow::Context;
+use std::{process::ExitCode, time::Duration};
+
+/// Default base URL of the server to check
+pub const DEFAULT_HEALTHCHECK_URL: &str = "http://127.0.0.1:8080";
+
+/// Default number of seconds to wait for the server to respond
+pub const DEFAULT_HEALTHCHECK_TIMEOUT: u64 = 5;
+
+/// Check the health endpoint of a running server, used as a container
+/// healthcheck so that curl doesn't need to be installed in the image
+///
+/// ## Arguments
+/// * `url` - Base URL of the server
+/// * `timeout` - Maximum time to wait for a response
+pub fn run_healthcheck(url: &str, timeout: Duration) -> ExitCode {
+    match check_health(url, timeout) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("healthcheck failed: {err:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn check_health(url: &str, timeout: Duration) -> anyhow::Result<()> {
+    let url = format!("{}/health", url.trim_end_matches('/'));
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("failed to create async runtime")?;
+
+    runtime.block_on(async {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .context("failed to create http client")?;
+
+        let response = client
+            .get(&url)
+            .send()
+            .await
+            .with_context(|| format!("failed to reach {url}"))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            anyhow::bail!("{url} responded with {status}");
+        }
+
+        Ok(())
+    })
+}
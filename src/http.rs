@@ -0,0 +1,349 @@
+use axum::{Router, extract::ConnectInfo, http::Request};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use rustls::ServerConfig;
+use std::{
+    future::Future,
+    io::IoSlice,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpListener,
+};
+use tokio_rustls::TlsAcceptor;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tower::ServiceExt;
+
+/// Maximum time a client can take to complete the TLS handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connection settings for the HTTP server
+#[derive(Debug, Default, Clone)]
+pub struct HttpTuning {
+    /// Whether HTTP/1.1 connections are kept open between requests
+    pub disable_keep_alive: bool,
+    /// Maximum time a client can take to send the headers of a request,
+    /// also closes HTTP/1.1 connections waiting this long for a request
+    pub header_timeout: Option<Duration>,
+    /// Connections without any traffic for this long are closed once their
+    /// in-flight requests have finished
+    pub idle_timeout: Option<Duration>,
+    /// Interval between HTTP/2 pings used to detect dead connections
+    pub http2_keep_alive_interval: Option<Duration>,
+    /// Maximum number of concurrent HTTP/2 streams per connection
+    pub http2_max_concurrent_streams: Option<u32>,
+    /// Maximum time to wait for in-flight requests during shutdown, any
+    /// connections still open afterwards are closed
+    pub shutdown_timeout: Option<Duration>,
+}
+
+/// Serves HTTP/1.1 and HTTP/2 connections, HTTP/2 is used over TLS when
+/// negotiated and over plain connections when the client sends the HTTP/2
+/// preface (h2c with prior knowledge)
+#[derive(Clone)]
+pub struct HttpServer {
+    builder: auto::Builder<TokioExecutor>,
+    idle_timeout: Option<Duration>,
+    shutdown_timeout: Option<Duration>,
+    /// Cancelled to gracefully shutdown every connection
+    shutdown: CancellationToken,
+    /// Tracks the open connections
+    connections: TaskTracker,
+}
+
+impl HttpServer {
+    pub fn new(tuning: &HttpTuning) -> Self {
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+
+        let mut http1 = builder.http1();
+        http1
+            .timer(TokioTimer::new())
+            .keep_alive(!tuning.disable_keep_alive);
+        if let Some(header_timeout) = tuning.header_timeout {
+            http1.header_read_timeout(header_timeout);
+        }
+
+        let mut http2 = builder.http2();
+        http2
+            .timer(TokioTimer::new())
+            .keep_alive_interval(tuning.http2_keep_alive_interval)
+            .max_concurrent_streams(tuning.http2_max_concurrent_streams);
+
+        Self {
+            builder,
+            idle_timeout: tuning.idle_timeout,
+            shutdown_timeout: tuning.shutdown_timeout,
+            shutdown: CancellationToken::new(),
+            connections: TaskTracker::new(),
+        }
+    }
+
+    /// Serve the app on an accepted connection, the address of the peer is
+    /// provided to the app as [ConnectInfo] when known
+    pub fn serve_connection<IO>(&self, io: IO, peer: Option<SocketAddr>, app: Router)
+    where
+        IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        // Inserted per request rather than layered onto the app, which would
+        // rebuild the router for every connection
+        let app = app.map_request(move |mut request: Request<_>| {
+            if let Some(peer) = peer {
+                request.extensions_mut().insert(ConnectInfo(peer));
+            }
+
+            request
+        });
+
+        let activity = Arc::new(Activity::new());
+        let io = TokioIo::new(ActivityStream {
+            inner: io,
+            activity: activity.clone(),
+        });
+
+        let builder = self.builder.clone();
+        let idle_timeout = self.idle_timeout;
+        let shutdown = self.shutdown.clone();
+
+        self.connections.spawn(async move {
+            let service = TowerToHyperService::new(app);
+            let connection = builder.serve_connection_with_upgrades(io, service);
+            tokio::pin!(connection);
+
+            let mut shutting_down = false;
+
+            loop {
+                let idle = async {
+                    match idle_timeout {
+                        Some(idle_timeout) => activity.idle(idle_timeout).await,
+                        None => std::future::pending().await,
+                    }
+                };
+
+                tokio::select! {
+                    result = connection.as_mut() => {
+                        if let Err(err) = result {
+                            tracing::debug!(?err, "connection closed with error");
+                        }
+                        break;
+                    }
+                    _ = shutdown.cancelled(), if !shutting_down => {
+                        shutting_down = true;
+                        connection.as_mut().graceful_shutdown();
+                    }
+                    _ = idle, if !shutting_down => {
+                        shutting_down = true;
+                        connection.as_mut().graceful_shutdown();
+                    }
+                }
+            }
+        });
+    }
+
+    /// Gracefully shutdown every connection, waits for in-flight requests
+    /// to finish up to the shutdown timeout
+    pub async fn shutdown(&self) {
+        self.shutdown.cancel();
+        self.connections.close();
+
+        let Some(shutdown_timeout) = self.shutdown_timeout else {
+            self.connections.wait().await;
+            return;
+        };
+
+        // Connections still open are dropped along with the runtime
+        if tokio::time::timeout(shutdown_timeout, self.connections.wait())
+            .await
+            .is_err()
+        {
+            tracing::warn!(
+                connections = self.connections.len(),
+                "shutdown timeout reached, closing remaining connections"
+            );
+        }
+    }
+}
+
+/// Serve the app on a TCP listener until the shutdown future completes,
+/// in-flight requests are allowed to finish before returning
+///
+/// ## Arguments
+/// * `listener` - Listener to accept connections from
+/// * `tls_config` - TLS configuration, connections are plain HTTP when None
+/// * `tuning` - Connection settings
+/// * `app` - The app to serve
+/// * `shutdown` - Future that completes when the server should shutdown
+pub async fn serve_tcp<F>(
+    listener: TcpListener,
+    tls_config: Option<Arc<ServerConfig>>,
+    tuning: &HttpTuning,
+    app: Router,
+    shutdown: F,
+) -> anyhow::Result<()>
+where
+    F: Future<Output = ()>,
+{
+    let server = HttpServer::new(tuning);
+    let tls_acceptor = tls_config.map(TlsAcceptor::from);
+
+    tokio::pin!(shutdown);
+
+    loop {
+        let (stream, peer) = tokio::select! {
+            result = listener.accept() => match result {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    tracing::error!(?err, "failed to accept connection");
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+
+        if let Err(err) = stream.set_nodelay(true) {
+            tracing::debug!(?err, "failed to set TCP_NODELAY");
+        }
+
+        let Some(tls_acceptor) = &tls_acceptor else {
+            server.serve_connection(stream, Some(peer), app.clone());
+            continue;
+        };
+
+        let tls_acceptor = tls_acceptor.clone();
+        let app = app.clone();
+        let handshake_server = server.clone();
+
+        // Handshakes are tracked so shutdown waits for connections that are
+        // still being established
+        server.connections.spawn(async move {
+            match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, tls_acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => handshake_server.serve_connection(stream, Some(peer), app),
+                Ok(Err(err)) => tracing::debug!(?err, "tls handshake failed"),
+                Err(_) => tracing::debug!("tls handshake timed out"),
+            }
+        });
+    }
+
+    // Stop accepting connections and wait for the existing ones to finish
+    drop(listener);
+    server.shutdown().await;
+
+    Ok(())
+}
+
+/// Time of the last read or write on a connection
+struct Activity {
+    /// When the connection was accepted
+    started_at: Instant,
+    /// Milliseconds after the connection was accepted of the last activity
+    last_activity: AtomicU64,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            last_activity: AtomicU64::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        let elapsed = self.started_at.elapsed().as_millis() as u64;
+        self.last_activity.store(elapsed, Ordering::Relaxed);
+    }
+
+    /// Wait until there has been no activity for the provided duration
+    async fn idle(&self, idle_timeout: Duration) {
+        loop {
+            let last_activity = Duration::from_millis(self.last_activity.load(Ordering::Relaxed));
+            let deadline = self.started_at + last_activity + idle_timeout;
+
+            if Instant::now() >= deadline {
+                return;
+            }
+
+            tokio::time::sleep_until(deadline.into()).await;
+        }
+    }
+}
+
+/// Stream that records the time of its last read or write
+struct ActivityStream<IO> {
+    inner: IO,
+    activity: Arc<Activity>,
+}
+
+impl<IO> AsyncRead for ActivityStream<IO>
+where
+    IO: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+
+        if buf.filled().len() > filled {
+            self.activity.touch();
+        }
+
+        result
+    }
+}
+
+impl<IO> AsyncWrite for ActivityStream<IO>
+where
+    IO: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+
+        if matches!(result, Poll::Ready(Ok(written)) if written > 0) {
+            self.activity.touch();
+        }
+
+        result
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+
+        if matches!(result, Poll::Ready(Ok(written)) if written > 0) {
+            self.activity.touch();
+        }
+
+        result
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
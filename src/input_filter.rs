@@ -0,0 +1,102 @@
+use anyhow::Context;
+use std::collections::HashSet;
+
+use crate::{ErrorKind, ErrorResponse, convert::normalize_extension};
+
+/// Formats detected from file content that can't be relied on to identify
+/// the actual input format (i.e CSV files are detected as text)
+const AMBIGUOUS_DETECTED_FORMATS: &[&str] = &["zip", "txt", "html"];
+
+/// Restricts which input formats are accepted for conversion
+#[derive(Debug, Default, Clone)]
+pub struct InputFormatFilter {
+    /// Formats that are accepted, all formats are accepted when None
+    allowed: Option<HashSet<String>>,
+    /// Formats that are always rejected
+    denied: HashSet<String>,
+}
+
+impl InputFormatFilter {
+    /// Create a filter from comma separated lists of input format extensions
+    /// (i.e "docx,xlsx,pptx")
+    ///
+    /// ## Arguments
+    /// * `allowed` - Formats to accept, all formats are accepted when None
+    /// * `denied` - Formats to reject
+    pub fn from_lists(allowed: Option<&str>, denied: Option<&str>) -> anyhow::Result<Self> {
+        let allowed = allowed
+            .map(|value| parse_formats(value).context("invalid allowed input formats"))
+            .transpose()?;
+        let denied = denied
+            .map(|value| parse_formats(value).context("invalid denied input formats"))
+            .transpose()?
+            .unwrap_or_default();
+
+        Ok(Self { allowed, denied })
+    }
+
+    /// Whether any input formats are restricted
+    pub fn is_restricted(&self) -> bool {
+        self.allowed.is_some() || !self.denied.is_empty()
+    }
+
+    /// Whether the input format is accepted
+    pub fn is_allowed(&self, format: &str) -> bool {
+        !self.denied.contains(format)
+            && self
+                .allowed
+                .as_ref()
+                .is_none_or(|allowed| allowed.contains(format))
+    }
+
+    /// Check that the resolved input format is accepted, inputs with an
+    /// unknown format are only accepted when there is no allow list
+    ///
+    /// ## Arguments
+    /// * `format` - Format resolved from the request or file name
+    /// * `detected` - Format detected from the content of the file
+    pub fn check(&self, format: Option<&str>, detected: Option<&str>) -> Result<(), ErrorResponse> {
+        // Detection can't tell variants of a format apart (i.e XLSM is
+        // detected as XLSX) so the detected format is only checked against
+        // the deny list, this stops denied formats from being accepted by
+        // renaming the file
+        let detected = detected
+            .filter(|detected| !AMBIGUOUS_DETECTED_FORMATS.contains(detected))
+            .filter(|detected| self.denied.contains(*detected));
+
+        if let Some(format) = format
+            .filter(|format| !self.is_allowed(format))
+            .or(detected)
+        {
+            return Err(ErrorResponse {
+                code: None,
+                kind: ErrorKind::UnsupportedFormat,
+                message: format!("input format \"{format}\" is not allowed"),
+                backtrace: None,
+            });
+        }
+
+        if format.is_none() && self.allowed.is_some() {
+            return Err(ErrorResponse {
+                code: None,
+                kind: ErrorKind::UnsupportedFormat,
+                message: "unable to determine the input format".to_string(),
+                backtrace: None,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse a comma separated list of format extensions
+fn parse_formats(value: &str) -> anyhow::Result<HashSet<String>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|format| !format.is_empty())
+        .map(|format| {
+            normalize_extension(format).with_context(|| format!("invalid format \"{format}\""))
+        })
+        .collect()
+}
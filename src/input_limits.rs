@@ -0,0 +1,117 @@
+use std::{fmt::Write, path::Path};
+
+use crate::{ErrorKind, ErrorResponse, error_backtrace};
+
+/// Default maximum uncompressed size of ZIP based inputs in megabytes,
+/// matches the largest limit used by the ONLYOFFICE DocumentServer
+pub const DEFAULT_MAX_UNCOMPRESSED_SIZE: u64 = 300;
+
+/// Input format groups the x2t input limits are applied to, x2t matches the
+/// limit for a file by its extension
+const ZIP_INPUT_TYPES: &[&str] = &[
+    "docx;dotx;docm;dotm;odt;ott",
+    "xlsx;xltx;xlsm;xltm;ods;ots",
+    "pptx;ppsx;potx;pptm;ppsm;potm;odp;otp",
+];
+
+/// Limits on the complexity of input documents, guards against small
+/// compressed inputs (i.e ZIP bombs) expanding into huge conversions
+#[derive(Debug, Default, Clone, Copy)]
+pub struct InputLimits {
+    /// Maximum total uncompressed size in bytes of the entries in a ZIP
+    /// based input (OOXML, ODF)
+    pub max_uncompressed_size: Option<u64>,
+}
+
+impl InputLimits {
+    /// Create the x2t config (m_oInputLimits) for the limits so that x2t
+    /// enforces them as well, x2t fails with the limits error code when an
+    /// input exceeds them
+    pub fn config(&self) -> String {
+        let Some(max_uncompressed_size) = self.max_uncompressed_size else {
+            return String::new();
+        };
+
+        let mut config = String::from("<m_oInputLimits>");
+
+        for input_type in ZIP_INPUT_TYPES {
+            _ = write!(
+                config,
+                r#"<m_oInputLimit type="{input_type}"><m_oZip uncompressed="{max_uncompressed_size}" template="*.xml"/></m_oInputLimit>"#
+            );
+        }
+
+        config.push_str("</m_oInputLimits>");
+        config
+    }
+
+    /// Check a ZIP based input against the limits before it is converted
+    /// using the uncompressed sizes declared in the ZIP central directory,
+    /// inputs that aren't ZIP files are not checked
+    ///
+    /// ## Arguments
+    /// * `path` - Path to the input file
+    /// * `header` - Start of the input file
+    pub async fn check(&self, path: &Path, header: &[u8]) -> Result<(), ErrorResponse> {
+        let Some(max_uncompressed_size) = self.max_uncompressed_size else {
+            return Ok(());
+        };
+
+        if !header.starts_with(b"PK\x03\x04") {
+            return Ok(());
+        }
+
+        let path = path.to_path_buf();
+        let uncompressed_size = tokio::task::spawn_blocking(move || zip_uncompressed_size(&path))
+            .await
+            .map_err(|err| {
+                tracing::error!(?err, "failed to check input size");
+                ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::Internal,
+                    message: "failed to check input size".to_string(),
+                    backtrace: error_backtrace(&err),
+                }
+            })?;
+
+        // Invalid archives are left for x2t to report as corrupted
+        let Some(uncompressed_size) = uncompressed_size else {
+            return Ok(());
+        };
+
+        if uncompressed_size > max_uncompressed_size {
+            tracing::warn!(
+                uncompressed_size,
+                max_uncompressed_size,
+                "rejected input exceeding the uncompressed size limit"
+            );
+
+            return Err(ErrorResponse {
+                code: None,
+                kind: ErrorKind::ResourceLimit,
+                message: format!(
+                    "input exceeds the uncompressed size limit of {} MB",
+                    max_uncompressed_size / (1024 * 1024)
+                ),
+                backtrace: None,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Total uncompressed size of the entries in a ZIP file, None when the file
+/// isn't a valid ZIP file
+fn zip_uncompressed_size(path: &Path) -> Option<u64> {
+    let file = std::fs::File::open(path).ok()?;
+    let mut archive = zip::ZipArchive::new(file).ok()?;
+
+    let mut total: u64 = 0;
+    for index in 0..archive.len() {
+        let entry = archive.by_index_raw(index).ok()?;
+        total = total.saturating_add(entry.size());
+    }
+
+    Some(total)
+}
@@ -0,0 +1,62 @@
+use axum::{Extension, Json, extract::Multipart};
+use office_file_inspect::{Confidence, FileVerdict};
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::{
+    ErrorKind, ErrorResponse, RuntimeConfig,
+    convert::{create_convert_temp_paths, read_file_sample},
+    error_backtrace,
+    upload::read_file_upload,
+};
+
+#[derive(Serialize)]
+pub struct InspectResponse {
+    /// File extension of the detected format, null when the format is unknown
+    detected_format: Option<&'static str>,
+    /// Whether the file appears to be password protected
+    likely_encrypted: bool,
+    /// Whether the file appears to be corrupted
+    likely_corrupted: bool,
+    /// How much the encrypted and corrupted checks can be relied on
+    confidence: Confidence,
+    /// Signal that marked the file as encrypted or corrupted
+    reason: Option<String>,
+    /// Size of the file in bytes
+    size: u64,
+}
+
+/// POST /inspect
+///
+/// Checks the format and condition of an uploaded file without converting it,
+/// allowing callers to cheaply validate files before converting them
+pub async fn inspect(
+    Extension(runtime_config): Extension<Arc<RuntimeConfig>>,
+    multipart: Multipart,
+) -> Result<Json<InspectResponse>, ErrorResponse> {
+    let temp_paths = create_convert_temp_paths(&runtime_config).await?;
+
+    read_file_upload(multipart, &temp_paths).await?;
+
+    let sample = read_file_sample(&temp_paths.input_path)
+        .await
+        .map_err(|err| {
+            tracing::error!(?err, "failed to read uploaded file");
+            ErrorResponse {
+                code: None,
+                kind: ErrorKind::Internal,
+                message: "failed to read uploaded file".to_string(),
+                backtrace: error_backtrace(&err),
+            }
+        })?;
+    let condition = sample.condition();
+
+    Ok(Json(InspectResponse {
+        detected_format: sample.format(),
+        likely_encrypted: condition.verdict == FileVerdict::LikelyEncrypted,
+        likely_corrupted: condition.verdict == FileVerdict::LikelyCorrupted,
+        confidence: condition.confidence,
+        reason: condition.reason.map(|reason| reason.to_string()),
+        size: sample.size,
+    }))
+}
@@ -0,0 +1,266 @@
+use anyhow::Context;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::convert::X2T_BIN;
+
+/// Name of the manifest listing the builds available from a mirror
+const MANIFEST_NAME: &str = "manifest.json";
+
+/// Maximum time to spend downloading the manifest or a build
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Builds of the x2t converter available from a mirror
+#[derive(Deserialize)]
+struct X2tManifest {
+    builds: Vec<X2tBuild>,
+}
+
+/// Single x2t build, a ZIP archive containing x2t and its libraries
+#[derive(Deserialize)]
+struct X2tBuild {
+    /// Version of the converter (i.e "8.2.1")
+    version: String,
+    /// Platform the build is for as "{os}-{arch}" (i.e "linux-x86_64")
+    platform: String,
+    /// URL of the archive, relative URLs are resolved against the mirror
+    url: String,
+    /// Hex encoded SHA-256 of the archive
+    sha256: String,
+}
+
+/// Options for installing x2t
+pub struct InstallOptions {
+    /// Base URL of the mirror serving the manifest and builds
+    pub mirror: String,
+    /// Version to install, components can be "x" to match any value
+    /// (i.e "8.x") or "latest" for the newest build
+    pub version: String,
+    /// Directory to install x2t to
+    pub dest: PathBuf,
+    /// Whether an existing install at the destination is replaced
+    pub force: bool,
+}
+
+/// Download the newest x2t build matching the requested version for the
+/// current platform from the mirror, verify its checksum and unpack it to
+/// the destination. The build is unpacked next to the destination and moved
+/// into place once complete so a failed install never leaves a partial one
+pub async fn install_x2t(options: InstallOptions) -> anyhow::Result<()> {
+    let InstallOptions {
+        mirror,
+        version,
+        dest,
+        force,
+    } = options;
+
+    if dest.exists() && !force {
+        anyhow::bail!(
+            "{} already exists, use --force to replace it",
+            dest.display()
+        );
+    }
+
+    let http = reqwest::Client::builder()
+        .timeout(DOWNLOAD_TIMEOUT)
+        .build()
+        .context("failed to create http client")?;
+
+    let mirror = mirror.trim_end_matches('/');
+    let manifest_url = format!("{mirror}/{MANIFEST_NAME}");
+
+    let manifest: X2tManifest = http
+        .get(&manifest_url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("failed to download {manifest_url}"))?
+        .json()
+        .await
+        .with_context(|| format!("invalid manifest at {manifest_url}"))?;
+
+    let platform = current_platform();
+    let build = manifest
+        .builds
+        .iter()
+        .filter(|build| build.platform == platform && version_matches(&version, &build.version))
+        .max_by(|a, b| parse_version(&a.version).cmp(&parse_version(&b.version)))
+        .with_context(|| format!("no x2t build matching {version} for {platform}"))?;
+
+    let build_url = if build.url.contains("://") {
+        build.url.clone()
+    } else {
+        format!("{mirror}/{}", build.url.trim_start_matches('/'))
+    };
+
+    println!("downloading x2t {} from {build_url}", build.version);
+
+    let parent = match dest.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    tokio::fs::create_dir_all(&parent)
+        .await
+        .with_context(|| format!("failed to create {}", parent.display()))?;
+
+    let staging_id = Uuid::new_v4();
+    let archive_path = parent.join(format!(".x2t-{staging_id}.zip"));
+    let staging_path = parent.join(format!(".x2t-{staging_id}"));
+
+    let result = async {
+        let checksum = download(&http, &build_url, &archive_path).await?;
+
+        if !checksum.eq_ignore_ascii_case(build.sha256.trim()) {
+            anyhow::bail!(
+                "checksum mismatch for {build_url}, expected {} but got {checksum}",
+                build.sha256
+            );
+        }
+
+        let (archive, staging) = (archive_path.clone(), staging_path.clone());
+        tokio::task::spawn_blocking(move || unpack(&archive, &staging))
+            .await
+            .context("failed to unpack x2t")??;
+
+        replace_dir(&staging_path, &dest).await
+    }
+    .await;
+
+    _ = tokio::fs::remove_file(&archive_path).await;
+
+    if result.is_err() {
+        _ = tokio::fs::remove_dir_all(&staging_path).await;
+    }
+
+    result?;
+
+    println!("installed x2t {} to {}", build.version, dest.display());
+
+    Ok(())
+}
+
+/// Platform builds are published for, as "{os}-{arch}"
+fn current_platform() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+/// Whether a version matches the requested version, missing trailing
+/// components in the request match any value (i.e "8" matches "8.2.1")
+fn version_matches(requested: &str, version: &str) -> bool {
+    let requested = requested.trim();
+    if requested.eq_ignore_ascii_case("latest") {
+        return true;
+    }
+
+    let mut components = version.split('.');
+
+    requested.split('.').all(|expected| {
+        let Some(component) = components.next() else {
+            return false;
+        };
+
+        matches!(expected, "x" | "X" | "*") || expected == component
+    })
+}
+
+/// Numeric components of a version for ordering, non numeric components are
+/// ordered first
+fn parse_version(version: &str) -> Vec<u64> {
+    version
+        .split('.')
+        .map(|component| component.parse().unwrap_or_default())
+        .collect()
+}
+
+/// Download a build to the provided path, returns the hex encoded SHA-256 of
+/// the downloaded archive
+async fn download(http: &reqwest::Client, url: &str, path: &Path) -> anyhow::Result<String> {
+    let mut response = http
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("failed to download {url}"))?;
+
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    let mut hasher = Sha256::new();
+
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .with_context(|| format!("failed to download {url}"))?
+    {
+        hasher.update(&chunk);
+        file.write_all(&chunk)
+            .await
+            .context("failed to write x2t archive")?;
+    }
+
+    file.flush().await.context("failed to write x2t archive")?;
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Unpack the archive into the staging directory, archives containing a
+/// single top level directory have its contents unpacked instead
+fn unpack(archive_path: &Path, staging_path: &Path) -> anyhow::Result<()> {
+    let file = std::fs::File::open(archive_path).context("failed to open x2t archive")?;
+    let mut archive = zip::ZipArchive::new(file).context("x2t archive is not a valid zip")?;
+
+    archive
+        .extract_unwrapped_root_dir(staging_path, zip::read::root_dir_common_filter)
+        .context("failed to unpack x2t archive")?;
+
+    let x2t = staging_path.join(X2T_BIN);
+    if !x2t.is_file() {
+        anyhow::bail!("x2t archive doesn't contain {X2T_BIN}");
+    }
+
+    // Permissions aren't always preserved by the tools creating archives
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut permissions = std::fs::metadata(&x2t)?.permissions();
+        permissions.set_mode(permissions.mode() | 0o755);
+        std::fs::set_permissions(&x2t, permissions)?;
+    }
+
+    Ok(())
+}
+
+/// Move the unpacked build to the destination, an existing install is only
+/// removed once the new one is in place
+async fn replace_dir(staging_path: &Path, dest: &Path) -> anyhow::Result<()> {
+    let previous = dest.with_file_name(format!(".x2t-previous-{}", Uuid::new_v4()));
+    let replacing = tokio::fs::try_exists(dest).await.unwrap_or_default();
+
+    if replacing {
+        tokio::fs::rename(dest, &previous)
+            .await
+            .with_context(|| format!("failed to move aside {}", dest.display()))?;
+    }
+
+    if let Err(err) = tokio::fs::rename(staging_path, dest).await {
+        if replacing {
+            _ = tokio::fs::rename(&previous, dest).await;
+        }
+
+        return Err(err).with_context(|| format!("failed to move x2t to {}", dest.display()));
+    }
+
+    if replacing && let Err(err) = tokio::fs::remove_dir_all(&previous).await {
+        tracing::warn!(?err, "failed to remove previous x2t install");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+/// Default age after which files in the temporary directory are deleted
+pub const DEFAULT_TEMP_MAX_AGE: u64 = 60 * 60 * 24;
+
+/// Interval between sweeps of the temporary directory
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 10);
+
+/// Deletes files left behind in the temporary directories, such as the files
+/// of conversions that were interrupted by the server crashing
+pub struct TempJanitor {
+    /// Temporary directories to sweep, directories nested within another are
+    /// swept on their own rather than deleted
+    temp_paths: Vec<PathBuf>,
+    /// Age after which files are considered orphaned
+    max_age: Duration,
+}
+
+impl TempJanitor {
+    pub fn new(temp_paths: Vec<PathBuf>, max_age: Duration) -> Self {
+        Self {
+            temp_paths,
+            max_age,
+        }
+    }
+
+    /// Delete the files in the temporary directories that are older than the
+    /// maximum age, returns the number of deleted entries
+    pub async fn sweep(&self) -> std::io::Result<usize> {
+        let mut deleted = 0;
+
+        for temp_path in &self.temp_paths {
+            deleted += self.sweep_dir(temp_path).await?;
+        }
+
+        Ok(deleted)
+    }
+
+    async fn sweep_dir(&self, temp_path: &Path) -> std::io::Result<usize> {
+        let mut entries = match tokio::fs::read_dir(temp_path).await {
+            Ok(entries) => entries,
+            // Directory is created by the first conversion
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err),
+        };
+
+        let now = SystemTime::now();
+        let mut deleted = 0;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = match entry.metadata().await {
+                Ok(metadata) => metadata,
+                // Entry was removed while sweeping
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+
+            let age = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .unwrap_or_default();
+
+            if age < self.max_age {
+                continue;
+            }
+
+            let path = entry.path();
+            if self.temp_paths.contains(&path) {
+                continue;
+            }
+
+            let result = if metadata.is_dir() {
+                tokio::fs::remove_dir_all(&path).await
+            } else {
+                tokio::fs::remove_file(&path).await
+            };
+
+            match result {
+                Ok(()) => deleted += 1,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => {
+                    tracing::error!(?err, path = %path.display(), "failed to delete orphaned temp file")
+                }
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    /// Background task that periodically sweeps the temporary directory
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+
+        // First tick completes immediately, the startup sweep has already run
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            self.sweep_logged().await;
+        }
+    }
+
+    /// Sweep the temporary directory logging the outcome
+    pub async fn sweep_logged(&self) {
+        match self.sweep().await {
+            Ok(0) => {}
+            Ok(deleted) => tracing::info!(deleted, "deleted orphaned temp files"),
+            Err(err) => tracing::error!(?err, "failed to sweep temp directory"),
+        }
+    }
+}
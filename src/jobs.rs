@@ -0,0 +1,736 @@
+use axum::{
+    Extension, Json,
+    body::Body,
+    extract::{Multipart, Path, Query, RawQuery},
+    http::{HeaderMap, HeaderValue, Response, StatusCode, header},
+    response::{
+        IntoResponse,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use futures_util::{Stream, stream};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    io::SeekFrom,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::watch,
+};
+use tokio_util::{io::ReaderStream, sync::CancellationToken};
+use uuid::Uuid;
+
+use crate::{
+    ErrorKind, ErrorResponse, RuntimeConfig,
+    auth::AdminAccess,
+    checksum::{OUTPUT_CHECKSUM_HEADER, output_checksum, verify_input_checksum},
+    convert::{ConvertOptions, ConvertTempPaths, convert_file, create_convert_temp_paths},
+    deadline::RequestDeadline,
+    disposition::{attachment, output_file_name},
+    error_backtrace,
+    format::OutputFormat,
+    limiter::QueueTicket,
+    range::{RangeRequest, checksum_etag, requested_range, unsatisfiable_content_range},
+    upload::read_convert_upload,
+    webhook::WebhookSender,
+};
+
+/// Interval between checks for expired job results
+const JOB_CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    /// Job is waiting for a conversion slot
+    Queued,
+    /// Job is being converted
+    Running,
+    /// Job has finished and the result is available
+    Completed,
+    /// Job failed to convert
+    Failed,
+    /// Job was cancelled before it finished
+    Cancelled,
+}
+
+/// Progress stage of a job reported to event stream subscribers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum JobStage {
+    /// Job is waiting for a conversion slot
+    Queued,
+    /// Conversion has started
+    Started,
+    /// Conversion finished and the output is being written
+    WritingOutput,
+    /// Job has finished and the result is available
+    Done,
+    /// Job failed to convert
+    Failed,
+    /// Job was cancelled before it finished
+    Cancelled,
+}
+
+impl JobStage {
+    /// Whether the stage is final and no more stages will follow
+    fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobStage::Done | JobStage::Failed | JobStage::Cancelled
+        )
+    }
+}
+
+struct Job {
+    status: JobStatus,
+    /// Current progress stage, subscribed to by event streams
+    stage: watch::Sender<JobStage>,
+    output_format: OutputFormat,
+    /// Name the result is downloaded as
+    output_name: String,
+    created_at: Instant,
+    finished_at: Option<Instant>,
+    /// Path to the converted file once completed
+    result_path: Option<PathBuf>,
+    /// Hex encoded SHA-256 of the converted file once completed
+    output_checksum: Option<String>,
+    /// Error that caused the job to fail
+    error: Option<ErrorResponse>,
+    /// Token cancelled to stop the job
+    cancel: CancellationToken,
+}
+
+/// Reasons a job could not be cancelled
+enum CancelError {
+    /// Job does not exist or has expired
+    NotFound,
+    /// Job has already finished
+    Finished,
+}
+
+/// Store for asynchronous conversion jobs
+pub struct JobStore {
+    jobs: Mutex<HashMap<Uuid, Job>>,
+    /// Time to keep finished job results around for
+    result_ttl: Duration,
+}
+
+impl JobStore {
+    pub fn new(result_ttl: Duration) -> Self {
+        Self {
+            jobs: Default::default(),
+            result_ttl,
+        }
+    }
+
+    /// Add a new queued job, returns the token that is cancelled when the
+    /// job is cancelled
+    fn insert(
+        &self,
+        id: Uuid,
+        output_format: OutputFormat,
+        output_name: String,
+    ) -> CancellationToken {
+        let cancel = CancellationToken::new();
+        let job = Job {
+            status: JobStatus::Queued,
+            stage: watch::Sender::new(JobStage::Queued),
+            output_format,
+            output_name,
+            created_at: Instant::now(),
+            finished_at: None,
+            result_path: None,
+            output_checksum: None,
+            error: None,
+            cancel: cancel.clone(),
+        };
+
+        self.jobs
+            .lock()
+            .expect("job store lock poisoned")
+            .insert(id, job);
+
+        cancel
+    }
+
+    /// Update a job, cancelled and removed jobs are left unchanged. Returns
+    /// whether the job was updated
+    fn update(&self, id: Uuid, action: impl FnOnce(&mut Job)) -> bool {
+        if let Some(job) = self
+            .jobs
+            .lock()
+            .expect("job store lock poisoned")
+            .get_mut(&id)
+            && job.status != JobStatus::Cancelled
+        {
+            action(job);
+            return true;
+        }
+
+        false
+    }
+
+    fn set_running(&self, id: Uuid) {
+        self.update(id, |job| {
+            job.status = JobStatus::Running;
+            job.stage.send_replace(JobStage::Started);
+        });
+    }
+
+    fn set_writing_output(&self, id: Uuid) {
+        self.update(id, |job| {
+            job.stage.send_replace(JobStage::WritingOutput);
+        });
+    }
+
+    /// Store the result of a job, returns false when the job was cancelled
+    /// or removed in which case nothing references the result
+    fn set_completed(&self, id: Uuid, result: JobResult) -> bool {
+        self.update(id, |job| {
+            job.status = JobStatus::Completed;
+            job.stage.send_replace(JobStage::Done);
+            job.finished_at = Some(Instant::now());
+            job.result_path = Some(result.path);
+            job.output_checksum = Some(result.checksum);
+        })
+    }
+
+    fn set_failed(&self, id: Uuid, error: ErrorResponse) {
+        self.update(id, |job| {
+            job.status = JobStatus::Failed;
+            job.stage.send_replace(JobStage::Failed);
+            job.finished_at = Some(Instant::now());
+            job.error = Some(error);
+        });
+    }
+
+    /// Cancel a queued or running job, stopping its conversion
+    fn cancel(&self, id: Uuid) -> Result<(), CancelError> {
+        let mut jobs = self.jobs.lock().expect("job store lock poisoned");
+        let job = jobs.get_mut(&id).ok_or(CancelError::NotFound)?;
+
+        if job.finished_at.is_some() {
+            return Err(CancelError::Finished);
+        }
+
+        job.status = JobStatus::Cancelled;
+        job.stage.send_replace(JobStage::Cancelled);
+        job.finished_at = Some(Instant::now());
+        job.cancel.cancel();
+
+        Ok(())
+    }
+
+    /// Removes finished jobs that are older than the result TTL, returns
+    /// the result files that should be deleted
+    fn remove_expired(&self) -> Vec<PathBuf> {
+        let mut jobs = self.jobs.lock().expect("job store lock poisoned");
+        let mut expired_paths = Vec::new();
+
+        jobs.retain(|_, job| {
+            let expired = job
+                .finished_at
+                .is_some_and(|finished_at| finished_at.elapsed() >= self.result_ttl);
+
+            if expired && let Some(path) = job.result_path.take() {
+                expired_paths.push(path);
+            }
+
+            !expired
+        });
+
+        expired_paths
+    }
+
+    /// Background task that periodically removes expired job results
+    pub async fn run_cleanup(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(JOB_CLEANUP_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            for path in self.remove_expired() {
+                if let Err(err) = tokio::fs::remove_file(&path).await {
+                    tracing::error!(?err, "failed to delete expired job result");
+                }
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct JobResponse {
+    /// Unique ID of the job
+    id: Uuid,
+    /// Current job status
+    status: JobStatus,
+    /// Number of seconds since the job was created
+    elapsed: u64,
+    /// Number of seconds until the job result expires, once finished
+    expires_in: Option<u64>,
+    /// Hex encoded SHA-256 of the converted file, once completed
+    output_checksum: Option<String>,
+    /// Error that caused the job to fail
+    error: Option<ErrorResponse>,
+}
+
+/// Converted file of a completed job
+struct JobResult {
+    /// Path the converted file was persisted to
+    path: PathBuf,
+    /// Hex encoded SHA-256 of the converted file
+    checksum: String,
+}
+
+/// Query parameters specific to creating a job
+#[derive(Deserialize)]
+pub struct CreateJobQuery {
+    /// URL to POST a notification to once the job finishes
+    callback_url: Option<String>,
+}
+
+fn job_not_found() -> (StatusCode, ErrorResponse) {
+    (
+        StatusCode::NOT_FOUND,
+        ErrorResponse {
+            code: None,
+            kind: ErrorKind::NotFound,
+            message: "job not found".to_string(),
+            backtrace: None,
+        },
+    )
+}
+
+/// POST /jobs
+///
+/// Queues the provided file for conversion in the background responding
+/// with the details of the created job
+#[allow(clippy::too_many_arguments)]
+pub async fn create_job(
+    Extension(runtime_config): Extension<Arc<RuntimeConfig>>,
+    Extension(job_store): Extension<Arc<JobStore>>,
+    Extension(webhook_sender): Extension<Arc<WebhookSender>>,
+    admin_access: AdminAccess,
+    RequestDeadline(deadline): RequestDeadline,
+    queue_ticket: QueueTicket,
+    Query(CreateJobQuery { callback_url }): Query<CreateJobQuery>,
+    RawQuery(query): RawQuery,
+    multipart: Multipart,
+) -> Result<(StatusCode, Json<JobResponse>), ErrorResponse> {
+    if let Some(callback_url) = &callback_url
+        && let Err(message) = webhook_sender.check_callback_url(callback_url).await
+    {
+        return Err(ErrorResponse {
+            code: None,
+            kind: ErrorKind::InvalidRequest,
+            message,
+            backtrace: None,
+        });
+    }
+
+    let mut temp_paths = create_convert_temp_paths(&runtime_config).await?;
+
+    let mut upload = read_convert_upload(query, multipart, &temp_paths).await?;
+    tracing::debug!(size = upload.size, "received file for conversion job");
+    queue_ticket.set_input_size(upload.size);
+
+    if let Some(input_checksum) = upload.fields.input_checksum.take() {
+        verify_input_checksum(&temp_paths.input_path, &input_checksum).await?;
+    }
+
+    let mut options = upload.fields.into_options(runtime_config.default_pdfa)?;
+    admin_access.authorize(&options)?;
+    // Jobs that don't start or finish before the deadline fail with a timeout
+    options.deadline = deadline;
+
+    temp_paths
+        .resolve_input_extension(
+            &runtime_config,
+            options.input_format.as_deref(),
+            upload.file_name.as_deref(),
+        )
+        .await?;
+
+    let output_format = options.output_format;
+    // Jobs share the ID of their conversion so they can be found in the admin listing
+    let id = queue_ticket.id();
+    let output_name = output_file_name(upload.file_name.as_deref(), output_format.extension());
+    let cancel = job_store.insert(id, output_format, output_name);
+    let result_path = runtime_config.temp_path.join(format!(
+        "job_result_{}.{}",
+        id.simple(),
+        output_format.extension()
+    ));
+
+    tokio::spawn({
+        let job_store = job_store.clone();
+
+        async move {
+            let conversion = async {
+                // Wait for a free conversion slot
+                let _permit = queue_ticket
+                    .acquire_before(options.priority, options.deadline)
+                    .await?;
+                job_store.set_running(id);
+
+                run_job(
+                    &runtime_config,
+                    &job_store,
+                    id,
+                    &temp_paths,
+                    &options,
+                    &result_path,
+                )
+                .await
+            };
+
+            // Dropping the conversion when cancelled kills x2t and releases the slot
+            let result = tokio::select! {
+                result = conversion => Some(result),
+                _ = cancel.cancelled() => None,
+            };
+
+            drop(temp_paths);
+
+            // Whether the result was stored, jobs can be cancelled or removed
+            // after their conversion finished
+            let result =
+                result.map(|result| result.map(|result| job_store.set_completed(id, result)));
+
+            let (status, error) = match result {
+                Some(Ok(true)) => (JobStatus::Completed, None),
+                Some(Err(err)) => {
+                    job_store.set_failed(id, err.clone());
+                    (JobStatus::Failed, Some(err))
+                }
+                // Cancelled while the result was being written or after it
+                // was written, nothing references the result
+                _ => {
+                    tracing::debug!(%id, "conversion job cancelled");
+                    remove_job_result(&result_path).await;
+                    (JobStatus::Cancelled, None)
+                }
+            };
+
+            if let Some(callback_url) = callback_url {
+                webhook_sender
+                    .send(&callback_url, id, status, error.as_ref())
+                    .await;
+            }
+        }
+    });
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(JobResponse {
+            id,
+            status: JobStatus::Queued,
+            elapsed: 0,
+            expires_in: None,
+            output_checksum: None,
+            error: None,
+        }),
+    ))
+}
+
+/// Converts the file for a job, persisting the result until it is
+/// downloaded or expires
+async fn run_job(
+    runtime_config: &RuntimeConfig,
+    job_store: &JobStore,
+    id: Uuid,
+    temp_paths: &ConvertTempPaths,
+    options: &ConvertOptions,
+    result_path: &std::path::Path,
+) -> Result<JobResult, ErrorResponse> {
+    let output_file = convert_file(runtime_config, temp_paths, options).await?;
+    job_store.set_writing_output(id);
+
+    let checksum = output_checksum(output_file.path()).await?;
+
+    output_file.persist(result_path).await.map_err(|err| {
+        tracing::error!(?err, "failed to write job result");
+        ErrorResponse {
+            code: None,
+            kind: ErrorKind::Internal,
+            message: "failed to write job result".to_string(),
+            backtrace: error_backtrace(&err),
+        }
+    })?;
+
+    Ok(JobResult {
+        path: result_path.to_path_buf(),
+        checksum,
+    })
+}
+
+/// Delete the result of a job that is no longer referenced, the result may
+/// not have been written yet
+async fn remove_job_result(path: &std::path::Path) {
+    if let Err(err) = tokio::fs::remove_file(path).await
+        && err.kind() != std::io::ErrorKind::NotFound
+    {
+        tracing::error!(?err, "failed to delete cancelled job result");
+    }
+}
+
+/// GET /jobs/:id
+///
+/// Responds with the current status of a job
+pub async fn get_job(
+    Extension(job_store): Extension<Arc<JobStore>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<JobResponse>, (StatusCode, ErrorResponse)> {
+    let jobs = job_store.jobs.lock().expect("job store lock poisoned");
+    let job = jobs.get(&id).ok_or_else(job_not_found)?;
+
+    let expires_in = job.finished_at.map(|finished_at| {
+        job_store
+            .result_ttl
+            .saturating_sub(finished_at.elapsed())
+            .as_secs()
+    });
+
+    Ok(Json(JobResponse {
+        id,
+        status: job.status,
+        elapsed: job.created_at.elapsed().as_secs(),
+        expires_in,
+        output_checksum: job.output_checksum.clone(),
+        error: job.error.clone(),
+    }))
+}
+
+/// DELETE /jobs/:id
+///
+/// Cancels a queued or running job, stopping its conversion and
+/// removing its temporary files
+pub async fn cancel_job(
+    Extension(job_store): Extension<Arc<JobStore>>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, ErrorResponse)> {
+    job_store.cancel(id).map_err(|err| match err {
+        CancelError::NotFound => job_not_found(),
+        CancelError::Finished => (
+            StatusCode::CONFLICT,
+            ErrorResponse {
+                code: None,
+                kind: ErrorKind::Conflict,
+                message: "job has already finished".to_string(),
+                backtrace: None,
+            },
+        ),
+    })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Payload of a job event
+#[derive(Serialize)]
+struct JobEventPayload {
+    /// Current stage of the job
+    stage: JobStage,
+    /// Error that caused the job to fail
+    error: Option<ErrorResponse>,
+}
+
+/// GET /jobs/:id/events
+///
+/// Server-sent event stream of the progress of a job, a "stage" event is sent
+/// with the current stage and whenever the stage changes. The stream ends once
+/// the job has finished
+pub async fn get_job_events(
+    Extension(job_store): Extension<Arc<JobStore>>,
+    Path(id): Path<Uuid>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, ErrorResponse)> {
+    let stages = {
+        let jobs = job_store.jobs.lock().expect("job store lock poisoned");
+        let job = jobs.get(&id).ok_or_else(job_not_found)?;
+        job.stage.subscribe()
+    };
+
+    // Receiver is marked as changed so the current stage is sent first
+    let mut stages = stages;
+    stages.mark_changed();
+
+    let events = stream::unfold(Some(stages), move |stages| {
+        let job_store = job_store.clone();
+
+        async move {
+            let mut stages = stages?;
+
+            // Sender is dropped when the job expires
+            stages.changed().await.ok()?;
+            let stage = *stages.borrow_and_update();
+
+            let error = match stage {
+                JobStage::Failed => job_store
+                    .jobs
+                    .lock()
+                    .expect("job store lock poisoned")
+                    .get(&id)
+                    .and_then(|job| job.error.clone()),
+                _ => None,
+            };
+
+            let event = Event::default()
+                .event("stage")
+                .json_data(JobEventPayload { stage, error })
+                .unwrap_or_else(|err| {
+                    tracing::error!(?err, "failed to serialize job event");
+                    Event::default().event("stage")
+                });
+
+            // Stop after sending the final stage
+            let next = (!stage.is_finished()).then_some(stages);
+
+            Some((Ok(event), next))
+        }
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// GET /jobs/:id/result
+///
+/// Responds with the converted file for a completed job
+pub async fn get_job_result(
+    Extension(job_store): Extension<Arc<JobStore>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, (StatusCode, ErrorResponse)> {
+    let (result_path, output_format, output_name, checksum) = {
+        let jobs = job_store.jobs.lock().expect("job store lock poisoned");
+        let job = jobs.get(&id).ok_or_else(job_not_found)?;
+
+        match (job.status, &job.result_path, &job.error) {
+            (JobStatus::Completed, Some(result_path), _) => (
+                result_path.clone(),
+                job.output_format,
+                job.output_name.clone(),
+                job.output_checksum.clone(),
+            ),
+            (JobStatus::Failed, _, Some(error)) => {
+                return Err((error.kind.status_code(), error.clone()));
+            }
+            (JobStatus::Cancelled, _, _) => {
+                return Err((
+                    StatusCode::CONFLICT,
+                    ErrorResponse {
+                        code: None,
+                        kind: ErrorKind::Conflict,
+                        message: "job was cancelled".to_string(),
+                        backtrace: None,
+                    },
+                ));
+            }
+            _ => {
+                return Err((
+                    StatusCode::CONFLICT,
+                    ErrorResponse {
+                        code: None,
+                        kind: ErrorKind::Conflict,
+                        message: "job has not finished".to_string(),
+                        backtrace: None,
+                    },
+                ));
+            }
+        }
+    };
+
+    let read_error = |err: std::io::Error| {
+        tracing::error!(?err, "failed to open job result");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorResponse {
+                code: None,
+                kind: ErrorKind::Internal,
+                message: "failed to read job result".to_string(),
+                backtrace: error_backtrace(&err),
+            },
+        )
+    };
+
+    let mut file = tokio::fs::File::open(&result_path)
+        .await
+        .map_err(read_error)?;
+    let size = file.metadata().await.map_err(read_error)?.len();
+
+    let etag = checksum.as_deref().and_then(checksum_etag);
+
+    let (status, length, content_range) = match requested_range(&headers, size, etag.as_ref()) {
+        RangeRequest::Full => (StatusCode::OK, size, None),
+        RangeRequest::Partial(range) => {
+            file.seek(SeekFrom::Start(range.start))
+                .await
+                .map_err(read_error)?;
+            (
+                StatusCode::PARTIAL_CONTENT,
+                range.length(),
+                Some(range.content_range(size)),
+            )
+        }
+        RangeRequest::Unsatisfiable => {
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, unsatisfiable_content_range(size))],
+                Json(ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::InvalidRequest,
+                    message: "requested range is outside of the job result".to_string(),
+                    backtrace: None,
+                }),
+            )
+                .into_response());
+        }
+    };
+
+    let mut response = Response::builder()
+        .status(status)
+        .header(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(output_format.content_type()),
+        )
+        .header(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"))
+        .header(header::CONTENT_LENGTH, length);
+
+    if let Some(content_range) = content_range {
+        response = response.header(header::CONTENT_RANGE, content_range);
+    }
+
+    if let Some(etag) = etag {
+        response = response.header(header::ETAG, etag);
+    }
+
+    if let Some(policy) = output_format.content_security_policy() {
+        response = response.header(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static(policy),
+        );
+    }
+
+    if let Some(checksum) = checksum {
+        response = response.header(OUTPUT_CHECKSUM_HEADER, checksum);
+    }
+
+    response
+        .header(header::CONTENT_DISPOSITION, attachment(&output_name))
+        .body(Body::from_stream(ReaderStream::new(file.take(length))))
+        .map_err(|err| {
+            tracing::error!(?err, "failed to make response");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::Internal,
+                    message: "failed to make response".to_string(),
+                    backtrace: error_backtrace(&err),
+                },
+            )
+        })
+}
@@ -0,0 +1,103 @@
+use anyhow::Context;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::process::Command;
+
+use crate::{format::OutputFormat, limits::ProcessLimits};
+
+/// x2t error codes that are retried with LibreOffice, x2t doesn't support
+/// the input format (UNKNOWN_FORMAT) or requires LibreOffice to convert it
+/// (CONVERT_LIBREOFFICE)
+const FALLBACK_ERROR_CODES: &[i32] = &[0x0052, 0x0057];
+
+/// Maximum time LibreOffice can take to convert a file
+const LIBREOFFICE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Converts files that x2t rejects using a LibreOffice install
+#[derive(Debug)]
+pub struct LibreOffice {
+    /// Path to the soffice binary
+    soffice_path: PathBuf,
+}
+
+impl LibreOffice {
+    pub fn new(soffice_path: PathBuf) -> Self {
+        Self { soffice_path }
+    }
+
+    /// Whether a conversion that failed with the provided x2t error code
+    /// should be retried with LibreOffice
+    pub fn should_fallback(&self, error_code: Option<i32>, output_format: OutputFormat) -> bool {
+        output_format == OutputFormat::Pdf
+            && error_code.is_some_and(|code| FALLBACK_ERROR_CODES.contains(&code))
+    }
+
+    /// Convert the input file to PDF
+    ///
+    /// ## Arguments
+    /// * `work_dir` - Directory for the LibreOffice profile and output
+    /// * `input_path` - Path to the file to convert
+    /// * `output_path` - Path to write the converted file to
+    /// * `limits` - Resource limits applied to the LibreOffice process
+    pub async fn convert(
+        &self,
+        work_dir: &Path,
+        input_path: &Path,
+        output_path: &Path,
+        limits: &ProcessLimits,
+    ) -> anyhow::Result<()> {
+        // Each conversion uses its own profile, LibreOffice refuses to run
+        // concurrently with a shared profile
+        let profile_path = work_dir.join("libreoffice-profile");
+        let output_dir = work_dir.join("libreoffice-output");
+
+        let mut command = Command::new(&self.soffice_path);
+        command
+            .arg(format!("-env:UserInstallation={}", file_url(&profile_path)))
+            .args(["--headless", "--norestore", "--nolockcheck"])
+            .args(["--convert-to", "pdf", "--outdir"])
+            .arg(&output_dir)
+            .arg(input_path)
+            .kill_on_drop(true);
+
+        limits.apply(&mut command);
+
+        let output = tokio::time::timeout(LIBREOFFICE_TIMEOUT, command.output())
+            .await
+            .context("libreoffice timed out")?
+            .context("failed to run libreoffice")?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "libreoffice exited with {} (stderr = {})",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        // LibreOffice names the output after the input file
+        let file_stem = input_path.file_stem().context("input path has no name")?;
+        let converted_path = output_dir.join(file_stem).with_extension("pdf");
+
+        tokio::fs::rename(&converted_path, output_path)
+            .await
+            .context("libreoffice didn't produce an output file")?;
+
+        Ok(())
+    }
+}
+
+/// Create a file URL for a path, LibreOffice expects the profile location
+/// as a URL
+fn file_url(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let path = path.replace('%', "%25").replace(' ', "%20");
+
+    if path.starts_with('/') {
+        format!("file://{path}")
+    } else {
+        format!("file:///{path}")
+    }
+}
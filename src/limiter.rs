@@ -0,0 +1,1131 @@
+use axum::{
+    Extension, Json, async_trait,
+    extract::FromRequestParts,
+    http::{HeaderValue, StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::oneshot;
+use tokio_util::task::{TaskTracker, task_tracker::TaskTrackerToken};
+use uuid::Uuid;
+
+use crate::{ErrorKind, ErrorResponse, deadline::deadline_exceeded};
+
+/// Number of seconds clients are told to wait before retrying when the queue is full
+const RETRY_AFTER_SECONDS: u64 = 5;
+
+/// Error code for conversions rejected because every slot and the queue are
+/// full, outside the range used by x2t error codes
+pub const SATURATED_ERROR_CODE: i32 = 0x1000;
+
+/// Default fraction of the conversion slots reserved for the fast lane
+pub const DEFAULT_FAST_LANE_SLOTS: f64 = 0.25;
+
+/// Time a conversion waits in the queue before it is treated as the next
+/// priority level up, prevents low priority conversions from starving
+const PRIORITY_AGING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Priority of a conversion in the queue
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    /// Bulk and background conversions
+    Low,
+    #[default]
+    Normal,
+    /// Interactive user facing conversions
+    High,
+}
+
+impl Priority {
+    pub fn from_name(name: &str) -> Option<Priority> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "low" => Priority::Low,
+            "normal" => Priority::Normal,
+            "high" => Priority::High,
+            _ => return None,
+        })
+    }
+
+    /// Priority used for scheduling, raised by one level for every
+    /// [PRIORITY_AGING_INTERVAL] spent waiting
+    fn effective(&self, queued_at: Instant) -> u64 {
+        let aged_levels = queued_at.elapsed().as_secs() / PRIORITY_AGING_INTERVAL.as_secs();
+        *self as u64 + aged_levels
+    }
+}
+
+/// Conversion slots reserved for small inputs so that interactive
+/// conversions of small documents aren't stuck behind large conversions
+#[derive(Debug, Clone, Copy)]
+pub struct FastLane {
+    /// Inputs up to this size in bytes can use the reserved slots
+    pub max_input_size: u64,
+    /// Fraction of the conversion slots reserved for small inputs
+    pub reserved_fraction: f64,
+}
+
+impl FastLane {
+    /// Whether an input of the provided size can use the reserved slots,
+    /// inputs of an unknown size are treated as large
+    fn is_small(&self, input_size: Option<u64>) -> bool {
+        input_size.is_some_and(|input_size| input_size <= self.max_input_size)
+    }
+
+    /// Number of the slots reserved for small inputs, at least one slot is
+    /// always left for large inputs
+    fn reserved(&self, limit: usize) -> usize {
+        ((limit as f64 * self.reserved_fraction).ceil() as usize).min(limit.saturating_sub(1))
+    }
+}
+
+/// Limits the number of conversions that can run at once, excess conversions
+/// wait in a queue up to a maximum length. Waiting conversions are granted
+/// slots in priority order, then in the order they joined the queue. With a
+/// [FastLane] large conversions can't use the slots reserved for small ones
+pub struct ConversionLimiter {
+    /// Available conversion slots and the conversions waiting for one
+    slots: Mutex<SlotQueue>,
+    /// Number of conversions currently waiting for a slot
+    queued: AtomicUsize,
+    /// Maximum number of conversions allowed to wait
+    max_queued: AtomicUsize,
+    /// Tracks all in-flight conversions (queued and running) for draining
+    tracker: TaskTracker,
+    /// Whether the server is draining and new conversions should be rejected
+    draining: AtomicBool,
+    /// Details of the conversions that are queued or running
+    conversions: Mutex<HashMap<Uuid, ConversionEntry>>,
+    /// Sequence number assigned to the next conversion to join the queue
+    next_sequence: AtomicU64,
+    /// Slots reserved for small inputs
+    fast_lane: Option<FastLane>,
+}
+
+/// Conversion slots that are in use and the conversions waiting for one
+struct SlotQueue {
+    /// Number of slots in use, can exceed the maximum after the limit is
+    /// lowered until enough conversions have finished
+    running: usize,
+    /// Number of slots in use by conversions that can't use the fast lane
+    running_large: usize,
+    /// Slots reserved for small inputs
+    fast_lane: Option<FastLane>,
+    /// Maximum number of conversions that can run at once
+    max_concurrent: usize,
+    /// Lower limit set by the adaptive concurrency controller based on the
+    /// load of the system
+    adaptive_limit: Option<usize>,
+    /// Conversions waiting for a slot
+    waiters: Vec<SlotWaiter>,
+}
+
+/// Conversion waiting for a slot
+struct SlotWaiter {
+    priority: Priority,
+    /// Whether the conversion can't use the fast lane
+    large: bool,
+    sequence: u64,
+    queued_at: Instant,
+    /// Channel the slot is sent over once granted
+    sender: oneshot::Sender<SlotPermit>,
+}
+
+impl SlotQueue {
+    /// Number of conversions that can currently run at once
+    fn limit(&self) -> usize {
+        match self.adaptive_limit {
+            Some(adaptive_limit) => adaptive_limit.min(self.max_concurrent),
+            None => self.max_concurrent,
+        }
+    }
+
+    /// Number of slots that are free
+    fn available(&self) -> usize {
+        self.limit().saturating_sub(self.running)
+    }
+
+    /// Whether a conversion can start in one of the free slots, large
+    /// conversions can't use the slots reserved for the fast lane
+    fn can_start(&self, large: bool) -> bool {
+        if self.available() == 0 {
+            return false;
+        }
+
+        match (large, self.fast_lane) {
+            (true, Some(fast_lane)) => {
+                let limit = self.limit();
+                self.running_large < limit - fast_lane.reserved(limit)
+            }
+            _ => true,
+        }
+    }
+
+    /// Mark a slot as in use
+    fn start(&mut self, large: bool) {
+        self.running += 1;
+        if large {
+            self.running_large += 1;
+        }
+    }
+
+    /// Remove the waiter that should be granted the next slot, None when
+    /// there is no free slot any of the waiters can use
+    fn take_next_waiter(&mut self) -> Option<SlotWaiter> {
+        // Waiters that have given up no longer need a slot
+        self.waiters.retain(|waiter| !waiter.sender.is_closed());
+
+        let index = self
+            .waiters
+            .iter()
+            .enumerate()
+            .filter(|(_, waiter)| self.can_start(waiter.large))
+            .max_by_key(|(_, waiter)| {
+                (
+                    waiter.priority.effective(waiter.queued_at),
+                    std::cmp::Reverse(waiter.sequence),
+                )
+            })
+            .map(|(index, _)| index)?;
+
+        Some(self.waiters.swap_remove(index))
+    }
+}
+
+/// Conversion slot, the slot is released to the next waiter when dropped
+struct SlotPermit {
+    limiter: Option<Arc<ConversionLimiter>>,
+    /// Whether the slot is used by a conversion that can't use the fast lane
+    large: bool,
+}
+
+impl Drop for SlotPermit {
+    fn drop(&mut self) {
+        if let Some(limiter) = self.limiter.take() {
+            limiter.release_slot(self.large);
+        }
+    }
+}
+
+/// Details of a queued or running conversion
+struct ConversionEntry {
+    /// Order the conversion joined the queue in
+    sequence: u64,
+    /// Priority of the conversion, known once it starts waiting for a slot
+    priority: Priority,
+    /// Size of the input file in bytes, once it has been received
+    input_size: Option<u64>,
+    /// When the conversion joined the queue
+    queued_at: Instant,
+    /// When the conversion acquired a slot and started running
+    started_at: Option<Instant>,
+}
+
+/// State of a conversion in the limiter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConversionState {
+    /// Waiting for a free conversion slot
+    Queued,
+    /// Holding a conversion slot
+    Running,
+}
+
+/// Snapshot of a queued or running conversion
+#[derive(Serialize)]
+pub struct ConversionInfo {
+    /// Unique ID of the conversion, matches the job ID for async jobs
+    id: Uuid,
+    /// Current state of the conversion
+    state: ConversionState,
+    /// Priority of the conversion
+    priority: Priority,
+    /// Size of the input file in bytes, once it has been received
+    input_size: Option<u64>,
+    /// Number of seconds since the conversion joined the queue
+    elapsed: u64,
+    /// Number of seconds the conversion has been running for
+    running_for: Option<u64>,
+    /// Position in the queue starting from 1, only present while queued
+    queue_position: Option<usize>,
+}
+
+/// Load on the conversion limiter
+#[derive(Serialize)]
+pub struct LimiterStatus {
+    /// Number of conversions currently running
+    running: usize,
+    /// Maximum number of conversions that can run at once
+    max_concurrent: usize,
+    /// Number of conversions waiting for a slot
+    queued: usize,
+    /// Maximum number of conversions allowed to wait
+    max_queued: usize,
+    /// Whether every slot and the queue are full, new conversions are
+    /// rejected while saturated
+    saturated: bool,
+    /// Whether the server is shutting down
+    draining: bool,
+}
+
+#[derive(Serialize)]
+pub struct ConversionsResponse {
+    jobs: Vec<ConversionInfo>,
+}
+
+/// Error responded with when the queue is full, includes the depth of the
+/// queue so clients can decide whether to retry or try another server
+#[derive(Serialize)]
+struct QueueFullResponse {
+    #[serde(flatten)]
+    error: ErrorResponse,
+    /// Number of conversions waiting for a slot
+    queued: usize,
+    /// Maximum number of conversions allowed to wait
+    max_queued: usize,
+}
+
+/// Reasons a conversion could not join the queue
+#[derive(Debug)]
+pub enum EnqueueError {
+    /// Queue has reached its maximum length
+    QueueFull,
+    /// Server is shutting down and not accepting new conversions
+    Draining,
+}
+
+impl ConversionLimiter {
+    pub fn new(max_concurrent: usize, max_queued: usize, fast_lane: Option<FastLane>) -> Self {
+        Self {
+            slots: Mutex::new(SlotQueue {
+                running: 0,
+                running_large: 0,
+                fast_lane,
+                max_concurrent,
+                adaptive_limit: None,
+                waiters: Vec::new(),
+            }),
+            queued: AtomicUsize::new(0),
+            max_queued: AtomicUsize::new(max_queued),
+            tracker: TaskTracker::new(),
+            draining: AtomicBool::new(false),
+            conversions: Mutex::new(HashMap::new()),
+            next_sequence: AtomicU64::new(0),
+            fast_lane,
+        }
+    }
+
+    /// Attempt to join the queue
+    pub fn try_enqueue(self: &Arc<Self>) -> Result<QueueTicket, EnqueueError> {
+        if self.draining.load(Ordering::SeqCst) {
+            return Err(EnqueueError::Draining);
+        }
+
+        self.queued
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| {
+                (queued < self.max_queued.load(Ordering::SeqCst)).then_some(queued + 1)
+            })
+            .map_err(|_| EnqueueError::QueueFull)?;
+
+        let id = Uuid::new_v4();
+
+        self.conversions
+            .lock()
+            .expect("conversions lock poisoned")
+            .insert(
+                id,
+                ConversionEntry {
+                    sequence: self.next_sequence.fetch_add(1, Ordering::SeqCst),
+                    priority: Priority::default(),
+                    input_size: None,
+                    queued_at: Instant::now(),
+                    started_at: None,
+                },
+            );
+
+        Ok(QueueTicket {
+            limiter: self.clone(),
+            token: self.tracker.token(),
+            registration: Arc::new(ConversionRegistration {
+                limiter: self.clone(),
+                id,
+            }),
+        })
+    }
+
+    /// Current load on the limiter
+    pub fn status(&self) -> LimiterStatus {
+        let (running, max_concurrent) = {
+            let slots = self.slots.lock().expect("slots lock poisoned");
+            (slots.running, slots.limit())
+        };
+        let queued = self.queued.load(Ordering::SeqCst);
+        let max_queued = self.max_queued.load(Ordering::SeqCst);
+
+        LimiterStatus {
+            running,
+            max_concurrent,
+            queued,
+            max_queued,
+            saturated: running >= max_concurrent && queued >= max_queued,
+            draining: self.draining.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Whether the server is draining and rejecting new conversions
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Change the maximum number of running and queued conversions, running
+    /// conversions are never interrupted. When the concurrency limit is
+    /// lowered new conversions wait until enough running conversions finish
+    pub fn set_limits(self: &Arc<Self>, max_concurrent: usize, max_queued: usize) {
+        self.max_queued.store(max_queued, Ordering::SeqCst);
+
+        let mut slots = self.slots.lock().expect("slots lock poisoned");
+        slots.max_concurrent = max_concurrent;
+        self.grant_available_slots(&mut slots);
+    }
+
+    /// Maximum number of conversions that can run at once, ignoring the
+    /// adaptive limit
+    pub fn max_concurrent(&self) -> usize {
+        self.slots
+            .lock()
+            .expect("slots lock poisoned")
+            .max_concurrent
+    }
+
+    /// Number of conversions currently running
+    pub fn running(&self) -> usize {
+        self.slots.lock().expect("slots lock poisoned").running
+    }
+
+    /// Lower the number of conversions that can run at once below the
+    /// maximum, running conversions are never interrupted
+    pub fn set_adaptive_limit(self: &Arc<Self>, adaptive_limit: usize) {
+        let mut slots = self.slots.lock().expect("slots lock poisoned");
+        slots.adaptive_limit = Some(adaptive_limit);
+        self.grant_available_slots(&mut slots);
+    }
+
+    /// Grant the free slots to the waiters that can use them
+    fn grant_available_slots(self: &Arc<Self>, slots: &mut SlotQueue) {
+        while let Some(waiter) = slots.take_next_waiter() {
+            let permit = SlotPermit {
+                limiter: Some(self.clone()),
+                large: waiter.large,
+            };
+
+            match waiter.sender.send(permit) {
+                Ok(()) => slots.start(waiter.large),
+                // Waiter gave up, the permit is disarmed so dropping it
+                // doesn't release the slot again
+                Err(mut permit) => {
+                    permit.limiter.take();
+                }
+            }
+        }
+    }
+
+    /// Release a slot, handing it to the next waiter that can use it
+    fn release_slot(self: Arc<Self>, large: bool) {
+        let mut slots = self.slots.lock().expect("slots lock poisoned");
+
+        slots.running -= 1;
+        if large {
+            slots.running_large -= 1;
+        }
+
+        self.grant_available_slots(&mut slots);
+    }
+
+    /// Update the entry for a conversion
+    fn update_conversion(&self, id: Uuid, update: impl FnOnce(&mut ConversionEntry)) {
+        if let Some(entry) = self
+            .conversions
+            .lock()
+            .expect("conversions lock poisoned")
+            .get_mut(&id)
+        {
+            update(entry);
+        }
+    }
+
+    /// Snapshot of the conversions that are running followed by the
+    /// conversions waiting in the queue in the order they joined
+    pub fn conversions(&self) -> Vec<ConversionInfo> {
+        let conversions = self.conversions.lock().expect("conversions lock poisoned");
+
+        // Queued conversions are listed in the order they will be granted slots
+        let mut entries: Vec<(&Uuid, &ConversionEntry)> = conversions.iter().collect();
+        entries.sort_by_key(|(_, entry)| {
+            (
+                entry.started_at.is_none(),
+                std::cmp::Reverse(entry.priority.effective(entry.queued_at)),
+                entry.sequence,
+            )
+        });
+
+        let mut queue_position = 0;
+
+        entries
+            .into_iter()
+            .map(|(id, entry)| {
+                let state = match entry.started_at {
+                    Some(_) => ConversionState::Running,
+                    None => ConversionState::Queued,
+                };
+
+                let queue_position = match state {
+                    ConversionState::Queued => {
+                        queue_position += 1;
+                        Some(queue_position)
+                    }
+                    ConversionState::Running => None,
+                };
+
+                ConversionInfo {
+                    id: *id,
+                    state,
+                    priority: entry.priority,
+                    input_size: entry.input_size,
+                    elapsed: entry.queued_at.elapsed().as_secs(),
+                    running_for: entry
+                        .started_at
+                        .map(|started_at| started_at.elapsed().as_secs()),
+                    queue_position,
+                }
+            })
+            .collect()
+    }
+
+    /// Stop accepting new conversions and wait for in-flight conversions
+    /// to complete, gives up waiting once the timeout is reached. Returns
+    /// whether every in-flight conversion completed
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.draining.store(true, Ordering::SeqCst);
+        self.tracker.close();
+
+        if self.tracker.is_empty() {
+            return true;
+        }
+
+        tracing::info!(
+            in_flight = self.tracker.len(),
+            "waiting for in-flight conversions to complete"
+        );
+
+        if tokio::time::timeout(timeout, self.tracker.wait())
+            .await
+            .is_err()
+        {
+            tracing::warn!(
+                in_flight = self.tracker.len(),
+                "drain timeout reached, cancelling in-flight conversions"
+            );
+            return false;
+        }
+
+        true
+    }
+}
+
+/// Registration of a conversion in the limiter, the conversion is removed
+/// once the ticket and any permit acquired from it are dropped
+struct ConversionRegistration {
+    limiter: Arc<ConversionLimiter>,
+    id: Uuid,
+}
+
+impl Drop for ConversionRegistration {
+    fn drop(&mut self) {
+        self.limiter
+            .conversions
+            .lock()
+            .expect("conversions lock poisoned")
+            .remove(&self.id);
+    }
+}
+
+/// Place in the conversion queue, the place is released when dropped
+pub struct QueueTicket {
+    limiter: Arc<ConversionLimiter>,
+    token: TaskTrackerToken,
+    registration: Arc<ConversionRegistration>,
+}
+
+/// Permission to run a conversion, the slot is released when dropped
+pub struct ConversionPermit {
+    _permit: SlotPermit,
+    _token: TaskTrackerToken,
+    _registration: Arc<ConversionRegistration>,
+}
+
+impl QueueTicket {
+    /// Unique ID of the conversion
+    pub fn id(&self) -> Uuid {
+        self.registration.id
+    }
+
+    /// Record the size of the input file once it has been received
+    pub fn set_input_size(&self, input_size: u64) {
+        self.limiter
+            .update_conversion(self.registration.id, |entry| {
+                entry.input_size = Some(input_size)
+            });
+    }
+
+    /// Wait for a conversion slot, leaving the queue once one is available.
+    /// Higher priority conversions are granted slots first, large inputs
+    /// can't use the slots reserved for the fast lane
+    pub async fn acquire(self, priority: Priority) -> ConversionPermit {
+        let limiter = &self.limiter;
+
+        limiter.update_conversion(self.registration.id, |entry| entry.priority = priority);
+
+        let (sequence, queued_at, input_size) = limiter
+            .conversions
+            .lock()
+            .expect("conversions lock poisoned")
+            .get(&self.registration.id)
+            .map(|entry| (entry.sequence, entry.queued_at, entry.input_size))
+            .unwrap_or_else(|| (u64::MAX, Instant::now(), None));
+
+        let large = limiter
+            .fast_lane
+            .is_some_and(|fast_lane| !fast_lane.is_small(input_size));
+
+        let receiver = {
+            let mut slots = limiter.slots.lock().expect("slots lock poisoned");
+
+            if slots.can_start(large) {
+                slots.start(large);
+                None
+            } else {
+                let (sender, receiver) = oneshot::channel();
+
+                slots.waiters.push(SlotWaiter {
+                    priority,
+                    large,
+                    sequence,
+                    queued_at,
+                    sender,
+                });
+
+                Some(receiver)
+            }
+        };
+
+        let permit = match receiver {
+            Some(receiver) => receiver
+                .await
+                .expect("slot waiters are only dropped after their receiver"),
+            None => SlotPermit {
+                limiter: Some(limiter.clone()),
+                large,
+            },
+        };
+
+        self.limiter
+            .update_conversion(self.registration.id, |entry| {
+                entry.started_at = Some(Instant::now())
+            });
+
+        ConversionPermit {
+            _permit: permit,
+            _token: self.token.clone(),
+            _registration: self.registration.clone(),
+        }
+    }
+
+    /// Wait for a conversion slot, giving up once the deadline is reached
+    /// so the conversion is rejected instead of starting too late
+    pub async fn acquire_before(
+        self,
+        priority: Priority,
+        deadline: Option<Instant>,
+    ) -> Result<ConversionPermit, ErrorResponse> {
+        let Some(deadline) = deadline else {
+            return Ok(self.acquire(priority).await);
+        };
+
+        // Dropping the acquire future leaves the queue
+        tokio::time::timeout_at(deadline.into(), self.acquire(priority))
+            .await
+            .map_err(|_| deadline_exceeded("conversion couldn't start before the request deadline"))
+    }
+}
+
+impl Drop for QueueTicket {
+    fn drop(&mut self) {
+        self.limiter.queued.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Extracting a queue ticket happens before the request body is read so that
+/// requests are rejected before buffering uploads when the queue is full
+#[async_trait]
+impl<S> FromRequestParts<S> for QueueTicket
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let limiter = parts
+            .extensions
+            .get::<Arc<ConversionLimiter>>()
+            .ok_or_else(|| {
+                tracing::error!("conversion limiter extension is missing");
+                ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::Internal,
+                    message: "conversion limiter is not available".to_string(),
+                    backtrace: None,
+                }
+                .into_response()
+            })?;
+
+        limiter.try_enqueue().map_err(|err| {
+            let mut response = match err {
+                EnqueueError::QueueFull => {
+                    let status = limiter.status();
+
+                    (
+                        StatusCode::TOO_MANY_REQUESTS,
+                        Json(QueueFullResponse {
+                            error: ErrorResponse {
+                                code: Some(SATURATED_ERROR_CODE),
+                                kind: ErrorKind::Busy,
+                                message: "conversion queue is full, try again later".to_string(),
+                                backtrace: None,
+                            },
+                            queued: status.queued,
+                            max_queued: status.max_queued,
+                        }),
+                    )
+                        .into_response()
+                }
+                EnqueueError::Draining => ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::Unavailable,
+                    message: "server is shutting down".to_string(),
+                    backtrace: None,
+                }
+                .into_response(),
+            };
+
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(RETRY_AFTER_SECONDS));
+
+            response
+        })
+    }
+}
+
+/// GET /admin/jobs
+///
+/// List the conversions that are currently running or waiting in the queue
+pub async fn list_conversions(
+    Extension(limiter): Extension<Arc<ConversionLimiter>>,
+) -> Json<ConversionsResponse> {
+    Json(ConversionsResponse {
+        jobs: limiter.conversions(),
+    })
+}
+
+/// GET /status
+///
+/// Current load on the server, including whether it is saturated and
+/// rejecting new conversions
+pub async fn status(Extension(limiter): Extension<Arc<ConversionLimiter>>) -> Json<LimiterStatus> {
+    Json(limiter.status())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::to_bytes, http::Request};
+    use tokio::sync::mpsc;
+
+    fn fast_lane() -> FastLane {
+        FastLane {
+            max_input_size: 1024,
+            reserved_fraction: 0.25,
+        }
+    }
+
+    /// Slot queue with every slot free and no waiters
+    fn slot_queue(max_concurrent: usize, fast_lane: Option<FastLane>) -> SlotQueue {
+        SlotQueue {
+            running: 0,
+            running_large: 0,
+            fast_lane,
+            max_concurrent,
+            adaptive_limit: None,
+            waiters: Vec::new(),
+        }
+    }
+
+    /// Add a waiter to the queue, the receiver must be kept alive for the
+    /// waiter to be considered
+    fn push_waiter(
+        slots: &mut SlotQueue,
+        priority: Priority,
+        large: bool,
+        sequence: u64,
+        queued_at: Instant,
+    ) -> oneshot::Receiver<SlotPermit> {
+        let (sender, receiver) = oneshot::channel();
+        slots.waiters.push(SlotWaiter {
+            priority,
+            large,
+            sequence,
+            queued_at,
+            sender,
+        });
+        receiver
+    }
+
+    /// Queue a conversion that records its name once it is granted a slot,
+    /// returns once the conversion is waiting
+    async fn queue_conversion(
+        limiter: &Arc<ConversionLimiter>,
+        name: &'static str,
+        priority: Priority,
+        input_size: Option<u64>,
+        started: mpsc::UnboundedSender<&'static str>,
+    ) {
+        let ticket = limiter.try_enqueue().unwrap();
+        if let Some(input_size) = input_size {
+            ticket.set_input_size(input_size);
+        }
+
+        let waiting = limiter.slots.lock().unwrap().waiters.len();
+
+        tokio::spawn(async move {
+            let _permit = ticket.acquire(priority).await;
+            started.send(name).unwrap();
+        });
+
+        while limiter.slots.lock().unwrap().waiters.len() == waiting {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    async fn enqueue_response(limiter: &Arc<ConversionLimiter>) -> Result<QueueTicket, Response> {
+        let (mut parts, _) = Request::builder()
+            .extension(limiter.clone())
+            .body(())
+            .unwrap()
+            .into_parts();
+
+        QueueTicket::from_request_parts(&mut parts, &()).await
+    }
+
+    #[test]
+    fn test_priority_from_name() {
+        assert_eq!(Priority::from_name("low"), Some(Priority::Low));
+        assert_eq!(Priority::from_name("Normal"), Some(Priority::Normal));
+        assert_eq!(Priority::from_name("HIGH"), Some(Priority::High));
+        assert_eq!(Priority::from_name("urgent"), None);
+    }
+
+    #[test]
+    fn test_higher_priority_granted_first() {
+        let mut slots = slot_queue(1, None);
+        let now = Instant::now();
+
+        let _low = push_waiter(&mut slots, Priority::Low, false, 0, now);
+        let _high = push_waiter(&mut slots, Priority::High, false, 1, now);
+        let _normal = push_waiter(&mut slots, Priority::Normal, false, 2, now);
+
+        let order: Vec<Priority> = std::iter::from_fn(|| slots.take_next_waiter())
+            .map(|waiter| waiter.priority)
+            .collect();
+
+        assert_eq!(order, [Priority::High, Priority::Normal, Priority::Low]);
+    }
+
+    #[test]
+    fn test_same_priority_granted_in_queue_order() {
+        let mut slots = slot_queue(1, None);
+        let now = Instant::now();
+
+        let _second = push_waiter(&mut slots, Priority::Normal, false, 2, now);
+        let _first = push_waiter(&mut slots, Priority::Normal, false, 1, now);
+        let _third = push_waiter(&mut slots, Priority::Normal, false, 3, now);
+
+        let order: Vec<u64> = std::iter::from_fn(|| slots.take_next_waiter())
+            .map(|waiter| waiter.sequence)
+            .collect();
+
+        assert_eq!(order, [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_abandoned_waiters_skipped() {
+        let mut slots = slot_queue(1, None);
+        let now = Instant::now();
+
+        drop(push_waiter(&mut slots, Priority::High, false, 0, now));
+        let _normal = push_waiter(&mut slots, Priority::Normal, false, 1, now);
+
+        assert_eq!(slots.take_next_waiter().unwrap().sequence, 1);
+        assert!(slots.take_next_waiter().is_none());
+    }
+
+    #[test]
+    fn test_priority_aging() {
+        let now = Instant::now();
+        let aged = now - PRIORITY_AGING_INTERVAL * 2;
+
+        assert_eq!(Priority::Low.effective(now), 0);
+        assert_eq!(Priority::Low.effective(aged), 2);
+        assert_eq!(Priority::High.effective(now), 2);
+    }
+
+    #[test]
+    fn test_aged_low_priority_overtakes_newer_high_priority() {
+        let mut slots = slot_queue(1, None);
+        let now = Instant::now();
+
+        let _high = push_waiter(&mut slots, Priority::High, false, 5, now);
+        let _low = push_waiter(
+            &mut slots,
+            Priority::Low,
+            false,
+            0,
+            now - PRIORITY_AGING_INTERVAL * 2,
+        );
+
+        // Equal effective priority, the low priority conversion joined first
+        assert_eq!(slots.take_next_waiter().unwrap().priority, Priority::Low);
+    }
+
+    #[test]
+    fn test_partially_aged_low_priority_stays_behind() {
+        let mut slots = slot_queue(1, None);
+        let now = Instant::now();
+
+        let _high = push_waiter(&mut slots, Priority::High, false, 5, now);
+        let _low = push_waiter(
+            &mut slots,
+            Priority::Low,
+            false,
+            0,
+            now - PRIORITY_AGING_INTERVAL,
+        );
+
+        assert_eq!(slots.take_next_waiter().unwrap().priority, Priority::High);
+    }
+
+    #[test]
+    fn test_fast_lane_threshold() {
+        let fast_lane = fast_lane();
+
+        assert!(fast_lane.is_small(Some(0)));
+        assert!(fast_lane.is_small(Some(1024)));
+        assert!(!fast_lane.is_small(Some(1025)));
+        // Unknown sizes are treated as large
+        assert!(!fast_lane.is_small(None));
+    }
+
+    #[test]
+    fn test_fast_lane_reserved_slots() {
+        let fast_lane = fast_lane();
+
+        assert_eq!(fast_lane.reserved(4), 1);
+        assert_eq!(fast_lane.reserved(5), 2);
+        // At least one slot is left for large inputs
+        assert_eq!(fast_lane.reserved(1), 0);
+        assert_eq!(fast_lane.reserved(0), 0);
+    }
+
+    #[test]
+    fn test_large_inputs_kept_out_of_fast_lane() {
+        let mut slots = slot_queue(4, Some(fast_lane()));
+
+        for _ in 0..3 {
+            assert!(slots.can_start(true));
+            slots.start(true);
+        }
+
+        // Remaining slot is reserved for small inputs
+        assert!(!slots.can_start(true));
+        assert!(slots.can_start(false));
+
+        slots.start(false);
+        assert!(!slots.can_start(false));
+    }
+
+    #[test]
+    fn test_small_waiter_granted_reserved_slot() {
+        let mut slots = slot_queue(2, Some(fast_lane()));
+        slots.start(true);
+
+        let now = Instant::now();
+        let _large = push_waiter(&mut slots, Priority::High, true, 0, now);
+        let _small = push_waiter(&mut slots, Priority::Low, false, 1, now);
+
+        // Large waiter has the higher priority but can't use the reserved slot
+        let waiter = slots.take_next_waiter().unwrap();
+        assert!(!waiter.large);
+        slots.start(waiter.large);
+        assert!(slots.take_next_waiter().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_conversions_start_in_priority_order() {
+        let limiter = Arc::new(ConversionLimiter::new(1, 10, None));
+        let (started, mut started_rx) = mpsc::unbounded_channel();
+
+        let running = limiter
+            .try_enqueue()
+            .unwrap()
+            .acquire(Priority::Normal)
+            .await;
+
+        queue_conversion(&limiter, "low", Priority::Low, None, started.clone()).await;
+        queue_conversion(&limiter, "normal", Priority::Normal, None, started.clone()).await;
+        queue_conversion(&limiter, "high", Priority::High, None, started.clone()).await;
+        queue_conversion(&limiter, "high2", Priority::High, None, started).await;
+
+        drop(running);
+
+        let mut order = Vec::new();
+        while let Some(name) = started_rx.recv().await {
+            order.push(name);
+        }
+
+        assert_eq!(order, ["high", "high2", "normal", "low"]);
+    }
+
+    #[tokio::test]
+    async fn test_small_conversion_uses_fast_lane() {
+        let limiter = Arc::new(ConversionLimiter::new(2, 10, Some(fast_lane())));
+        let (started, mut started_rx) = mpsc::unbounded_channel();
+
+        let large = limiter.try_enqueue().unwrap();
+        large.set_input_size(4096);
+        let _running = large.acquire(Priority::Normal).await;
+
+        queue_conversion(
+            &limiter,
+            "large",
+            Priority::High,
+            Some(4096),
+            started.clone(),
+        )
+        .await;
+
+        let small = limiter.try_enqueue().unwrap();
+        small.set_input_size(512);
+        let _small = tokio::time::timeout(Duration::from_secs(1), small.acquire(Priority::Low))
+            .await
+            .expect("small conversion should start in the reserved slot");
+
+        drop(started);
+        assert!(started_rx.try_recv().is_err());
+        assert_eq!(limiter.running(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_acquire_before_deadline() {
+        let limiter = Arc::new(ConversionLimiter::new(1, 10, None));
+        let _running = limiter
+            .try_enqueue()
+            .unwrap()
+            .acquire(Priority::Normal)
+            .await;
+
+        let deadline = Instant::now() + Duration::from_millis(100);
+        let err = limiter
+            .try_enqueue()
+            .unwrap()
+            .acquire_before(Priority::High, Some(deadline))
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(err.kind, ErrorKind::Timeout));
+        // Timed out conversion left the queue
+        assert_eq!(limiter.status().queued, 0);
+        assert!(limiter.slots.lock().unwrap().take_next_waiter().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_rejected() {
+        let limiter = Arc::new(ConversionLimiter::new(1, 2, None));
+
+        let _first = enqueue_response(&limiter).await.ok().unwrap();
+        let _second = enqueue_response(&limiter).await.ok().unwrap();
+        let response = enqueue_response(&limiter).await.err().unwrap();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            response.headers().get(header::RETRY_AFTER).unwrap(),
+            &RETRY_AFTER_SECONDS.to_string()
+        );
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["kind"], "busy");
+        assert_eq!(body["code"], SATURATED_ERROR_CODE);
+        assert_eq!(body["queued"], 2);
+        assert_eq!(body["max_queued"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_queue_space_released_on_drop() {
+        let limiter = Arc::new(ConversionLimiter::new(1, 1, None));
+
+        let ticket = limiter.try_enqueue().unwrap();
+        assert!(matches!(
+            limiter.try_enqueue(),
+            Err(EnqueueError::QueueFull)
+        ));
+
+        drop(ticket);
+        assert!(limiter.try_enqueue().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_draining_rejected() {
+        let limiter = Arc::new(ConversionLimiter::new(1, 10, None));
+
+        assert!(limiter.drain(Duration::from_secs(1)).await);
+        assert!(limiter.is_draining());
+
+        let response = enqueue_response(&limiter).await.err().unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+    }
+
+    #[tokio::test]
+    async fn test_drain_timeout() {
+        let limiter = Arc::new(ConversionLimiter::new(1, 10, None));
+        let permit = limiter
+            .try_enqueue()
+            .unwrap()
+            .acquire(Priority::Normal)
+            .await;
+
+        assert!(!limiter.drain(Duration::from_millis(100)).await);
+        assert!(matches!(limiter.try_enqueue(), Err(EnqueueError::Draining)));
+
+        drop(permit);
+        assert!(limiter.drain(Duration::from_millis(100)).await);
+    }
+}
@@ -0,0 +1,183 @@
+use std::{path::Path, process::ExitStatus};
+use tokio::process::Command;
+
+/// Environment variable the dynamic linker searches for shared libraries
+#[cfg(windows)]
+const LIBRARY_PATH_VAR: &str = "PATH";
+#[cfg(target_os = "macos")]
+const LIBRARY_PATH_VAR: &str = "DYLD_LIBRARY_PATH";
+#[cfg(not(any(windows, target_os = "macos")))]
+const LIBRARY_PATH_VAR: &str = "LD_LIBRARY_PATH";
+
+/// Prepend a directory to the library search path of a command, ensures the
+/// shared libraries shipped alongside x2t are found
+///
+/// ## Arguments
+/// * `command` - Command to set the library search path for
+/// * `dir` - Directory containing the shared libraries
+pub fn prepend_library_path(command: &mut Command, dir: &Path) {
+    let existing = std::env::var_os(LIBRARY_PATH_VAR).unwrap_or_default();
+    let paths = std::iter::once(dir.to_path_buf()).chain(std::env::split_paths(&existing));
+
+    match std::env::join_paths(paths) {
+        Ok(value) => {
+            command.env(LIBRARY_PATH_VAR, value);
+        }
+        Err(err) => tracing::warn!(?err, "failed to set the library search path"),
+    }
+}
+
+/// Resource limits applied to each spawned x2t process so that a single
+/// malicious or pathological document can't exhaust the resources of the host
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ProcessLimits {
+    /// Maximum size of the process address space in bytes
+    pub max_memory: Option<u64>,
+    /// Maximum CPU time the process can use in seconds
+    pub max_cpu_time: Option<u64>,
+    /// Maximum size of any file the process writes in bytes
+    pub max_file_size: Option<u64>,
+}
+
+/// Limit that a process was stopped for exceeding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExceededLimit {
+    Memory,
+    CpuTime,
+    FileSize,
+}
+
+impl ExceededLimit {
+    /// Message describing the exceeded limit
+    pub fn message(&self) -> &'static str {
+        match self {
+            ExceededLimit::Memory => "conversion exceeded the memory limit",
+            ExceededLimit::CpuTime => "conversion exceeded the cpu time limit",
+            ExceededLimit::FileSize => "conversion exceeded the file size limit",
+        }
+    }
+}
+
+impl ProcessLimits {
+    /// Whether any limits are configured
+    pub fn is_empty(&self) -> bool {
+        self.max_memory.is_none() && self.max_cpu_time.is_none() && self.max_file_size.is_none()
+    }
+
+    /// Apply the limits to a command, the limits are set in the child process
+    /// before x2t is executed
+    #[cfg(unix)]
+    pub fn apply(&self, command: &mut Command) {
+        if self.is_empty() {
+            return;
+        }
+
+        let limits = *self;
+
+        // SAFETY: The closure only calls setrlimit which is async-signal-safe
+        // and doesn't allocate or access any shared state
+        unsafe {
+            command.pre_exec(move || limits.set_rlimits());
+        }
+    }
+
+    /// Limits are enforced by assigning the process to a job object once it
+    /// has started on Windows
+    #[cfg(not(unix))]
+    pub fn apply(&self, _command: &mut Command) {}
+
+    /// Set the resource limits for the current process
+    #[cfg(unix)]
+    fn set_rlimits(&self) -> std::io::Result<()> {
+        let limits = [
+            (libc::RLIMIT_AS, self.max_memory, 0),
+            // Hard limit is a second above the soft limit so the process receives
+            // SIGXCPU before it is forcefully killed
+            (libc::RLIMIT_CPU, self.max_cpu_time, 1),
+            (libc::RLIMIT_FSIZE, self.max_file_size, 0),
+        ];
+
+        for (resource, limit, grace) in limits {
+            let Some(limit) = limit else {
+                continue;
+            };
+
+            let rlimit = libc::rlimit {
+                rlim_cur: limit as libc::rlim_t,
+                rlim_max: limit.saturating_add(grace) as libc::rlim_t,
+            };
+
+            if unsafe { libc::setrlimit(resource, &rlimit) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Determine which limit, if any, caused a failed process to stop based
+    /// on its exit status and stderr output
+    pub fn exceeded_limit(&self, status: &ExitStatus, stderr: &str) -> Option<ExceededLimit> {
+        if self.max_cpu_time.is_some() && exited_with_signal(status, &[SIGXCPU, SIGKILL]) {
+            return Some(ExceededLimit::CpuTime);
+        }
+
+        if self.max_file_size.is_some() && exited_with_signal(status, &[SIGXFSZ]) {
+            return Some(ExceededLimit::FileSize);
+        }
+
+        // Allocations fail once the address space limit is reached
+        if self.max_memory.is_some()
+            && (stderr.contains("std::bad_alloc") || stderr.contains("out of memory"))
+        {
+            return Some(ExceededLimit::Memory);
+        }
+
+        None
+    }
+}
+
+#[cfg(unix)]
+const SIGXCPU: i32 = libc::SIGXCPU;
+#[cfg(unix)]
+const SIGXFSZ: i32 = libc::SIGXFSZ;
+#[cfg(unix)]
+const SIGKILL: i32 = libc::SIGKILL;
+#[cfg(unix)]
+const CRASH_SIGNALS: &[i32] = &[
+    libc::SIGSEGV,
+    libc::SIGBUS,
+    libc::SIGILL,
+    libc::SIGFPE,
+    libc::SIGABRT,
+];
+
+#[cfg(not(unix))]
+const SIGXCPU: i32 = 0;
+#[cfg(not(unix))]
+const SIGXFSZ: i32 = 0;
+#[cfg(not(unix))]
+const SIGKILL: i32 = 0;
+#[cfg(not(unix))]
+const CRASH_SIGNALS: &[i32] = &[];
+
+/// Check if the process crashed (i.e segfaulted or aborted) rather than
+/// exiting with an error code
+pub fn is_crash(status: &ExitStatus) -> bool {
+    exited_with_signal(status, CRASH_SIGNALS)
+}
+
+/// Check if the process was terminated by one of the provided signals
+#[cfg(unix)]
+fn exited_with_signal(status: &ExitStatus, signals: &[i32]) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+
+    status
+        .signal()
+        .is_some_and(|signal| signals.contains(&signal))
+}
+
+#[cfg(not(unix))]
+fn exited_with_signal(_status: &ExitStatus, _signals: &[i32]) -> bool {
+    false
+}
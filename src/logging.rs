@@ -0,0 +1,112 @@
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use clap::ValueEnum;
+use std::time::Instant;
+use tracing::Instrument;
+use tracing_subscriber::{EnvFilter, reload};
+use uuid::Uuid;
+
+use crate::{access_log::RequestId, client_ip::ClientIp};
+
+/// Format to output logs in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human readable text logs
+    #[default]
+    Text,
+    /// Structured JSON logs, one object per line
+    Json,
+}
+
+/// Handle for replacing the filter of the global tracing subscriber
+pub struct LogFilterHandle {
+    reload: Box<dyn Fn(EnvFilter) -> Result<(), reload::Error> + Send + Sync>,
+}
+
+impl LogFilterHandle {
+    /// Replace the log filter
+    pub fn set_filter(&self, filter: EnvFilter) -> anyhow::Result<()> {
+        (self.reload)(filter)?;
+        Ok(())
+    }
+}
+
+/// Setup the global tracing subscriber using the provided log format
+pub fn init_logging(log_format: LogFormat) -> anyhow::Result<LogFilterHandle> {
+    // Start configuring a `fmt` subscriber
+    let builder = tracing_subscriber::fmt()
+        // Use the logging options from env variables
+        .with_env_filter(EnvFilter::from_default_env())
+        // Display source code file paths
+        .with_file(true)
+        // Display source code line numbers
+        .with_line_number(true)
+        // Don't display the event's target (module path)
+        .with_target(false);
+
+    // use that subscriber to process traces emitted after this point
+    let reload: Box<dyn Fn(EnvFilter) -> Result<(), reload::Error> + Send + Sync> = match log_format
+    {
+        LogFormat::Text => {
+            // Allow the filter to be replaced when the config is reloaded
+            let builder = builder.with_filter_reloading();
+            let handle = builder.reload_handle();
+            tracing::subscriber::set_global_default(builder.finish())?;
+            Box::new(move |filter| handle.reload(filter))
+        }
+        LogFormat::Json => {
+            let builder = builder
+                .json()
+                // Include the fields of the current request span
+                .with_current_span(true)
+                .with_span_list(false)
+                .with_filter_reloading();
+            let handle = builder.reload_handle();
+            tracing::subscriber::set_global_default(builder.finish())?;
+            Box::new(move |filter| handle.reload(filter))
+        }
+    };
+
+    Ok(LogFilterHandle { reload })
+}
+
+/// Middleware wrapping conversion requests in a span with a unique request ID
+/// and the matched route, logging the final status and duration of the request
+pub async fn request_span(request: Request, next: Next) -> Response {
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .map(|RequestId(request_id)| *request_id)
+        .unwrap_or_else(Uuid::new_v4);
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+
+    let client_ip = request
+        .extensions()
+        .get::<ClientIp>()
+        .and_then(|ClientIp(client_ip)| *client_ip)
+        .map(tracing::field::display);
+
+    let span = tracing::info_span!("request", %request_id, route, client_ip);
+
+    async move {
+        let start = Instant::now();
+        let response = next.run(request).await;
+
+        tracing::info!(
+            status = response.status().as_u16(),
+            duration_ms = start.elapsed().as_millis() as u64,
+            "conversion request finished"
+        );
+
+        response
+    }
+    .instrument(span)
+    .await
+}
@@ -2,448 +2,2045 @@ use anyhow::Context;
 use axum::{
     Extension, Json, Router,
     body::Body,
-    extract::DefaultBodyLimit,
-    http::{HeaderValue, Response, StatusCode, header},
+    extract::{DefaultBodyLimit, Multipart, RawQuery},
+    http::{HeaderName, HeaderValue, Response, StatusCode, header},
+    middleware,
     response::IntoResponse,
-    routing::post,
+    routing::{get, post},
 };
-use axum_typed_multipart::{FieldData, TryFromMultipart, TypedMultipart};
-use bytes::Bytes;
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use futures_util::FutureExt;
 use serde::Serialize;
 use std::{
+    collections::HashSet,
     env::temp_dir,
     path::{Path, PathBuf, absolute},
-    sync::Arc,
+    process::ExitCode,
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
 };
-use tokio::{process::Command, signal::ctrl_c, try_join};
+use tokio::signal::ctrl_c;
+use tokio_util::sync::CancellationToken;
+use tonic::transport::server::TcpIncoming;
+use tower_http::decompression::RequestDecompressionLayer;
 use tracing::{debug, error};
-use tracing_subscriber::EnvFilter;
-use uuid::Uuid;
 
-use crate::encrypted::{FileCondition, get_file_condition};
+use crate::{
+    access_log::{AccessLog, AccessLogFormat, log_access},
+    adaptive::AdaptiveConcurrency,
+    antivirus::{ClamdAddress, VirusScanner},
+    archive::convert_archive,
+    auth::{AdminAccess, AdminAuth, DEFAULT_JWT_HEADER, JwtAuth, require_admin, require_jwt},
+    batch::convert_batch,
+    checksum::{OUTPUT_CHECKSUM_HEADER, output_checksum, verify_input_checksum},
+    cli::convert_local,
+    client_ip::{IpAccessList, TrustedProxies, enforce_ip_access, resolve_client_ip},
+    coalesce::{ConversionCoalescer, conversion_key},
+    convert::{convert_file, create_convert_temp_paths},
+    convert_service::{
+        ConvertServiceStore, convert_service, get_convert_service_result,
+        require_convert_service_jwt,
+    },
+    crashes::{CrashReports, DEFAULT_CRASH_RETENTION, DEFAULT_MAX_CRASH_REPORTS, list_crashes},
+    deadline::{RequestDeadline, deadline_exceeded},
+    discover::{discover_fonts_path, discover_x2t_path},
+    disposition::{attachment, output_file_name},
+    docbuilder::{DocBuilder, convert_docbuilder},
+    doctor::run_doctor,
+    fonts::{DEFAULT_CUSTOM_FONTS_PATH, FontManager, list_fonts, regenerate_fonts, upload_fonts},
+    format::AcceptedFormat,
+    grpc::{ConvertServiceServer, GrpcAuthInterceptor, GrpcConvertService},
+    healthcheck::{DEFAULT_HEALTHCHECK_TIMEOUT, DEFAULT_HEALTHCHECK_URL, run_healthcheck},
+    http::{HttpTuning, serve_tcp},
+    input_filter::InputFormatFilter,
+    input_limits::{DEFAULT_MAX_UNCOMPRESSED_SIZE, InputLimits},
+    inspect::inspect,
+    install::{InstallOptions, install_x2t},
+    janitor::{DEFAULT_TEMP_MAX_AGE, TempJanitor},
+    jobs::{JobStore, cancel_job, create_job, get_job, get_job_events, get_job_result},
+    libreoffice::LibreOffice,
+    limiter::{
+        ConversionLimiter, DEFAULT_FAST_LANE_SLOTS, FastLane, QueueTicket, list_conversions, status,
+    },
+    limits::ProcessLimits,
+    logging::{LogFormat, init_logging, request_span},
+    merge::convert_merge,
+    metrics::{Metrics, metrics},
+    poison::{DEFAULT_POISON_WINDOW, PoisonDocuments},
+    readiness::{Readiness, ready},
+    reload::{ConfigReloader, ReloadableSettings, reload_config},
+    retry::RetryPolicy,
+    s3::{DEFAULT_S3_REGION, S3Client, convert_s3},
+    scratch::{DEFAULT_MIN_FREE_SPACE, ScratchSpace},
+    selftest::{run_self_test, self_test},
+    startup::{StartupConfig, StartupError, validate_startup},
+    tenants::{Tenants, TenantsFile, select_tenant},
+    tls::load_tls_config,
+    upload::{ConvertFields, read_convert_upload},
+    upload_token::{UploadGrant, UploadTokens, require_upload_token},
+    url_filter::UrlFilter,
+    webhook::WebhookSender,
+};
+
+mod access_log;
+mod adaptive;
+mod antivirus;
+mod archive;
+mod auth;
+mod batch;
+mod checksum;
+mod cli;
+mod client_ip;
+mod coalesce;
+mod convert;
+mod convert_service;
+mod crashes;
+mod csv;
+mod deadline;
+mod discover;
+mod disposition;
+mod docbuilder;
+mod doctor;
+mod encoding;
+mod fonts;
+mod format;
+mod forms;
+mod grpc;
+mod healthcheck;
+mod http;
+mod input_filter;
+mod input_limits;
+mod inspect;
+mod install;
+mod janitor;
+mod jobs;
+mod libreoffice;
+mod limiter;
+mod limits;
+mod logging;
+mod merge;
+mod metrics;
+mod openapi;
+mod params;
+mod pdf_version;
+mod planner;
+mod poison;
+mod range;
+mod readiness;
+mod reload;
+mod repair;
+mod retry;
+mod s3;
+mod scratch;
+mod selftest;
+mod sheets;
+mod spreadsheet;
+mod startup;
+mod systemd;
+mod tenants;
+mod tls;
+#[cfg(unix)]
+mod unix;
+mod upload;
+mod upload_token;
+mod url_filter;
+mod usage;
+mod watermark;
+mod webhook;
+#[cfg(windows)]
+mod windows;
+
+#[derive(Parser, Debug, Clone)]
+#[command(version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Path to the x2t installation (Omit to determine automatically)
+    #[arg(long)]
+    x2t_path: Option<String>,
+
+    /// Path to the converter fonts folder
+    #[arg(long)]
+    fonts_path: Option<String>,
+
+    /// Port to bind the server to, defaults to 8080
+    #[arg(long)]
+    port: Option<u16>,
+
+    /// Host to bind the server to, defaults to 0.0.0.0
+    #[arg(long)]
+    host: Option<String>,
+
+    /// Produce PDF/A output by default when converting to PDF
+    #[arg(long)]
+    pdfa: bool,
+
+    /// Include the error chain and a backtrace in responses for internal errors
+    #[arg(long)]
+    debug_errors: bool,
+
+    /// Maximum number of conversions to run at once, defaults to the number of CPUs
+    #[arg(long)]
+    max_concurrent: Option<usize>,
+
+    /// Lower the number of concurrent conversions below the maximum while
+    /// the system is under memory pressure or the CPU is saturated
+    #[arg(long)]
+    adaptive_concurrency: bool,
+
+    /// Maximum number of conversions allowed to wait for a free slot, further
+    /// conversions are rejected with 429 Too Many Requests, defaults to 100
+    #[arg(long)]
+    max_queue: Option<usize>,
+
+    /// Reserve conversion slots for inputs up to this size in megabytes so
+    /// small documents aren't stuck behind large ones
+    #[arg(long)]
+    fast_lane_max_size: Option<u64>,
+
+    /// Fraction of the conversion slots reserved for small inputs when the
+    /// fast lane is enabled, defaults to 0.25. At least one slot is always
+    /// left for large inputs
+    #[arg(long)]
+    fast_lane_slots: Option<f64>,
+
+    /// Number of seconds to keep asynchronous job results for, defaults to 3600
+    #[arg(long)]
+    job_result_ttl: Option<u64>,
+
+    /// Secret used to sign job webhook payloads (HMAC-SHA256)
+    #[arg(long)]
+    webhook_secret: Option<String>,
+
+    /// Public base URL of the server, used when building result URLs for
+    /// webhooks. Webhook payloads don't include a result URL when omitted
+    #[arg(long)]
+    public_url: Option<String>,
+
+    /// Comma separated list of addresses or CIDRs job webhooks are allowed
+    /// to be sent to, only public addresses are allowed by default
+    #[arg(long)]
+    webhook_allowed_networks: Option<String>,
+
+    /// Serve the DocumentServer conversion API (/ConvertService.ashx and
+    /// /converter) for integrations built against the official converter,
+    /// requires the public URL to be set
+    #[arg(long)]
+    convert_service_api: bool,
+
+    /// Comma separated list of addresses or CIDRs the conversion API is
+    /// allowed to download files from, only public addresses are allowed
+    /// by default
+    #[arg(long)]
+    convert_service_allowed_networks: Option<String>,
+
+    /// Shared secret for validating JWTs on requests (HS256), compatible with the
+    /// ONLYOFFICE DocumentServer JWT_SECRET. Authentication is disabled when omitted
+    #[arg(long)]
+    jwt_secret: Option<String>,
+
+    /// Header to read the JWT from, defaults to Authorization
+    #[arg(long)]
+    jwt_header: Option<String>,
+
+    /// Path to a PEM encoded certificate chain to serve HTTPS with (requires --tls-key)
+    #[arg(long)]
+    tls_cert: Option<PathBuf>,
+
+    /// Path to the PEM encoded private key for the TLS certificate (requires --tls-cert)
+    #[arg(long)]
+    tls_key: Option<PathBuf>,
+
+    /// Path to a PEM encoded CA bundle, when provided clients must present a certificate
+    /// signed by one of these CAs (mutual TLS)
+    #[arg(long)]
+    tls_client_ca: Option<PathBuf>,
+
+    /// Comma separated list of proxy addresses or CIDRs (i.e "10.0.0.0/8")
+    /// whose Forwarded and X-Forwarded-For headers are trusted to contain
+    /// the address of the client
+    #[arg(long)]
+    trusted_proxies: Option<String>,
+
+    /// Comma separated list of client addresses or CIDRs allowed to make
+    /// requests, all clients are allowed by default
+    #[arg(long)]
+    allowed_ips: Option<String>,
+
+    /// Comma separated list of client addresses or CIDRs denied from making
+    /// requests, takes precedence over the allowed addresses
+    #[arg(long)]
+    denied_ips: Option<String>,
+
+    /// Secret used to verify upload tokens, when provided POST /convert/upload
+    /// accepts single use tokens signed by a backend in place of credentials
+    #[arg(long)]
+    upload_token_secret: Option<String>,
+
+    /// Close HTTP/1.1 connections after each request instead of keeping them
+    /// open for further requests
+    #[arg(long)]
+    disable_keep_alive: bool,
+
+    /// Serve a Swagger UI for the OpenAPI specification at /docs
+    #[arg(long)]
+    swagger_ui: bool,
+
+    /// Maximum number of seconds a client can take to send the headers of a
+    /// request, also closes HTTP/1.1 connections idle between requests for
+    /// this long
+    #[arg(long)]
+    http_header_timeout: Option<u64>,
+
+    /// Number of seconds without any traffic after which a connection is
+    /// closed once its in-flight requests have finished
+    #[arg(long)]
+    http_idle_timeout: Option<u64>,
+
+    /// Number of seconds between HTTP/2 pings used to detect dead connections,
+    /// disabled by default
+    #[arg(long)]
+    http2_keep_alive_interval: Option<u64>,
+
+    /// Maximum number of concurrent HTTP/2 streams per connection, defaults to 200
+    #[arg(long)]
+    http2_max_concurrent_streams: Option<u32>,
+
+    /// Format to output logs in, defaults to text
+    #[arg(long, value_enum)]
+    log_format: Option<LogFormat>,
+
+    /// Write an entry for every request to an access log, "-" for stdout or
+    /// the path of a file to append to
+    #[arg(long)]
+    access_log: Option<String>,
+
+    /// Format to write access log entries in, defaults to text
+    #[arg(long, value_enum)]
+    access_log_format: Option<AccessLogFormat>,
+
+    /// Maximum number of seconds to wait for in-flight conversions to finish
+    /// when shutting down, conversions still running are then killed.
+    /// Defaults to 30
+    #[arg(long)]
+    drain_timeout: Option<u64>,
+
+    /// Path to a unix domain socket to serve on instead of a TCP port
+    #[arg(long)]
+    unix_socket: Option<PathBuf>,
+
+    /// Octal permissions to create the unix socket with, defaults to 660
+    #[arg(long)]
+    unix_socket_mode: Option<String>,
+
+    /// Bearer token required to access the admin endpoints, the admin
+    /// endpoints are disabled when not provided
+    #[arg(long)]
+    admin_token: Option<String>,
+
+    /// Directory to store uploaded fonts in
+    #[arg(long)]
+    custom_fonts_path: Option<PathBuf>,
+
+    /// Regenerate the fonts cache before starting the server
+    #[arg(long)]
+    regenerate_fonts: bool,
+
+    /// Convert a sample document, print the result of each stage and exit
+    #[arg(long)]
+    self_test: bool,
+
+    /// Run a warm-up conversion at startup, /ready only reports ready once
+    /// it has succeeded. The server exits when the warm-up conversion fails
+    #[arg(long)]
+    warmup: bool,
+
+    /// Keep running when the warm-up conversion fails instead of exiting,
+    /// /ready reports the failure instead
+    #[arg(long)]
+    allow_warmup_failure: bool,
+
+    /// Endpoint of the S3 compatible object storage to convert objects from,
+    /// the object storage endpoint is disabled when not provided
+    #[arg(long)]
+    s3_endpoint: Option<String>,
+
+    /// Region of the object storage, defaults to us-east-1
+    #[arg(long)]
+    s3_region: Option<String>,
+
+    /// Access key ID for the object storage
+    #[arg(long)]
+    s3_access_key_id: Option<String>,
+
+    /// Secret access key for the object storage
+    #[arg(long)]
+    s3_secret_access_key: Option<String>,
+
+    /// Maximum memory in megabytes each x2t process can use, unlimited by default
+    #[arg(long)]
+    x2t_max_memory: Option<u64>,
+
+    /// Maximum CPU time in seconds each x2t process can use, unlimited by default
+    #[arg(long)]
+    x2t_max_cpu_time: Option<u64>,
+
+    /// Maximum size in megabytes of any file written by x2t, unlimited by default
+    #[arg(long)]
+    x2t_max_file_size: Option<u64>,
+
+    /// Maximum time in seconds each x2t process can run for before it is
+    /// killed, unlimited by default
+    #[arg(long)]
+    x2t_timeout: Option<u64>,
+
+    /// Maximum number of times to run x2t for a conversion that fails with a
+    /// transient error, defaults to 1 (no retries)
+    #[arg(long)]
+    x2t_max_attempts: Option<u32>,
+
+    /// Comma separated list of x2t exit codes to retry, defaults to the
+    /// timeout (0x0053) and ICU (0x005c) errors
+    #[arg(long)]
+    x2t_retry_codes: Option<String>,
+
+    /// Comma separated list of input formats to accept (i.e docx,xlsx,pptx),
+    /// all formats are accepted when not provided
+    #[arg(long)]
+    allowed_input_formats: Option<String>,
+
+    /// Comma separated list of input formats to reject (i.e doc,xls,ppt)
+    #[arg(long)]
+    denied_input_formats: Option<String>,
+
+    /// Maximum total uncompressed size in megabytes of ZIP based inputs (DOCX,
+    /// XLSX, ODT, ...), defaults to 300. Set to 0 to disable the limit
+    #[arg(long)]
+    max_uncompressed_size: Option<u64>,
+
+    /// Address of a clamd daemon to scan uploaded files with before they are
+    /// converted (i.e 127.0.0.1:3310 or unix:/run/clamav/clamd.ctl), files
+    /// aren't scanned when not provided
+    #[arg(long)]
+    clamd_address: Option<String>,
+
+    /// Path to the LibreOffice soffice binary, PDF conversions of inputs x2t
+    /// rejects as unsupported are retried with LibreOffice when provided
+    #[arg(long)]
+    soffice_path: Option<PathBuf>,
+
+    /// Attempt to salvage ZIP based inputs (OOXML, ODF) that x2t reports as
+    /// corrupted, i.e uploads that were truncated or have trailing garbage,
+    /// the salvaged entries are converted instead
+    #[arg(long)]
+    repair_corrupted_inputs: bool,
+
+    /// Number of times a document can crash x2t within the poison window
+    /// before conversions of it are rejected without running x2t, documents
+    /// aren't quarantined when not provided
+    #[arg(long)]
+    poison_crash_limit: Option<usize>,
+
+    /// Number of seconds crashes of a document are counted for when
+    /// quarantining documents, defaults to 3600 (1 hour)
+    #[arg(long)]
+    poison_window: Option<u64>,
+
+    /// Directory to preserve the input file, config and stderr of x2t runs
+    /// that crash in, crash artifacts aren't kept when not provided. Must be
+    /// outside of the temporary directory
+    #[arg(long)]
+    crash_dir: Option<PathBuf>,
+
+    /// Number of seconds crash artifacts are kept for, defaults to 604800 (7 days)
+    #[arg(long)]
+    crash_retention: Option<u64>,
+
+    /// Maximum number of crashes to keep the artifacts of, defaults to 50
+    #[arg(long)]
+    max_crash_reports: Option<usize>,
+
+    /// Path to the ONLYOFFICE docbuilder binary, enables the /docbuilder
+    /// endpoint for running scripts against documents before converting them
+    #[arg(long)]
+    docbuilder_path: Option<PathBuf>,
+
+    /// Minimum free disk space in megabytes that must remain in the temporary
+    /// directory, uploads that would leave less are rejected. Defaults to 256
+    #[arg(long)]
+    min_free_space: Option<u64>,
+
+    /// Maximum total size in megabytes of the temporary files of all
+    /// in-flight conversions, unlimited by default
+    #[arg(long)]
+    temp_quota: Option<u64>,
+
+    /// Path to a JSON file defining tenants with their own configuration,
+    /// tenants are selected by the X-Api-Key or X-Tenant headers
+    #[arg(long)]
+    tenants_file: Option<PathBuf>,
+
+    /// Number of seconds a conversion can take before a warning is logged with
+    /// its details, slow conversions aren't logged when not provided
+    #[arg(long)]
+    slow_conversion_threshold: Option<u64>,
+
+    /// Number of seconds after which files left in the temporary directory are
+    /// deleted, defaults to 86400 (1 day)
+    #[arg(long)]
+    temp_max_age: Option<u64>,
+
+    /// Number of worker threads for the async runtime, defaults to the number of CPUs
+    #[arg(long)]
+    workers: Option<usize>,
+
+    /// Address to serve the gRPC API on (i.e 0.0.0.0:50051), the gRPC API
+    /// is disabled when not provided
+    #[arg(long)]
+    grpc_address: Option<String>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Check the health of a running server, exits with 0 when the server is
+    /// healthy and 1 otherwise
+    Healthcheck {
+        /// Base URL of the server, defaults to http://127.0.0.1:8080
+        #[arg(long)]
+        url: Option<String>,
+
+        /// Number of seconds to wait for a response, defaults to 5
+        #[arg(long)]
+        timeout: Option<u64>,
+    },
+
+    /// Convert a local file without starting the server
+    Convert {
+        /// Path to the file to convert
+        input: PathBuf,
+
+        /// Path to write the converted file to
+        output: PathBuf,
+
+        /// Format to convert to, defaults to the extension of the output path
+        #[arg(long)]
+        format: Option<String>,
+
+        /// Format of the input file, defaults to the extension of the input path
+        #[arg(long)]
+        input_format: Option<String>,
+
+        /// Produce PDF/A output when converting to PDF
+        #[arg(long)]
+        pdfa: bool,
+
+        /// Version of the PDF specification the PDF output declares (1.5,
+        /// 1.6 or 1.7), can't be older than the version x2t produces
+        #[arg(long)]
+        pdf_version: Option<String>,
+
+        /// Password to open the input file with if its encrypted
+        #[arg(long)]
+        password: Option<String>,
+    },
+
+    /// Check the x2t install, fonts and temporary directory for common
+    /// problems and suggest fixes
+    Doctor,
+
+    /// Download and unpack an x2t build from a mirror, verifying its checksum
+    InstallX2t {
+        /// Version to install, components can be "x" to match any value
+        /// (i.e "8.x"), defaults to the latest version
+        #[arg(long)]
+        version: Option<String>,
+
+        /// Directory to install x2t to
+        #[arg(long)]
+        dest: PathBuf,
+
+        /// Base URL of the mirror serving the manifest.json listing the
+        /// available builds, can also be set with X2T_MIRROR
+        #[arg(long)]
+        mirror: Option<String>,
+
+        /// Replace an existing install at the destination
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+impl Args {
+    /// Whether a single task is run before exiting instead of serving requests
+    fn is_one_shot(&self) -> bool {
+        self.self_test || self.command.is_some()
+    }
+}
+
+const DEFAULT_JOB_RESULT_TTL: u64 = 60 * 60;
+const DEFAULT_DRAIN_TIMEOUT: u64 = 30;
+const DEFAULT_MAX_QUEUE_LENGTH: usize = 100;
+
+/// Response header naming the backend that produced the converted file
+const CONVERSION_BACKEND_HEADER: &str = "x-conversion-backend";
+
+fn main() -> anyhow::Result<ExitCode> {
+    // Variables from the process environment take precedence over the .env
+    // file, including when the configuration is reloaded
+    let process_env: HashSet<String> = std::env::vars_os()
+        .filter_map(|(name, _)| name.into_string().ok())
+        .collect();
+
+    _ = dotenvy::dotenv();
+
+    let args = Args::parse();
+
+    if let Some(Command::Healthcheck { url, timeout }) = &args.command {
+        let url = url
+            .clone()
+            .or_else(|| std::env::var("HEALTHCHECK_URL").ok())
+            .unwrap_or_else(|| DEFAULT_HEALTHCHECK_URL.to_string());
+        let timeout = Duration::from_secs(timeout.unwrap_or(DEFAULT_HEALTHCHECK_TIMEOUT));
+
+        return Ok(run_healthcheck(&url, timeout));
+    }
+
+    let workers = match args.workers {
+        Some(value) => value,
+        None => match std::env::var("WORKERS") {
+            Ok(value) => value.parse().context("invalid WORKERS value")?,
+            Err(_) => std::thread::available_parallelism()
+                .map(|value| value.get())
+                .unwrap_or(1),
+        },
+    };
+
+    if workers == 0 {
+        anyhow::bail!("WORKERS must be at least 1");
+    }
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(workers)
+        .enable_all()
+        .build()
+        .context("failed to create async runtime")?;
+
+    match runtime.block_on(run(args, workers, process_env)) {
+        Ok(()) => Ok(ExitCode::SUCCESS),
+        // Startup problems have already been reported
+        Err(err) => match err.downcast_ref::<StartupError>() {
+            Some(err) => Ok(err.exit_code()),
+            None => Err(err),
+        },
+    }
+}
+
+async fn run(args: Args, workers: usize, process_env: HashSet<String>) -> anyhow::Result<()> {
+    let log_format = match args.log_format {
+        Some(value) => value,
+        None => match std::env::var("LOG_FORMAT") {
+            Ok(value) => LogFormat::from_str(&value, true)
+                .map_err(|err| anyhow::anyhow!("invalid LOG_FORMAT value: {err}"))?,
+            Err(_) => LogFormat::default(),
+        },
+    };
+
+    let log_filter = init_logging(log_format)?;
+
+    if let Some(Command::InstallX2t {
+        version,
+        dest,
+        mirror,
+        force,
+    }) = &args.command
+    {
+        let mirror = mirror
+            .clone()
+            .or_else(|| std::env::var("X2T_MIRROR").ok())
+            .context("no x2t mirror configured, set --mirror or X2T_MIRROR")?;
+
+        return install_x2t(InstallOptions {
+            mirror,
+            version: version.clone().unwrap_or_else(|| "latest".to_string()),
+            dest: dest.clone(),
+            force: *force,
+        })
+        .await;
+    }
+
+    // Arguments are kept for reloading the configuration
+    let reload_args = args.clone();
+    let one_shot = args.is_one_shot();
+    let settings = ReloadableSettings::load(&args, |name| std::env::var(name).ok())?;
+
+    let mut x2t_path: Option<PathBuf> = None;
+    let mut fonts_path: Option<PathBuf> = None;
+
+    // Try loading paths from command line
+    if let Some(path) = args.x2t_path {
+        x2t_path = Some(PathBuf::from(&path));
+    }
+
+    if let Some(path) = args.fonts_path {
+        fonts_path = Some(PathBuf::from(&path));
+    }
+
+    // Try loading paths from environment variables
+    if x2t_path.is_none()
+        && let Ok(path) = std::env::var("X2T_PATH")
+    {
+        x2t_path = Some(PathBuf::from(&path));
+    }
+
+    if fonts_path.is_none()
+        && let Ok(path) = std::env::var("X2T_FONTS_PATH")
+    {
+        fonts_path = Some(PathBuf::from(&path));
+    }
+
+    // Try find an install in the default locations or on the PATH
+    if x2t_path.is_none() {
+        x2t_path = discover_x2t_path();
+    }
+
+    let x2t_path = x2t_path
+        .map(absolute)
+        .transpose()
+        .context("failed to make x2t path absolute")?;
+    let fonts_path = fonts_path
+        .map(absolute)
+        .transpose()
+        .context("failed to make fonts path absolute")?
+        .unwrap_or_else(|| discover_fonts_path(x2t_path.as_deref()));
+
+    let unix_socket = args
+        .unix_socket
+        .or_else(|| std::env::var("UNIX_SOCKET").ok().map(PathBuf::from));
+
+    let grpc_address = args
+        .grpc_address
+        .or_else(|| std::env::var("GRPC_ADDRESS").ok());
+
+    // Determine the address to run the server on, no address is needed when
+    // serving on a unix socket or only running a single task
+    // Listener passed by systemd when started through socket activation
+    let activated_listener =
+        systemd::activated_listener().context("failed to use the socket passed by systemd")?;
+
+    let server_address = if one_shot || unix_socket.is_some() || activated_listener.is_some() {
+        None
+    } else if args.host.is_some() || args.port.is_some() {
+        let host = args.host.unwrap_or_else(|| "0.0.0.0".to_string());
+        let port = args.port.unwrap_or(8080);
+
+        Some(format!("{host}:{port}"))
+    } else {
+        Some(std::env::var("SERVER_ADDRESS").context("missing SERVER_ADDRESS")?)
+    };
+
+    let temp_path = temp_dir();
+    let temp_path = temp_path.join("onlyoffice-convert-server");
+
+    // Validate the configuration before starting anything
+    let mut addresses: Vec<&str> = server_address.iter().map(String::as_str).collect();
+    if !one_shot {
+        addresses.extend(grpc_address.as_deref());
+    }
+
+    // Diagnostics run before validation so that every problem is reported
+    if let Some(Command::Doctor) = &args.command {
+        if !run_doctor(x2t_path.as_deref(), &fonts_path, &temp_path).await {
+            anyhow::bail!("problems were found with the install");
+        }
+
+        return Ok(());
+    }
+
+    validate_startup(&StartupConfig {
+        x2t_path: x2t_path.as_deref(),
+        fonts_path: &fonts_path,
+        temp_path: &temp_path,
+        addresses,
+    })?;
+
+    let x2t_path = x2t_path.context("no x2t install found")?;
+
+    tracing::debug!("using x2t install from: {}", x2t_path.display());
+    tracing::debug!("using {workers} runtime worker threads");
+
+    let default_pdfa = args.pdfa || env_flag("DEFAULT_PDFA");
+
+    if args.debug_errors || env_flag("DEBUG_ERRORS") {
+        debug!("including error details in responses");
+        DEBUG_ERRORS.store(true, Ordering::Relaxed);
+    }
+
+    let custom_fonts_path = args
+        .custom_fonts_path
+        .or_else(|| std::env::var("CUSTOM_FONTS_PATH").ok().map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_CUSTOM_FONTS_PATH));
+
+    let font_manager = Arc::new(FontManager::new(
+        custom_fonts_path,
+        x2t_path.clone(),
+        fonts_path.clone(),
+    ));
+
+    // Regenerate the fonts cache before accepting any conversions so that
+    // conversions don't render using a stale cache
+    if args.regenerate_fonts || env_flag("REGENERATE_FONTS") {
+        debug!("regenerating fonts cache");
+
+        let start = Instant::now();
+        font_manager
+            .regenerate()
+            .await
+            .context("failed to regenerate fonts cache")?;
+
+        debug!(
+            duration_ms = start.elapsed().as_millis() as u64,
+            "regenerated fonts cache"
+        );
+    }
+
+    if !settings.x2t_limits.is_empty() {
+        debug!("limiting x2t processes to {:?}", settings.x2t_limits);
+    }
+
+    if let Some(x2t_timeout) = settings.x2t_timeout {
+        debug!("killing x2t processes running longer than {x2t_timeout:?}");
+    }
+
+    if settings.x2t_retry_policy.max_attempts > 1 {
+        debug!(
+            "retrying transient x2t failures ({:?})",
+            settings.x2t_retry_policy
+        );
+    }
+
+    let temp_max_age = match args.temp_max_age {
+        Some(value) => value,
+        None => match std::env::var("TEMP_MAX_AGE") {
+            Ok(value) => value.parse().context("invalid TEMP_MAX_AGE value")?,
+            Err(_) => DEFAULT_TEMP_MAX_AGE,
+        },
+    };
+
+    let slow_conversion_threshold = match args.slow_conversion_threshold {
+        Some(value) => Some(value),
+        None => match std::env::var("SLOW_CONVERSION_THRESHOLD") {
+            Ok(value) => Some(
+                value
+                    .parse()
+                    .context("invalid SLOW_CONVERSION_THRESHOLD value")?,
+            ),
+            Err(_) => None,
+        },
+    };
+
+    let input_filter = InputFormatFilter::from_lists(
+        args.allowed_input_formats
+            .or_else(|| std::env::var("ALLOWED_INPUT_FORMATS").ok())
+            .as_deref(),
+        args.denied_input_formats
+            .or_else(|| std::env::var("DENIED_INPUT_FORMATS").ok())
+            .as_deref(),
+    )?;
+
+    if input_filter.is_restricted() {
+        debug!("restricting input formats ({input_filter:?})");
+    }
+
+    let max_uncompressed_size = match args.max_uncompressed_size {
+        Some(value) => value,
+        None => match std::env::var("MAX_UNCOMPRESSED_SIZE") {
+            Ok(value) => value
+                .parse()
+                .context("invalid MAX_UNCOMPRESSED_SIZE value")?,
+            Err(_) => DEFAULT_MAX_UNCOMPRESSED_SIZE,
+        },
+    };
+
+    let input_limits = InputLimits {
+        max_uncompressed_size: (max_uncompressed_size > 0)
+            .then(|| max_uncompressed_size.saturating_mul(1024 * 1024)),
+    };
+
+    let virus_scanner = match args
+        .clamd_address
+        .or_else(|| std::env::var("CLAMD_ADDRESS").ok())
+    {
+        Some(value) => {
+            let address = ClamdAddress::parse(&value).context("invalid CLAMD_ADDRESS value")?;
+            debug!("scanning uploads with clamd at {address:?}");
+            Some(Arc::new(VirusScanner::new(address)))
+        }
+        None => None,
+    };
+
+    let libreoffice = args
+        .soffice_path
+        .or_else(|| std::env::var("SOFFICE_PATH").ok().map(PathBuf::from))
+        .map(|soffice_path| {
+            debug!("using libreoffice fallback at {}", soffice_path.display());
+            Arc::new(LibreOffice::new(soffice_path))
+        });
+
+    let repair_corrupted_inputs =
+        args.repair_corrupted_inputs || env_flag("REPAIR_CORRUPTED_INPUTS");
+
+    if repair_corrupted_inputs {
+        debug!("repairing corrupted zip based inputs");
+    }
+
+    let poison_crash_limit = match args.poison_crash_limit {
+        Some(value) => Some(value),
+        None => match std::env::var("POISON_CRASH_LIMIT") {
+            Ok(value) => Some(value.parse().context("invalid POISON_CRASH_LIMIT value")?),
+            Err(_) => None,
+        },
+    };
+
+    let poison_window = match args.poison_window {
+        Some(value) => value,
+        None => match std::env::var("POISON_WINDOW") {
+            Ok(value) => value.parse().context("invalid POISON_WINDOW value")?,
+            Err(_) => DEFAULT_POISON_WINDOW,
+        },
+    };
+
+    let poison_documents = poison_crash_limit.map(|max_crashes| {
+        debug!("quarantining documents that crash x2t {max_crashes} times within {poison_window}s");
+        Arc::new(PoisonDocuments::new(
+            max_crashes,
+            Duration::from_secs(poison_window),
+        ))
+    });
+
+    let crash_retention = match args.crash_retention {
+        Some(value) => value,
+        None => match std::env::var("CRASH_RETENTION") {
+            Ok(value) => value.parse().context("invalid CRASH_RETENTION value")?,
+            Err(_) => DEFAULT_CRASH_RETENTION,
+        },
+    };
+
+    let max_crash_reports = match args.max_crash_reports {
+        Some(value) => value,
+        None => match std::env::var("MAX_CRASH_REPORTS") {
+            Ok(value) => value.parse().context("invalid MAX_CRASH_REPORTS value")?,
+            Err(_) => DEFAULT_MAX_CRASH_REPORTS,
+        },
+    };
+
+    let crash_reports = args
+        .crash_dir
+        .or_else(|| std::env::var("CRASH_DIR").ok().map(PathBuf::from))
+        .map(|crash_dir| -> anyhow::Result<_> {
+            let crash_dir = absolute(crash_dir).context("failed to resolve crash directory")?;
+            debug!("preserving x2t crash artifacts in {}", crash_dir.display());
+            Ok(Arc::new(CrashReports::new(
+                crash_dir,
+                Duration::from_secs(crash_retention),
+                max_crash_reports,
+            )))
+        })
+        .transpose()?;
+
+    let docbuilder = args
+        .docbuilder_path
+        .or_else(|| std::env::var("DOCBUILDER_PATH").ok().map(PathBuf::from))
+        .map(|docbuilder_path| {
+            debug!("docbuilder enabled ({})", docbuilder_path.display());
+            Arc::new(DocBuilder::new(docbuilder_path))
+        });
+
+    let min_free_space = match args.min_free_space {
+        Some(value) => value,
+        None => match std::env::var("MIN_FREE_SPACE") {
+            Ok(value) => value.parse().context("invalid MIN_FREE_SPACE value")?,
+            Err(_) => DEFAULT_MIN_FREE_SPACE,
+        },
+    };
+
+    let temp_quota = match args.temp_quota {
+        Some(value) => Some(value),
+        None => match std::env::var("TEMP_QUOTA") {
+            Ok(value) => Some(value.parse().context("invalid TEMP_QUOTA value")?),
+            Err(_) => None,
+        },
+    };
+
+    let scratch_space = Arc::new(ScratchSpace::new(
+        temp_path.clone(),
+        min_free_space.saturating_mul(1024 * 1024),
+        temp_quota.map(|value: u64| value.saturating_mul(1024 * 1024)),
+    ));
+
+    let tenants_file = args
+        .tenants_file
+        .or_else(|| std::env::var("TENANTS_FILE").ok().map(PathBuf::from))
+        .map(|path| TenantsFile::load(&path))
+        .transpose()?;
+
+    // Tenant temporary directories are swept individually
+    let mut janitor_paths = vec![temp_path.clone()];
+    if let Some(tenants_file) = &tenants_file {
+        janitor_paths.extend(tenants_file.temp_paths(&temp_path));
+    }
+
+    let janitor = Arc::new(TempJanitor::new(
+        janitor_paths,
+        Duration::from_secs(temp_max_age),
+    ));
+
+    // Clean up files orphaned by a previous run before accepting conversions
+    janitor.sweep_logged().await;
+
+    // Spawn the background task to remove orphaned temp files
+    tokio::spawn(janitor.run());
+
+    let runtime_config = Arc::new(RuntimeConfig {
+        tenant: None,
+        temp_path,
+        x2t_path,
+        fonts_path,
+        x2t_limits: RwLock::new(settings.x2t_limits),
+        x2t_timeout: RwLock::new(settings.x2t_timeout),
+        x2t_retry_policy: RwLock::new(settings.x2t_retry_policy.clone()),
+        default_pdfa,
+        input_filter,
+        input_limits,
+        virus_scanner,
+        libreoffice,
+        repair_corrupted_inputs,
+        poison_documents,
+        crash_reports,
+        docbuilder,
+        scratch_space,
+        slow_conversion_threshold: slow_conversion_threshold.map(Duration::from_secs),
+        started_at: Instant::now(),
+        metrics: Arc::new(Metrics::default()),
+        cancel_conversions: CancellationToken::new(),
+    });
+
+    let tenants = match tenants_file {
+        Some(tenants_file) => Some(Arc::new(Tenants::new(tenants_file, &runtime_config)?)),
+        None => None,
+    };
+
+    if let Some(Command::Convert {
+        input,
+        output,
+        format,
+        input_format,
+        pdfa,
+        pdf_version,
+        password,
+    }) = &args.command
+    {
+        let fields = ConvertFields {
+            target_format: format.clone(),
+            input_format: input_format.clone(),
+            pdfa: pdfa.then_some(true),
+            pdf_version: pdf_version.clone(),
+            password: password.clone(),
+            ..Default::default()
+        };
+
+        return convert_local(&runtime_config, input, output, fields).await;
+    }
+
+    if args.self_test {
+        let report = run_self_test(&runtime_config).await;
+        let report_json =
+            serde_json::to_string_pretty(&report).context("failed to serialize self-test")?;
+        println!("{report_json}");
+
+        if let Some(failure) = report.failure() {
+            anyhow::bail!("self-test failed at {failure}");
+        }
+
+        return Ok(());
+    }
+
+    // Readiness checks run in the background so that liveness checks can
+    // succeed while a warm-up conversion is running
+    let readiness = Arc::new(Readiness::default());
+    let warmup = args.warmup || env_flag("WARMUP_CONVERSION");
+    let allow_warmup_failure = args.allow_warmup_failure || env_flag("ALLOW_WARMUP_FAILURE");
+
+    tokio::spawn({
+        let readiness = readiness.clone();
+        let runtime_config = runtime_config.clone();
+
+        async move {
+            let warmup_failure = readiness.run_checks(&runtime_config, warmup).await;
+
+            // Fail fast on broken installs rather than running a server
+            // that can never become ready
+            if let Some(failure) = warmup_failure
+                && !allow_warmup_failure
+            {
+                tracing::error!(failure, "warm-up conversion failed, exiting");
+                std::process::exit(1);
+            }
+        }
+    });
+
+    let max_concurrent = settings.max_concurrent;
+    let max_queue = settings.max_queue;
+
+    debug!("allowing {max_concurrent} concurrent conversions (max queue = {max_queue})");
+
+    let fast_lane_max_size = match args.fast_lane_max_size {
+        Some(value) => Some(value),
+        None => match std::env::var("FAST_LANE_MAX_SIZE") {
+            Ok(value) => Some(value.parse().context("invalid FAST_LANE_MAX_SIZE value")?),
+            Err(_) => None,
+        },
+    };
+
+    let fast_lane_slots = match args.fast_lane_slots {
+        Some(value) => value,
+        None => match std::env::var("FAST_LANE_SLOTS") {
+            Ok(value) => value.parse().context("invalid FAST_LANE_SLOTS value")?,
+            Err(_) => DEFAULT_FAST_LANE_SLOTS,
+        },
+    };
+
+    if !(fast_lane_slots > 0.0 && fast_lane_slots <= 1.0) {
+        anyhow::bail!("FAST_LANE_SLOTS must be greater than 0 and at most 1");
+    }
+
+    let fast_lane = fast_lane_max_size.map(|max_size: u64| {
+        debug!(
+            "reserving {}% of conversion slots for inputs up to {max_size}MB",
+            fast_lane_slots * 100.0
+        );
+        FastLane {
+            max_input_size: max_size.saturating_mul(1024 * 1024),
+            reserved_fraction: fast_lane_slots,
+        }
+    });
 
-mod encrypted;
+    let limiter = Arc::new(ConversionLimiter::new(max_concurrent, max_queue, fast_lane));
 
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
-    /// Path to the x2t installation (Omit to determine automatically)
-    #[arg(long)]
-    x2t_path: Option<String>,
+    if args.adaptive_concurrency || env_flag("ADAPTIVE_CONCURRENCY") {
+        let controller = AdaptiveConcurrency::new(limiter.clone())?;
+        debug!("adapting concurrent conversions to the system load");
+        tokio::spawn(controller.run());
+    }
 
-    /// Path to the converter fonts folder
-    #[arg(long)]
-    fonts_path: Option<String>,
+    let job_result_ttl = match args.job_result_ttl {
+        Some(value) => value,
+        None => match std::env::var("JOB_RESULT_TTL") {
+            Ok(value) => value.parse().context("invalid JOB_RESULT_TTL value")?,
+            Err(_) => DEFAULT_JOB_RESULT_TTL,
+        },
+    };
 
-    /// Port to bind the server to, defaults to 8080
-    #[arg(long)]
-    port: Option<u16>,
+    // Job results are stored in the temporary directory
+    if job_result_ttl > temp_max_age {
+        tracing::warn!(
+            "TEMP_MAX_AGE is shorter than JOB_RESULT_TTL, job results may be deleted before they expire"
+        );
+    }
 
-    /// Host to bind the server to, defaults to 0.0.0.0
-    #[arg(long)]
-    host: Option<String>,
-}
+    let job_store = Arc::new(JobStore::new(Duration::from_secs(job_result_ttl)));
 
-const DEFAULT_X2T_PATH: &str = "/var/www/onlyoffice/documentserver/server/FileConverter/bin";
-const DEFAULT_FONTS_PATH: &str = "/var/www/onlyoffice/documentserver/fonts";
+    // Spawn the background task to remove expired job results
+    tokio::spawn(job_store.clone().run_cleanup());
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> anyhow::Result<()> {
-    _ = dotenvy::dotenv();
+    let webhook_secret = args
+        .webhook_secret
+        .or_else(|| std::env::var("WEBHOOK_SECRET").ok());
+    let public_url = args.public_url.or_else(|| std::env::var("PUBLIC_URL").ok());
 
-    // Start configuring a `fmt` subscriber
-    let subscriber = tracing_subscriber::fmt()
-        // Use the logging options from env variables
-        .with_env_filter(EnvFilter::from_default_env())
-        // Display source code file paths
-        .with_file(true)
-        // Display source code line numbers
-        .with_line_number(true)
-        // Don't display the event's target (module path)
-        .with_target(false)
-        // Build the subscriber
-        .finish();
-
-    // use that subscriber to process traces emitted after this point
-    tracing::subscriber::set_global_default(subscriber)?;
+    let convert_service_store = if args.convert_service_api || env_flag("CONVERT_SERVICE_API") {
+        // Result URLs are only built from the configured public URL, the Host
+        // header of the request can't be trusted
+        let convert_service_url = public_url
+            .clone()
+            .context("PUBLIC_URL is required by the conversion api")?;
 
-    let args = Args::parse();
+        let url_filter = match args
+            .convert_service_allowed_networks
+            .clone()
+            .or_else(|| std::env::var("CONVERT_SERVICE_ALLOWED_NETWORKS").ok())
+        {
+            Some(value) => UrlFilter::parse(&value)
+                .context("invalid CONVERT_SERVICE_ALLOWED_NETWORKS value")?,
+            None => UrlFilter::default(),
+        };
 
-    let mut x2t_path: Option<PathBuf> = None;
-    let mut fonts_path: Option<PathBuf> = None;
+        let store = ConvertServiceStore::new(
+            convert_service_url,
+            url_filter,
+            Duration::from_secs(job_result_ttl),
+        )
+        .context("failed to create conversion api http client")?;
+        let store = Arc::new(store);
 
-    // Try loading paths from command line
-    if let Some(path) = args.x2t_path {
-        x2t_path = Some(PathBuf::from(&path));
+        // Spawn the background task to remove expired conversion results
+        tokio::spawn(store.clone().run_cleanup());
+
+        Some(store)
+    } else {
+        None
+    };
+
+    let webhook_url_filter = match args
+        .webhook_allowed_networks
+        .clone()
+        .or_else(|| std::env::var("WEBHOOK_ALLOWED_NETWORKS").ok())
+    {
+        Some(value) => {
+            UrlFilter::parse(&value).context("invalid WEBHOOK_ALLOWED_NETWORKS value")?
+        }
+        None => UrlFilter::default(),
+    };
+
+    let webhook_sender = Arc::new(
+        WebhookSender::new(webhook_secret, public_url, webhook_url_filter)
+            .context("failed to create webhook http client")?,
+    );
+
+    let jwt_auth = match &settings.jwt_secret {
+        Some(secret) => {
+            let header = args
+                .jwt_header
+                .clone()
+                .or_else(|| std::env::var("JWT_HEADER").ok())
+                .unwrap_or_else(|| DEFAULT_JWT_HEADER.to_string());
+            let header = HeaderName::try_from(header).context("invalid jwt header name")?;
+
+            debug!("jwt authentication enabled (header = {header})");
+            Some(Arc::new(JwtAuth::new(secret, header)))
+        }
+        None => None,
+    };
+
+    // Routes that require authentication when enabled
+    let mut protected = Router::new()
+        .route("/convert", post(convert))
+        .route("/convert/archive", post(convert_archive))
+        .route("/convert/batch", post(convert_batch))
+        .route("/convert/merge", post(convert_merge))
+        .route("/inspect", post(inspect))
+        .route("/selftest", post(self_test))
+        .route("/jobs", post(create_job))
+        .route("/jobs/:id", get(get_job).delete(cancel_job))
+        .route("/jobs/:id/events", get(get_job_events))
+        .route("/jobs/:id/result", get(get_job_result));
+
+    let s3_endpoint = args
+        .s3_endpoint
+        .or_else(|| std::env::var("S3_ENDPOINT").ok());
+
+    // Object storage conversions are only available when storage is configured
+    if let Some(s3_endpoint) = s3_endpoint {
+        let region = args
+            .s3_region
+            .or_else(|| std::env::var("S3_REGION").ok())
+            .unwrap_or_else(|| DEFAULT_S3_REGION.to_string());
+        let access_key_id = args
+            .s3_access_key_id
+            .or_else(|| std::env::var("S3_ACCESS_KEY_ID").ok())
+            .context("missing S3_ACCESS_KEY_ID")?;
+        let secret_access_key = args
+            .s3_secret_access_key
+            .or_else(|| std::env::var("S3_SECRET_ACCESS_KEY").ok())
+            .context("missing S3_SECRET_ACCESS_KEY")?;
+
+        let s3 = S3Client::new(&s3_endpoint, region, access_key_id, secret_access_key)?;
+
+        debug!("object storage conversions enabled (endpoint = {s3_endpoint})");
+
+        protected = protected
+            .route("/convert/s3", post(convert_s3))
+            .layer(Extension(Arc::new(s3)));
     }
 
-    if let Some(path) = args.fonts_path {
-        fonts_path = Some(PathBuf::from(&path));
+    // Scripting is only available when docbuilder is configured
+    if runtime_config.docbuilder.is_some() {
+        protected = protected.route("/docbuilder", post(convert_docbuilder));
     }
 
-    // Try loading paths from environment variables
-    if x2t_path.is_none()
-        && let Ok(path) = std::env::var("X2T_PATH")
-    {
-        x2t_path = Some(PathBuf::from(&path));
+    // Results are downloaded by their unguessable ID without authentication
+    // as integrations fetch them like any other file URL
+    let mut public = Router::new();
+
+    // Conversion API routes accept tokens in the request body as well as the
+    // header so they are authenticated separately from the other routes
+    let mut convert_service_routes = None;
+
+    if let Some(store) = convert_service_store {
+        debug!("documentserver conversion api enabled");
+
+        convert_service_routes = Some(
+            Router::new()
+                .route("/ConvertService.ashx", post(convert_service))
+                .route("/converter", post(convert_service))
+                .layer(Extension(store.clone())),
+        );
+
+        public = public
+            .route("/converter/results/:id", get(get_convert_service_result))
+            .layer(Extension(store));
     }
 
-    if fonts_path.is_none()
-        && let Ok(path) = std::env::var("X2T_FONTS_PATH")
+    // Browsers can upload directly using a token issued by a backend
+    if let Some(secret) = args
+        .upload_token_secret
+        .clone()
+        .or_else(|| std::env::var("UPLOAD_TOKEN_SECRET").ok())
     {
-        fonts_path = Some(PathBuf::from(&path));
+        debug!("upload token conversions enabled");
+
+        let upload_tokens = Arc::new(UploadTokens::new(&secret));
+        let upload = Router::new()
+            .route("/convert/upload", post(convert))
+            .route_layer(middleware::from_fn_with_state(
+                upload_tokens,
+                require_upload_token,
+            ))
+            .route_layer(middleware::from_fn(request_span));
+
+        public = public.merge(upload);
     }
 
-    // Try determine default path
-    if x2t_path.is_none() {
-        let default_path = Path::new(DEFAULT_X2T_PATH);
+    // Tenant is selected after authentication so that it can replace the
+    // runtime configuration for the request
+    if let Some(tenants) = tenants {
+        protected = protected.route_layer(middleware::from_fn_with_state(
+            tenants.clone(),
+            select_tenant,
+        ));
+        convert_service_routes = convert_service_routes.map(|routes| {
+            routes.route_layer(middleware::from_fn_with_state(tenants, select_tenant))
+        });
+    }
 
-        if default_path.is_dir() {
-            x2t_path = Some(default_path.to_path_buf());
-        }
+    if let Some(jwt_auth) = jwt_auth.clone() {
+        protected = protected.route_layer(middleware::from_fn_with_state(
+            jwt_auth.clone(),
+            require_jwt,
+        ));
+        convert_service_routes = convert_service_routes.map(|routes| {
+            routes.route_layer(middleware::from_fn_with_state(
+                jwt_auth,
+                require_convert_service_jwt,
+            ))
+        });
     }
 
-    if fonts_path.is_none() {
-        let default_path = Path::new(DEFAULT_FONTS_PATH);
-        fonts_path = Some(default_path.to_path_buf());
+    if let Some(convert_service_routes) = convert_service_routes {
+        protected = protected.merge(convert_service_routes);
     }
 
-    // Check a path was provided
-    let x2t_path = match x2t_path {
-        Some(value) => absolute(value).context("failed to make x2t path absolute")?,
-        None => {
-            error!("no x2t install path provided, cannot start server");
-            panic!();
-        }
-    };
+    let protected = protected.route_layer(middleware::from_fn(request_span));
 
-    let fonts_path = match fonts_path {
-        Some(value) => absolute(value).context("failed to make fonts path absolute")?,
-        None => {
-            error!("no fonts path provided, cannot start server");
-            panic!();
+    let admin_auth = settings
+        .admin_token
+        .clone()
+        .map(|token| Arc::new(AdminAuth::new(token)));
+
+    let reloader = Arc::new(ConfigReloader {
+        args: reload_args,
+        process_env,
+        runtime_config: runtime_config.clone(),
+        limiter: limiter.clone(),
+        jwt_auth: jwt_auth.clone(),
+        admin_auth: admin_auth.clone(),
+        log_filter,
+    });
+
+    // Reload the configuration on SIGHUP without dropping in-flight conversions
+    #[cfg(unix)]
+    tokio::spawn(reloader.clone().run_on_hangup());
+
+    // Admin routes are only available when an admin token is configured
+    let admin = match admin_auth.clone() {
+        Some(admin_auth) => {
+            debug!("admin endpoints enabled");
+
+            Router::new()
+                .route("/admin/crashes", get(list_crashes))
+                .route("/admin/fonts", get(list_fonts).post(upload_fonts))
+                .route("/admin/fonts/regenerate", post(regenerate_fonts))
+                .route("/admin/jobs", get(list_conversions))
+                .route("/admin/reload", post(reload_config))
+                .route_layer(middleware::from_fn_with_state(admin_auth, require_admin))
         }
+        None => Router::new(),
     };
 
-    tracing::debug!("using x2t install from: {}", x2t_path.display());
+    let trusted_proxies = match args
+        .trusted_proxies
+        .clone()
+        .or_else(|| std::env::var("TRUSTED_PROXIES").ok())
+    {
+        Some(value) => TrustedProxies::parse(&value).context("invalid TRUSTED_PROXIES value")?,
+        None => TrustedProxies::default(),
+    };
 
-    let temp_path = temp_dir();
-    let temp_path = temp_path.join("onlyoffice-convert-server");
+    if !trusted_proxies.is_empty() {
+        debug!("trusting forwarded headers from {trusted_proxies:?}");
+    }
 
-    let runtime_config = Arc::new(RuntimeConfig {
-        temp_path,
-        x2t_path,
-        fonts_path,
-    });
+    let allowed_ips = args
+        .allowed_ips
+        .clone()
+        .or_else(|| std::env::var("ALLOWED_IPS").ok());
+    let denied_ips = args
+        .denied_ips
+        .clone()
+        .or_else(|| std::env::var("DENIED_IPS").ok());
+    let ip_access_list = Arc::new(
+        IpAccessList::parse(allowed_ips.as_deref(), denied_ips.as_deref())
+            .context("invalid ALLOWED_IPS or DENIED_IPS value")?,
+    );
 
-    // Determine the address to run the server on
-    let server_address = if args.host.is_some() || args.port.is_some() {
-        let host = args.host.unwrap_or_else(|| "0.0.0.0".to_string());
-        let port = args.port.unwrap_or(8080);
+    if !ip_access_list.is_empty() {
+        debug!("restricting client addresses to {ip_access_list:?}");
+    }
 
-        format!("{host}:{port}")
+    let docs = if args.swagger_ui || env_flag("SWAGGER_UI") {
+        debug!("swagger ui enabled");
+        Router::new().route("/docs", get(openapi::swagger_ui))
     } else {
-        std::env::var("SERVER_ADDRESS").context("missing SERVER_ADDRESS")?
+        Router::new()
+    };
+
+    let access_log = match args
+        .access_log
+        .clone()
+        .or_else(|| std::env::var("ACCESS_LOG").ok())
+    {
+        Some(destination) => {
+            let format = match args.access_log_format {
+                Some(value) => value,
+                None => match std::env::var("ACCESS_LOG_FORMAT") {
+                    Ok(value) => AccessLogFormat::from_str(&value, true)
+                        .map_err(|err| anyhow::anyhow!("invalid ACCESS_LOG_FORMAT value: {err}"))?,
+                    Err(_) => AccessLogFormat::default(),
+                },
+            };
+
+            debug!("writing access log to {destination}");
+            Some(Arc::new(AccessLog::open(&destination, format)?))
+        }
+        None => None,
     };
 
     // Create the router
     let app = Router::new()
-        .route("/convert", post(convert))
-        .layer(Extension(runtime_config))
-        .layer(DefaultBodyLimit::max(1024 * 1024 * 1024));
+        .route("/health", get(health))
+        .route("/metrics", get(metrics))
+        .route("/status", get(status))
+        .route("/ready", get(ready))
+        .route("/openapi.json", get(openapi::openapi_json))
+        .merge(docs)
+        .merge(protected)
+        .merge(public)
+        .merge(admin)
+        .layer(Extension(runtime_config.clone()))
+        .layer(Extension(limiter.clone()))
+        .layer(Extension(job_store))
+        .layer(Extension(webhook_sender))
+        .layer(Extension(font_manager))
+        .layer(Extension(admin_auth))
+        .layer(Extension(readiness))
+        .layer(Extension(reloader))
+        .layer(Extension(Arc::new(ConversionCoalescer::default())))
+        // Access is checked against the client address resolved by the outer layer
+        .layer(middleware::from_fn_with_state(
+            ip_access_list.clone(),
+            enforce_ip_access,
+        ))
+        // Requests rejected by the access list are still logged
+        .layer(middleware::from_fn_with_state(access_log, log_access))
+        .layer(middleware::from_fn_with_state(
+            Arc::new(trusted_proxies),
+            resolve_client_ip,
+        ))
+        .layer(DefaultBodyLimit::max(1024 * 1024 * 1024))
+        // Uploads compressed by clients or gateways are decompressed before
+        // reaching the handlers, the body limit applies to the decompressed size
+        .layer(RequestDecompressionLayer::new().gzip(true).zstd(true));
+
+    let tls_cert = args
+        .tls_cert
+        .or_else(|| std::env::var("TLS_CERT").ok().map(PathBuf::from));
+    let tls_key = args
+        .tls_key
+        .or_else(|| std::env::var("TLS_KEY").ok().map(PathBuf::from));
+
+    let tls_client_ca = args
+        .tls_client_ca
+        .or_else(|| std::env::var("TLS_CLIENT_CA").ok().map(PathBuf::from));
+
+    let tls_config = match (tls_cert, tls_key) {
+        (Some(cert), Some(key)) => Some(load_tls_config(&cert, &key, tls_client_ca.as_deref())?),
+        (None, None) if tls_client_ca.is_some() => {
+            anyhow::bail!("client certificate verification requires tls to be enabled")
+        }
+        (None, None) => None,
+        _ => anyhow::bail!("both a tls certificate and key must be provided to enable tls"),
+    };
+
+    let env_seconds = |value: Option<u64>, name: &str| -> anyhow::Result<Option<Duration>> {
+        let value = match value {
+            Some(value) => Some(value),
+            None => match std::env::var(name) {
+                Ok(value) => Some(
+                    value
+                        .parse()
+                        .with_context(|| format!("invalid {name} value"))?,
+                ),
+                Err(_) => None,
+            },
+        };
+
+        Ok(value.map(Duration::from_secs))
+    };
+
+    let drain_timeout = match args.drain_timeout {
+        Some(value) => value,
+        None => match std::env::var("DRAIN_TIMEOUT") {
+            Ok(value) => value.parse().context("invalid DRAIN_TIMEOUT value")?,
+            Err(_) => DEFAULT_DRAIN_TIMEOUT,
+        },
+    };
+
+    let http_tuning = HttpTuning {
+        disable_keep_alive: args.disable_keep_alive || env_flag("DISABLE_KEEP_ALIVE"),
+        header_timeout: env_seconds(args.http_header_timeout, "HTTP_HEADER_TIMEOUT")?,
+        idle_timeout: env_seconds(args.http_idle_timeout, "HTTP_IDLE_TIMEOUT")?,
+        http2_keep_alive_interval: env_seconds(
+            args.http2_keep_alive_interval,
+            "HTTP2_KEEP_ALIVE_INTERVAL",
+        )?,
+        http2_max_concurrent_streams: match args.http2_max_concurrent_streams {
+            Some(value) => Some(value),
+            None => match std::env::var("HTTP2_MAX_CONCURRENT_STREAMS") {
+                Ok(value) => Some(
+                    value
+                        .parse()
+                        .context("invalid HTTP2_MAX_CONCURRENT_STREAMS value")?,
+                ),
+                Err(_) => None,
+            },
+        },
+        // In-flight requests are bounded by the drain timeout as well
+        shutdown_timeout: Some(Duration::from_secs(drain_timeout)),
+    };
+
+    debug!("http connection settings: {http_tuning:?}");
+
+    let shutdown = CancellationToken::new();
+
+    // Wait for the shutdown signal then drain in-flight conversions, new
+    // conversions are rejected while draining
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        let limiter = limiter.clone();
+        let cancel_conversions = runtime_config.cancel_conversions.clone();
+
+        async move {
+            shutdown_signal().await;
+            tracing::debug!("server shutting down");
+            systemd::notify_stopping();
+
+            // Conversions still running at the timeout are killed rather
+            // than holding up the shutdown
+            if !limiter.drain(Duration::from_secs(drain_timeout)).await {
+                cancel_conversions.cancel();
+            }
+
+            shutdown.cancel();
+        }
+    });
+
+    // Serve the gRPC API alongside the HTTP API when enabled
+    if let Some(grpc_address) = grpc_address {
+        let listener = tokio::net::TcpListener::bind(&grpc_address)
+            .await
+            .context("failed to bind grpc server")?;
+        let incoming = TcpIncoming::from_listener(listener, true, None)
+            .map_err(|err| anyhow::anyhow!("failed to create grpc listener: {err}"))?;
+
+        let service = ConvertServiceServer::with_interceptor(
+            GrpcConvertService::new(runtime_config.clone(), limiter.clone()),
+            GrpcAuthInterceptor::new(jwt_auth, ip_access_list),
+        );
+
+        debug!("grpc server started on: {grpc_address}");
+
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_incoming_shutdown(incoming, shutdown.clone().cancelled_owned())
+                .map(|result| {
+                    if let Err(err) = result {
+                        error!(?err, "grpc server failed");
+                    }
+                }),
+        );
+    }
+
+    let shutdown_signal = shutdown.cancelled_owned();
+
+    // Keep the systemd watchdog satisfied while the server is running
+    tokio::spawn(systemd::run_watchdog());
+
+    if let Some(unix_socket) = unix_socket
+        && activated_listener.is_none()
+    {
+        if tls_config.is_some() {
+            anyhow::bail!("tls is not supported when serving on a unix socket");
+        }
+
+        let unix_socket_mode = args
+            .unix_socket_mode
+            .or_else(|| std::env::var("UNIX_SOCKET_MODE").ok());
+
+        return serve_unix_socket(
+            &unix_socket,
+            unix_socket_mode,
+            &http_tuning,
+            app,
+            shutdown_signal,
+        )
+        .await;
+    }
 
     // Create a TCP listener
-    let listener = tokio::net::TcpListener::bind(&server_address)
-        .await
-        .context("failed to bind http server")?;
+    let listener = match activated_listener {
+        Some(listener) => tokio::net::TcpListener::from_std(listener)
+            .context("failed to use the socket passed by systemd")?,
+        None => {
+            let server_address = server_address.context("missing SERVER_ADDRESS")?;
+            tokio::net::TcpListener::bind(&server_address)
+                .await
+                .context("failed to bind http server")?
+        }
+    };
 
-    debug!("server started on: {server_address}");
+    let server_address = listener
+        .local_addr()
+        .context("failed to get server address")?;
+
+    systemd::notify_ready();
+
+    if tls_config.is_some() {
+        debug!("server started on: https://{server_address}");
+    } else {
+        debug!("server started on: {server_address}");
+    }
 
     // Serve the app from the listener
-    axum::serve(listener, app)
-        .with_graceful_shutdown(async move {
-            _ = ctrl_c().await;
-            tracing::debug!("server shutting down");
-        })
+    serve_tcp(listener, tls_config, &http_tuning, app, shutdown_signal)
+        .await
+        .context("failed to serve")
+}
+
+/// Serve the app on a unix domain socket
+#[cfg(unix)]
+async fn serve_unix_socket(
+    path: &Path,
+    mode: Option<String>,
+    tuning: &HttpTuning,
+    app: Router,
+    shutdown_signal: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    let mode = match mode {
+        Some(mode) => {
+            u32::from_str_radix(mode.trim(), 8).context("invalid UNIX_SOCKET_MODE value")?
+        }
+        None => unix::DEFAULT_UNIX_SOCKET_MODE,
+    };
+
+    debug!("server started on: unix:{}", path.display());
+
+    unix::serve_unix(path, mode, tuning, app, shutdown_signal)
         .await
-        .context("failed to serve")?;
+        .context("failed to serve")
+}
+
+#[cfg(not(unix))]
+async fn serve_unix_socket(
+    _path: &Path,
+    _mode: Option<String>,
+    _tuning: &HttpTuning,
+    _app: Router,
+    _shutdown_signal: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    anyhow::bail!("unix sockets are not supported on this platform")
+}
+
+/// Wait for the signal to shutdown the server, SIGINT (Ctrl+C) or SIGTERM
+/// on unix systems
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(err) => error!(?err, "failed to listen for SIGTERM"),
+        }
+    }
+
+    _ = ctrl_c().await;
+}
 
-    Ok(())
+/// Check if a boolean flag environment variable is enabled
+fn env_flag(name: &str) -> bool {
+    std::env::var(name).is_ok_and(|value| matches!(value.trim(), "1" | "true" | "TRUE" | "yes"))
 }
 
 struct RuntimeConfig {
+    /// Name of the tenant the configuration belongs to, None for the
+    /// server configuration
+    tenant: Option<String>,
     temp_path: PathBuf,
     x2t_path: PathBuf,
     fonts_path: PathBuf,
+    /// Resource limits applied to each x2t process
+    x2t_limits: RwLock<ProcessLimits>,
+    /// Maximum time each x2t process can run for
+    x2t_timeout: RwLock<Option<Duration>>,
+    /// Policy for retrying transient x2t failures
+    x2t_retry_policy: RwLock<RetryPolicy>,
+    /// Whether PDF output should be PDF/A unless the request specifies otherwise
+    default_pdfa: bool,
+    /// Input formats accepted for conversion
+    input_filter: InputFormatFilter,
+    /// Limits on the complexity of input documents
+    input_limits: InputLimits,
+    /// Scanner uploaded files are checked with before being converted
+    virus_scanner: Option<Arc<VirusScanner>>,
+    /// LibreOffice install used for inputs x2t rejects
+    libreoffice: Option<Arc<LibreOffice>>,
+    /// Whether corrupted ZIP based inputs are repaired and converted again
+    repair_corrupted_inputs: bool,
+    /// Documents that crashed x2t, shared between tenants
+    poison_documents: Option<Arc<PoisonDocuments>>,
+    /// Storage for the artifacts of x2t crashes, shared between tenants
+    crash_reports: Option<Arc<CrashReports>>,
+    /// docbuilder install used for scripts and form filling
+    docbuilder: Option<Arc<DocBuilder>>,
+    /// Disk space used by conversions in the temporary directory
+    scratch_space: Arc<ScratchSpace>,
+    /// Conversions taking at least this long are logged as slow
+    slow_conversion_threshold: Option<Duration>,
+    /// When the server was started
+    started_at: Instant,
+    /// Server metrics, shared between tenants
+    metrics: Arc<Metrics>,
+    /// Cancelled when the drain timeout is reached during shutdown, running
+    /// x2t processes are killed. Shared between tenants
+    cancel_conversions: CancellationToken,
 }
 
-/// Request to convert a file
-#[derive(TryFromMultipart)]
-struct UploadAssetRequest {
-    /// The file to convert
-    #[form_data(limit = "unlimited")]
-    file: FieldData<Bytes>,
-}
-
-struct ConvertTempPaths {
-    config_path: PathBuf,
-    input_path: PathBuf,
-    output_path: PathBuf,
+/// Response for the health check endpoint
+#[derive(Serialize)]
+struct HealthResponse {
+    /// Number of seconds the server has been running
+    uptime: u64,
+    /// Version of the server
+    version: &'static str,
+    /// Resolved path to the x2t install
+    x2t_path: String,
 }
 
-fn create_convert_temp_paths(temp_dir: &Path) -> std::io::Result<ConvertTempPaths> {
-    // Generate random unique ID
-    let random_id = Uuid::new_v4().simple();
-
-    // Create paths in temp directory
-    let config_path = temp_dir.join(format!("tmp_native_config_{random_id}.xml"));
-    let input_path = temp_dir.join(format!("tmp_native_input_{random_id}"));
-    let output_path = temp_dir.join(format!("tmp_native_output_{random_id}.pdf"));
-
-    // Make paths absolute
-    let config_path = absolute(config_path)
-        .inspect_err(|err| tracing::error!(?err, "failed to make file path absolute (config)"))?;
-    let input_path = absolute(input_path)
-        .inspect_err(|err| tracing::error!(?err, "failed to make file path absolute (input)"))?;
-    let output_path = absolute(output_path)
-        .inspect_err(|err| tracing::error!(?err, "failed to make file path absolute (output)"))?;
-
-    Ok(ConvertTempPaths {
-        config_path,
-        input_path,
-        output_path,
+/// GET /health
+///
+/// Liveness check, responds with basic details about the server
+async fn health(Extension(runtime_config): Extension<Arc<RuntimeConfig>>) -> Json<HealthResponse> {
+    Json(HealthResponse {
+        uptime: runtime_config.started_at.elapsed().as_secs(),
+        version: env!("CARGO_PKG_VERSION"),
+        x2t_path: runtime_config.x2t_path.display().to_string(),
     })
 }
 
 /// POST /convert
 ///
-/// Converts the provided file to PDF format responding with the PDF file
+/// Converts the provided file to the requested format (PDF by default)
+/// responding with the converted file
+#[allow(clippy::too_many_arguments)]
 async fn convert(
     Extension(runtime_config): Extension<Arc<RuntimeConfig>>,
-    TypedMultipart(UploadAssetRequest { file }): TypedMultipart<UploadAssetRequest>,
+    Extension(coalescer): Extension<Arc<ConversionCoalescer>>,
+    admin_access: AdminAccess,
+    AcceptedFormat(accepted_format): AcceptedFormat,
+    RequestDeadline(deadline): RequestDeadline,
+    queue_ticket: QueueTicket,
+    RawQuery(query): RawQuery,
+    upload_grant: Option<Extension<UploadGrant>>,
+    multipart: Multipart,
 ) -> Result<Response<Body>, ErrorResponse> {
-    // Ensure temporary path exists
-    if !runtime_config.temp_path.exists() {
-        tokio::fs::create_dir_all(&runtime_config.temp_path)
-            .await
-            .map_err(|err| {
-                tracing::error!(?err, "failed to create temporary directory");
-                ErrorResponse {
-                    code: None,
-                    message: "failed to create temporary directory".to_string(),
-                }
-            })?
+    let mut temp_paths = create_convert_temp_paths(&runtime_config).await?;
+
+    let mut upload = read_convert_upload(query, multipart, &temp_paths).await?;
+    debug!(size = upload.size, "received file for conversion");
+    queue_ticket.set_input_size(upload.size);
+
+    if let Some(input_checksum) = upload.fields.input_checksum.take() {
+        verify_input_checksum(&temp_paths.input_path, &input_checksum).await?;
+    }
+
+    // Explicit target format takes priority over the Accept header
+    if upload.fields.target_format.is_none()
+        && let Some(accepted_format) = accepted_format
+    {
+        upload.fields.target_format = Some(accepted_format.extension().to_string());
     }
 
-    // Create temporary path
-    let ConvertTempPaths {
-        config_path,
-        input_path,
-        output_path,
-    } = create_convert_temp_paths(&runtime_config.temp_path).map_err(|err| {
-        tracing::error!(?err, "failed to setup temporary paths");
+    let mut options = upload.fields.into_options(runtime_config.default_pdfa)?;
+    admin_access.authorize(&options)?;
+    options.deadline = deadline;
+
+    if let Some(Extension(upload_grant)) = upload_grant {
+        upload_grant.authorize(upload.size, &options)?;
+    }
+
+    temp_paths
+        .resolve_input_extension(
+            &runtime_config,
+            options.input_format.as_deref(),
+            upload.file_name.as_deref(),
+        )
+        .await?;
+
+    let key = conversion_key(
+        &temp_paths.input_path,
+        &options,
+        runtime_config.tenant.as_deref(),
+    )
+    .await
+    .map_err(|err| {
+        tracing::error!(?err, "failed to hash input file");
         ErrorResponse {
             code: None,
-            message: "failed to setup temporary paths".to_string(),
+            kind: ErrorKind::Internal,
+            message: "failed to read uploaded file".to_string(),
+            backtrace: error_backtrace(&err),
         }
     })?;
 
-    let config = format!(
-        r#"
-        <?xml version="1.0" encoding="utf-8"?>
-        <TaskQueueDataConvert xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance"
-                              xmlns:xsd="http://www.w3.org/2001/XMLSchema">
-          <m_sFileFrom>{}</m_sFileFrom>
-          <m_sFileTo>{}</m_sFileTo>
-          <m_sFontDir>{}</m_sFontDir>
-          <m_nFormatTo>513</m_nFormatTo>
-        </TaskQueueDataConvert>
-        "#,
-        input_path.display(),
-        output_path.display(),
-        runtime_config.fonts_path.display(),
-    );
-
-    let result = x2t(
-        &input_path,
-        &config_path,
-        &output_path,
-        &runtime_config.x2t_path,
-        &file.contents,
-        config.as_bytes(),
-    )
-    .await;
+    let output_format = options.output_format;
+    let debug = options.debug;
+    let output_name = output_file_name(upload.file_name.as_deref(), output_format.extension());
 
-    // Spawn a cleanup task
-    tokio::spawn(async move {
-        if input_path.exists()
-            && let Err(err) = tokio::fs::remove_file(input_path).await
-        {
-            tracing::error!(?err, "failed to delete config file");
+    let conversion = match coalescer.join(&key) {
+        // Identical conversion is already running, wait for its result instead
+        Some(conversion) => {
+            debug!("joining in-flight conversion of identical input");
+            drop(queue_ticket);
+            drop(temp_paths);
+            conversion
         }
+        None => {
+            let runtime_config = runtime_config.clone();
 
-        if config_path.exists()
-            && let Err(err) = tokio::fs::remove_file(config_path).await
-        {
-            tracing::error!(?err, "failed to delete config file");
-        }
+            coalescer.start(key, async move {
+                // Wait for a free conversion slot
+                let _permit = queue_ticket
+                    .acquire_before(options.priority, options.deadline)
+                    .await?;
 
-        if output_path.exists()
-            && let Err(err) = tokio::fs::remove_file(output_path).await
-        {
-            tracing::error!(?err, "failed to delete config file");
+                convert_file(&runtime_config, &temp_paths, &options).await
+            })
         }
-    });
+    };
 
-    let converted = match result {
-        Ok(value) => value,
-        Err(err) => return Err(err),
+    // Requests joining a conversion started by another request still give
+    // up at their own deadline
+    let output_file = match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline.into(), conversion)
+            .await
+            .map_err(|_| {
+                deadline_exceeded("conversion didn't finish before the request deadline")
+            })??,
+        None => conversion.await?,
     };
+    let backend = output_file.backend();
+    let usage = output_file.usage();
+    let checksum = output_checksum(output_file.path()).await?;
+
+    let body = output_file.into_shared_body().await.map_err(|err| {
+        tracing::error!(?err, "failed to open output file");
+        ErrorResponse {
+            code: None,
+            kind: ErrorKind::Internal,
+            message: "failed to read output".to_string(),
+            backtrace: error_backtrace(&err),
+        }
+    })?;
 
     // Build the response
-    let response = Response::builder()
-        .header(
-            header::CONTENT_TYPE,
-            HeaderValue::from_static("application/pdf"),
-        )
-        .body(Body::from(converted))
+    let mut response = Response::builder().header(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(output_format.content_type()),
+    );
+
+    if let Some(policy) = output_format.content_security_policy() {
+        response = response.header(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static(policy),
+        );
+    }
+
+    // Resource usage is only included when diagnostics were requested
+    if debug {
+        for (name, value) in usage.headers() {
+            response = response.header(name, value);
+        }
+    }
+
+    let response = response
+        .header(header::CONTENT_DISPOSITION, attachment(&output_name))
+        .header(CONVERSION_BACKEND_HEADER, backend.as_str())
+        .header(OUTPUT_CHECKSUM_HEADER, checksum)
+        .body(body)
         .map_err(|err| {
             tracing::error!(?err, "failed to make response");
             ErrorResponse {
                 code: None,
+                kind: ErrorKind::Internal,
                 message: "failed to make response".to_string(),
+                backtrace: error_backtrace(&err),
             }
         })?;
 
     Ok(response)
 }
 
-#[cfg(not(windows))]
-const X2T_BIN: &str = "x2t";
-#[cfg(windows)]
-const X2T_BIN: &str = "x2t.exe";
-
-async fn x2t(
-    input_path: &Path,
-    config_path: &Path,
-    output_path: &Path,
-    x2t_path: &Path,
-    input_bytes: &[u8],
-    config_bytes: &[u8],
-) -> Result<Vec<u8>, ErrorResponse> {
-    let file_condition = get_file_condition(input_bytes);
-    let write_file = tokio::fs::write(input_path, input_bytes);
-    let write_config = tokio::fs::write(config_path, config_bytes);
-
-    let x2t = x2t_path.join(X2T_BIN);
-    let x2t = x2t.to_string_lossy();
-
-    try_join!(write_config, write_file).map_err(|err| {
-        tracing::error!(?err, "failed to write files");
-        ErrorResponse {
-            code: None,
-            message: "failed to write files".to_string(),
-        }
-    })?;
-
-    // Update the library path to include the x2t bin directory, fixes a bug where some of the requires
-    // .so libraries aren't loaded when they need to be
-    let ld_library_path = std::env::var("LD_LIBRARY_PATH").unwrap_or_default();
-    let ld_library_path = format!("{}:{}", x2t_path.display(), ld_library_path);
+/// Stable category of an error, allows clients to handle errors without
+/// matching on the message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// Request was malformed or had invalid options
+    InvalidRequest,
+    /// Request is missing valid credentials
+    Unauthorized,
+    /// Client isn't allowed to make the request
+    Forbidden,
+    /// Requested resource doesn't exist
+    NotFound,
+    /// Resource is in a state that doesn't allow the request
+    Conflict,
+    /// File is encrypted and the password is missing or incorrect
+    Encrypted,
+    /// File is corrupted and can't be read
+    Corrupted,
+    /// Format of the file or the requested output format isn't supported
+    UnsupportedFormat,
+    /// Conversion took too long
+    Timeout,
+    /// File or request is too large
+    TooLarge,
+    /// Conversion exceeded one of the x2t resource limits
+    ResourceLimit,
+    /// File was rejected by the virus scanner
+    Infected,
+    /// File repeatedly crashed x2t and is quarantined
+    PoisonDocument,
+    /// Conversion queue is full, the request can be retried later
+    Busy,
+    /// Server is unable to handle the request or is shutting down
+    Unavailable,
+    /// x2t failed to convert the file for an unknown reason
+    ConversionFailed,
+    /// Unexpected server failure
+    Internal,
+}
 
-    let output = Command::new(x2t.as_ref())
-        .arg(config_path.display().to_string())
-        .env("LD_LIBRARY_PATH", &ld_library_path)
-        .output()
-        .await
-        .map_err(|err| {
-            tracing::error!(?err, "failed to run x2t");
-            ErrorResponse {
-                code: None,
-                message: "failed to run x2t".to_string(),
-            }
-        })?;
+impl ErrorKind {
+    /// HTTP status code responded with for this kind of error
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ErrorKind::InvalidRequest => StatusCode::BAD_REQUEST,
+            ErrorKind::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorKind::Forbidden => StatusCode::FORBIDDEN,
+            ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            ErrorKind::Conflict => StatusCode::CONFLICT,
+            ErrorKind::Encrypted
+            | ErrorKind::Corrupted
+            | ErrorKind::ResourceLimit
+            | ErrorKind::Infected
+            | ErrorKind::PoisonDocument => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorKind::UnsupportedFormat => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ErrorKind::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorKind::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            ErrorKind::Busy => StatusCode::TOO_MANY_REQUESTS,
+            ErrorKind::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorKind::ConversionFailed | ErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
 
-    if !output.status.success() {
-        let error_code = output.status.code();
-        let message = error_code
-            .and_then(get_error_code_message)
-            .unwrap_or("unknown error occurred");
+#[derive(Clone, Serialize)]
+pub struct ErrorResponse {
+    pub code: Option<i32>,
+    /// Category of the error
+    pub kind: ErrorKind,
+    pub message: String,
+    /// Diagnostic details about the failure, only included for debug requests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backtrace: Option<String>,
+}
 
-        let stderr = String::from_utf8_lossy(&output.stderr);
+/// Whether internal errors include their details in the backtrace field,
+/// enabled with `--debug-errors`
+static DEBUG_ERRORS: AtomicBool = AtomicBool::new(false);
 
-        tracing::error!(
-            "error processing file (stderr = {stderr}, exit code = {error_code:?}, file_condition = {file_condition:?})"
-        );
+/// Create the backtrace for an internal error from its chain of causes and
+/// the current stack, returns None unless error details are enabled
+pub fn error_backtrace(err: &dyn std::error::Error) -> Option<String> {
+    if !DEBUG_ERRORS.load(Ordering::Relaxed) {
+        return None;
+    }
 
-        // Assume encryption for out of range crashes
-        if stderr.contains("std::out_of_range") {
-            return Err(ErrorResponse {
-                code: error_code,
-                message: "file is encrypted".to_string(),
-            });
-        }
+    let mut backtrace = err.to_string();
+    let mut source = err.source();
 
-        return Err(match file_condition {
-            FileCondition::LikelyCorrupted => ErrorResponse {
-                code: error_code,
-                message: "file is corrupted".to_string(),
-            },
-            FileCondition::LikelyEncrypted => ErrorResponse {
-                code: error_code,
-                message: "file is encrypted".to_string(),
-            },
-            _ => ErrorResponse {
-                code: error_code,
-                message: message.to_string(),
-            },
-        });
+    if source.is_some() {
+        backtrace.push_str("\n\nCaused by:");
     }
 
-    // Read the output file back
-    tokio::fs::read(output_path).await.map_err(|err| {
-        tracing::error!(?err, "failed to read output");
-        ErrorResponse {
-            code: None,
-            message: "failed to read output".to_string(),
-        }
-    })
-}
+    let mut index = 0;
+    while let Some(cause) = source {
+        backtrace.push_str(&format!("\n    {index}: {cause}"));
+        source = cause.source();
+        index += 1;
+    }
 
-/// Translate a x2t error code to the common x2t error messages
-fn get_error_code_message(code: i32) -> Option<&'static str> {
-    Some(match code {
-        0x0001 => "AVS_FILEUTILS_ERROR_UNKNOWN",
-        0x0050 => "AVS_FILEUTILS_ERROR_CONVERT",
-        0x0051 => "AVS_FILEUTILS_ERROR_CONVERT_DOWNLOAD",
-        0x0052 => "AVS_FILEUTILS_ERROR_CONVERT_UNKNOWN_FORMAT",
-        0x0053 => "AVS_FILEUTILS_ERROR_CONVERT_TIMEOUT",
-        0x0054 => "AVS_FILEUTILS_ERROR_CONVERT_READ_FILE",
-        0x0055 => "AVS_FILEUTILS_ERROR_CONVERT_DRM_UNSUPPORTED",
-        0x0056 => "AVS_FILEUTILS_ERROR_CONVERT_CORRUPTED",
-        0x0057 => "AVS_FILEUTILS_ERROR_CONVERT_LIBREOFFICE",
-        0x0058 => "AVS_FILEUTILS_ERROR_CONVERT_PARAMS",
-        0x0059 => "AVS_FILEUTILS_ERROR_CONVERT_NEED_PARAMS",
-        0x005a => "AVS_FILEUTILS_ERROR_CONVERT_DRM",
-        0x005b => "AVS_FILEUTILS_ERROR_CONVERT_PASSWORD",
-        0x005c => "AVS_FILEUTILS_ERROR_CONVERT_ICU",
-        0x005d => "AVS_FILEUTILS_ERROR_CONVERT_LIMITS",
-        0x005e => "AVS_FILEUTILS_ERROR_CONVERT_ROWLIMITS",
-        0x005f => "AVS_FILEUTILS_ERROR_CONVERT_DETECT",
-        0x0060 => "AVS_FILEUTILS_ERROR_CONVERT_CELLLIMITS",
-        _ => return None,
-    })
-}
+    backtrace.push_str(&format!(
+        "\n\nStack backtrace:\n{}",
+        std::backtrace::Backtrace::force_capture()
+    ));
 
-#[derive(Serialize)]
-pub struct ErrorResponse {
-    pub code: Option<i32>,
-    pub message: String,
+    Some(backtrace)
 }
 
 impl IntoResponse for ErrorResponse {
     fn into_response(self) -> axum::response::Response {
-        (StatusCode::INTERNAL_SERVER_ERROR, Json(self)).into_response()
+        (self.kind.status_code(), Json(self)).into_response()
     }
 }
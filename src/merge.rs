@@ -0,0 +1,314 @@
+use axum::{
+    Extension,
+    body::Body,
+    extract::{Multipart, RawQuery},
+    http::{HeaderValue, Response, header},
+};
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
+
+use crate::{
+    ErrorKind, ErrorResponse, RuntimeConfig,
+    auth::AdminAccess,
+    convert::{OutputFile, convert_file, create_temp_dir},
+    deadline::{RequestDeadline, deadline_exceeded},
+    disposition::attachment,
+    error_backtrace,
+    format::OutputFormat,
+    limiter::QueueTicket,
+    pdf_version::{read_pdf_version, version_unavailable},
+    upload::read_batch_upload,
+};
+
+/// Name the merged document is downloaded as
+const MERGED_FILE_NAME: &str = "merged.pdf";
+
+/// Page attributes that can be inherited from the parent nodes of the page tree
+const INHERITABLE_PAGE_ATTRIBUTES: &[&[u8]] = &[b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+/// Maximum depth of the page tree walked when collecting inherited attributes
+const MAX_PAGE_TREE_DEPTH: usize = 64;
+
+/// POST /convert/merge
+///
+/// Converts multiple files to PDF and concatenates them, in the order they
+/// were uploaded, into a single PDF document
+pub async fn convert_merge(
+    Extension(runtime_config): Extension<Arc<RuntimeConfig>>,
+    admin_access: AdminAccess,
+    RequestDeadline(deadline): RequestDeadline,
+    queue_ticket: QueueTicket,
+    RawQuery(query): RawQuery,
+    multipart: Multipart,
+) -> Result<Response<Body>, ErrorResponse> {
+    let mut upload = read_batch_upload(&runtime_config, query, multipart).await?;
+
+    queue_ticket.set_input_size(upload.files.iter().map(|file| file.size).sum());
+
+    // The merged document can't be guaranteed to conform to PDF/A
+    if upload.fields.pdfa == Some(true) {
+        return Err(ErrorResponse {
+            code: None,
+            kind: ErrorKind::InvalidRequest,
+            message: "pdfa is not supported when merging".to_string(),
+            backtrace: None,
+        });
+    }
+    upload.fields.pdfa = Some(false);
+
+    let mut options = upload.fields.into_options(runtime_config.default_pdfa)?;
+    admin_access.authorize(&options)?;
+    options.deadline = deadline;
+
+    if options.output_format != OutputFormat::Pdf {
+        return Err(ErrorResponse {
+            code: None,
+            kind: ErrorKind::UnsupportedFormat,
+            message: "merged files can only be converted to pdf".to_string(),
+            backtrace: None,
+        });
+    }
+
+    // Version is declared by the merged document rather than each file
+    let pdf_version = options.pdf_version.take();
+
+    let merged_dir = Arc::new(create_temp_dir(&runtime_config).await?);
+    let merged_file = OutputFile::temporary(merged_dir.path().join(MERGED_FILE_NAME), merged_dir);
+
+    // Wait for a free conversion slot, the files are converted one at a time
+    let _permit = queue_ticket
+        .acquire_before(options.priority, options.deadline)
+        .await?;
+
+    let mut output_files: Vec<OutputFile> = Vec::with_capacity(upload.files.len());
+
+    for mut file in upload.files {
+        // Deadline applies to the whole merge, the slot isn't held past it
+        if options
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(deadline_exceeded(
+                "merge didn't finish before the request deadline",
+            ));
+        }
+
+        tracing::debug!(
+            file_name = file.file_name,
+            size = file.size,
+            "converting merge file"
+        );
+
+        file.temp_paths
+            .resolve_input_extension(
+                &runtime_config,
+                options.input_format.as_deref(),
+                Some(&file.file_name),
+            )
+            .await?;
+
+        let output_file = convert_file(&runtime_config, &file.temp_paths, &options)
+            .await
+            .map_err(|err| ErrorResponse {
+                message: format!("failed to convert \"{}\": {}", file.file_name, err.message),
+                ..err
+            })?;
+
+        output_files.push(output_file);
+    }
+
+    // Merged document declares the newest version of the converted files,
+    // which can only be raised by the requested version
+    let mut produced: u8 = 0;
+    for output_file in &output_files {
+        let version = read_pdf_version(output_file.path()).await.map_err(|err| {
+            tracing::error!(?err, "failed to read converted file");
+            ErrorResponse {
+                code: None,
+                kind: ErrorKind::Internal,
+                message: "failed to read converted file".to_string(),
+                backtrace: error_backtrace(&err),
+            }
+        })?;
+        produced = produced.max(version.unwrap_or_default());
+    }
+
+    let pdf_version = match pdf_version {
+        Some(version) if version.minor() < produced => {
+            return Err(version_unavailable(version, produced));
+        }
+        Some(version) => version.as_str().to_string(),
+        None if produced == 0 => "1.5".to_string(),
+        None => format!("1.{produced}"),
+    };
+
+    let merged_path = merged_file.path().to_path_buf();
+
+    tokio::task::spawn_blocking(move || {
+        let paths: Vec<PathBuf> = output_files
+            .iter()
+            .map(|output_file| output_file.path().to_path_buf())
+            .collect();
+
+        merge_pdfs(&paths, &merged_path, &pdf_version)
+    })
+    .await
+    .map_err(|err| lopdf::Error::IO(std::io::Error::other(err)))
+    .and_then(|result| result)
+    .map_err(|err| {
+        tracing::error!(?err, "failed to merge converted files");
+        ErrorResponse {
+            code: None,
+            kind: ErrorKind::Internal,
+            message: "failed to merge converted files".to_string(),
+            backtrace: error_backtrace(&err),
+        }
+    })?;
+
+    let body = merged_file.into_body().await.map_err(|err| {
+        tracing::error!(?err, "failed to open output file");
+        ErrorResponse {
+            code: None,
+            kind: ErrorKind::Internal,
+            message: "failed to read output".to_string(),
+            backtrace: error_backtrace(&err),
+        }
+    })?;
+
+    Response::builder()
+        .header(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(OutputFormat::Pdf.content_type()),
+        )
+        .header(header::CONTENT_DISPOSITION, attachment(MERGED_FILE_NAME))
+        .body(body)
+        .map_err(|err| {
+            tracing::error!(?err, "failed to make response");
+            ErrorResponse {
+                code: None,
+                kind: ErrorKind::Internal,
+                message: "failed to make response".to_string(),
+                backtrace: error_backtrace(&err),
+            }
+        })
+}
+
+/// Concatenate the pages of the PDF documents at the provided paths into a
+/// single document declaring the PDF version written to the `output_path`
+fn merge_pdfs(
+    paths: &[PathBuf],
+    output_path: &Path,
+    pdf_version: &str,
+) -> Result<(), lopdf::Error> {
+    let mut max_id = 1;
+    let mut pages: Vec<(ObjectId, Dictionary)> = Vec::new();
+    let mut objects: BTreeMap<ObjectId, Object> = BTreeMap::new();
+
+    for path in paths {
+        let mut document = Document::load(path)?;
+
+        // Object IDs must be unique across all the merged documents
+        document.renumber_objects_with(max_id);
+        max_id = document.max_id + 1;
+
+        for page_id in document.get_pages().into_values() {
+            pages.push((page_id, flatten_page(&document, page_id)?));
+        }
+
+        objects.extend(document.objects);
+    }
+
+    let mut merged = Document::with_version(pdf_version);
+    let mut catalog: Option<(ObjectId, Dictionary)> = None;
+    let mut pages_root: Option<(ObjectId, Dictionary)> = None;
+
+    for (object_id, object) in objects {
+        match object.type_name().unwrap_or_default() {
+            "Catalog" => {
+                if catalog.is_none() {
+                    catalog = Some((object_id, object.as_dict()?.clone()));
+                }
+            }
+            "Pages" => {
+                if pages_root.is_none() {
+                    pages_root = Some((object_id, object.as_dict()?.clone()));
+                }
+            }
+            // Pages are added separately, outlines reference pages of the
+            // original documents so they are dropped
+            "Page" | "Outlines" | "Outline" => {}
+            _ => {
+                merged.objects.insert(object_id, object);
+            }
+        }
+    }
+
+    let (Some((catalog_id, mut catalog)), Some((pages_id, mut pages_root))) = (catalog, pages_root)
+    else {
+        return Err(lopdf::Error::Invalid(
+            "converted document is missing its catalog or page tree".to_string(),
+        ));
+    };
+
+    let kids: Vec<Object> = pages
+        .iter()
+        .map(|(page_id, _)| Object::Reference(*page_id))
+        .collect();
+
+    pages_root.set("Kids", kids);
+    pages_root.set("Count", pages.len() as u32);
+    pages_root.remove(b"Parent");
+
+    for (page_id, mut page) in pages {
+        page.set("Parent", pages_id);
+        merged.objects.insert(page_id, Object::Dictionary(page));
+    }
+
+    catalog.set("Pages", pages_id);
+    catalog.remove(b"Outlines");
+
+    merged
+        .objects
+        .insert(pages_id, Object::Dictionary(pages_root));
+    merged
+        .objects
+        .insert(catalog_id, Object::Dictionary(catalog));
+    merged.trailer.set("Root", catalog_id);
+    merged.max_id = max_id;
+    merged.renumber_objects();
+
+    merged.save(output_path)?;
+
+    Ok(())
+}
+
+/// Copy a page dictionary including the attributes it inherits from its
+/// parent nodes, the parent nodes are replaced when the documents are merged
+fn flatten_page(document: &Document, page_id: ObjectId) -> Result<Dictionary, lopdf::Error> {
+    let mut page = document.get_dictionary(page_id)?.clone();
+    let mut parent = page.get(b"Parent").and_then(Object::as_reference).ok();
+
+    for _ in 0..MAX_PAGE_TREE_DEPTH {
+        let Some(parent_id) = parent else {
+            break;
+        };
+        let node = document.get_dictionary(parent_id)?;
+
+        for attribute in INHERITABLE_PAGE_ATTRIBUTES {
+            if !page.has(attribute)
+                && let Ok(value) = node.get(attribute)
+            {
+                page.set(*attribute, value.clone());
+            }
+        }
+
+        parent = node.get(b"Parent").and_then(Object::as_reference).ok();
+    }
+
+    Ok(page)
+}
@@ -0,0 +1,317 @@
+use axum::{
+    Extension,
+    http::{HeaderValue, header},
+    response::IntoResponse,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::{RuntimeConfig, usage::ResourceUsage};
+
+/// Input formats tracked individually in the conversion metrics, any other
+/// format is grouped under "other" to keep the number of series bounded
+const TRACKED_INPUT_FORMATS: &[&str] = &[
+    "doc", "docx", "docm", "dot", "dotx", "odt", "ott", "rtf", "txt", "html", "htm", "mht", "epub",
+    "fb2", "xml", "xls", "xlsx", "xlsm", "xlsb", "xlt", "xltx", "ods", "ots", "csv", "ppt", "pptx",
+    "pptm", "pps", "ppsx", "pot", "potx", "odp", "otp", "pdf", "djvu", "xps", "oxps", "png", "jpg",
+    "gif",
+];
+
+/// Upper bounds of the conversion duration histogram buckets in seconds
+const DURATION_BUCKETS: &[f64] = &[
+    0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+/// Upper bounds of the file size histogram buckets in bytes
+const SIZE_BUCKETS: &[f64] = &[1e4, 1e5, 1e6, 5e6, 1e7, 5e7, 1e8, 5e8];
+
+/// Upper bounds of the x2t peak memory histogram buckets in bytes
+const MEMORY_BUCKETS: &[f64] = &[5e7, 1e8, 2.5e8, 5e8, 1e9, 2e9, 4e9, 8e9];
+
+/// Counters tracked by the server, exposed in the Prometheus text format
+#[derive(Default)]
+pub struct Metrics {
+    /// Number of times x2t was retried after a transient failure
+    x2t_retries: AtomicU64,
+    /// Number of conversions that still failed after being retried
+    x2t_retry_failures: AtomicU64,
+    /// Number of x2t processes killed for exceeding the x2t timeout
+    x2t_timeouts: AtomicU64,
+    /// Number of x2t processes that crashed
+    x2t_crashes: AtomicU64,
+    /// Number of conversions rejected for repeatedly crashing x2t
+    poison_rejections: AtomicU64,
+    /// Conversion histograms keyed by the input format
+    conversions: Mutex<BTreeMap<&'static str, FormatMetrics>>,
+}
+
+/// Histograms for conversions of a single input format
+struct FormatMetrics {
+    duration: Histogram,
+    input_size: Histogram,
+    output_size: Histogram,
+    cpu_time: Histogram,
+    peak_rss: Histogram,
+}
+
+impl Default for FormatMetrics {
+    fn default() -> Self {
+        Self {
+            duration: Histogram::new(DURATION_BUCKETS),
+            input_size: Histogram::new(SIZE_BUCKETS),
+            output_size: Histogram::new(SIZE_BUCKETS),
+            cpu_time: Histogram::new(DURATION_BUCKETS),
+            peak_rss: Histogram::new(MEMORY_BUCKETS),
+        }
+    }
+}
+
+/// Cumulative histogram of observed values
+struct Histogram {
+    /// Upper bound of each bucket
+    bounds: &'static [f64],
+    /// Number of observations less than or equal to each bound
+    counts: Vec<u64>,
+    /// Total number of observations
+    count: u64,
+    /// Sum of all observed values
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len()],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+impl Metrics {
+    pub fn record_x2t_retry(&self) {
+        self.x2t_retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_x2t_retry_failure(&self) {
+        self.x2t_retry_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_x2t_timeout(&self) {
+        self.x2t_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_x2t_crash(&self) {
+        self.x2t_crashes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_poison_rejection(&self) {
+        self.poison_rejections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a finished conversion
+    ///
+    /// ## Arguments
+    /// * `input_format` - Extension of the input file
+    /// * `duration` - Time spent running x2t
+    /// * `input_size` - Size of the input file in bytes
+    /// * `output_size` - Size of the output file in bytes, None when the
+    ///   conversion failed
+    pub fn record_conversion(
+        &self,
+        input_format: &str,
+        duration: Duration,
+        input_size: u64,
+        output_size: Option<u64>,
+    ) {
+        let mut conversions = self.conversions.lock().expect("metrics lock poisoned");
+        let metrics = conversions.entry(tracked_format(input_format)).or_default();
+
+        metrics.duration.observe(duration.as_secs_f64());
+        metrics.input_size.observe(input_size as f64);
+
+        if let Some(output_size) = output_size {
+            metrics.output_size.observe(output_size as f64);
+        }
+    }
+
+    /// Record the resources used by the x2t processes of a conversion
+    ///
+    /// ## Arguments
+    /// * `input_format` - Extension of the input file
+    /// * `usage` - Resources used by the x2t processes
+    pub fn record_x2t_usage(&self, input_format: &str, usage: &ResourceUsage) {
+        let mut conversions = self.conversions.lock().expect("metrics lock poisoned");
+        let metrics = conversions.entry(tracked_format(input_format)).or_default();
+
+        metrics.cpu_time.observe(usage.cpu_time.as_secs_f64());
+        metrics.peak_rss.observe(usage.peak_rss as f64);
+    }
+
+    /// Render the metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+
+        write_counter(
+            &mut output,
+            "x2t_retries_total",
+            "Number of times x2t was retried after a transient failure",
+            self.x2t_retries.load(Ordering::Relaxed),
+        );
+        write_counter(
+            &mut output,
+            "x2t_retry_failures_total",
+            "Number of conversions that still failed after being retried",
+            self.x2t_retry_failures.load(Ordering::Relaxed),
+        );
+        write_counter(
+            &mut output,
+            "x2t_timeouts_total",
+            "Number of x2t processes killed for exceeding the x2t timeout",
+            self.x2t_timeouts.load(Ordering::Relaxed),
+        );
+        write_counter(
+            &mut output,
+            "x2t_crashes_total",
+            "Number of x2t processes that crashed",
+            self.x2t_crashes.load(Ordering::Relaxed),
+        );
+        write_counter(
+            &mut output,
+            "poison_rejections_total",
+            "Number of conversions rejected for repeatedly crashing x2t",
+            self.poison_rejections.load(Ordering::Relaxed),
+        );
+
+        let conversions = self.conversions.lock().expect("metrics lock poisoned");
+
+        write_histograms(
+            &mut output,
+            "conversion_duration_seconds",
+            "Time spent running x2t for each conversion",
+            conversions
+                .iter()
+                .map(|(format, value)| (*format, &value.duration)),
+        );
+        write_histograms(
+            &mut output,
+            "conversion_input_bytes",
+            "Size of the input file for each conversion",
+            conversions
+                .iter()
+                .map(|(format, value)| (*format, &value.input_size)),
+        );
+        write_histograms(
+            &mut output,
+            "conversion_output_bytes",
+            "Size of the output file for each successful conversion",
+            conversions
+                .iter()
+                .map(|(format, value)| (*format, &value.output_size)),
+        );
+        write_histograms(
+            &mut output,
+            "x2t_cpu_seconds",
+            "CPU time used by x2t for each conversion",
+            conversions
+                .iter()
+                .map(|(format, value)| (*format, &value.cpu_time)),
+        );
+        write_histograms(
+            &mut output,
+            "x2t_peak_rss_bytes",
+            "Peak resident memory of x2t for each conversion",
+            conversions
+                .iter()
+                .map(|(format, value)| (*format, &value.peak_rss)),
+        );
+
+        output
+    }
+}
+
+/// Input format to track metrics under, formats that aren't tracked
+/// individually are grouped under "other"
+fn tracked_format(input_format: &str) -> &'static str {
+    TRACKED_INPUT_FORMATS
+        .iter()
+        .find(|format| **format == input_format)
+        .copied()
+        .unwrap_or("other")
+}
+
+fn write_counter(output: &mut String, name: &str, help: &str, value: u64) {
+    _ = writeln!(output, "# HELP {name} {help}");
+    _ = writeln!(output, "# TYPE {name} counter");
+    _ = writeln!(output, "{name} {value}");
+}
+
+/// Write a histogram with a series for each input format
+fn write_histograms<'a>(
+    output: &mut String,
+    name: &str,
+    help: &str,
+    histograms: impl Iterator<Item = (&'a str, &'a Histogram)>,
+) {
+    _ = writeln!(output, "# HELP {name} {help}");
+    _ = writeln!(output, "# TYPE {name} histogram");
+
+    for (format, histogram) in histograms {
+        for (bound, count) in histogram.bounds.iter().zip(&histogram.counts) {
+            _ = writeln!(
+                output,
+                "{name}_bucket{{input_format=\"{format}\",le=\"{bound}\"}} {count}"
+            );
+        }
+
+        _ = writeln!(
+            output,
+            "{name}_bucket{{input_format=\"{format}\",le=\"+Inf\"}} {}",
+            histogram.count
+        );
+        _ = writeln!(
+            output,
+            "{name}_sum{{input_format=\"{format}\"}} {}",
+            histogram.sum
+        );
+        _ = writeln!(
+            output,
+            "{name}_count{{input_format=\"{format}\"}} {}",
+            histogram.count
+        );
+    }
+}
+
+/// GET /metrics
+///
+/// Server metrics in the Prometheus text format
+pub async fn metrics(
+    Extension(runtime_config): Extension<Arc<RuntimeConfig>>,
+) -> impl IntoResponse {
+    (
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; version=0.0.4"),
+        )],
+        runtime_config.metrics.render(),
+    )
+}
@@ -0,0 +1,880 @@
+use axum::{
+    Json,
+    response::{Html, IntoResponse},
+};
+use serde_json::{Map, Value, json};
+use std::sync::LazyLock;
+
+/// Names of the formats files can be converted to
+const TARGET_FORMATS: &[&str] = &[
+    "pdf", "pdfa", "docx", "xlsx", "pptx", "odt", "txt", "html", "htmlzip", "png", "jpg",
+];
+
+/// Serialized names of the error kinds
+const ERROR_KINDS: &[&str] = &[
+    "invalid_request",
+    "unauthorized",
+    "forbidden",
+    "not_found",
+    "conflict",
+    "encrypted",
+    "corrupted",
+    "unsupported_format",
+    "timeout",
+    "too_large",
+    "resource_limit",
+    "infected",
+    "poison_document",
+    "busy",
+    "unavailable",
+    "conversion_failed",
+    "internal",
+];
+
+/// OpenAPI document describing every route, routes that depend on optional
+/// configuration (object storage, docbuilder, upload tokens, the admin token)
+/// are included and note when they are available
+static OPENAPI_DOCUMENT: LazyLock<Value> = LazyLock::new(openapi_document);
+
+/// Swagger UI page rendering the OpenAPI document, the assets are loaded
+/// from a CDN so the page needs internet access in the browser
+const SWAGGER_UI_PAGE: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8" />
+  <title>onlyoffice-convert-server API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css" />
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js" crossorigin></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
+
+/// GET /openapi.json
+///
+/// OpenAPI 3 specification of the HTTP API
+pub async fn openapi_json() -> Json<&'static Value> {
+    Json(&OPENAPI_DOCUMENT)
+}
+
+/// GET /docs
+///
+/// Swagger UI for exploring the HTTP API
+pub async fn swagger_ui() -> impl IntoResponse {
+    Html(SWAGGER_UI_PAGE)
+}
+
+fn openapi_document() -> Value {
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "onlyoffice-convert-server",
+            "description": env!("CARGO_PKG_DESCRIPTION"),
+            "version": env!("CARGO_PKG_VERSION"),
+            "license": { "name": env!("CARGO_PKG_LICENSE") },
+        },
+        "paths": paths(),
+        "components": {
+            "schemas": schemas(),
+            "securitySchemes": {
+                "jwt": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "bearerFormat": "JWT",
+                    "description": "HS256 token signed with JWT_SECRET, required when JWT_SECRET is set",
+                },
+                "admin": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "Admin token configured with ADMIN_TOKEN",
+                },
+                "uploadToken": {
+                    "type": "apiKey",
+                    "in": "header",
+                    "name": "x-upload-token",
+                    "description": "Single use token issued by a backend, can also be provided in the upload_token query parameter",
+                },
+            },
+        },
+    })
+}
+
+fn paths() -> Value {
+    json!({
+        "/health": {
+            "get": {
+                "summary": "Liveness check",
+                "tags": ["status"],
+                "responses": {
+                    "200": json_response("Server is running", "HealthResponse"),
+                },
+            },
+        },
+        "/ready": {
+            "get": {
+                "summary": "Readiness check, ready once the startup checks have passed",
+                "tags": ["status"],
+                "responses": {
+                    "200": json_response("Server is ready", "ReadyResponse"),
+                    "503": json_response("Server isn't ready or is shutting down", "ReadyResponse"),
+                },
+            },
+        },
+        "/status": {
+            "get": {
+                "summary": "Current load on the conversion queue",
+                "tags": ["status"],
+                "responses": {
+                    "200": json_response("Queue status", "LimiterStatus"),
+                },
+            },
+        },
+        "/metrics": {
+            "get": {
+                "summary": "Prometheus metrics",
+                "tags": ["status"],
+                "responses": {
+                    "200": {
+                        "description": "Metrics in the Prometheus text format",
+                        "content": { "text/plain": { "schema": { "type": "string" } } },
+                    },
+                },
+            },
+        },
+        "/convert": {
+            "post": {
+                "summary": "Convert a file, responding with the converted file",
+                "description": "Options can be provided as multipart fields or query parameters, multipart fields take priority",
+                "tags": ["convert"],
+                "security": jwt_security(),
+                "parameters": convert_parameters(),
+                "requestBody": convert_request_body(false),
+                "responses": with_errors(json!({
+                    "200": converted_file_response(),
+                }), &["400", "401", "403", "413", "415", "422", "429", "500", "503", "504"]),
+            },
+        },
+        "/convert/upload": {
+            "post": {
+                "summary": "Convert a file using an upload token",
+                "description": "Only available when UPLOAD_TOKEN_SECRET is set, the token limits the size and formats of the conversion. Priorities above normal, x2t_params and form_data are rejected unless allowed by the max_priority, x2t_params and form_data claims of the token",
+                "tags": ["convert"],
+                "security": [{ "uploadToken": [] }],
+                "parameters": convert_parameters(),
+                "requestBody": convert_request_body(false),
+                "responses": with_errors(json!({
+                    "200": converted_file_response(),
+                }), &["400", "401", "403", "413", "415", "422", "429", "500", "503", "504"]),
+            },
+        },
+        "/convert/batch": {
+            "post": {
+                "summary": "Convert multiple files, responding with a ZIP archive of the results",
+                "description": "Files that fail to convert are listed in an error manifest within the archive",
+                "tags": ["convert"],
+                "security": jwt_security(),
+                "parameters": convert_parameters(),
+                "requestBody": convert_request_body(true),
+                "responses": with_errors(json!({
+                    "200": binary_response("ZIP archive of the converted files", "application/zip"),
+                }), &["400", "401", "413", "429", "500", "503", "504"]),
+            },
+        },
+        "/convert/archive": {
+            "post": {
+                "summary": "Convert every document within a ZIP archive",
+                "description": "Responds with a ZIP archive of the converted files mirroring the directory structure of the upload, the outcome of every entry is listed in a manifest.json within the archive",
+                "tags": ["convert"],
+                "security": jwt_security(),
+                "parameters": convert_parameters(),
+                "requestBody": convert_request_body(false),
+                "responses": with_errors(json!({
+                    "200": binary_response("ZIP archive of the converted files", "application/zip"),
+                }), &["400", "401", "413", "415", "422", "429", "500", "503", "504"]),
+            },
+        },
+        "/convert/merge": {
+            "post": {
+                "summary": "Convert multiple files to PDF and merge them in upload order",
+                "tags": ["convert"],
+                "security": jwt_security(),
+                "parameters": convert_parameters(),
+                "requestBody": convert_request_body(true),
+                "responses": with_errors(json!({
+                    "200": binary_response("Merged PDF document", "application/pdf"),
+                }), &["400", "401", "413", "422", "429", "500", "503", "504"]),
+            },
+        },
+        "/convert/s3": {
+            "post": {
+                "summary": "Convert an object from storage, uploading the result back to storage",
+                "description": "Only available when S3_ENDPOINT is set",
+                "tags": ["convert"],
+                "security": jwt_security(),
+                "parameters": deadline_parameters(),
+                "requestBody": {
+                    "required": true,
+                    "content": { "application/json": { "schema": schema_ref("S3ConvertRequest") } },
+                },
+                "responses": with_errors(json!({
+                    "200": json_response("Details of the uploaded result", "S3ConvertResponse"),
+                }), &["400", "401", "422", "429", "500", "503", "504"]),
+            },
+        },
+        "/docbuilder": {
+            "post": {
+                "summary": "Run a docbuilder script or transform against a document before converting it",
+                "description": "Only available when docbuilder is configured, raw scripts require the admin token",
+                "tags": ["convert"],
+                "security": jwt_security(),
+                "parameters": convert_parameters(),
+                "requestBody": {
+                    "required": true,
+                    "content": {
+                        "multipart/form-data": {
+                            "schema": {
+                                "allOf": [
+                                    schema_ref("ConvertFields"),
+                                    {
+                                        "type": "object",
+                                        "required": ["file"],
+                                        "properties": {
+                                            "file": { "type": "string", "format": "binary" },
+                                            "script": { "type": "string", "description": "Docbuilder script to run, requires the admin token" },
+                                            "transform": { "type": "string", "description": "JSON transform of text replacements" },
+                                        },
+                                    },
+                                ],
+                            },
+                        },
+                    },
+                },
+                "responses": with_errors(json!({
+                    "200": converted_file_response(),
+                }), &["400", "401", "403", "422", "429", "500", "503", "504"]),
+            },
+        },
+        "/inspect": {
+            "post": {
+                "summary": "Detect the format and condition of a file without converting it",
+                "tags": ["convert"],
+                "security": jwt_security(),
+                "requestBody": file_request_body(),
+                "responses": with_errors(json!({
+                    "200": json_response("Details about the file", "InspectResponse"),
+                }), &["400", "401", "413", "500"]),
+            },
+        },
+        "/selftest": {
+            "post": {
+                "summary": "Convert an embedded sample document and report each stage",
+                "tags": ["status"],
+                "security": jwt_security(),
+                "responses": {
+                    "200": json_response("Every stage passed", "SelfTestReport"),
+                    "500": json_response("A stage failed", "SelfTestReport"),
+                },
+            },
+        },
+        "/jobs": {
+            "post": {
+                "summary": "Queue a file for conversion in the background",
+                "tags": ["jobs"],
+                "security": jwt_security(),
+                "parameters": [
+                    {
+                        "name": "callback_url",
+                        "in": "query",
+                        "description": "URL to POST a notification to once the job finishes, must resolve to a public address unless allowed by WEBHOOK_ALLOWED_NETWORKS. The notification only includes a result_url when PUBLIC_URL is configured",
+                        "schema": { "type": "string", "format": "uri" },
+                    },
+                    deadline_parameters()[0],
+                    deadline_parameters()[1],
+                ],
+                "requestBody": convert_request_body(false),
+                "responses": with_errors(json!({
+                    "202": json_response("Job was queued", "JobResponse"),
+                }), &["400", "401", "413", "429", "503"]),
+            },
+        },
+        "/jobs/{id}": {
+            "parameters": [job_id_parameter()],
+            "get": {
+                "summary": "Status of a job",
+                "tags": ["jobs"],
+                "security": jwt_security(),
+                "responses": with_errors(json!({
+                    "200": json_response("Job status", "JobResponse"),
+                }), &["401", "404"]),
+            },
+            "delete": {
+                "summary": "Cancel a queued or running job",
+                "tags": ["jobs"],
+                "security": jwt_security(),
+                "responses": with_errors(json!({
+                    "204": { "description": "Job was cancelled" },
+                }), &["401", "404", "409"]),
+            },
+        },
+        "/jobs/{id}/events": {
+            "parameters": [job_id_parameter()],
+            "get": {
+                "summary": "Server-sent event stream of the stage of a job",
+                "tags": ["jobs"],
+                "security": jwt_security(),
+                "responses": with_errors(json!({
+                    "200": {
+                        "description": "Stream of \"stage\" events, ends once the job has finished",
+                        "content": { "text/event-stream": { "schema": schema_ref("JobStage") } },
+                    },
+                }), &["401", "404"]),
+            },
+        },
+        "/jobs/{id}/result": {
+            "parameters": [job_id_parameter()],
+            "get": {
+                "summary": "Converted file of a completed job",
+                "description": "Supports HEAD requests and a single byte range for resuming downloads",
+                "tags": ["jobs"],
+                "security": jwt_security(),
+                "parameters": [
+                    {
+                        "name": "range",
+                        "in": "header",
+                        "description": "Single byte range of the result to download (i.e \"bytes=0-1023\")",
+                        "schema": { "type": "string" },
+                    },
+                    {
+                        "name": "if-range",
+                        "in": "header",
+                        "description": "ETag of the result, the full result is returned when it doesn't match",
+                        "schema": { "type": "string" },
+                    },
+                ],
+                "responses": with_errors(json!({
+                    "200": converted_file_response(),
+                    "206": binary_response("Requested range of the converted file", "application/octet-stream"),
+                }), &["401", "404", "409", "416"]),
+            },
+        },
+        "/ConvertService.ashx": {
+            "post": convert_service_operation(),
+        },
+        "/converter": {
+            "post": convert_service_operation(),
+        },
+        "/converter/results/{id}": {
+            "get": {
+                "summary": "Converted file of a DocumentServer conversion API request",
+                "tags": ["documentserver"],
+                "parameters": [
+                    { "name": "id", "in": "path", "required": true, "schema": { "type": "string" } },
+                ],
+                "responses": with_errors(json!({
+                    "200": converted_file_response(),
+                }), &["404"]),
+            },
+        },
+        "/admin/jobs": {
+            "get": {
+                "summary": "Conversions that are running or waiting in the queue",
+                "tags": ["admin"],
+                "security": admin_security(),
+                "responses": with_errors(json!({
+                    "200": json_response("Queued and running conversions", "ConversionsResponse"),
+                }), &["401"]),
+            },
+        },
+        "/admin/crashes": {
+            "get": {
+                "summary": "Preserved artifacts of x2t crashes, newest first",
+                "tags": ["admin"],
+                "security": admin_security(),
+                "responses": with_errors(json!({
+                    "200": {
+                        "description": "Crash reports",
+                        "content": {
+                            "application/json": {
+                                "schema": { "type": "array", "items": schema_ref("CrashReport") },
+                            },
+                        },
+                    },
+                }), &["401", "404"]),
+            },
+        },
+        "/admin/fonts": {
+            "get": {
+                "summary": "Custom fonts that have been uploaded",
+                "tags": ["admin"],
+                "security": admin_security(),
+                "responses": with_errors(json!({
+                    "200": json_response("Custom fonts", "FontsResponse"),
+                }), &["401"]),
+            },
+            "post": {
+                "summary": "Upload TTF/OTF fonts, the fonts cache must be regenerated before they are used",
+                "tags": ["admin"],
+                "security": admin_security(),
+                "requestBody": {
+                    "required": true,
+                    "content": {
+                        "multipart/form-data": {
+                            "schema": {
+                                "type": "object",
+                                "additionalProperties": { "type": "string", "format": "binary" },
+                            },
+                        },
+                    },
+                },
+                "responses": with_errors(json!({
+                    "200": json_response("Uploaded fonts", "FontsResponse"),
+                }), &["400", "401", "500"]),
+            },
+        },
+        "/admin/fonts/regenerate": {
+            "post": {
+                "summary": "Regenerate the fonts cache",
+                "tags": ["admin"],
+                "security": admin_security(),
+                "responses": with_errors(json!({
+                    "200": {
+                        "description": "Fonts cache was regenerated",
+                        "content": {
+                            "application/json": {
+                                "schema": object_schema(&[("duration_ms", json!({ "type": "integer" }))]),
+                            },
+                        },
+                    },
+                }), &["401", "500"]),
+            },
+        },
+        "/admin/reload": {
+            "post": {
+                "summary": "Reload the configuration, equivalent to sending SIGHUP",
+                "tags": ["admin"],
+                "security": admin_security(),
+                "responses": with_errors(json!({
+                    "200": {
+                        "description": "Configuration was reloaded",
+                        "content": {
+                            "application/json": {
+                                "schema": object_schema(&[
+                                    ("max_concurrent", json!({ "type": "integer" })),
+                                    ("max_queued", json!({ "type": "integer" })),
+                                ]),
+                            },
+                        },
+                    },
+                }), &["401", "500"]),
+            },
+        },
+    })
+}
+
+fn schemas() -> Value {
+    json!({
+        "ErrorResponse": {
+            "type": "object",
+            "required": ["kind", "message"],
+            "properties": {
+                "code": {
+                    "type": "integer",
+                    "nullable": true,
+                    "description": "x2t error code when the error came from x2t",
+                },
+                "kind": { "type": "string", "enum": ERROR_KINDS },
+                "message": { "type": "string" },
+                "backtrace": {
+                    "type": "string",
+                    "description": "Error details, only included when DEBUG_ERRORS is enabled",
+                },
+            },
+        },
+        "QueueFullResponse": {
+            "allOf": [
+                schema_ref("ErrorResponse"),
+                object_schema(&[
+                    ("queued", json!({ "type": "integer" })),
+                    ("max_queued", json!({ "type": "integer" })),
+                ]),
+            ],
+        },
+        "ConvertFields": {
+            "type": "object",
+            "properties": convert_fields(),
+        },
+        "HealthResponse": object_schema(&[
+            ("uptime", json!({ "type": "integer", "description": "Seconds the server has been running" })),
+            ("version", json!({ "type": "string" })),
+            ("x2t_path", json!({ "type": "string" })),
+        ]),
+        "ReadyResponse": object_schema(&[
+            ("ready", json!({ "type": "boolean" })),
+            ("draining", json!({ "type": "boolean" })),
+            ("checks", json!({
+                "type": "array",
+                "items": object_schema(&[
+                    ("name", json!({ "type": "string" })),
+                    ("passed", json!({ "type": "boolean" })),
+                    ("message", json!({ "type": "string" })),
+                ]),
+            })),
+        ]),
+        "LimiterStatus": object_schema(&[
+            ("running", json!({ "type": "integer" })),
+            ("max_concurrent", json!({ "type": "integer" })),
+            ("queued", json!({ "type": "integer" })),
+            ("max_queued", json!({ "type": "integer" })),
+            ("saturated", json!({ "type": "boolean" })),
+            ("draining", json!({ "type": "boolean" })),
+        ]),
+        "ConversionsResponse": object_schema(&[
+            ("jobs", json!({
+                "type": "array",
+                "items": object_schema(&[
+                    ("id", json!({ "type": "string", "format": "uuid" })),
+                    ("state", json!({ "type": "string", "enum": ["queued", "running"] })),
+                    ("priority", json!({ "type": "string", "enum": ["low", "normal", "high"] })),
+                    ("input_size", json!({ "type": "integer", "nullable": true })),
+                    ("elapsed", json!({ "type": "integer" })),
+                    ("running_for", json!({ "type": "integer", "nullable": true })),
+                    ("queue_position", json!({ "type": "integer", "nullable": true })),
+                ]),
+            })),
+        ]),
+        "InspectResponse": object_schema(&[
+            ("detected_format", json!({ "type": "string", "nullable": true })),
+            ("likely_encrypted", json!({ "type": "boolean" })),
+            ("likely_corrupted", json!({ "type": "boolean" })),
+            ("confidence", json!({ "type": "string", "enum": ["low", "high"] })),
+            ("reason", json!({ "type": "string", "nullable": true })),
+            ("size", json!({ "type": "integer" })),
+        ]),
+        "SelfTestReport": object_schema(&[
+            ("passed", json!({ "type": "boolean" })),
+            ("stages", json!({
+                "type": "array",
+                "items": object_schema(&[
+                    ("name", json!({ "type": "string" })),
+                    ("status", json!({ "type": "string", "enum": ["passed", "failed", "skipped"] })),
+                    ("duration_ms", json!({ "type": "integer" })),
+                    ("message", json!({ "type": "string" })),
+                ]),
+            })),
+        ]),
+        "JobResponse": object_schema(&[
+            ("id", json!({ "type": "string", "format": "uuid" })),
+            ("status", json!({
+                "type": "string",
+                "enum": ["queued", "running", "completed", "failed", "cancelled"],
+            })),
+            ("elapsed", json!({ "type": "integer" })),
+            ("expires_in", json!({ "type": "integer", "nullable": true })),
+            ("output_checksum", json!({ "type": "string", "nullable": true })),
+            ("error", json!({ "allOf": [schema_ref("ErrorResponse")], "nullable": true })),
+        ]),
+        "JobStage": {
+            "type": "string",
+            "enum": ["queued", "started", "writing-output", "done", "failed", "cancelled"],
+        },
+        "S3ConvertRequest": {
+            "allOf": [
+                {
+                    "type": "object",
+                    "required": ["bucket", "source_key", "destination_key"],
+                    "properties": {
+                        "bucket": { "type": "string" },
+                        "source_key": { "type": "string" },
+                        "destination_key": { "type": "string" },
+                        "destination_bucket": { "type": "string" },
+                    },
+                },
+                schema_ref("ConvertFields"),
+            ],
+        },
+        "S3ConvertResponse": object_schema(&[
+            ("bucket", json!({ "type": "string" })),
+            ("key", json!({ "type": "string" })),
+            ("size", json!({ "type": "integer" })),
+            ("content_type", json!({ "type": "string" })),
+            ("duration_ms", json!({ "type": "integer" })),
+        ]),
+        "ConvertServiceRequest": {
+            "type": "object",
+            "required": ["key", "outputtype", "url"],
+            "properties": {
+                "async": { "type": "boolean" },
+                "codePage": { "type": "integer" },
+                "delimiter": { "type": "integer", "minimum": 0, "maximum": 5 },
+                "filetype": { "type": "string" },
+                "key": { "type": "string" },
+                "outputtype": { "type": "string" },
+                "password": { "type": "string" },
+                "title": { "type": "string" },
+                "token": {
+                    "type": "string",
+                    "description": "JWT whose claims replace the request, used when the token isn't provided in the JWT header",
+                },
+                "url": {
+                    "type": "string",
+                    "format": "uri",
+                    "description": "http or https URL of the file, must resolve to a public address",
+                },
+            },
+        },
+        "ConvertServiceResponse": {
+            "type": "object",
+            "properties": {
+                "endConvert": { "type": "boolean" },
+                "fileType": { "type": "string" },
+                "fileUrl": { "type": "string", "format": "uri" },
+                "percent": { "type": "integer" },
+                "error": { "type": "integer" },
+            },
+        },
+        "CrashReport": object_schema(&[
+            ("id", json!({ "type": "string" })),
+            ("created_at", json!({ "type": "integer" })),
+            ("exit_status", json!({ "type": "string" })),
+            ("input_file", json!({ "type": "string" })),
+            ("input_size", json!({ "type": "integer" })),
+            ("path", json!({ "type": "string" })),
+        ]),
+        "FontsResponse": object_schema(&[
+            ("fonts", json!({
+                "type": "array",
+                "items": object_schema(&[
+                    ("name", json!({ "type": "string" })),
+                    ("size", json!({ "type": "integer" })),
+                ]),
+            })),
+        ]),
+    })
+}
+
+/// Properties of the conversion options accepted as multipart fields, query
+/// parameters or JSON
+fn convert_fields() -> Value {
+    json!({
+        "target_format": {
+            "type": "string",
+            "enum": TARGET_FORMATS,
+            "description": "Format to convert the file to, defaults to pdf or the Accept header",
+        },
+        "pdfa": { "type": "boolean", "description": "Produce archival PDF/A output" },
+        "pdf_version": {
+            "type": "string",
+            "enum": ["1.5", "1.6", "1.7"],
+            "description": "Version of the PDF specification the PDF output declares, can't be combined with pdfa. The version produced by x2t can be raised but not lowered, requesting an older version than x2t produces is rejected with a 415. x2t can't produce tagged (accessible) PDF output",
+        },
+        "all_pages": { "type": "boolean", "description": "Render every page to an image, returned as a ZIP archive" },
+        "split_sheets": { "type": "boolean", "description": "Convert each sheet to its own PDF, returned as a ZIP archive" },
+        "speaker_notes": { "type": "boolean", "description": "Include slide notes pages when converting presentations to PDF" },
+        "password": { "type": "string", "description": "Password to open an encrypted file with" },
+        "watermark_text": { "type": "string" },
+        "watermark_opacity": { "type": "number", "minimum": 0, "maximum": 1 },
+        "watermark_angle": { "type": "number" },
+        "watermark_font_size": { "type": "integer" },
+        "csv_delimiter": { "type": "string", "description": "Delimiter of CSV/TXT inputs (i.e \"semicolon\" or \";\")" },
+        "codepage": { "type": "integer", "description": "x2t codepage of CSV/TXT inputs, detected when not provided" },
+        "fit_to_width": { "type": "integer" },
+        "fit_to_height": { "type": "integer" },
+        "ignore_print_area": { "type": "boolean" },
+        "sheets": { "type": "string", "description": "Comma separated zero based indexes of the sheets to convert" },
+        "debug": { "type": "boolean", "description": "Include x2t diagnostics in errors, requires the x-admin-token header" },
+        "priority": { "type": "string", "enum": ["low", "normal", "high"] },
+        "input_format": { "type": "string", "description": "Extension of the input format (i.e \"docx\")" },
+        "x2t_params": { "type": "string", "description": "JSON object of additional x2t config elements" },
+        "form_data": { "type": "string", "description": "JSON object of form field values to fill in before converting" },
+        "input_checksum": { "type": "string", "description": "Hex encoded SHA-256 the upload is expected to have" },
+    })
+}
+
+/// Query and header parameters of the conversion routes
+fn convert_parameters() -> Value {
+    let mut parameters: Vec<Value> = convert_fields()
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(name, schema)| json!({ "name": name, "in": "query", "schema": schema }))
+        .collect();
+
+    parameters.push(json!({
+        "name": "x-admin-token",
+        "in": "header",
+        "description": "Admin token, required for debug and raw docbuilder scripts",
+        "schema": { "type": "string" },
+    }));
+
+    parameters.extend(deadline_parameters());
+
+    Value::Array(parameters)
+}
+
+/// Header parameters limiting how long the conversion can take
+fn deadline_parameters() -> [Value; 2] {
+    [
+        json!({
+            "name": "x-deadline-ms",
+            "in": "header",
+            "description": "Milliseconds the conversion must finish within, conversions that can't start in time are rejected",
+            "schema": { "type": "integer" },
+        }),
+        json!({
+            "name": "request-timeout",
+            "in": "header",
+            "description": "Seconds the conversion must finish within, used when x-deadline-ms isn't provided",
+            "schema": { "type": "number" },
+        }),
+    ]
+}
+
+/// Multipart body containing the file (or files) to convert and the options
+fn convert_request_body(multiple: bool) -> Value {
+    let file = if multiple {
+        json!({
+            "type": "array",
+            "items": { "type": "string", "format": "binary" },
+            "description": "Files to convert, in order",
+        })
+    } else {
+        json!({ "type": "string", "format": "binary", "description": "File to convert" })
+    };
+
+    json!({
+        "required": true,
+        "content": {
+            "multipart/form-data": {
+                "schema": {
+                    "allOf": [
+                        schema_ref("ConvertFields"),
+                        { "type": "object", "required": ["file"], "properties": { "file": file } },
+                    ],
+                },
+            },
+        },
+    })
+}
+
+/// Multipart body containing only a file
+fn file_request_body() -> Value {
+    json!({
+        "required": true,
+        "content": {
+            "multipart/form-data": {
+                "schema": object_schema(&[
+                    ("file", json!({ "type": "string", "format": "binary" })),
+                ]),
+            },
+        },
+    })
+}
+
+fn convert_service_operation() -> Value {
+    json!({
+        "summary": "DocumentServer compatible conversion API",
+        "description": "Only available when the DocumentServer conversion API is enabled and PUBLIC_URL is set, responds with XML unless JSON is accepted. The token can be provided in the JWT header or the token field of the body, the claims of a body token are used as the request. Files are only downloaded from public addresses unless their network is allowed with CONVERT_SERVICE_ALLOWED_NETWORKS",
+        "tags": ["documentserver"],
+        "security": jwt_security(),
+        "parameters": deadline_parameters(),
+        "requestBody": {
+            "required": true,
+            "content": { "application/json": { "schema": schema_ref("ConvertServiceRequest") } },
+        },
+        "responses": {
+            "200": {
+                "description": "Progress or result of the conversion",
+                "content": {
+                    "application/json": { "schema": schema_ref("ConvertServiceResponse") },
+                    "application/xml": { "schema": schema_ref("ConvertServiceResponse") },
+                },
+            },
+        },
+    })
+}
+
+fn job_id_parameter() -> Value {
+    json!({
+        "name": "id",
+        "in": "path",
+        "required": true,
+        "schema": { "type": "string", "format": "uuid" },
+    })
+}
+
+fn converted_file_response() -> Value {
+    json!({
+        "description": "Converted file",
+        "headers": {
+            "x-conversion-backend": {
+                "description": "Backend that produced the converted file",
+                "schema": { "type": "string", "enum": ["x2t", "libreoffice"] },
+            },
+            "x-output-sha256": {
+                "description": "Hex encoded SHA-256 of the converted file",
+                "schema": { "type": "string" },
+            },
+        },
+        "content": {
+            "application/octet-stream": { "schema": { "type": "string", "format": "binary" } },
+        },
+    })
+}
+
+fn binary_response(description: &str, content_type: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {
+            content_type: { "schema": { "type": "string", "format": "binary" } },
+        },
+    })
+}
+
+fn json_response(description: &str, schema: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": schema_ref(schema) } },
+    })
+}
+
+/// Add the error responses with the provided status codes to the responses
+fn with_errors(mut responses: Value, status_codes: &[&str]) -> Value {
+    if let Some(responses) = responses.as_object_mut() {
+        for status_code in status_codes {
+            let schema = match *status_code {
+                "429" => "QueueFullResponse",
+                _ => "ErrorResponse",
+            };
+
+            responses.insert(status_code.to_string(), json_response("Error", schema));
+        }
+    }
+
+    responses
+}
+
+fn object_schema(properties: &[(&str, Value)]) -> Value {
+    let properties: Map<String, Value> = properties
+        .iter()
+        .map(|(name, schema)| (name.to_string(), schema.clone()))
+        .collect();
+
+    json!({ "type": "object", "properties": properties })
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{name}") })
+}
+
+fn jwt_security() -> Value {
+    json!([{ "jwt": [] }, {}])
+}
+
+fn admin_security() -> Value {
+    json!([{ "admin": [] }])
+}
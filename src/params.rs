@@ -0,0 +1,92 @@
+use serde_json::{Map, Value};
+
+use crate::{ErrorKind, ErrorResponse, convert::escape_xml};
+
+/// TaskQueueDataConvert elements that can be set through `x2t_params`.
+///
+/// Elements controlled by the server (paths, formats, passwords and the
+/// options exposed as their own fields) are intentionally excluded
+const ALLOWED_ELEMENTS: &[&str] = &[
+    "m_nFormatFrom",
+    "m_nLcid",
+    "m_sTitle",
+    "m_sDocumentID",
+    "m_bEmbeddedFonts",
+    "m_bIsNoBase64",
+    "m_bDontSaveAdditional",
+    "m_nDoctParams",
+    "m_bPaid",
+];
+
+/// Additional x2t config elements provided by the client for x2t features
+/// the server doesn't expose options for
+#[derive(Debug, Default)]
+pub struct X2tParams {
+    /// Element names and their serialized values
+    elements: Vec<(String, String)>,
+}
+
+impl X2tParams {
+    /// Whether no additional elements were provided
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    /// Parse the params from a JSON object mapping element names to values,
+    /// only scalar values for allowed elements are accepted
+    pub fn from_param(value: Option<String>) -> Result<X2tParams, ErrorResponse> {
+        let Some(value) = value else {
+            return Ok(X2tParams::default());
+        };
+
+        let params: Map<String, Value> =
+            serde_json::from_str(&value).map_err(|err| ErrorResponse {
+                code: None,
+                kind: ErrorKind::InvalidRequest,
+                message: format!("x2t_params must be a JSON object: {err}"),
+                backtrace: None,
+            })?;
+
+        let elements = params
+            .into_iter()
+            .map(|(name, value)| {
+                if !ALLOWED_ELEMENTS.contains(&name.as_str()) {
+                    return Err(ErrorResponse {
+                        code: None,
+                        kind: ErrorKind::InvalidRequest,
+                        message: format!("x2t param \"{name}\" is not allowed"),
+                        backtrace: None,
+                    });
+                }
+
+                let value = match value {
+                    Value::String(value) => value,
+                    Value::Number(value) => value.to_string(),
+                    Value::Bool(value) => value.to_string(),
+                    _ => {
+                        return Err(ErrorResponse {
+                            code: None,
+                            kind: ErrorKind::InvalidRequest,
+                            message: format!(
+                                "x2t param \"{name}\" must be a string, number or boolean"
+                            ),
+                            backtrace: None,
+                        });
+                    }
+                };
+
+                Ok((name, value))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(X2tParams { elements })
+    }
+
+    /// Additional x2t config elements for these params
+    pub fn config(&self) -> String {
+        self.elements
+            .iter()
+            .map(|(name, value)| format!("<{name}>{}</{name}>", escape_xml(value)))
+            .collect()
+    }
+}
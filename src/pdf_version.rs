@@ -0,0 +1,141 @@
+use std::{io::SeekFrom, path::Path};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+use crate::{ErrorKind, ErrorResponse, error_backtrace};
+
+/// Version of the PDF specification converted PDFs declare, for consumers
+/// that reject documents declaring a newer version than they support. Only
+/// versions at or above the one x2t produces can be declared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PdfVersion {
+    V1_5,
+    V1_6,
+    V1_7,
+}
+
+impl PdfVersion {
+    /// Parse the version from its name (i.e "1.5")
+    pub fn from_param(value: Option<String>) -> Result<Option<PdfVersion>, ErrorResponse> {
+        let Some(value) = value else {
+            return Ok(None);
+        };
+
+        let version = match value.trim() {
+            "1.5" => PdfVersion::V1_5,
+            "1.6" => PdfVersion::V1_6,
+            "1.7" => PdfVersion::V1_7,
+            _ => {
+                return Err(ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::InvalidRequest,
+                    message: format!("invalid pdf version \"{value}\", expected 1.5, 1.6 or 1.7"),
+                    backtrace: None,
+                });
+            }
+        };
+
+        Ok(Some(version))
+    }
+
+    /// Minor version number, the major version is always 1
+    pub fn minor(&self) -> u8 {
+        match self {
+            PdfVersion::V1_5 => 5,
+            PdfVersion::V1_6 => 6,
+            PdfVersion::V1_7 => 7,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PdfVersion::V1_5 => "1.5",
+            PdfVersion::V1_6 => "1.6",
+            PdfVersion::V1_7 => "1.7",
+        }
+    }
+}
+
+/// Header every PDF starts with, followed by the minor version
+const PDF_HEADER: &[u8] = b"%PDF-1.";
+
+/// Read the minor version declared by the header of the PDF at the
+/// provided path, only the header is read
+pub async fn read_pdf_version(path: &Path) -> std::io::Result<Option<u8>> {
+    let mut header = [0u8; PDF_HEADER.len() + 1];
+    let mut file = File::open(path).await?;
+
+    match file.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+
+    let (prefix, minor) = header.split_at(PDF_HEADER.len());
+    if prefix != PDF_HEADER || !minor[0].is_ascii_digit() {
+        return Ok(None);
+    }
+
+    Ok(Some(minor[0] - b'0'))
+}
+
+/// Error for PDFs produced with a newer version than requested, x2t output
+/// can't be downgraded without dropping the features it uses
+pub fn version_unavailable(version: PdfVersion, produced: u8) -> ErrorResponse {
+    ErrorResponse {
+        code: None,
+        kind: ErrorKind::UnsupportedFormat,
+        message: format!(
+            "x2t produced a pdf 1.{produced} document which can't be downgraded to pdf {}",
+            version.as_str()
+        ),
+        backtrace: None,
+    }
+}
+
+/// Make the PDF at the provided path declare the PDF version. Newer versions
+/// are a superset of older ones so the header is raised in place without
+/// reading the rest of the document, documents produced with a newer
+/// version than requested are rejected
+pub async fn set_pdf_version(path: &Path, version: PdfVersion) -> Result<(), ErrorResponse> {
+    let io_error = |err: std::io::Error| {
+        tracing::error!(?err, "failed to set pdf version");
+        ErrorResponse {
+            code: None,
+            kind: ErrorKind::Internal,
+            message: "failed to set pdf version".to_string(),
+            backtrace: error_backtrace(&err),
+        }
+    };
+
+    let Some(produced) = read_pdf_version(path).await.map_err(io_error)? else {
+        return Err(ErrorResponse {
+            code: None,
+            kind: ErrorKind::ConversionFailed,
+            message: "converted file isn't a pdf document".to_string(),
+            backtrace: None,
+        });
+    };
+
+    let minor = version.minor();
+    if produced > minor {
+        return Err(version_unavailable(version, produced));
+    }
+
+    if produced < minor {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(path)
+            .await
+            .map_err(io_error)?;
+        file.seek(SeekFrom::Start(PDF_HEADER.len() as u64))
+            .await
+            .map_err(io_error)?;
+        file.write_all(&[b'0' + minor]).await.map_err(io_error)?;
+        file.flush().await.map_err(io_error)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use crate::format::OutputFormat;
+
+/// Maximum number of intermediate formats in a conversion plan
+const MAX_INTERMEDIATE_STEPS: usize = 2;
+
+/// Conversion x2t can't make in a single pass, the input is converted to
+/// the intermediate format first
+struct ConversionChain {
+    /// Extensions of the input formats the chain applies to
+    inputs: &'static [&'static str],
+    /// Output formats the chain applies to
+    outputs: &'static [OutputFormat],
+    /// Format the input is converted to before the output
+    intermediate: OutputFormat,
+}
+
+/// Output formats that are rendered from the document layout
+const FIXED_LAYOUT_FORMATS: &[OutputFormat] = &[
+    OutputFormat::Pdf,
+    OutputFormat::PdfA,
+    OutputFormat::Png,
+    OutputFormat::Jpg,
+    OutputFormat::PngPages,
+    OutputFormat::JpgPages,
+];
+
+const CONVERSION_CHAINS: &[ConversionChain] = &[
+    // Legacy binary formats can't be converted to PDF/A directly
+    ConversionChain {
+        inputs: &["doc", "dot", "wps", "wpt"],
+        outputs: &[OutputFormat::PdfA],
+        intermediate: OutputFormat::Docx,
+    },
+    ConversionChain {
+        inputs: &["xls", "xlt", "et", "ett"],
+        outputs: &[OutputFormat::PdfA],
+        intermediate: OutputFormat::Xlsx,
+    },
+    ConversionChain {
+        inputs: &["ppt", "pps", "pot", "dps", "dpt"],
+        outputs: &[OutputFormat::PdfA],
+        intermediate: OutputFormat::Pptx,
+    },
+    // E-books have no page layout until they are converted to a document
+    ConversionChain {
+        inputs: &["fb2", "epub"],
+        outputs: FIXED_LAYOUT_FORMATS,
+        intermediate: OutputFormat::Docx,
+    },
+];
+
+/// Plan the intermediate formats a file is converted through before the
+/// output format, empty when x2t can convert the file directly
+///
+/// ## Arguments
+/// * `input_format` - Extension of the input file
+/// * `output_format` - Format the file is being converted to
+pub fn plan_conversion(input_format: &str, output_format: OutputFormat) -> Vec<OutputFormat> {
+    let mut intermediates = Vec::new();
+    let mut current_format = input_format;
+
+    while intermediates.len() < MAX_INTERMEDIATE_STEPS {
+        let Some(chain) = CONVERSION_CHAINS.iter().find(|chain| {
+            chain.inputs.contains(&current_format) && chain.outputs.contains(&output_format)
+        }) else {
+            break;
+        };
+
+        intermediates.push(chain.intermediate);
+        current_format = chain.intermediate.extension();
+    }
+
+    intermediates
+}
@@ -0,0 +1,81 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{ErrorKind, ErrorResponse};
+
+/// Default number of seconds crashes of a document are counted for
+pub const DEFAULT_POISON_WINDOW: u64 = 60 * 60;
+
+/// Documents that crashed x2t keyed by the SHA-256 of their contents, a
+/// document that crashes x2t too many times within the window is rejected
+/// instead of tying up a worker for another attempt
+pub struct PoisonDocuments {
+    /// Number of crashes within the window after which a document is rejected
+    max_crashes: usize,
+    /// Duration crashes are counted for
+    window: Duration,
+    /// When each document crashed x2t within the window
+    crashes: Mutex<HashMap<String, Vec<Instant>>>,
+}
+
+impl PoisonDocuments {
+    pub fn new(max_crashes: usize, window: Duration) -> Self {
+        Self {
+            max_crashes,
+            window,
+            crashes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Ensure the document hasn't crashed x2t too many times recently
+    ///
+    /// ## Arguments
+    /// * `hash` - Hex encoded SHA-256 of the document
+    pub fn check(&self, hash: &str) -> Result<(), ErrorResponse> {
+        let mut crashes = self.crashes.lock().expect("poison documents lock poisoned");
+        let now = Instant::now();
+
+        let count = match crashes.get_mut(hash) {
+            Some(times) => {
+                times.retain(|time| now.duration_since(*time) < self.window);
+                times.len()
+            }
+            None => 0,
+        };
+
+        if count < self.max_crashes {
+            return Ok(());
+        }
+
+        tracing::warn!(%hash, count, "rejected document that repeatedly crashed x2t");
+
+        Err(ErrorResponse {
+            code: None,
+            kind: ErrorKind::PoisonDocument,
+            message: format!(
+                "document crashed the converter {count} times recently and has been quarantined"
+            ),
+            backtrace: None,
+        })
+    }
+
+    /// Record that the document crashed x2t
+    ///
+    /// ## Arguments
+    /// * `hash` - Hex encoded SHA-256 of the document
+    pub fn record_crash(&self, hash: String) {
+        let mut crashes = self.crashes.lock().expect("poison documents lock poisoned");
+        let now = Instant::now();
+
+        // Forget crashes that have left the window
+        crashes.retain(|_, times| {
+            times.retain(|time| now.duration_since(*time) < self.window);
+            !times.is_empty()
+        });
+
+        crashes.entry(hash).or_default().push(now);
+    }
+}
@@ -0,0 +1,257 @@
+use axum::http::{HeaderMap, HeaderValue, header};
+
+/// Inclusive range of bytes within a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+/// Portion of a file a request asked for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// Entire file, either no range was requested or the range can't be
+    /// honored (i.e multiple ranges or a stale If-Range)
+    Full,
+    /// Single range within the file
+    Partial(ByteRange),
+    /// Range starts beyond the end of the file
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    /// Number of bytes in the range
+    pub fn length(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Value of the Content-Range header for the range
+    pub fn content_range(&self, size: u64) -> HeaderValue {
+        HeaderValue::from_str(&format!("bytes {}-{}/{size}", self.start, self.end))
+            .expect("content range is a valid header value")
+    }
+}
+
+/// Value of the Content-Range header responded with when the range is
+/// unsatisfiable
+pub fn unsatisfiable_content_range(size: u64) -> HeaderValue {
+    HeaderValue::from_str(&format!("bytes */{size}"))
+        .expect("content range is a valid header value")
+}
+
+/// Strong entity tag for a file from its hex encoded checksum
+pub fn checksum_etag(checksum: &str) -> Option<HeaderValue> {
+    HeaderValue::from_str(&format!("\"{checksum}\"")).ok()
+}
+
+/// Determine the portion of a file of the provided size requested by the
+/// Range header. Only single byte ranges are supported, other ranges are
+/// ignored and the full file is served as allowed by RFC 9110. When an
+/// If-Range header is present the range is only honored if it matches the
+/// entity tag of the file
+pub fn requested_range(headers: &HeaderMap, size: u64, etag: Option<&HeaderValue>) -> RangeRequest {
+    let Some(range) = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return RangeRequest::Full;
+    };
+
+    if let Some(if_range) = headers.get(header::IF_RANGE)
+        && etag.is_none_or(|etag| etag != if_range)
+    {
+        return RangeRequest::Full;
+    }
+
+    let Some(range) = range.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+
+    if range.contains(',') {
+        return RangeRequest::Full;
+    }
+
+    let Some((start, end)) = range.trim().split_once('-') else {
+        return RangeRequest::Full;
+    };
+
+    let (start, end) = (start.trim(), end.trim());
+
+    // Suffix range of the last N bytes (i.e "bytes=-500")
+    if start.is_empty() {
+        return match end.parse::<u64>() {
+            Ok(0) => RangeRequest::Unsatisfiable,
+            Ok(_) if size == 0 => RangeRequest::Unsatisfiable,
+            Ok(length) => RangeRequest::Partial(ByteRange {
+                start: size.saturating_sub(length),
+                end: size - 1,
+            }),
+            Err(_) => RangeRequest::Full,
+        };
+    }
+
+    let Ok(start) = start.parse::<u64>() else {
+        return RangeRequest::Full;
+    };
+
+    let end = if end.is_empty() {
+        None
+    } else {
+        match end.parse::<u64>() {
+            Ok(end) if end >= start => Some(end),
+            _ => return RangeRequest::Full,
+        }
+    };
+
+    if start >= size {
+        return RangeRequest::Unsatisfiable;
+    }
+
+    RangeRequest::Partial(ByteRange {
+        start,
+        end: end.map_or(size - 1, |end| end.min(size - 1)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(range: &str, if_range: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, HeaderValue::from_str(range).unwrap());
+
+        if let Some(if_range) = if_range {
+            headers.insert(header::IF_RANGE, HeaderValue::from_str(if_range).unwrap());
+        }
+
+        headers
+    }
+
+    fn partial(start: u64, end: u64) -> RangeRequest {
+        RangeRequest::Partial(ByteRange { start, end })
+    }
+
+    #[test]
+    fn test_no_range() {
+        assert_eq!(
+            requested_range(&HeaderMap::new(), 100, None),
+            RangeRequest::Full
+        );
+    }
+
+    #[test]
+    fn test_bounded_range() {
+        assert_eq!(
+            requested_range(&headers("bytes=0-99", None), 1000, None),
+            partial(0, 99)
+        );
+
+        // End past the end of the file is clamped
+        assert_eq!(
+            requested_range(&headers("bytes=900-5000", None), 1000, None),
+            partial(900, 999)
+        );
+    }
+
+    #[test]
+    fn test_open_ended_range() {
+        assert_eq!(
+            requested_range(&headers("bytes=500-", None), 1000, None),
+            partial(500, 999)
+        );
+    }
+
+    #[test]
+    fn test_suffix_range() {
+        assert_eq!(
+            requested_range(&headers("bytes=-100", None), 1000, None),
+            partial(900, 999)
+        );
+
+        // Suffix longer than the file is the whole file
+        assert_eq!(
+            requested_range(&headers("bytes=-5000", None), 1000, None),
+            partial(0, 999)
+        );
+
+        assert_eq!(
+            requested_range(&headers("bytes=-0", None), 1000, None),
+            RangeRequest::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn test_start_beyond_end() {
+        assert_eq!(
+            requested_range(&headers("bytes=1000-", None), 1000, None),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(
+            requested_range(&headers("bytes=2000-3000", None), 1000, None),
+            RangeRequest::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn test_zero_length_file() {
+        assert_eq!(
+            requested_range(&headers("bytes=0-", None), 0, None),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(
+            requested_range(&headers("bytes=-10", None), 0, None),
+            RangeRequest::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn test_multiple_ranges() {
+        assert_eq!(
+            requested_range(&headers("bytes=0-9,20-29", None), 1000, None),
+            RangeRequest::Full
+        );
+    }
+
+    #[test]
+    fn test_invalid_range() {
+        for range in ["items=0-9", "bytes=9-0", "bytes=a-b", "bytes=", "bytes=-"] {
+            assert_eq!(
+                requested_range(&headers(range, None), 1000, None),
+                RangeRequest::Full,
+                "{range}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_if_range() {
+        let etag = HeaderValue::from_static("\"abc\"");
+
+        assert_eq!(
+            requested_range(&headers("bytes=0-9", Some("\"abc\"")), 1000, Some(&etag)),
+            partial(0, 9)
+        );
+
+        // Stale entity tag serves the full file
+        assert_eq!(
+            requested_range(&headers("bytes=0-9", Some("\"old\"")), 1000, Some(&etag)),
+            RangeRequest::Full
+        );
+
+        // Without an entity tag the If-Range can't match
+        assert_eq!(
+            requested_range(&headers("bytes=0-9", Some("\"abc\"")), 1000, None),
+            RangeRequest::Full
+        );
+    }
+
+    #[test]
+    fn test_content_range() {
+        let range = ByteRange { start: 10, end: 19 };
+
+        assert_eq!(range.length(), 10);
+        assert_eq!(range.content_range(100), "bytes 10-19/100");
+        assert_eq!(unsatisfiable_content_range(100), "bytes */100");
+    }
+}
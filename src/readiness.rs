@@ -0,0 +1,171 @@
+use axum::{Extension, Json, http::StatusCode};
+use serde::Serialize;
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::{RuntimeConfig, convert::X2T_BIN, limiter::ConversionLimiter, selftest::run_self_test};
+
+/// Outcome of a single readiness check
+#[derive(Clone, Serialize)]
+pub struct ReadinessCheck {
+    /// Name of the check
+    name: &'static str,
+    /// Whether the check passed
+    passed: bool,
+    /// Reason the check failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+impl ReadinessCheck {
+    fn new(name: &'static str, result: Result<(), String>) -> Self {
+        match result {
+            Ok(()) => Self {
+                name,
+                passed: true,
+                message: None,
+            },
+            Err(message) => Self {
+                name,
+                passed: false,
+                message: Some(message),
+            },
+        }
+    }
+}
+
+/// Tracks whether the server is ready to accept conversions, the checks
+/// are run once in the background after startup
+#[derive(Default)]
+pub struct Readiness {
+    /// Results of the checks, None until the checks have finished
+    checks: Mutex<Option<Vec<ReadinessCheck>>>,
+}
+
+impl Readiness {
+    /// Run the readiness checks, storing their results. Returns the reason
+    /// the warm-up conversion failed when it was run and didn't succeed
+    ///
+    /// ## Arguments
+    /// * `runtime_config` - Runtime configuration to check
+    /// * `warmup` - Whether a warm-up conversion must succeed
+    pub async fn run_checks(&self, runtime_config: &RuntimeConfig, warmup: bool) -> Option<String> {
+        let mut checks = vec![
+            ReadinessCheck::new("x2t", check_x2t(&runtime_config.x2t_path)),
+            ReadinessCheck::new("fonts", check_fonts(&runtime_config.fonts_path)),
+        ];
+
+        let mut warmup_failure = None;
+
+        // Warm-up is skipped when x2t is known to be missing
+        if warmup && checks.iter().all(|check| check.passed) {
+            let report = run_self_test(runtime_config).await;
+            let result = match report.failure() {
+                Some(failure) => Err(format!("warm-up conversion failed: {failure}")),
+                None => Ok(()),
+            };
+
+            warmup_failure = result.clone().err();
+            checks.push(ReadinessCheck::new("warmup", result));
+        } else if warmup {
+            warmup_failure = Some("warm-up conversion skipped, x2t install is broken".to_string());
+        }
+
+        for check in checks.iter().filter(|check| !check.passed) {
+            tracing::error!(
+                check = check.name,
+                message = check.message,
+                "readiness check failed"
+            );
+        }
+
+        *self.checks.lock().expect("readiness lock poisoned") = Some(checks);
+
+        warmup_failure
+    }
+}
+
+/// Check that the x2t binary exists and can be executed
+pub fn check_x2t(x2t_path: &Path) -> Result<(), String> {
+    let path = x2t_path.join(X2T_BIN);
+    let metadata = std::fs::metadata(&path)
+        .map_err(|err| format!("x2t binary not found at {}: {err}", path.display()))?;
+
+    if !metadata.is_file() {
+        return Err(format!("x2t binary at {} is not a file", path.display()));
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        if metadata.permissions().mode() & 0o111 == 0 {
+            return Err(format!(
+                "x2t binary at {} is not executable",
+                path.display()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that the fonts directory exists
+pub fn check_fonts(fonts_path: &Path) -> Result<(), String> {
+    if !fonts_path.is_dir() {
+        return Err(format!(
+            "fonts directory {} does not exist",
+            fonts_path.display()
+        ));
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct ReadyResponse {
+    /// Whether the server is ready to accept conversions
+    ready: bool,
+    /// Whether the server is shutting down
+    draining: bool,
+    /// Results of the readiness checks, empty while they are running
+    checks: Vec<ReadinessCheck>,
+}
+
+/// GET /ready
+///
+/// Readiness check, responds with 200 once the startup checks have passed
+/// and 503 while they are running, if any failed or while shutting down
+pub async fn ready(
+    Extension(readiness): Extension<Arc<Readiness>>,
+    Extension(limiter): Extension<Arc<ConversionLimiter>>,
+) -> (StatusCode, Json<ReadyResponse>) {
+    let checks = readiness
+        .checks
+        .lock()
+        .expect("readiness lock poisoned")
+        .clone();
+
+    let draining = limiter.is_draining();
+    let passed = checks
+        .as_ref()
+        .is_some_and(|checks| checks.iter().all(|check| check.passed));
+    let ready = passed && !draining;
+
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        status,
+        Json(ReadyResponse {
+            ready,
+            draining,
+            checks: checks.unwrap_or_default(),
+        }),
+    )
+}
@@ -0,0 +1,310 @@
+use anyhow::Context;
+use axum::{Extension, Json};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+use tracing_subscriber::EnvFilter;
+
+use crate::{
+    Args, DEFAULT_MAX_QUEUE_LENGTH, ErrorKind, ErrorResponse, RuntimeConfig,
+    auth::{AdminAuth, JwtAuth},
+    limiter::ConversionLimiter,
+    limits::ProcessLimits,
+    logging::LogFilterHandle,
+    retry::{DEFAULT_RETRY_EXIT_CODES, RetryPolicy, parse_exit_codes},
+};
+
+/// Settings that can be changed while the server is running, command line
+/// arguments take precedence over environment variables
+pub struct ReloadableSettings {
+    /// Maximum number of conversions to run at once
+    pub max_concurrent: usize,
+    /// Maximum number of conversions allowed to wait for a free slot
+    pub max_queue: usize,
+    /// Resource limits applied to each x2t process
+    pub x2t_limits: ProcessLimits,
+    /// Maximum time each x2t process can run for
+    pub x2t_timeout: Option<Duration>,
+    /// Policy for retrying transient x2t failures
+    pub x2t_retry_policy: RetryPolicy,
+    /// Shared secret for validating JWTs
+    pub jwt_secret: Option<String>,
+    /// Bearer token for the admin endpoints
+    pub admin_token: Option<String>,
+}
+
+impl ReloadableSettings {
+    /// Load the settings from the command line arguments, falling back to
+    /// the environment variables provided by `env`
+    pub fn load(args: &Args, env: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+        let max_concurrent = match args.max_concurrent {
+            Some(value) => value,
+            None => match env("MAX_CONCURRENT_CONVERSIONS") {
+                Some(value) => value
+                    .parse()
+                    .context("invalid MAX_CONCURRENT_CONVERSIONS value")?,
+                None => std::thread::available_parallelism()
+                    .map(|value| value.get())
+                    .unwrap_or(1),
+            },
+        };
+
+        // Queue is always bounded so excess requests are rejected rather than
+        // buffered without limit
+        let max_queue = match args.max_queue {
+            Some(value) => value,
+            None => match env("MAX_QUEUE_LENGTH") {
+                Some(value) => value.parse().context("invalid MAX_QUEUE_LENGTH value")?,
+                None => DEFAULT_MAX_QUEUE_LENGTH,
+            },
+        };
+
+        let x2t_max_memory = match args.x2t_max_memory {
+            Some(value) => Some(value),
+            None => match env("X2T_MAX_MEMORY") {
+                Some(value) => Some(value.parse().context("invalid X2T_MAX_MEMORY value")?),
+                None => None,
+            },
+        };
+
+        let x2t_max_cpu_time = match args.x2t_max_cpu_time {
+            Some(value) => Some(value),
+            None => match env("X2T_MAX_CPU_TIME") {
+                Some(value) => Some(value.parse().context("invalid X2T_MAX_CPU_TIME value")?),
+                None => None,
+            },
+        };
+
+        let x2t_max_file_size = match args.x2t_max_file_size {
+            Some(value) => Some(value),
+            None => match env("X2T_MAX_FILE_SIZE") {
+                Some(value) => Some(value.parse().context("invalid X2T_MAX_FILE_SIZE value")?),
+                None => None,
+            },
+        };
+
+        let x2t_limits = ProcessLimits {
+            max_memory: x2t_max_memory.map(|value: u64| value.saturating_mul(1024 * 1024)),
+            max_cpu_time: x2t_max_cpu_time,
+            max_file_size: x2t_max_file_size.map(|value: u64| value.saturating_mul(1024 * 1024)),
+        };
+
+        if !x2t_limits.is_empty() && cfg!(not(unix)) {
+            anyhow::bail!("x2t resource limits are not supported on this platform");
+        }
+
+        let x2t_timeout = match args.x2t_timeout {
+            Some(value) => Some(value),
+            None => match env("X2T_TIMEOUT") {
+                Some(value) => Some(value.parse().context("invalid X2T_TIMEOUT value")?),
+                None => None,
+            },
+        };
+
+        if x2t_timeout == Some(0) {
+            anyhow::bail!("X2T_TIMEOUT must be at least 1 second");
+        }
+
+        let x2t_max_attempts = match args.x2t_max_attempts {
+            Some(value) => value,
+            None => match env("X2T_MAX_ATTEMPTS") {
+                Some(value) => value.parse().context("invalid X2T_MAX_ATTEMPTS value")?,
+                None => 1,
+            },
+        };
+
+        if x2t_max_attempts == 0 {
+            anyhow::bail!("X2T_MAX_ATTEMPTS must be at least 1");
+        }
+
+        let x2t_retry_codes = match args
+            .x2t_retry_codes
+            .clone()
+            .or_else(|| env("X2T_RETRY_CODES"))
+        {
+            Some(value) => parse_exit_codes(&value).context("invalid X2T_RETRY_CODES value")?,
+            None => DEFAULT_RETRY_EXIT_CODES.to_vec(),
+        };
+
+        let jwt_secret = args.jwt_secret.clone().or_else(|| env("JWT_SECRET"));
+
+        let admin_token = args
+            .admin_token
+            .clone()
+            .or_else(|| env("ADMIN_TOKEN"))
+            .filter(|token| !token.is_empty());
+
+        Ok(Self {
+            max_concurrent,
+            max_queue,
+            x2t_limits,
+            x2t_timeout: x2t_timeout.map(Duration::from_secs),
+            x2t_retry_policy: RetryPolicy {
+                max_attempts: x2t_max_attempts,
+                exit_codes: x2t_retry_codes,
+            },
+            jwt_secret,
+            admin_token,
+        })
+    }
+}
+
+/// Reloads the [ReloadableSettings] from the command line arguments and the
+/// .env file, applying them without interrupting in-flight conversions
+pub struct ConfigReloader {
+    /// Command line arguments the server was started with
+    pub args: Args,
+    /// Names of the variables set in the process environment before the .env
+    /// file was loaded, these take precedence over the .env file
+    pub process_env: HashSet<String>,
+    pub runtime_config: Arc<RuntimeConfig>,
+    pub limiter: Arc<ConversionLimiter>,
+    pub jwt_auth: Option<Arc<JwtAuth>>,
+    pub admin_auth: Option<Arc<AdminAuth>>,
+    pub log_filter: LogFilterHandle,
+}
+
+impl ConfigReloader {
+    /// Reload and apply the settings, nothing is changed if any of the
+    /// settings are invalid
+    pub fn reload(&self) -> anyhow::Result<ReloadableSettings> {
+        // Variables loaded from the .env file at startup are still present in
+        // the process environment, the file is read again to pick up changes
+        let dotenv: HashMap<String, String> = match dotenvy::dotenv_iter() {
+            Ok(iter) => iter
+                .collect::<Result<_, _>>()
+                .context("failed to read .env file")?,
+            Err(err) if err.not_found() => HashMap::new(),
+            Err(err) => return Err(err).context("failed to read .env file"),
+        };
+
+        let env = |name: &str| {
+            if self.process_env.contains(name) {
+                std::env::var(name).ok()
+            } else {
+                dotenv.get(name).cloned()
+            }
+        };
+
+        let settings = ReloadableSettings::load(&self.args, env)?;
+        let log_filter = EnvFilter::builder()
+            .parse(env("RUST_LOG").unwrap_or_default())
+            .context("invalid RUST_LOG value")?;
+
+        self.log_filter.set_filter(log_filter)?;
+
+        self.limiter
+            .set_limits(settings.max_concurrent, settings.max_queue);
+
+        *self
+            .runtime_config
+            .x2t_limits
+            .write()
+            .expect("x2t limits lock poisoned") = settings.x2t_limits;
+        *self
+            .runtime_config
+            .x2t_timeout
+            .write()
+            .expect("x2t timeout lock poisoned") = settings.x2t_timeout;
+        *self
+            .runtime_config
+            .x2t_retry_policy
+            .write()
+            .expect("x2t retry policy lock poisoned") = settings.x2t_retry_policy.clone();
+
+        // Authentication can't be enabled or disabled without rebuilding the
+        // router so only the secrets are replaced
+        match (&self.jwt_auth, &settings.jwt_secret) {
+            (Some(jwt_auth), Some(secret)) => jwt_auth.set_secret(secret),
+            (Some(_), None) => {
+                tracing::warn!(
+                    "JWT_SECRET was removed, disabling jwt authentication requires a restart"
+                )
+            }
+            (None, Some(_)) => {
+                tracing::warn!(
+                    "JWT_SECRET was added, enabling jwt authentication requires a restart"
+                )
+            }
+            (None, None) => {}
+        }
+
+        match (&self.admin_auth, &settings.admin_token) {
+            (Some(admin_auth), Some(token)) => admin_auth.set_token(token.clone()),
+            (Some(_), None) => {
+                tracing::warn!(
+                    "ADMIN_TOKEN was removed, disabling the admin endpoints requires a restart"
+                )
+            }
+            (None, Some(_)) => {
+                tracing::warn!(
+                    "ADMIN_TOKEN was added, enabling the admin endpoints requires a restart"
+                )
+            }
+            (None, None) => {}
+        }
+
+        tracing::info!(
+            max_concurrent = settings.max_concurrent,
+            max_queue = settings.max_queue,
+            "reloaded configuration"
+        );
+
+        Ok(settings)
+    }
+
+    /// Reload the configuration whenever the process receives SIGHUP
+    #[cfg(unix)]
+    pub async fn run_on_hangup(self: Arc<Self>) {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(value) => value,
+            Err(err) => {
+                tracing::error!(?err, "failed to listen for SIGHUP");
+                return;
+            }
+        };
+
+        while hangup.recv().await.is_some() {
+            tracing::debug!("received SIGHUP, reloading configuration");
+
+            if let Err(err) = self.reload() {
+                tracing::error!(?err, "failed to reload configuration");
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ReloadResponse {
+    /// Maximum number of conversions that can run at once
+    max_concurrent: usize,
+    /// Maximum number of conversions allowed to wait
+    max_queued: usize,
+}
+
+/// POST /admin/reload
+///
+/// Reload the configuration, equivalent to sending SIGHUP
+pub async fn reload_config(
+    Extension(reloader): Extension<Arc<ConfigReloader>>,
+) -> Result<Json<ReloadResponse>, ErrorResponse> {
+    let settings = reloader.reload().map_err(|err| {
+        tracing::error!(?err, "failed to reload configuration");
+        ErrorResponse {
+            code: None,
+            kind: ErrorKind::Internal,
+            message: format!("failed to reload configuration: {err:#}"),
+            backtrace: None,
+        }
+    })?;
+
+    Ok(Json(ReloadResponse {
+        max_concurrent: settings.max_concurrent,
+        max_queued: settings.max_queue,
+    }))
+}
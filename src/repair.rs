@@ -0,0 +1,448 @@
+use flate2::bufread::DeflateDecoder;
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+/// Signature at the start of each ZIP local file header
+const LOCAL_FILE_HEADER_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x03, 0x04];
+
+/// Optional signature at the start of a ZIP data descriptor
+const DATA_DESCRIPTOR_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x07, 0x08];
+
+/// Length of the fixed portion of a ZIP local file header
+const LOCAL_FILE_HEADER_LENGTH: usize = 30;
+
+/// Entries that identify an archive as an office document, OOXML and ODF
+const DOCUMENT_MARKERS: &[&str] = &["[Content_Types].xml", "mimetype"];
+
+/// Attempt to salvage a ZIP based input (OOXML, ODF) that is missing its
+/// central directory, usually because the upload was truncated or has
+/// trailing garbage. The entries are read from their local headers in order
+/// until one is incomplete and written to a new archive at `output_path`.
+/// Returns whether the repaired archive looks like a usable document
+///
+/// ## Arguments
+/// * `input_path` - Path to the corrupted input file
+/// * `output_path` - Path to write the repaired archive to
+/// * `max_uncompressed_size` - Limit on the total size of salvaged entries
+pub async fn repair_zip(
+    input_path: &Path,
+    output_path: &Path,
+    max_uncompressed_size: Option<u64>,
+) -> bool {
+    let input_path = input_path.to_path_buf();
+    let output_path = output_path.to_path_buf();
+
+    let result = tokio::task::spawn_blocking(move || {
+        salvage_entries(&input_path, &output_path, max_uncompressed_size)
+    })
+    .await;
+
+    match result {
+        Ok(Ok(repaired)) => repaired,
+        Ok(Err(err)) => {
+            tracing::warn!(?err, "failed to repair input archive");
+            false
+        }
+        Err(err) => {
+            tracing::error!(?err, "failed to repair input archive");
+            false
+        }
+    }
+}
+
+/// Copy every complete entry of the input archive into a new archive
+fn salvage_entries(
+    input_path: &Path,
+    output_path: &Path,
+    max_uncompressed_size: Option<u64>,
+) -> std::io::Result<bool> {
+    let mut input = BufReader::new(File::open(input_path)?);
+    let mut writer = ZipWriter::new(File::create(output_path)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let mut remaining = max_uncompressed_size.unwrap_or(u64::MAX);
+    let mut salvaged = Vec::new();
+
+    // Entries are read until the central directory or the first damaged entry
+    while let Some(header) = read_local_header(&mut input)? {
+        // Encrypted entries and ZIP64 sizes aren't supported
+        if header.flags & 0x01 != 0
+            || header.compressed_size == u32::MAX
+            || header.uncompressed_size == u32::MAX
+        {
+            break;
+        }
+
+        let data_start = input.stream_position()?;
+
+        if header.name.ends_with('/') {
+            input.seek(SeekFrom::Start(
+                data_start + u64::from(header.compressed_size),
+            ))?;
+            continue;
+        }
+
+        writer.start_file(header.name.as_str(), options)?;
+
+        let copied = match header.method {
+            // Stored entries can only be read when their size is known upfront
+            0 if !header.has_data_descriptor() => copy_entry(
+                (&mut input).take(u64::from(header.compressed_size)),
+                &mut writer,
+                remaining,
+            ),
+            8 => copy_entry(DeflateDecoder::new(&mut input), &mut writer, remaining),
+            _ => break,
+        };
+
+        let Ok((size, crc)) = copied else {
+            writer.abort_file()?;
+            break;
+        };
+
+        let expected_crc = if header.has_data_descriptor() {
+            match read_data_descriptor_crc(&mut input) {
+                Ok(crc) => crc,
+                Err(_) => {
+                    writer.abort_file()?;
+                    break;
+                }
+            }
+        } else {
+            input.seek(SeekFrom::Start(
+                data_start + u64::from(header.compressed_size),
+            ))?;
+            header.crc
+        };
+
+        if crc != expected_crc {
+            writer.abort_file()?;
+            break;
+        }
+
+        remaining -= size;
+        salvaged.push(header.name);
+    }
+
+    writer.finish()?;
+
+    tracing::debug!(
+        entries = salvaged.len(),
+        "salvaged entries from damaged archive"
+    );
+
+    Ok(salvaged
+        .iter()
+        .any(|name| DOCUMENT_MARKERS.contains(&name.as_str())))
+}
+
+/// Fields of a ZIP local file header needed to read its entry
+struct LocalHeader {
+    flags: u16,
+    method: u16,
+    crc: u32,
+    compressed_size: u32,
+    uncompressed_size: u32,
+    name: String,
+}
+
+impl LocalHeader {
+    /// Whether the sizes and CRC follow the entry data instead of being
+    /// included in the header
+    fn has_data_descriptor(&self) -> bool {
+        self.flags & 0x08 != 0
+    }
+}
+
+/// Read the next local file header, None once the entries have ended
+fn read_local_header<R: Read + Seek>(input: &mut R) -> std::io::Result<Option<LocalHeader>> {
+    let mut fixed = [0; LOCAL_FILE_HEADER_LENGTH];
+    if input.read_exact(&mut fixed).is_err() || fixed[..4] != LOCAL_FILE_HEADER_SIGNATURE {
+        return Ok(None);
+    }
+
+    let u16_at = |offset: usize| u16::from_le_bytes([fixed[offset], fixed[offset + 1]]);
+    let u32_at = |offset: usize| {
+        u32::from_le_bytes([
+            fixed[offset],
+            fixed[offset + 1],
+            fixed[offset + 2],
+            fixed[offset + 3],
+        ])
+    };
+
+    let mut name = vec![0; usize::from(u16_at(26))];
+    if input.read_exact(&mut name).is_err() {
+        return Ok(None);
+    }
+
+    input.seek(SeekFrom::Current(i64::from(u16_at(28))))?;
+
+    Ok(Some(LocalHeader {
+        flags: u16_at(6),
+        method: u16_at(8),
+        crc: u32_at(14),
+        compressed_size: u32_at(18),
+        uncompressed_size: u32_at(22),
+        name: String::from_utf8_lossy(&name).into_owned(),
+    }))
+}
+
+/// Read the CRC from the data descriptor following an entry, the descriptor
+/// signature is optional
+fn read_data_descriptor_crc<R: Read>(input: &mut R) -> std::io::Result<u32> {
+    let mut value = [0; 4];
+    input.read_exact(&mut value)?;
+
+    if value == DATA_DESCRIPTOR_SIGNATURE {
+        input.read_exact(&mut value)?;
+    }
+
+    let crc = u32::from_le_bytes(value);
+
+    // Skip the compressed and uncompressed sizes
+    let mut sizes = [0; 8];
+    input.read_exact(&mut sizes)?;
+
+    Ok(crc)
+}
+
+/// Copy the contents of an entry to the writer, returns the size and CRC of
+/// the contents. Fails when the contents exceed the remaining size limit
+fn copy_entry<R: Read, W: Write>(
+    mut reader: R,
+    writer: &mut W,
+    remaining: u64,
+) -> std::io::Result<(u64, u32)> {
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0; 64 * 1024];
+    let mut size: u64 = 0;
+
+    loop {
+        let count = reader.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+
+        size += count as u64;
+        if size > remaining {
+            return Err(std::io::Error::other("archive exceeds the size limit"));
+        }
+
+        hasher.update(&buffer[..count]);
+        writer.write_all(&buffer[..count])?;
+    }
+
+    Ok((size, hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Cursor, path::PathBuf};
+    use uuid::Uuid;
+    use zip::ZipArchive;
+
+    const CONTENT_TYPES: &[u8] = b"<?xml version=\"1.0\"?><Types/>";
+    const DOCUMENT: &[u8] = b"<w:document><w:body>Hello, world!</w:body></w:document>";
+    const STYLES: &[u8] = b"<w:styles><w:style w:styleId=\"Normal\"/></w:styles>";
+
+    /// Input and output files for a repair, removed once dropped
+    struct RepairFiles {
+        input: PathBuf,
+        output: PathBuf,
+    }
+
+    impl RepairFiles {
+        fn new(input: &[u8]) -> Self {
+            let id = Uuid::new_v4();
+            let temp_dir = std::env::temp_dir();
+            let files = Self {
+                input: temp_dir.join(format!("repair-input-{id}.docx")),
+                output: temp_dir.join(format!("repair-output-{id}.docx")),
+            };
+
+            std::fs::write(&files.input, input).unwrap();
+            files
+        }
+
+        fn repair(&self, max_uncompressed_size: Option<u64>) -> bool {
+            salvage_entries(&self.input, &self.output, max_uncompressed_size).unwrap()
+        }
+
+        /// Names and contents of the entries in the repaired archive
+        fn entries(&self) -> Vec<(String, Vec<u8>)> {
+            let mut archive = ZipArchive::new(File::open(&self.output).unwrap()).unwrap();
+
+            (0..archive.len())
+                .map(|index| {
+                    let mut file = archive.by_index(index).unwrap();
+                    let mut contents = Vec::new();
+                    file.read_to_end(&mut contents).unwrap();
+                    (file.name().to_string(), contents)
+                })
+                .collect()
+        }
+    }
+
+    impl Drop for RepairFiles {
+        fn drop(&mut self) {
+            _ = std::fs::remove_file(&self.input);
+            _ = std::fs::remove_file(&self.output);
+        }
+    }
+
+    /// Create a minimal OOXML archive using the provided compression method
+    fn document(method: CompressionMethod) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default().compression_method(method);
+
+        writer.add_directory("word/", options).unwrap();
+
+        for (name, contents) in [
+            ("[Content_Types].xml", CONTENT_TYPES),
+            ("word/document.xml", DOCUMENT),
+            ("word/styles.xml", STYLES),
+        ] {
+            writer.start_file(name, options).unwrap();
+            writer.write_all(contents).unwrap();
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    /// Position of the first occurrence of `needle` in `haystack`
+    fn find(haystack: &[u8], needle: &[u8]) -> usize {
+        haystack
+            .windows(needle.len())
+            .position(|window| window == needle)
+            .unwrap()
+    }
+
+    /// Position of the central directory of an archive
+    fn central_directory(archive: &[u8]) -> usize {
+        find(archive, &[0x50, 0x4b, 0x01, 0x02])
+    }
+
+    fn expected_entries(count: usize) -> Vec<(String, Vec<u8>)> {
+        [
+            ("[Content_Types].xml", CONTENT_TYPES),
+            ("word/document.xml", DOCUMENT),
+            ("word/styles.xml", STYLES),
+        ]
+        .into_iter()
+        .take(count)
+        .map(|(name, contents)| (name.to_string(), contents.to_vec()))
+        .collect()
+    }
+
+    #[test]
+    fn test_missing_central_directory() {
+        for method in [CompressionMethod::Stored, CompressionMethod::Deflated] {
+            let archive = document(method);
+            let files = RepairFiles::new(&archive[..central_directory(&archive)]);
+
+            assert!(files.repair(None));
+            assert_eq!(files.entries(), expected_entries(3));
+        }
+    }
+
+    #[test]
+    fn test_trailing_garbage() {
+        let mut archive = document(CompressionMethod::Deflated);
+        archive.truncate(central_directory(&archive));
+        archive.extend_from_slice(b"this is not part of the archive");
+
+        let files = RepairFiles::new(&archive);
+
+        assert!(files.repair(None));
+        assert_eq!(files.entries(), expected_entries(3));
+    }
+
+    #[test]
+    fn test_truncated_entry() {
+        for method in [CompressionMethod::Stored, CompressionMethod::Deflated] {
+            let archive = document(method);
+            let styles = find(&archive, b"word/styles.xml");
+
+            // Cut off partway through the contents of the last entry
+            let files = RepairFiles::new(&archive[..styles + 20]);
+
+            assert!(files.repair(None));
+            assert_eq!(files.entries(), expected_entries(2));
+        }
+    }
+
+    #[test]
+    fn test_truncated_header() {
+        let archive = document(CompressionMethod::Deflated);
+        let files = RepairFiles::new(&archive[..LOCAL_FILE_HEADER_LENGTH - 1]);
+
+        assert!(!files.repair(None));
+        assert!(files.entries().is_empty());
+    }
+
+    #[test]
+    fn test_truncated_marker_entry() {
+        let archive = document(CompressionMethod::Deflated);
+
+        // Content types entry is incomplete, so the archive isn't a document
+        let content_types = find(&archive, b"[Content_Types].xml");
+        let files = RepairFiles::new(&archive[..content_types + 25]);
+
+        assert!(!files.repair(None));
+        assert!(files.entries().is_empty());
+    }
+
+    #[test]
+    fn test_corrupted_entry() {
+        let mut archive = document(CompressionMethod::Stored);
+        archive.truncate(central_directory(&archive));
+
+        // Contents no longer match the CRC of the entry
+        let position = find(&archive, b"Hello");
+        archive[position] = b'J';
+
+        let files = RepairFiles::new(&archive);
+
+        assert!(files.repair(None));
+        assert_eq!(files.entries(), expected_entries(1));
+    }
+
+    #[test]
+    fn test_corrupted_signature() {
+        let mut archive = document(CompressionMethod::Deflated);
+        archive[0] = b'X';
+
+        let files = RepairFiles::new(&archive);
+
+        assert!(!files.repair(None));
+        assert!(files.entries().is_empty());
+    }
+
+    #[test]
+    fn test_not_an_archive() {
+        let files = RepairFiles::new(b"%PDF-1.7\nthis is not a zip archive");
+
+        assert!(!files.repair(None));
+        assert!(files.entries().is_empty());
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let archive = document(CompressionMethod::Deflated);
+        let limit = (CONTENT_TYPES.len() + DOCUMENT.len()) as u64;
+        let files = RepairFiles::new(&archive[..central_directory(&archive)]);
+
+        // Entries past the limit are dropped
+        assert!(files.repair(Some(limit)));
+        assert_eq!(files.entries(), expected_entries(2));
+
+        assert!(!files.repair(Some(1)));
+        assert!(files.entries().is_empty());
+    }
+}
@@ -0,0 +1,53 @@
+use anyhow::Context;
+use std::time::Duration;
+
+/// x2t exit codes retried by default, conversion timeouts (0x0053) and
+/// ICU errors (0x005c) are known to fail sporadically
+pub const DEFAULT_RETRY_EXIT_CODES: &[i32] = &[0x0053, 0x005c];
+
+/// Delay between attempts of a failed conversion
+pub const RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// Policy for retrying x2t runs that failed with a transient error
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Maximum number of times x2t is run for a conversion, 1 disables retries
+    pub max_attempts: u32,
+    /// x2t exit codes that are considered transient and can be retried
+    pub exit_codes: Vec<i32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            exit_codes: DEFAULT_RETRY_EXIT_CODES.to_vec(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether a run that failed with the provided exit code should be
+    /// retried after the provided number of attempts
+    pub fn should_retry(&self, attempt: u32, exit_code: Option<i32>) -> bool {
+        attempt < self.max_attempts
+            && exit_code.is_some_and(|exit_code| self.exit_codes.contains(&exit_code))
+    }
+}
+
+/// Parse a comma separated list of exit codes, codes can be provided
+/// in decimal or hex (i.e 0x0053)
+pub fn parse_exit_codes(value: &str) -> anyhow::Result<Vec<i32>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|code| !code.is_empty())
+        .map(|code| {
+            match code.strip_prefix("0x").or_else(|| code.strip_prefix("0X")) {
+                Some(hex) => i32::from_str_radix(hex, 16),
+                None => code.parse(),
+            }
+            .with_context(|| format!("invalid exit code \"{code}\""))
+        })
+        .collect()
+}
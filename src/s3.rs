@@ -0,0 +1,372 @@
+use anyhow::Context;
+use axum::{Extension, Json};
+use futures_util::StreamExt;
+use hmac::{Hmac, Mac};
+use reqwest::{Method, RequestBuilder, Url};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    ErrorKind, ErrorResponse, RuntimeConfig,
+    auth::AdminAccess,
+    convert::{convert_file, create_convert_temp_paths},
+    deadline::RequestDeadline,
+    error_backtrace,
+    limiter::QueueTicket,
+    upload::ConvertFields,
+};
+
+/// Default region used for signing requests
+pub const DEFAULT_S3_REGION: &str = "us-east-1";
+
+/// Payload hash used for requests where the body is not signed, S3 accepts
+/// this for requests made over HTTPS
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+/// Hash of an empty payload
+const EMPTY_PAYLOAD_SHA256: &str =
+    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// Minimal client for S3 compatible object storage, requests are signed using
+/// AWS Signature Version 4 and objects are addressed path style
+/// (`{endpoint}/{bucket}/{key}`) which is supported by AWS and most
+/// S3 compatible stores
+pub struct S3Client {
+    http: reqwest::Client,
+    /// Base URL of the object storage service
+    endpoint: Url,
+    /// Region to sign requests for
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+}
+
+impl S3Client {
+    pub fn new(
+        endpoint: &str,
+        region: String,
+        access_key_id: String,
+        secret_access_key: String,
+    ) -> anyhow::Result<Self> {
+        let endpoint = Url::parse(endpoint).context("invalid s3 endpoint")?;
+        let http = reqwest::Client::builder()
+            .build()
+            .context("failed to create s3 http client")?;
+
+        Ok(Self {
+            http,
+            endpoint,
+            region,
+            access_key_id,
+            secret_access_key,
+        })
+    }
+
+    /// Download an object streaming it to the provided path, returns the
+    /// number of bytes written
+    pub async fn get_object(&self, bucket: &str, key: &str, path: &Path) -> anyhow::Result<u64> {
+        let response = self
+            .request(Method::GET, bucket, key, EMPTY_PAYLOAD_SHA256)?
+            .send()
+            .await
+            .context("failed to request object")?
+            .error_for_status()
+            .context("failed to download object")?;
+
+        let file = tokio::fs::File::create(path)
+            .await
+            .context("failed to create object file")?;
+        let mut writer = BufWriter::new(file);
+        let mut stream = response.bytes_stream();
+        let mut size: u64 = 0;
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.context("failed to read object")?;
+            size += chunk.len() as u64;
+            writer
+                .write_all(&chunk)
+                .await
+                .context("failed to write object file")?;
+        }
+
+        writer
+            .flush()
+            .await
+            .context("failed to write object file")?;
+
+        Ok(size)
+    }
+
+    /// Upload the file at the provided path as an object, the file is
+    /// streamed rather than loaded into memory
+    pub async fn put_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        path: &Path,
+        content_type: &str,
+    ) -> anyhow::Result<u64> {
+        let file = tokio::fs::File::open(path)
+            .await
+            .context("failed to open output file")?;
+        let size = file
+            .metadata()
+            .await
+            .context("failed to read output file metadata")?
+            .len();
+
+        self.request(Method::PUT, bucket, key, UNSIGNED_PAYLOAD)?
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .header(reqwest::header::CONTENT_LENGTH, size)
+            .body(reqwest::Body::wrap_stream(ReaderStream::new(file)))
+            .send()
+            .await
+            .context("failed to request object upload")?
+            .error_for_status()
+            .context("failed to upload object")?;
+
+        Ok(size)
+    }
+
+    /// Create a signed request for an object
+    fn request(
+        &self,
+        method: Method,
+        bucket: &str,
+        key: &str,
+        payload_hash: &str,
+    ) -> anyhow::Result<RequestBuilder> {
+        let base_path = self.endpoint.path().trim_end_matches('/');
+        let path = format!(
+            "{base_path}/{}/{}",
+            uri_encode(bucket, true),
+            uri_encode(key, false)
+        );
+
+        let mut url = self.endpoint.clone();
+        url.set_path(&path);
+
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => host.to_string(),
+            (None, _) => anyhow::bail!("s3 endpoint is missing a host"),
+        };
+
+        let (date, date_time) = amz_dates(SystemTime::now());
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+
+        let canonical_request = format!(
+            "{method}\n{path}\n\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{date_time}\n\n{signed_headers}\n{payload_hash}"
+        );
+
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{date_time}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let signing_key = [self.region.as_bytes(), b"s3", b"aws4_request"]
+            .iter()
+            .fold(
+                hmac_sha256(
+                    format!("AWS4{}", self.secret_access_key).as_bytes(),
+                    date.as_bytes(),
+                ),
+                |key, value| hmac_sha256(&key, value),
+            );
+        let signature = hex::encode(hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            self.access_key_id
+        );
+
+        Ok(self
+            .http
+            .request(method, url)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", date_time)
+            .header(reqwest::header::AUTHORIZATION, authorization))
+    }
+}
+
+/// Request to convert an object from storage
+#[derive(Deserialize)]
+pub struct S3ConvertRequest {
+    /// Bucket containing the source object
+    bucket: String,
+    /// Key of the object to convert
+    source_key: String,
+    /// Key to upload the converted object to
+    destination_key: String,
+    /// Bucket to upload the converted object to, defaults to the source bucket
+    destination_bucket: Option<String>,
+    /// Options for the conversion
+    #[serde(flatten)]
+    fields: ConvertFields,
+}
+
+#[derive(Serialize)]
+pub struct S3ConvertResponse {
+    /// Bucket the converted object was uploaded to
+    bucket: String,
+    /// Key the converted object was uploaded to
+    key: String,
+    /// Size of the converted object in bytes
+    size: u64,
+    /// Content type of the converted object
+    content_type: &'static str,
+    /// Time taken to download, convert and upload in milliseconds
+    duration_ms: u64,
+}
+
+/// POST /convert/s3
+///
+/// Downloads an object from storage, converts it and uploads the result back
+/// to storage, only the metadata of the result is returned
+pub async fn convert_s3(
+    Extension(runtime_config): Extension<Arc<RuntimeConfig>>,
+    Extension(s3): Extension<Arc<S3Client>>,
+    admin_access: AdminAccess,
+    RequestDeadline(deadline): RequestDeadline,
+    queue_ticket: QueueTicket,
+    Json(request): Json<S3ConvertRequest>,
+) -> Result<Json<S3ConvertResponse>, ErrorResponse> {
+    let start = Instant::now();
+    let mut options = request.fields.into_options(runtime_config.default_pdfa)?;
+    admin_access.authorize(&options)?;
+    options.deadline = deadline;
+    let mut temp_paths = create_convert_temp_paths(&runtime_config).await?;
+
+    let result = async {
+        let size = s3
+            .get_object(&request.bucket, &request.source_key, &temp_paths.input_path)
+            .await
+            .map_err(|err| {
+                tracing::error!(?err, "failed to download source object");
+                ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::Internal,
+                    message: "failed to download source object".to_string(),
+                    backtrace: error_backtrace(&*err),
+                }
+            })?;
+
+        tracing::debug!(size, "downloaded object for conversion");
+        temp_paths.scratch().record(size);
+        queue_ticket.set_input_size(size);
+
+        temp_paths
+            .resolve_input_extension(
+                &runtime_config,
+                options.input_format.as_deref(),
+                Some(&request.source_key),
+            )
+            .await?;
+
+        // Wait for a free conversion slot
+        let _permit = queue_ticket
+            .acquire_before(options.priority, options.deadline)
+            .await?;
+
+        convert_file(&runtime_config, &temp_paths, &options).await
+    }
+    .await;
+
+    let output_file = result?;
+    let bucket = request.destination_bucket.unwrap_or(request.bucket);
+    let content_type = options.output_format.content_type();
+
+    let size = s3
+        .put_object(
+            &bucket,
+            &request.destination_key,
+            output_file.path(),
+            content_type,
+        )
+        .await
+        .map_err(|err| {
+            tracing::error!(?err, "failed to upload converted object");
+            ErrorResponse {
+                code: None,
+                kind: ErrorKind::Internal,
+                message: "failed to upload converted object".to_string(),
+                backtrace: error_backtrace(&*err),
+            }
+        })?;
+
+    Ok(Json(S3ConvertResponse {
+        bucket,
+        key: request.destination_key,
+        size,
+        content_type,
+        duration_ms: start.elapsed().as_millis() as u64,
+    }))
+}
+
+fn hmac_sha256(key: &[u8], value: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(value);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// URI encode a value as required by SigV4, `/` is only encoded when `encode_slash` is set
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+
+    encoded
+}
+
+/// Format the date (YYYYMMDD) and date time (YYYYMMDD'T'HHMMSS'Z') used for signing
+fn amz_dates(time: SystemTime) -> (String, String) {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+
+    let days = (seconds / 86_400) as i64;
+    let time_of_day = seconds % 86_400;
+
+    // Convert days since the epoch to a civil date (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    let date = format!("{year:04}{month:02}{day:02}");
+    let date_time = format!(
+        "{date}T{:02}{:02}{:02}Z",
+        time_of_day / 3600,
+        (time_of_day % 3600) / 60,
+        time_of_day % 60
+    );
+
+    (date, date_time)
+}
@@ -0,0 +1,241 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use crate::{ErrorKind, ErrorResponse};
+
+/// Default amount of free disk space in megabytes that must remain in the
+/// temporary directory after writing a file
+pub const DEFAULT_MIN_FREE_SPACE: u64 = 256;
+
+/// Tracks the disk space used by conversions in the temporary directory,
+/// files are rejected before they are written when they would exceed the
+/// quota or leave too little free space on the disk
+pub struct ScratchSpace {
+    /// Temporary directory files are written to
+    path: PathBuf,
+    /// Free space in bytes that must remain after writing a file
+    min_free_space: u64,
+    /// Maximum total bytes used by all conversions, unlimited when None
+    quota: Option<u64>,
+    /// Total bytes currently used by conversions
+    used: AtomicU64,
+}
+
+impl ScratchSpace {
+    pub fn new(path: PathBuf, min_free_space: u64, quota: Option<u64>) -> Self {
+        Self {
+            path,
+            min_free_space,
+            quota,
+            used: AtomicU64::new(0),
+        }
+    }
+
+    /// Check that the quota isn't used up and there is enough free space
+    /// to start a conversion, used before any files are written
+    pub fn preflight(&self) -> Result<(), ErrorResponse> {
+        if self
+            .quota
+            .is_some_and(|quota| self.used.load(Ordering::Acquire) >= quota)
+        {
+            return Err(quota_error());
+        }
+
+        self.check_free_space(0)
+    }
+
+    /// Reserve space for `size` more bytes
+    fn reserve(&self, size: u64) -> Result<(), ErrorResponse> {
+        let reserved = self
+            .used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                let used = used.saturating_add(size);
+                match self.quota {
+                    Some(quota) if used > quota => None,
+                    _ => Some(used),
+                }
+            });
+
+        if reserved.is_err() {
+            return Err(quota_error());
+        }
+
+        if let Err(err) = self.check_free_space(size) {
+            self.release(size);
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    /// Record space used without checking the limits
+    fn record(&self, size: u64) {
+        self.used.fetch_add(size, Ordering::AcqRel);
+    }
+
+    fn release(&self, size: u64) {
+        self.used.fetch_sub(size, Ordering::AcqRel);
+    }
+
+    /// Check that writing `size` more bytes leaves the minimum free space
+    fn check_free_space(&self, size: u64) -> Result<(), ErrorResponse> {
+        let available = match available_space(&self.path) {
+            Ok(Some(available)) => available,
+            // Free space can't be determined on this platform
+            Ok(None) => return Ok(()),
+            Err(err) => {
+                tracing::warn!(?err, "failed to check free disk space");
+                return Ok(());
+            }
+        };
+
+        if available < size.saturating_add(self.min_free_space) {
+            tracing::warn!(
+                available,
+                size,
+                min_free_space = self.min_free_space,
+                "rejected file due to insufficient disk space"
+            );
+
+            return Err(ErrorResponse {
+                code: None,
+                kind: ErrorKind::Unavailable,
+                message: "insufficient disk space for conversion".to_string(),
+                backtrace: None,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+fn quota_error() -> ErrorResponse {
+    ErrorResponse {
+        code: None,
+        kind: ErrorKind::Unavailable,
+        message: "temporary storage quota exceeded, try again later".to_string(),
+        backtrace: None,
+    }
+}
+
+/// Space used by the files of a single temporary directory, the space is
+/// released when dropped
+pub struct ScratchUsage {
+    space: Arc<ScratchSpace>,
+    size: AtomicU64,
+}
+
+impl ScratchUsage {
+    pub fn new(space: Arc<ScratchSpace>) -> Self {
+        Self {
+            space,
+            size: AtomicU64::new(0),
+        }
+    }
+
+    /// Reserve space for a file before writing it, fails when the quota
+    /// would be exceeded or the disk would be left with too little space
+    pub fn reserve(&self, size: u64) -> Result<(), ErrorResponse> {
+        self.space.reserve(size)?;
+        self.size.fetch_add(size, Ordering::AcqRel);
+        Ok(())
+    }
+
+    /// Record space used by a file that has already been written
+    pub fn record(&self, size: u64) {
+        self.space.record(size);
+        self.size.fetch_add(size, Ordering::AcqRel);
+    }
+
+    /// Release space that was reserved but not used
+    pub fn release(&self, size: u64) {
+        let size = self
+            .size
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                Some(used.saturating_sub(size))
+            })
+            .map(|used| used.min(size))
+            .unwrap_or_default();
+
+        self.space.release(size);
+    }
+}
+
+impl Drop for ScratchUsage {
+    fn drop(&mut self) {
+        self.space.release(*self.size.get_mut());
+    }
+}
+
+/// Space is reserved in steps of this many bytes while a file is written so
+/// that the free space isn't checked for every chunk
+const RESERVE_STEP: u64 = 1024 * 1024;
+
+/// Reserves space for a file as it is being written
+pub struct WriteReservation<'a> {
+    usage: &'a ScratchUsage,
+    /// Bytes reserved for the file
+    reserved: u64,
+    /// Bytes written to the file
+    written: u64,
+}
+
+impl<'a> WriteReservation<'a> {
+    pub fn new(usage: &'a ScratchUsage) -> Self {
+        Self {
+            usage,
+            reserved: 0,
+            written: 0,
+        }
+    }
+
+    /// Reserve space for a chunk before it is written
+    pub fn write(&mut self, length: u64) -> Result<(), ErrorResponse> {
+        self.written += length;
+
+        if self.written > self.reserved {
+            let size = (self.written - self.reserved).max(RESERVE_STEP);
+            self.usage.reserve(size)?;
+            self.reserved += size;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for WriteReservation<'_> {
+    fn drop(&mut self) {
+        self.usage
+            .release(self.reserved.saturating_sub(self.written));
+    }
+}
+
+/// Free space in bytes available to unprivileged users on the filesystem
+/// containing `path`, None when not supported on this platform
+#[cfg(unix)]
+fn available_space(path: &Path) -> std::io::Result<Option<u64>> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+
+    // Safety: statvfs only writes to the provided stat buffer
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    Ok(Some(
+        (stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64),
+    ))
+}
+
+#[cfg(not(unix))]
+fn available_space(_path: &Path) -> std::io::Result<Option<u64>> {
+    Ok(None)
+}
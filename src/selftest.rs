@@ -0,0 +1,240 @@
+use axum::{Extension, Json, http::StatusCode};
+use serde::Serialize;
+use std::{sync::Arc, time::Instant};
+
+use crate::{
+    RuntimeConfig,
+    convert::{ConvertTempPaths, OutputFile, convert_file, create_convert_temp_paths},
+    limiter::{Priority, QueueTicket},
+    readiness::{check_fonts, check_x2t},
+    upload::ConvertFields,
+};
+
+/// Small DOCX document converted by the self-test
+const SAMPLE_DOCUMENT: &[u8] = include_bytes!("../assets/selftest.docx");
+
+/// Result of a single stage of the self-test
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StageStatus {
+    Passed,
+    Failed,
+    /// Stage wasn't run because an earlier stage failed
+    Skipped,
+}
+
+#[derive(Serialize)]
+pub struct SelfTestStage {
+    /// Name of the stage
+    name: &'static str,
+    /// Outcome of the stage
+    status: StageStatus,
+    /// Time taken to run the stage in milliseconds
+    duration_ms: u64,
+    /// Details about the outcome of the stage
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SelfTestReport {
+    /// Whether every stage passed
+    pub passed: bool,
+    /// Results of the individual stages, in the order they were run
+    pub stages: Vec<SelfTestStage>,
+}
+
+impl SelfTestReport {
+    /// Message of the first stage that failed
+    pub fn failure(&self) -> Option<String> {
+        self.stages
+            .iter()
+            .find(|stage| stage.status == StageStatus::Failed)
+            .map(|stage| {
+                format!(
+                    "{}: {}",
+                    stage.name,
+                    stage.message.as_deref().unwrap_or("failed")
+                )
+            })
+    }
+}
+
+/// Records the stages of the self-test, stages after a failed stage are skipped
+struct StageRecorder {
+    stages: Vec<SelfTestStage>,
+    failed: bool,
+}
+
+impl StageRecorder {
+    /// Run a stage, returning its value when it succeeds
+    async fn run<T, F>(&mut self, name: &'static str, stage: F) -> Option<T>
+    where
+        F: Future<Output = Result<(T, Option<String>), String>>,
+    {
+        if self.failed {
+            self.stages.push(SelfTestStage {
+                name,
+                status: StageStatus::Skipped,
+                duration_ms: 0,
+                message: None,
+            });
+            return None;
+        }
+
+        let start = Instant::now();
+        let result = stage.await;
+        let duration_ms = start.elapsed().as_millis() as u64;
+
+        let (status, message, value) = match result {
+            Ok((value, message)) => (StageStatus::Passed, message, Some(value)),
+            Err(message) => {
+                self.failed = true;
+                (StageStatus::Failed, Some(message), None)
+            }
+        };
+
+        self.stages.push(SelfTestStage {
+            name,
+            status,
+            duration_ms,
+            message,
+        });
+
+        value
+    }
+}
+
+/// Convert the embedded sample document to PDF, verifying each stage of
+/// the conversion pipeline
+pub async fn run_self_test(runtime_config: &RuntimeConfig) -> SelfTestReport {
+    let mut recorder = StageRecorder {
+        stages: Vec::new(),
+        failed: false,
+    };
+
+    recorder
+        .run("x2t", async {
+            check_x2t(&runtime_config.x2t_path)?;
+            Ok(((), Some(runtime_config.x2t_path.display().to_string())))
+        })
+        .await;
+
+    recorder
+        .run("fonts", async {
+            check_fonts(&runtime_config.fonts_path)?;
+            Ok(((), Some(runtime_config.fonts_path.display().to_string())))
+        })
+        .await;
+
+    let temp_paths = recorder
+        .run("prepare", prepare_sample(runtime_config))
+        .await;
+
+    let output_file = recorder
+        .run("convert", async {
+            let Some(temp_paths) = &temp_paths else {
+                return Err("sample document was not prepared".to_string());
+            };
+
+            convert_sample(runtime_config, temp_paths).await
+        })
+        .await;
+
+    recorder
+        .run("verify", async {
+            let Some(output_file) = &output_file else {
+                return Err("sample document was not converted".to_string());
+            };
+
+            verify_output(output_file).await
+        })
+        .await;
+
+    SelfTestReport {
+        passed: !recorder.failed,
+        stages: recorder.stages,
+    }
+}
+
+/// Write the sample document to a temporary input file
+async fn prepare_sample(
+    runtime_config: &RuntimeConfig,
+) -> Result<(ConvertTempPaths, Option<String>), String> {
+    let mut temp_paths = create_convert_temp_paths(runtime_config)
+        .await
+        .map_err(|err| err.message)?;
+
+    tokio::fs::write(&temp_paths.input_path, SAMPLE_DOCUMENT)
+        .await
+        .map_err(|err| format!("failed to write sample document: {err}"))?;
+
+    temp_paths
+        .resolve_input_extension(runtime_config, Some("docx"), None)
+        .await
+        .map_err(|err| err.message)?;
+
+    Ok((temp_paths, None))
+}
+
+/// Convert the sample document to PDF
+async fn convert_sample(
+    runtime_config: &RuntimeConfig,
+    temp_paths: &ConvertTempPaths,
+) -> Result<(OutputFile, Option<String>), String> {
+    let options = ConvertFields::default()
+        .into_options(false)
+        .map_err(|err| err.message)?;
+
+    let output_file = convert_file(runtime_config, temp_paths, &options)
+        .await
+        .map_err(|err| match err.code {
+            Some(code) => format!("{} (x2t error code {code:#06x})", err.message),
+            None => err.message,
+        })?;
+
+    Ok((output_file, None))
+}
+
+/// Check the converted sample is a complete PDF document
+async fn verify_output(output_file: &OutputFile) -> Result<((), Option<String>), String> {
+    let output = tokio::fs::read(output_file.path())
+        .await
+        .map_err(|err| format!("failed to read converted document: {err}"))?;
+
+    if !output.starts_with(b"%PDF-") {
+        return Err("converted document is not a PDF".to_string());
+    }
+
+    // The end of file marker may be followed by trailing whitespace
+    let tail = &output[output.len().saturating_sub(1024)..];
+    if !tail.windows(5).any(|window| window == b"%%EOF") {
+        return Err("converted PDF is truncated".to_string());
+    }
+
+    Ok(((), Some(format!("{} bytes", output.len()))))
+}
+
+/// POST /selftest
+///
+/// Converts an embedded sample document and reports the result of each
+/// stage, responds with 500 when any stage fails
+pub async fn self_test(
+    Extension(runtime_config): Extension<Arc<RuntimeConfig>>,
+    queue_ticket: QueueTicket,
+) -> (StatusCode, Json<SelfTestReport>) {
+    queue_ticket.set_input_size(SAMPLE_DOCUMENT.len() as u64);
+
+    // Wait for a free conversion slot
+    let _permit = queue_ticket.acquire(Priority::default()).await;
+
+    let report = run_self_test(&runtime_config).await;
+
+    let status = if report.passed {
+        StatusCode::OK
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+
+    (status, Json(report))
+}
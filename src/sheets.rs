@@ -0,0 +1,278 @@
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    ErrorKind, ErrorResponse, RuntimeConfig,
+    batch::{add_archive_file, archive_error, create_archive},
+    convert::{ConvertOptions, ConvertStep, ConvertTempPaths, run_step},
+    error_backtrace,
+    format::OutputFormat,
+};
+
+/// Input formats that can be split into a PDF per sheet
+const SPREADSHEET_FORMATS: &[&str] = &[
+    "xls", "xlsx", "xlsm", "xlsb", "xlt", "xltx", "xltm", "ods", "ots", "fods", "csv", "et", "ett",
+    "numbers",
+];
+
+/// Path to the workbook part within an XLSX file
+const WORKBOOK_PART: &str = "xl/workbook.xml";
+
+/// Sheet of a workbook
+struct Sheet {
+    /// Index of the sheet within the workbook
+    index: u32,
+    /// Name of the sheet
+    name: String,
+}
+
+/// Convert each visible sheet of a spreadsheet to its own PDF, the PDFs are
+/// written to a ZIP archive at the output path. Inputs that aren't XLSX (or
+/// are password protected) are converted to XLSX first so that the sheets
+/// can be read from the workbook
+///
+/// ## Arguments
+/// * `runtime_config` - Runtime configuration
+/// * `temp_paths` - Temporary paths for the conversion
+/// * `options` - Options for the conversion
+/// * `input_path` - Path to the spreadsheet to convert
+/// * `output_path` - Path to write the ZIP archive to
+pub async fn convert_sheets(
+    runtime_config: &RuntimeConfig,
+    temp_paths: &ConvertTempPaths,
+    options: &ConvertOptions,
+    input_path: &Path,
+    output_path: &Path,
+) -> Result<(), ErrorResponse> {
+    let input_format = input_path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
+
+    if !SPREADSHEET_FORMATS.contains(&input_format) {
+        return Err(ErrorResponse {
+            code: None,
+            kind: ErrorKind::UnsupportedFormat,
+            message: "only spreadsheets can be split into a PDF per sheet".to_string(),
+            backtrace: None,
+        });
+    }
+
+    let needs_workbook = input_format != "xlsx" || options.password.is_some();
+    let workbook_path = if needs_workbook {
+        let workbook_path = temp_paths.file_path("workbook.xlsx");
+        let step = ConvertStep {
+            input_path,
+            output_path: &workbook_path,
+            output_format: OutputFormat::Xlsx,
+            is_first: true,
+            is_last: false,
+            sheet: None,
+        };
+        run_step(runtime_config, temp_paths, options, &step).await?;
+        workbook_path
+    } else {
+        input_path.to_path_buf()
+    };
+
+    let sheets = {
+        let workbook_path = workbook_path.clone();
+        tokio::task::spawn_blocking(move || read_sheets(&workbook_path))
+            .await
+            .map_err(std::io::Error::other)
+            .and_then(|result| result)
+            .map_err(|err| {
+                tracing::warn!(?err, "failed to read workbook sheets");
+                ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::Corrupted,
+                    message: "failed to read the sheets of the workbook".to_string(),
+                    backtrace: error_backtrace(&err),
+                }
+            })?
+    };
+
+    if sheets.is_empty() {
+        return Err(ErrorResponse {
+            code: None,
+            kind: ErrorKind::ConversionFailed,
+            message: "workbook has no visible sheets".to_string(),
+            backtrace: None,
+        });
+    }
+
+    let mut sheet_outputs: Vec<(String, PathBuf)> = Vec::with_capacity(sheets.len());
+
+    for (position, sheet) in sheets.iter().enumerate() {
+        let sheet_path = temp_paths.file_path(&format!("sheet_{}.pdf", sheet.index));
+        let step = ConvertStep {
+            input_path: &workbook_path,
+            output_path: &sheet_path,
+            output_format: OutputFormat::Pdf,
+            is_first: !needs_workbook,
+            is_last: true,
+            sheet: Some(sheet.index),
+        };
+        run_step(runtime_config, temp_paths, options, &step).await?;
+
+        let size = tokio::fs::metadata(&sheet_path)
+            .await
+            .map(|metadata| metadata.len())
+            .unwrap_or_default();
+        temp_paths.scratch().record(size);
+
+        let entry_name = format!("{}_{}.pdf", position + 1, sanitize_sheet_name(&sheet.name));
+        sheet_outputs.push((entry_name, sheet_path));
+    }
+
+    let output_path = output_path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut archive = create_archive(&output_path)?;
+        for (entry_name, path) in &sheet_outputs {
+            add_archive_file(&mut archive, entry_name, path)?;
+        }
+        archive.finish()?;
+        Ok(())
+    })
+    .await
+    .map_err(std::io::Error::other)
+    .and_then(|result: std::io::Result<()>| result)
+    .map_err(archive_error)
+}
+
+/// Read the visible sheets from the workbook part of an XLSX file, hidden
+/// and very hidden sheets are skipped
+fn read_sheets(path: &Path) -> std::io::Result<Vec<Sheet>> {
+    let file = std::fs::File::open(path)?;
+    let mut archive = zip::ZipArchive::new(file).map_err(std::io::Error::other)?;
+
+    let mut workbook = String::new();
+    archive
+        .by_name(WORKBOOK_PART)
+        .map_err(std::io::Error::other)?
+        .read_to_string(&mut workbook)?;
+
+    let mut sheets = Vec::new();
+    let mut index = 0;
+
+    for tag in workbook.split('<').skip(1) {
+        let Some((tag, _)) = tag.split_once('>') else {
+            continue;
+        };
+
+        // Element names may be prefixed with a namespace (i.e "x:sheet")
+        let element = tag.split_whitespace().next().unwrap_or_default();
+        let local_name = element.rsplit(':').next().unwrap_or(element);
+        if local_name != "sheet" {
+            continue;
+        }
+
+        let state = xml_attribute(tag, "state");
+        if !matches!(state.as_deref(), Some("hidden" | "veryHidden")) {
+            sheets.push(Sheet {
+                index,
+                name: xml_attribute(tag, "name").unwrap_or_default(),
+            });
+        }
+
+        index += 1;
+    }
+
+    Ok(sheets)
+}
+
+/// Find the unescaped value of an attribute within the contents of an XML tag
+fn xml_attribute(tag: &str, name: &str) -> Option<String> {
+    let mut rest = tag;
+
+    while let Some(start) = rest.find(name) {
+        let preceded_by_space = rest[..start]
+            .chars()
+            .next_back()
+            .is_some_and(char::is_whitespace);
+        let after = rest[start + name.len()..].trim_start();
+        rest = &rest[start + name.len()..];
+
+        let Some(after) = after.strip_prefix('=') else {
+            continue;
+        };
+        if !preceded_by_space {
+            continue;
+        }
+
+        let after = after.trim_start();
+        let quote = after
+            .chars()
+            .next()
+            .filter(|quote| matches!(quote, '"' | '\''))?;
+        let value = &after[1..];
+        let end = value.find(quote)?;
+        return Some(unescape_xml(&value[..end]));
+    }
+
+    None
+}
+
+/// Replace the predefined XML entities and character references in a value
+fn unescape_xml(value: &str) -> String {
+    let mut output = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find('&') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+
+        let entity = &rest[1..end];
+        let character = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .and_then(|code| u32::from_str_radix(code, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|code| code.parse().ok()))
+                .and_then(char::from_u32),
+        };
+
+        match character {
+            Some(character) => {
+                output.push(character);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                output.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// Make a sheet name safe to use as an archive entry name
+fn sanitize_sheet_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|character| match character {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            character if character.is_control() => '_',
+            character => character,
+        })
+        .collect();
+
+    let name = name.trim();
+    if name.is_empty() {
+        "sheet".to_string()
+    } else {
+        name.to_string()
+    }
+}
@@ -0,0 +1,89 @@
+use serde_json::{Map, Value, json};
+
+use crate::{ErrorKind, ErrorResponse};
+
+/// Page layout options used when converting spreadsheets to paged formats (i.e PDF)
+#[derive(Debug, Default)]
+pub struct SpreadsheetLayout {
+    /// Number of pages wide to fit each sheet to (0 for automatic)
+    pub fit_to_width: Option<u32>,
+    /// Number of pages tall to fit each sheet to (0 for automatic)
+    pub fit_to_height: Option<u32>,
+    /// Whether the print areas defined in the spreadsheet should be ignored
+    pub ignore_print_area: Option<bool>,
+    /// Zero based indexes of the sheets to convert, all sheets when not provided
+    pub sheets: Option<Vec<u32>>,
+}
+
+impl SpreadsheetLayout {
+    /// Create the layout from the requested parameters
+    ///
+    /// ## Arguments
+    /// * `fit_to_width` - Number of pages wide to fit sheets to
+    /// * `fit_to_height` - Number of pages tall to fit sheets to
+    /// * `ignore_print_area` - Whether to ignore defined print areas
+    /// * `sheets` - Comma separated list of zero based sheet indexes
+    pub fn from_params(
+        fit_to_width: Option<u32>,
+        fit_to_height: Option<u32>,
+        ignore_print_area: Option<bool>,
+        sheets: Option<String>,
+    ) -> Result<SpreadsheetLayout, ErrorResponse> {
+        let sheets = match sheets {
+            Some(value) => Some(
+                value
+                    .split(',')
+                    .map(|index| index.trim().parse::<u32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| ErrorResponse {
+                        code: None,
+                        kind: ErrorKind::InvalidRequest,
+                        message: format!("invalid sheets \"{value}\", expected sheet indexes"),
+                        backtrace: None,
+                    })?,
+            ),
+            None => None,
+        };
+
+        Ok(SpreadsheetLayout {
+            fit_to_width,
+            fit_to_height,
+            ignore_print_area,
+            sheets,
+        })
+    }
+
+    /// Create the x2t "spreadsheetLayout" JSON param for these options, uses
+    /// the same structure as the DocumentServer conversion API, returns None
+    /// when no options were provided
+    ///
+    /// ## Arguments
+    /// * `sheet` - Only convert the sheet at this index, replaces the requested sheets
+    pub fn json_param(&self, sheet: Option<u32>) -> Option<Value> {
+        let mut layout = Map::new();
+
+        if let Some(fit_to_width) = self.fit_to_width {
+            layout.insert("fitToWidth".to_string(), json!(fit_to_width));
+        }
+
+        if let Some(fit_to_height) = self.fit_to_height {
+            layout.insert("fitToHeight".to_string(), json!(fit_to_height));
+        }
+
+        if let Some(ignore_print_area) = self.ignore_print_area {
+            layout.insert("ignorePrintArea".to_string(), json!(ignore_print_area));
+        }
+
+        if let Some(sheet) = sheet {
+            layout.insert("sheetsIndexes".to_string(), json!([sheet]));
+        } else if let Some(sheets) = &self.sheets {
+            layout.insert("sheetsIndexes".to_string(), json!(sheets));
+        }
+
+        if layout.is_empty() {
+            return None;
+        }
+
+        Some(Value::Object(layout))
+    }
+}
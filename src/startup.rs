@@ -0,0 +1,150 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use crate::readiness::{check_fonts, check_x2t};
+
+/// Name of the file written to check the temporary directory is writable
+const TEMP_PROBE_FILE_NAME: &str = ".startup-probe";
+
+/// Startup check that found a problem, each check exits with its own code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupCheck {
+    /// x2t install could not be found or isn't executable (exit code 3)
+    X2t,
+    /// Fonts directory doesn't exist (exit code 4)
+    Fonts,
+    /// Temporary directory can't be written to (exit code 5)
+    TempDir,
+    /// Server address can't be bound (exit code 6)
+    Address,
+}
+
+impl StartupCheck {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            StartupCheck::X2t => 3,
+            StartupCheck::Fonts => 4,
+            StartupCheck::TempDir => 5,
+            StartupCheck::Address => 6,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct StartupProblem {
+    /// Check that found the problem
+    pub check: StartupCheck,
+    /// Description of the problem
+    pub message: String,
+}
+
+/// Error for when the startup validation found problems, the server exits
+/// with the code of the first problem found
+#[derive(Debug)]
+pub struct StartupError {
+    pub problems: Vec<StartupProblem>,
+}
+
+impl StartupError {
+    pub fn exit_code(&self) -> ExitCode {
+        self.problems
+            .first()
+            .map(|problem| ExitCode::from(problem.check.exit_code()))
+            .unwrap_or(ExitCode::FAILURE)
+    }
+}
+
+impl fmt::Display for StartupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "startup validation failed with {} problem(s)",
+            self.problems.len()
+        )
+    }
+}
+
+impl std::error::Error for StartupError {}
+
+/// Configuration validated before the server starts
+pub struct StartupConfig<'a> {
+    /// Path to the x2t install, None when no install could be found
+    pub x2t_path: Option<&'a Path>,
+    /// Path to the fonts directory
+    pub fonts_path: &'a Path,
+    /// Path to the temporary directory conversions are performed in
+    pub temp_path: &'a Path,
+    /// Addresses the server will bind to
+    pub addresses: Vec<&'a str>,
+}
+
+/// Validate the configuration, every check is run so that all the problems
+/// can be reported together
+pub fn validate_startup(config: &StartupConfig<'_>) -> Result<(), StartupError> {
+    let mut problems = Vec::new();
+
+    let x2t_result = match config.x2t_path {
+        Some(x2t_path) => check_x2t(x2t_path),
+        None => {
+            Err("no x2t install found, provide its path with --x2t-path or X2T_PATH".to_string())
+        }
+    };
+
+    let results = [
+        (StartupCheck::X2t, x2t_result),
+        (StartupCheck::Fonts, check_fonts(config.fonts_path)),
+        (StartupCheck::TempDir, check_temp_dir(config.temp_path)),
+    ];
+
+    let address_results = config
+        .addresses
+        .iter()
+        .map(|address| (StartupCheck::Address, check_address(address)));
+
+    for (check, result) in results.into_iter().chain(address_results) {
+        if let Err(message) = result {
+            problems.push(StartupProblem { check, message });
+        }
+    }
+
+    if !problems.is_empty() {
+        for problem in &problems {
+            tracing::error!(
+                check = ?problem.check,
+                exit_code = problem.check.exit_code(),
+                "{}",
+                problem.message
+            );
+        }
+
+        return Err(StartupError { problems });
+    }
+
+    Ok(())
+}
+
+/// Check the temporary directory can be created and written to
+pub fn check_temp_dir(temp_path: &Path) -> Result<(), String> {
+    let probe_path: PathBuf = temp_path.join(TEMP_PROBE_FILE_NAME);
+
+    std::fs::create_dir_all(temp_path)
+        .and_then(|_| std::fs::write(&probe_path, []))
+        .and_then(|_| std::fs::remove_file(&probe_path))
+        .map_err(|err| {
+            format!(
+                "temporary directory {} is not writable: {err}",
+                temp_path.display()
+            )
+        })
+}
+
+/// Check the address can be bound, the listener is closed immediately so
+/// the server can bind it afterwards
+fn check_address(address: &str) -> Result<(), String> {
+    std::net::TcpListener::bind(address)
+        .map(|_| ())
+        .map_err(|err| format!("cannot bind to {address}: {err}"))
+}
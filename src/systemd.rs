@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+/// Notify systemd that the server has started and is accepting connections,
+/// notifications are ignored when the server isn't managed by systemd
+pub fn notify_ready() {
+    notify("READY=1");
+}
+
+/// Notify systemd that the server is shutting down
+pub fn notify_stopping() {
+    notify("STOPPING=1");
+}
+
+/// Send a watchdog keep-alive ping every half of the watchdog interval
+/// requested by systemd, returns immediately if the watchdog is disabled
+pub async fn run_watchdog() {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+
+    tracing::debug!(?interval, "systemd watchdog enabled");
+
+    let mut ticker = tokio::time::interval(interval / 2);
+
+    loop {
+        ticker.tick().await;
+        notify("WATCHDOG=1");
+    }
+}
+
+/// Interval systemd expects watchdog pings within
+fn watchdog_interval() -> Option<Duration> {
+    if !is_current_pid("WATCHDOG_PID") {
+        return None;
+    }
+
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Check that an optional systemd PID variable refers to this process,
+/// variables inherited from a parent process must be ignored
+fn is_current_pid(name: &str) -> bool {
+    match std::env::var(name) {
+        Ok(pid) => pid
+            .parse::<u32>()
+            .is_ok_and(|pid| pid == std::process::id()),
+        Err(_) => true,
+    }
+}
+
+/// Send a state notification to the systemd notification socket
+#[cfg(unix)]
+fn notify(state: &str) {
+    use std::os::unix::net::UnixDatagram;
+
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    let result = UnixDatagram::unbound().and_then(|socket| {
+        // Names starting with @ refer to sockets in the abstract namespace
+        #[cfg(target_os = "linux")]
+        if let Some(name) = path.as_encoded_bytes().strip_prefix(b"@") {
+            use std::os::linux::net::SocketAddrExt;
+
+            let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            return socket.send_to_addr(state.as_bytes(), &address);
+        }
+
+        socket.send_to(state.as_bytes(), &path)
+    });
+
+    if let Err(err) = result {
+        tracing::warn!(?err, state, "failed to send systemd notification");
+    }
+}
+
+#[cfg(not(unix))]
+fn notify(_state: &str) {}
+
+/// Take the TCP listener passed by systemd socket activation, returns None
+/// when the server wasn't socket activated. Only the first passed socket is
+/// used
+#[cfg(unix)]
+pub fn activated_listener() -> anyhow::Result<Option<std::net::TcpListener>> {
+    use anyhow::Context;
+    use std::os::fd::{FromRawFd, RawFd};
+
+    /// First file descriptor passed by systemd
+    const LISTEN_FDS_START: RawFd = 3;
+
+    let Ok(listen_pid) = std::env::var("LISTEN_PID") else {
+        return Ok(None);
+    };
+
+    if !listen_pid
+        .parse::<u32>()
+        .is_ok_and(|pid| pid == std::process::id())
+    {
+        return Ok(None);
+    }
+
+    let listen_fds: usize = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0);
+
+    if listen_fds == 0 {
+        return Ok(None);
+    }
+
+    if listen_fds > 1 {
+        tracing::warn!("systemd passed {listen_fds} sockets, only the first is used");
+    }
+
+    // Safety: fstat only writes to the provided stat buffer
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(LISTEN_FDS_START, &mut stat) } != 0
+        || stat.st_mode & libc::S_IFMT != libc::S_IFSOCK
+    {
+        anyhow::bail!("socket passed by systemd is not a valid socket");
+    }
+
+    // Passed sockets are inherited without close-on-exec, x2t processes
+    // must not inherit the listener
+    // Safety: only updates the flags of the file descriptor
+    if unsafe { libc::fcntl(LISTEN_FDS_START, libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("failed to update socket flags");
+    }
+
+    // Safety: systemd passes ownership of the socket to this process and it
+    // is only taken once
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(LISTEN_FDS_START) };
+    listener.set_nonblocking(true)?;
+
+    Ok(Some(listener))
+}
+
+#[cfg(not(unix))]
+pub fn activated_listener() -> anyhow::Result<Option<std::net::TcpListener>> {
+    Ok(None)
+}
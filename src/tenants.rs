@@ -0,0 +1,291 @@
+use anyhow::Context;
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    ErrorKind, ErrorResponse, RuntimeConfig, input_filter::InputFormatFilter,
+    input_limits::InputLimits,
+};
+
+/// Header containing the API key of the tenant making the request
+const TENANT_API_KEY_HEADER: &str = "x-api-key";
+
+/// Header containing the name of the tenant making the request, only used
+/// for tenants without an API key (i.e when a gateway identifies tenants)
+const TENANT_HEADER: &str = "x-tenant";
+
+/// Tenants configuration file
+#[derive(Debug, Deserialize)]
+pub struct TenantsFile {
+    /// Whether requests must identify a tenant, requests that don't are
+    /// converted with the default configuration otherwise
+    #[serde(default)]
+    pub require_tenant: bool,
+    pub tenants: Vec<TenantConfig>,
+}
+
+/// Configuration of a single tenant, settings that aren't provided use the
+/// server configuration
+#[derive(Debug, Deserialize)]
+pub struct TenantConfig {
+    /// Unique name of the tenant, used for its temporary directory
+    pub name: String,
+    /// API key that selects the tenant, tenants without a key are selected
+    /// by name using the X-Tenant header
+    pub api_key: Option<String>,
+    /// Comma separated list of input formats to accept
+    pub allowed_input_formats: Option<String>,
+    /// Comma separated list of input formats to reject
+    pub denied_input_formats: Option<String>,
+    /// Directory containing the fonts used for the tenant's conversions
+    pub fonts_path: Option<PathBuf>,
+    /// Maximum total uncompressed size in megabytes of ZIP based inputs
+    pub max_uncompressed_size: Option<u64>,
+    /// Maximum memory in megabytes each x2t process can use
+    pub x2t_max_memory: Option<u64>,
+    /// Maximum CPU time in seconds each x2t process can use
+    pub x2t_max_cpu_time: Option<u64>,
+}
+
+impl TenantsFile {
+    /// Load and validate the tenants from a JSON file
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read tenants file {}", path.display()))?;
+        let file: TenantsFile = serde_json::from_str(&contents)
+            .with_context(|| format!("invalid tenants file {}", path.display()))?;
+
+        let mut names = HashSet::new();
+        let mut api_keys = HashSet::new();
+
+        for tenant in &file.tenants {
+            if tenant.name.is_empty()
+                || !tenant
+                    .name
+                    .chars()
+                    .all(|value| value.is_ascii_alphanumeric() || matches!(value, '-' | '_'))
+            {
+                anyhow::bail!(
+                    "invalid tenant name \"{}\", names must be alphanumeric",
+                    tenant.name
+                );
+            }
+
+            if !names.insert(tenant.name.as_str()) {
+                anyhow::bail!("duplicate tenant name \"{}\"", tenant.name);
+            }
+
+            if let Some(api_key) = &tenant.api_key
+                && !api_keys.insert(api_key.as_str())
+            {
+                anyhow::bail!("tenant \"{}\" reuses another tenant's api key", tenant.name);
+            }
+        }
+
+        Ok(file)
+    }
+
+    /// Temporary directories of the tenants within the server temporary directory
+    pub fn temp_paths(&self, temp_path: &Path) -> Vec<PathBuf> {
+        self.tenants
+            .iter()
+            .map(|tenant| tenant_temp_path(temp_path, &tenant.name))
+            .collect()
+    }
+}
+
+fn tenant_temp_path(temp_path: &Path, name: &str) -> PathBuf {
+    temp_path.join(format!("tenant_{name}"))
+}
+
+/// Tenant with its own runtime configuration
+pub struct Tenant {
+    pub name: String,
+    pub runtime_config: Arc<RuntimeConfig>,
+}
+
+/// Tenants requests can be made on behalf of
+pub struct Tenants {
+    require_tenant: bool,
+    by_api_key: HashMap<String, Arc<Tenant>>,
+    /// Tenants without an API key by name
+    by_name: HashMap<String, Arc<Tenant>>,
+}
+
+impl Tenants {
+    /// Create the tenants from the configuration file, the runtime
+    /// configuration of each tenant is derived from the server configuration
+    pub fn new(file: TenantsFile, base: &RuntimeConfig) -> anyhow::Result<Self> {
+        let mut by_api_key = HashMap::new();
+        let mut by_name = HashMap::new();
+
+        for config in file.tenants {
+            let runtime_config = tenant_runtime_config(&config, base)
+                .with_context(|| format!("invalid configuration for tenant \"{}\"", config.name))?;
+
+            tracing::debug!(
+                tenant = config.name,
+                temp_path = %runtime_config.temp_path.display(),
+                "configured tenant"
+            );
+
+            let tenant = Arc::new(Tenant {
+                name: config.name,
+                runtime_config: Arc::new(runtime_config),
+            });
+
+            match config.api_key {
+                Some(api_key) => by_api_key.insert(api_key, tenant),
+                None => by_name.insert(tenant.name.clone(), tenant),
+            };
+        }
+
+        Ok(Self {
+            require_tenant: file.require_tenant,
+            by_api_key,
+            by_name,
+        })
+    }
+
+    /// Find the tenant identified by the request headers, returns Ok(None)
+    /// when the request doesn't identify a tenant
+    fn select(&self, request: &Request) -> Result<Option<&Arc<Tenant>>, ErrorResponse> {
+        let header = |name: &str| {
+            request
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+        };
+
+        if let Some(api_key) = header(TENANT_API_KEY_HEADER) {
+            return self
+                .by_api_key
+                .get(api_key)
+                .map(Some)
+                .ok_or_else(|| tenant_error("invalid api key"));
+        }
+
+        if let Some(name) = header(TENANT_HEADER) {
+            return self
+                .by_name
+                .get(name)
+                .map(Some)
+                .ok_or_else(|| tenant_error("unknown tenant"));
+        }
+
+        if self.require_tenant {
+            return Err(tenant_error("missing api key"));
+        }
+
+        Ok(None)
+    }
+}
+
+fn tenant_error(message: &str) -> ErrorResponse {
+    ErrorResponse {
+        code: None,
+        kind: ErrorKind::Unauthorized,
+        message: message.to_string(),
+        backtrace: None,
+    }
+}
+
+/// Derive the runtime configuration of a tenant from the server configuration
+fn tenant_runtime_config(
+    config: &TenantConfig,
+    base: &RuntimeConfig,
+) -> anyhow::Result<RuntimeConfig> {
+    let input_filter = match (&config.allowed_input_formats, &config.denied_input_formats) {
+        (None, None) => base.input_filter.clone(),
+        (allowed, denied) => InputFormatFilter::from_lists(allowed.as_deref(), denied.as_deref())?,
+    };
+
+    let input_limits = match config.max_uncompressed_size {
+        Some(0) => InputLimits {
+            max_uncompressed_size: None,
+        },
+        Some(value) => InputLimits {
+            max_uncompressed_size: Some(value.saturating_mul(1024 * 1024)),
+        },
+        None => base.input_limits,
+    };
+
+    let mut x2t_limits = *base.x2t_limits.read().expect("x2t limits lock poisoned");
+    if let Some(value) = config.x2t_max_memory {
+        x2t_limits.max_memory = Some(value.saturating_mul(1024 * 1024));
+    }
+    if let Some(value) = config.x2t_max_cpu_time {
+        x2t_limits.max_cpu_time = Some(value);
+    }
+
+    if !x2t_limits.is_empty() && cfg!(not(unix)) {
+        anyhow::bail!("x2t resource limits are not supported on this platform");
+    }
+
+    let fonts_path = match &config.fonts_path {
+        Some(fonts_path) => {
+            std::path::absolute(fonts_path).context("failed to resolve fonts path")?
+        }
+        None => base.fonts_path.clone(),
+    };
+
+    let x2t_retry_policy = base
+        .x2t_retry_policy
+        .read()
+        .expect("x2t retry policy lock poisoned")
+        .clone();
+
+    Ok(RuntimeConfig {
+        tenant: Some(config.name.clone()),
+        temp_path: tenant_temp_path(&base.temp_path, &config.name),
+        x2t_path: base.x2t_path.clone(),
+        fonts_path,
+        x2t_limits: RwLock::new(x2t_limits),
+        x2t_timeout: RwLock::new(*base.x2t_timeout.read().expect("x2t timeout lock poisoned")),
+        x2t_retry_policy: RwLock::new(x2t_retry_policy),
+        default_pdfa: base.default_pdfa,
+        input_filter,
+        input_limits,
+        virus_scanner: base.virus_scanner.clone(),
+        libreoffice: base.libreoffice.clone(),
+        repair_corrupted_inputs: base.repair_corrupted_inputs,
+        poison_documents: base.poison_documents.clone(),
+        crash_reports: base.crash_reports.clone(),
+        docbuilder: base.docbuilder.clone(),
+        scratch_space: base.scratch_space.clone(),
+        slow_conversion_threshold: base.slow_conversion_threshold,
+        started_at: base.started_at,
+        metrics: base.metrics.clone(),
+        cancel_conversions: base.cancel_conversions.clone(),
+    })
+}
+
+/// Middleware selecting the tenant of a request, the runtime configuration
+/// of the tenant replaces the server configuration for the request
+pub async fn select_tenant(
+    State(tenants): State<Arc<Tenants>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    match tenants.select(&request) {
+        Ok(Some(tenant)) => {
+            tracing::debug!(tenant = tenant.name, "selected tenant");
+            request
+                .extensions_mut()
+                .insert(tenant.runtime_config.clone());
+            next.run(request).await
+        }
+        Ok(None) => next.run(request).await,
+        Err(err) => err.into_response(),
+    }
+}
@@ -0,0 +1,80 @@
+use anyhow::Context;
+use rustls::{
+    RootCertStore, ServerConfig,
+    crypto::ring,
+    pki_types::{CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+};
+use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+
+/// Load the rustls server configuration from PEM encoded certificate
+/// chain and private key files
+///
+/// ## Arguments
+/// * `cert_path` - Path to the certificate chain
+/// * `key_path` - Path to the private key
+/// * `client_ca_path` - Optional CA bundle, when provided clients must present a
+///   certificate signed by one of these CAs
+pub fn load_tls_config(
+    cert_path: &Path,
+    key_path: &Path,
+    client_ca_path: Option<&Path>,
+) -> anyhow::Result<Arc<ServerConfig>> {
+    let certs = load_certs(cert_path)?;
+    let key = load_private_key(key_path)?;
+    let provider = Arc::new(ring::default_provider());
+
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .context("failed to configure tls protocol versions")?;
+
+    let builder = match client_ca_path {
+        Some(client_ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(client_ca_path)? {
+                roots.add(cert).context("invalid client ca certificate")?;
+            }
+
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .context("failed to create client certificate verifier")?;
+
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder
+        .with_single_cert(certs, key)
+        .context("invalid tls certificate or key")?;
+
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(Arc::new(config))
+}
+
+/// Load all the certificates from a PEM file
+fn load_certs(path: &Path) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let file = File::open(path)
+        .with_context(|| format!("failed to open certificate file {}", path.display()))?;
+
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("failed to parse certificate file {}", path.display()))?;
+
+    if certs.is_empty() {
+        anyhow::bail!("no certificates found in {}", path.display());
+    }
+
+    Ok(certs)
+}
+
+/// Load the first private key from a PEM file
+fn load_private_key(path: &Path) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let file = File::open(path)
+        .with_context(|| format!("failed to open private key file {}", path.display()))?;
+
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("failed to parse private key file {}", path.display()))?
+        .with_context(|| format!("no private key found in {}", path.display()))
+}
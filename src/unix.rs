@@ -0,0 +1,135 @@
+use anyhow::Context;
+use axum::Router;
+use std::{
+    fs::{DirBuilder, Permissions},
+    future::Future,
+    os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+    path::Path,
+};
+use tokio::net::UnixListener;
+use uuid::Uuid;
+
+use crate::{
+    http::{HttpServer, HttpTuning},
+    systemd,
+};
+
+/// Default permissions for the socket file, allows the owner and group to connect
+pub const DEFAULT_UNIX_SOCKET_MODE: u32 = 0o660;
+
+/// Serve the app over a unix domain socket until the shutdown future completes,
+/// in-flight connections are allowed to finish before the socket file is removed
+///
+/// ## Arguments
+/// * `path` - Path to create the socket file at
+/// * `mode` - Permissions to set on the socket file
+/// * `tuning` - Connection settings
+/// * `app` - The app to serve
+/// * `shutdown` - Future that completes when the server should shutdown
+pub async fn serve_unix<F>(
+    path: &Path,
+    mode: u32,
+    tuning: &HttpTuning,
+    app: Router,
+    shutdown: F,
+) -> anyhow::Result<()>
+where
+    F: Future<Output = ()>,
+{
+    let listener = bind_unix_socket(path, mode)?;
+    systemd::notify_ready();
+    let server = HttpServer::new(tuning);
+
+    tokio::pin!(shutdown);
+
+    loop {
+        let stream = tokio::select! {
+            result = listener.accept() => match result {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    tracing::error!(?err, "failed to accept unix socket connection");
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+
+        server.serve_connection(stream, None, app.clone());
+    }
+
+    // Stop accepting connections and wait for the existing ones to finish
+    drop(listener);
+    server.shutdown().await;
+
+    if let Err(err) = std::fs::remove_file(path) {
+        tracing::warn!(?err, "failed to remove unix socket file");
+    }
+
+    Ok(())
+}
+
+/// Bind a unix socket at the provided path, replacing any stale socket
+/// file left behind by a previous run
+fn bind_unix_socket(path: &Path, mode: u32) -> anyhow::Result<UnixListener> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                anyhow::bail!("unix socket {} is already in use", path.display());
+            }
+
+            std::fs::remove_file(path).context("failed to remove stale unix socket")?;
+        }
+        Ok(_) => anyhow::bail!(
+            "unix socket path {} exists and is not a socket",
+            path.display()
+        ),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err).context("failed to check unix socket path"),
+    }
+
+    // The socket is bound within a directory only the owner can access and
+    // moved into place once its permissions are set, otherwise it would be
+    // briefly reachable with the permissions derived from the umask
+    let file_name = path
+        .file_name()
+        .context("unix socket path has no file name")?;
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let bind_dir = parent.join(format!(
+        ".{}.{}",
+        file_name.to_string_lossy(),
+        Uuid::new_v4()
+    ));
+
+    DirBuilder::new()
+        .mode(0o700)
+        .create(&bind_dir)
+        .context("failed to create unix socket bind directory")?;
+
+    let result = bind_private_unix_socket(&bind_dir.join(file_name), path, mode);
+
+    if let Err(err) = std::fs::remove_dir_all(&bind_dir) {
+        tracing::warn!(?err, "failed to remove unix socket bind directory");
+    }
+
+    result
+}
+
+/// Bind a unix socket at the private path, set its permissions and move it
+/// to the public path
+fn bind_private_unix_socket(
+    private_path: &Path,
+    path: &Path,
+    mode: u32,
+) -> anyhow::Result<UnixListener> {
+    let listener = UnixListener::bind(private_path).context("failed to bind unix socket")?;
+
+    std::fs::set_permissions(private_path, Permissions::from_mode(mode))
+        .context("failed to set unix socket permissions")?;
+
+    std::fs::rename(private_path, path).context("failed to move unix socket into place")?;
+
+    Ok(listener)
+}
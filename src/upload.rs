@@ -0,0 +1,579 @@
+use axum::{
+    extract::{
+        Multipart,
+        multipart::{Field, MultipartError},
+    },
+    http::StatusCode,
+};
+use serde::Deserialize;
+use std::path::Path;
+use tokio::io::{AsyncWriteExt, BufWriter};
+
+use crate::{
+    ErrorKind, ErrorResponse, RuntimeConfig,
+    convert::{
+        ConvertOptions, ConvertTempPaths, create_convert_temp_paths, normalize_extension,
+        resolve_output_format,
+    },
+    csv::CsvOptions,
+    disposition::sanitize_file_name,
+    error_backtrace,
+    format::OutputFormat,
+    forms::FormData,
+    limiter::Priority,
+    params::X2tParams,
+    pdf_version::PdfVersion,
+    scratch::{ScratchUsage, WriteReservation},
+    spreadsheet::SpreadsheetLayout,
+    watermark::Watermark,
+};
+
+/// Size of the buffer used when writing uploaded files to disk
+const UPLOAD_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Name of the multipart field containing the file to convert
+const FILE_FIELD: &str = "file";
+
+/// Maximum size of a multipart field other than the file, large enough for
+/// docbuilder scripts and form data
+const MAX_TEXT_FIELD_SIZE: usize = 256 * 1024;
+
+/// Conversion options, accepted as either multipart fields or query
+/// parameters, multipart fields take priority when both are provided
+#[derive(Default, Deserialize)]
+pub struct ConvertFields {
+    /// Format to convert the file to (Defaults to PDF)
+    pub target_format: Option<String>,
+
+    /// Whether PDF output should be archival PDF/A
+    pub pdfa: Option<bool>,
+
+    /// Version of the PDF specification the PDF output declares ("1.5",
+    /// "1.6" or "1.7"), for consumers that reject newer versions
+    pub pdf_version: Option<String>,
+
+    /// Whether every page should be rendered when converting to an image
+    /// format, the images are returned as a ZIP archive
+    pub all_pages: Option<bool>,
+
+    /// Whether each sheet of a spreadsheet should be converted to its own
+    /// PDF, the PDFs are returned as a ZIP archive
+    pub split_sheets: Option<bool>,
+
+    /// Whether slide notes pages should be included when converting a
+    /// presentation to PDF
+    pub speaker_notes: Option<bool>,
+
+    /// Password to open the file with if its encrypted
+    pub password: Option<String>,
+
+    /// Text to stamp onto every page as a watermark
+    pub watermark_text: Option<String>,
+
+    /// Opacity of the watermark from 0 to 1
+    pub watermark_opacity: Option<f32>,
+
+    /// Rotation of the watermark in degrees
+    pub watermark_angle: Option<f32>,
+
+    /// Font size of the watermark text in points
+    pub watermark_font_size: Option<u32>,
+
+    /// Delimiter used by CSV/TXT input files (i.e "semicolon" or ";")
+    pub csv_delimiter: Option<String>,
+
+    /// x2t codepage identifier for the encoding of CSV/TXT input files,
+    /// detected from the content of the file when not provided
+    pub codepage: Option<u32>,
+
+    /// Number of pages wide to fit each spreadsheet sheet to
+    pub fit_to_width: Option<u32>,
+
+    /// Number of pages tall to fit each spreadsheet sheet to
+    pub fit_to_height: Option<u32>,
+
+    /// Whether the print areas defined in a spreadsheet should be ignored
+    pub ignore_print_area: Option<bool>,
+
+    /// Comma separated zero based indexes of the spreadsheet sheets to convert
+    pub sheets: Option<String>,
+
+    /// Whether x2t diagnostics should be included in error responses,
+    /// requires the admin token
+    pub debug: Option<bool>,
+
+    /// Priority of the conversion in the queue ("low", "normal" or "high")
+    pub priority: Option<String>,
+
+    /// Extension of the input format (i.e "docx"), used when the uploaded
+    /// file name is missing or has the wrong extension
+    pub input_format: Option<String>,
+
+    /// JSON object of additional x2t config elements, only a limited set
+    /// of elements are allowed
+    pub x2t_params: Option<String>,
+
+    /// JSON object of form field keys (or content control tags) to values
+    /// to fill into the document before converting it, requires docbuilder
+    pub form_data: Option<String>,
+
+    /// Hex encoded SHA-256 the uploaded file is expected to have, the
+    /// conversion is rejected when the upload doesn't match
+    pub input_checksum: Option<String>,
+}
+
+impl ConvertFields {
+    /// Resolve the fields into the options for the conversion
+    pub fn into_options(self, default_pdfa: bool) -> Result<ConvertOptions, ErrorResponse> {
+        let output_format = resolve_output_format(
+            self.target_format,
+            self.pdfa,
+            self.all_pages,
+            self.split_sheets,
+            default_pdfa,
+        )?;
+
+        let watermark = Watermark::from_params(
+            self.watermark_text,
+            self.watermark_opacity,
+            self.watermark_angle,
+            self.watermark_font_size,
+        )?;
+
+        let speaker_notes = self.speaker_notes.unwrap_or_default();
+        if speaker_notes && !matches!(output_format, OutputFormat::Pdf | OutputFormat::PdfA) {
+            return Err(ErrorResponse {
+                code: None,
+                kind: ErrorKind::InvalidRequest,
+                message: "speaker_notes is only supported when converting to pdf".to_string(),
+                backtrace: None,
+            });
+        }
+
+        let pdf_version = PdfVersion::from_param(self.pdf_version)?;
+        if pdf_version.is_some() && output_format != OutputFormat::Pdf {
+            return Err(ErrorResponse {
+                code: None,
+                kind: ErrorKind::InvalidRequest,
+                message: "pdf_version is only supported when converting to pdf without pdfa"
+                    .to_string(),
+                backtrace: None,
+            });
+        }
+
+        let csv = CsvOptions::from_params(self.csv_delimiter, self.codepage)?;
+
+        let priority = match self.priority {
+            Some(value) => Priority::from_name(&value).ok_or_else(|| ErrorResponse {
+                code: None,
+                kind: ErrorKind::InvalidRequest,
+                message: format!("invalid priority \"{value}\", expected low, normal or high"),
+                backtrace: None,
+            })?,
+            None => Priority::default(),
+        };
+
+        let input_format = match self.input_format {
+            Some(value) => Some(normalize_extension(&value).ok_or_else(|| ErrorResponse {
+                code: None,
+                kind: ErrorKind::InvalidRequest,
+                message: format!("invalid input format \"{value}\""),
+                backtrace: None,
+            })?),
+            None => None,
+        };
+
+        let x2t_params = X2tParams::from_param(self.x2t_params)?;
+        let form_data = FormData::from_param(self.form_data)?;
+
+        let spreadsheet_layout = SpreadsheetLayout::from_params(
+            self.fit_to_width,
+            self.fit_to_height,
+            self.ignore_print_area,
+            self.sheets,
+        )?;
+
+        Ok(ConvertOptions {
+            output_format,
+            password: self.password,
+            watermark,
+            csv,
+            spreadsheet_layout,
+            speaker_notes,
+            pdf_version,
+            debug: self.debug.unwrap_or_default(),
+            priority,
+            input_format,
+            x2t_params,
+            form_data,
+            deadline: None,
+        })
+    }
+}
+
+/// Convert request read from a multipart body
+pub struct ConvertUpload {
+    /// Size of the uploaded file in bytes
+    pub size: u64,
+    /// Sanitized original name of the uploaded file
+    pub file_name: Option<String>,
+    /// Options for the conversion
+    pub fields: ConvertFields,
+}
+
+/// Convert request read from a multipart body with its fields not yet
+/// parsed, allows endpoints to take fields that aren't convert options
+pub struct RawConvertUpload {
+    /// Size of the uploaded file in bytes
+    pub size: u64,
+    /// Sanitized original name of the uploaded file
+    pub file_name: Option<String>,
+    /// Multipart fields and query parameters
+    pub params: Vec<(String, String)>,
+}
+
+impl RawConvertUpload {
+    /// Remove a field that isn't a convert option, returns its value
+    pub fn take_param(&mut self, name: &str) -> Option<String> {
+        let index = self.params.iter().position(|(key, _)| key == name)?;
+        Some(self.params.remove(index).1)
+    }
+
+    /// Parse the remaining fields into the convert options
+    pub fn into_upload(self) -> Result<ConvertUpload, ErrorResponse> {
+        let fields = parse_convert_fields(&self.params)?;
+
+        Ok(ConvertUpload {
+            size: self.size,
+            file_name: self.file_name,
+            fields,
+        })
+    }
+}
+
+/// Reads a convert request from a multipart body, the file field is streamed
+/// directly to the `input_path` rather than being buffered in memory
+///
+/// ## Arguments
+/// * `query` - The raw query string of the request
+/// * `multipart` - The multipart request body
+/// * `temp_paths` - Temporary paths to write the uploaded file to
+pub async fn read_convert_upload(
+    query: Option<String>,
+    multipart: Multipart,
+    temp_paths: &ConvertTempPaths,
+) -> Result<ConvertUpload, ErrorResponse> {
+    read_raw_convert_upload(query, multipart, temp_paths)
+        .await?
+        .into_upload()
+}
+
+/// Reads a convert request from a multipart body without parsing its
+/// fields, the file field is streamed directly to the `input_path`
+///
+/// ## Arguments
+/// * `query` - The raw query string of the request
+/// * `multipart` - The multipart request body
+/// * `temp_paths` - Temporary paths to write the uploaded file to
+pub async fn read_raw_convert_upload(
+    query: Option<String>,
+    mut multipart: Multipart,
+    temp_paths: &ConvertTempPaths,
+) -> Result<RawConvertUpload, ErrorResponse> {
+    let mut params = parse_query_params(query.as_deref())?;
+    let mut size: Option<u64> = None;
+    let mut file_name: Option<String> = None;
+
+    while let Some(field) = multipart.next_field().await.map_err(|err| {
+        tracing::error!(?err, "failed to read multipart field");
+        multipart_error(&err, "failed to read multipart body".to_string())
+    })? {
+        let Some(name) = field.name().map(str::to_string) else {
+            continue;
+        };
+
+        if name == FILE_FIELD {
+            if size.is_some() {
+                return Err(ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::InvalidRequest,
+                    message: "only one file can be converted at a time".to_string(),
+                    backtrace: None,
+                });
+            }
+
+            file_name = field.file_name().and_then(sanitize_file_name);
+            size = Some(
+                write_field_to_file(field, &temp_paths.input_path, Some(temp_paths.scratch()))
+                    .await?,
+            );
+            continue;
+        }
+
+        let value = read_text_field(field, &name).await?;
+
+        // Multipart fields replace query parameters of the same name
+        params.retain(|(key, _)| key != &name);
+        params.push((name, value));
+    }
+
+    let size = size.ok_or_else(|| ErrorResponse {
+        code: None,
+        kind: ErrorKind::InvalidRequest,
+        message: "missing file to convert".to_string(),
+        backtrace: None,
+    })?;
+
+    Ok(RawConvertUpload {
+        size,
+        file_name,
+        params,
+    })
+}
+
+/// Reads a single file from a multipart body, streaming it directly to the
+/// input path, other fields are ignored. Returns the size of the file
+///
+/// ## Arguments
+/// * `multipart` - The multipart request body
+/// * `temp_paths` - Temporary paths to write the uploaded file to
+pub async fn read_file_upload(
+    mut multipart: Multipart,
+    temp_paths: &ConvertTempPaths,
+) -> Result<u64, ErrorResponse> {
+    let mut size: Option<u64> = None;
+
+    while let Some(field) = multipart.next_field().await.map_err(|err| {
+        tracing::error!(?err, "failed to read multipart field");
+        multipart_error(&err, "failed to read multipart body".to_string())
+    })? {
+        if field.name() != Some(FILE_FIELD) {
+            continue;
+        }
+
+        if size.is_some() {
+            return Err(ErrorResponse {
+                code: None,
+                kind: ErrorKind::InvalidRequest,
+                message: "only one file can be uploaded at a time".to_string(),
+                backtrace: None,
+            });
+        }
+
+        size = Some(
+            write_field_to_file(field, &temp_paths.input_path, Some(temp_paths.scratch())).await?,
+        );
+    }
+
+    size.ok_or_else(|| ErrorResponse {
+        code: None,
+        kind: ErrorKind::InvalidRequest,
+        message: "missing file".to_string(),
+        backtrace: None,
+    })
+}
+
+/// File uploaded as part of a batch convert request
+pub struct BatchFile {
+    /// Original name of the uploaded file
+    pub file_name: String,
+    /// Size of the uploaded file in bytes
+    pub size: u64,
+    /// Temporary paths for converting the file, the uploaded file
+    /// has been written to the input path
+    pub temp_paths: ConvertTempPaths,
+}
+
+/// Batch convert request read from a multipart body
+pub struct BatchUpload {
+    /// Uploaded files in the order they were received
+    pub files: Vec<BatchFile>,
+    /// Options for the conversions, shared by all the files
+    pub fields: ConvertFields,
+}
+
+/// Reads a batch convert request from a multipart body, each file field is
+/// streamed directly to its own temporary input path
+///
+/// ## Arguments
+/// * `runtime_config` - Runtime configuration for creating temporary paths
+/// * `query` - The raw query string of the request
+/// * `multipart` - The multipart request body
+pub async fn read_batch_upload(
+    runtime_config: &RuntimeConfig,
+    query: Option<String>,
+    mut multipart: Multipart,
+) -> Result<BatchUpload, ErrorResponse> {
+    let mut params = parse_query_params(query.as_deref())?;
+    let mut files: Vec<BatchFile> = Vec::new();
+
+    while let Some(field) = multipart.next_field().await.map_err(|err| {
+        tracing::error!(?err, "failed to read multipart field");
+        multipart_error(&err, "failed to read multipart body".to_string())
+    })? {
+        let Some(name) = field.name().map(str::to_string) else {
+            continue;
+        };
+
+        if name == FILE_FIELD {
+            let file_name = field
+                .file_name()
+                .and_then(sanitize_file_name)
+                .unwrap_or_else(|| format!("file_{}", files.len() + 1));
+
+            let temp_paths = create_convert_temp_paths(runtime_config).await?;
+            let size =
+                write_field_to_file(field, &temp_paths.input_path, Some(temp_paths.scratch()))
+                    .await?;
+
+            files.push(BatchFile {
+                file_name,
+                size,
+                temp_paths,
+            });
+            continue;
+        }
+
+        let value = read_text_field(field, &name).await?;
+
+        // Multipart fields replace query parameters of the same name
+        params.retain(|(key, _)| key != &name);
+        params.push((name, value));
+    }
+
+    if files.is_empty() {
+        return Err(ErrorResponse {
+            code: None,
+            kind: ErrorKind::InvalidRequest,
+            message: "missing files to convert".to_string(),
+            backtrace: None,
+        });
+    }
+
+    let fields = parse_convert_fields(&params)?;
+
+    Ok(BatchUpload { files, fields })
+}
+
+/// Parse the query string into a list of key value pairs
+pub fn parse_query_params(query: Option<&str>) -> Result<Vec<(String, String)>, ErrorResponse> {
+    let Some(query) = query else {
+        return Ok(Vec::new());
+    };
+
+    serde_urlencoded::from_str(query).map_err(|err| ErrorResponse {
+        code: None,
+        kind: ErrorKind::InvalidRequest,
+        message: format!("invalid query string: {err}"),
+        backtrace: None,
+    })
+}
+
+/// Parse the collected key value pairs into the convert fields
+pub fn parse_convert_fields(params: &[(String, String)]) -> Result<ConvertFields, ErrorResponse> {
+    let encoded = serde_urlencoded::to_string(params).map_err(|err| ErrorResponse {
+        code: None,
+        kind: ErrorKind::InvalidRequest,
+        message: format!("invalid convert options: {err}"),
+        backtrace: None,
+    })?;
+
+    serde_urlencoded::from_str(&encoded).map_err(|err| ErrorResponse {
+        code: None,
+        kind: ErrorKind::InvalidRequest,
+        message: format!("invalid convert options: {err}"),
+        backtrace: None,
+    })
+}
+
+/// Stream the contents of a multipart field to a file on disk using a
+/// bounded buffer, returns the number of bytes written
+///
+/// ## Arguments
+/// * `field` - The multipart field to write
+/// * `path` - Path to write the file to
+/// * `scratch` - Scratch space to reserve disk space from while writing
+pub async fn write_field_to_file(
+    mut field: Field<'_>,
+    path: &Path,
+    scratch: Option<&ScratchUsage>,
+) -> Result<u64, ErrorResponse> {
+    let write_error = |err: std::io::Error| {
+        tracing::error!(?err, "failed to write uploaded file");
+        ErrorResponse {
+            code: None,
+            kind: ErrorKind::Internal,
+            message: "failed to write uploaded file".to_string(),
+            backtrace: error_backtrace(&err),
+        }
+    };
+
+    let file = tokio::fs::File::create(path).await.map_err(write_error)?;
+    let mut writer = BufWriter::with_capacity(UPLOAD_BUFFER_SIZE, file);
+    let mut size: u64 = 0;
+    let mut reservation = scratch.map(WriteReservation::new);
+
+    while let Some(chunk) = field.chunk().await.map_err(|err| {
+        tracing::error!(?err, "failed to read uploaded file");
+        multipart_error(&err, "failed to read uploaded file".to_string())
+    })? {
+        if let Some(reservation) = &mut reservation {
+            reservation.write(chunk.len() as u64)?;
+        }
+
+        size += chunk.len() as u64;
+        writer.write_all(&chunk).await.map_err(write_error)?;
+    }
+
+    writer.flush().await.map_err(write_error)?;
+
+    Ok(size)
+}
+
+/// Read a multipart field other than the file as text, fields larger than
+/// [MAX_TEXT_FIELD_SIZE] are rejected without being buffered
+async fn read_text_field(mut field: Field<'_>, name: &str) -> Result<String, ErrorResponse> {
+    let mut value: Vec<u8> = Vec::new();
+
+    while let Some(chunk) = field.chunk().await.map_err(|err| {
+        tracing::error!(?err, "failed to read multipart field");
+        multipart_error(&err, format!("failed to read multipart field \"{name}\""))
+    })? {
+        if value.len() + chunk.len() > MAX_TEXT_FIELD_SIZE {
+            return Err(ErrorResponse {
+                code: None,
+                kind: ErrorKind::TooLarge,
+                message: format!(
+                    "multipart field \"{name}\" exceeds the maximum size of {MAX_TEXT_FIELD_SIZE} bytes"
+                ),
+                backtrace: None,
+            });
+        }
+
+        value.extend_from_slice(&chunk);
+    }
+
+    String::from_utf8(value).map_err(|_| ErrorResponse {
+        code: None,
+        kind: ErrorKind::InvalidRequest,
+        message: format!("multipart field \"{name}\" isn't valid utf-8"),
+        backtrace: None,
+    })
+}
+
+/// Create the error for a failure reading a multipart body, bodies that
+/// exceed the size limit are reported as too large
+fn multipart_error(err: &MultipartError, message: String) -> ErrorResponse {
+    let kind = if err.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        ErrorKind::TooLarge
+    } else {
+        ErrorKind::InvalidRequest
+    };
+
+    ErrorResponse {
+        code: None,
+        kind,
+        message,
+        backtrace: None,
+    }
+}
@@ -0,0 +1,540 @@
+use axum::{
+    extract::{DefaultBodyLimit, Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tower::{Layer, ServiceExt};
+
+use crate::{
+    ErrorKind, ErrorResponse, convert::ConvertOptions, format::OutputFormat, limiter::Priority,
+};
+
+/// Header the upload token can be provided in
+const UPLOAD_TOKEN_HEADER: &str = "x-upload-token";
+
+/// Query parameter the upload token can be provided in
+const UPLOAD_TOKEN_PARAM: &str = "upload_token";
+
+/// Allowance for the multipart framing around the uploaded file
+const MULTIPART_OVERHEAD: u64 = 64 * 1024;
+
+/// Maximum time in seconds an upload token can be valid for, keeps the set
+/// of used tokens small
+const MAX_TOKEN_LIFETIME: u64 = 60 * 60;
+
+/// Claims of an upload token
+#[derive(Deserialize)]
+struct UploadTokenClaims {
+    /// Unique ID of the token, each ID can only be used once
+    jti: String,
+    /// Unix timestamp the token expires at
+    exp: u64,
+    /// Maximum size of the uploaded file in bytes
+    #[serde(default)]
+    max_size: Option<u64>,
+    /// Target formats the file can be converted to
+    #[serde(default)]
+    formats: Option<Vec<String>>,
+    /// Highest priority the conversion can request, defaults to normal
+    #[serde(default)]
+    max_priority: Option<String>,
+    /// Whether the conversion can provide additional x2t params
+    #[serde(default)]
+    x2t_params: bool,
+    /// Whether the conversion can fill forms with form data
+    #[serde(default)]
+    form_data: bool,
+}
+
+/// Short lived tokens issued by a backend that each authorize a single
+/// conversion, allows browsers to upload files directly without exposing
+/// credentials. Tokens are JWTs signed with HS256 using the upload token
+/// secret, they must have a unique "jti" and an "exp" at most an hour away.
+/// Conversions are limited to normal priority without x2t params or form
+/// data unless the "max_priority", "x2t_params" or "form_data" claims allow
+/// them
+pub struct UploadTokens {
+    decoding_key: DecodingKey,
+    validation: Validation,
+    /// IDs of the tokens that have been used mapped to when they expire
+    used: Mutex<HashMap<String, u64>>,
+}
+
+/// Constraints of a verified upload token on the conversion it authorizes
+#[derive(Debug, Clone)]
+pub struct UploadGrant {
+    max_size: Option<u64>,
+    formats: Option<Vec<OutputFormat>>,
+    max_priority: Priority,
+    x2t_params: bool,
+    form_data: bool,
+}
+
+impl UploadTokens {
+    pub fn new(secret: &str) -> Self {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.set_required_spec_claims(&["exp", "jti"]);
+        validation.leeway = 0;
+
+        Self {
+            decoding_key: DecodingKey::from_secret(secret.as_bytes()),
+            validation,
+            used: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Verify the token and mark it as used, returns the constraints on the
+    /// conversion it authorizes
+    fn redeem(&self, token: &str) -> Result<UploadGrant, ErrorResponse> {
+        let invalid = |message: &str| ErrorResponse {
+            code: None,
+            kind: ErrorKind::Unauthorized,
+            message: message.to_string(),
+            backtrace: None,
+        };
+
+        let claims = decode::<UploadTokenClaims>(token, &self.decoding_key, &self.validation)
+            .map_err(|err| {
+                tracing::debug!(?err, "rejected invalid upload token");
+                invalid("missing or invalid upload token")
+            })?
+            .claims;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+
+        if claims.exp > now + MAX_TOKEN_LIFETIME {
+            return Err(invalid("upload token expires too far in the future"));
+        }
+
+        let formats = match claims.formats {
+            Some(formats) => Some(
+                formats
+                    .iter()
+                    .map(|format| {
+                        OutputFormat::from_name(format)
+                            .ok_or_else(|| invalid("upload token allows an unknown format"))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            None => None,
+        };
+
+        let max_priority = match &claims.max_priority {
+            Some(name) => Priority::from_name(name)
+                .ok_or_else(|| invalid("upload token allows an unknown priority"))?,
+            None => Priority::Normal,
+        };
+
+        {
+            let mut used = self.used.lock().expect("upload tokens lock poisoned");
+            used.retain(|_, exp| *exp >= now);
+
+            if used.contains_key(&claims.jti) {
+                return Err(invalid("upload token has already been used"));
+            }
+
+            used.insert(claims.jti, claims.exp);
+        }
+
+        Ok(UploadGrant {
+            max_size: claims.max_size,
+            formats,
+            max_priority,
+            x2t_params: claims.x2t_params,
+            form_data: claims.form_data,
+        })
+    }
+}
+
+impl UploadGrant {
+    /// Ensure the conversion is within the constraints of the token
+    ///
+    /// ## Arguments
+    /// * `size` - Size of the uploaded file in bytes
+    /// * `options` - Options for the conversion
+    pub fn authorize(&self, size: u64, options: &ConvertOptions) -> Result<(), ErrorResponse> {
+        if let Some(max_size) = self.max_size
+            && size > max_size
+        {
+            return Err(too_large(max_size));
+        }
+
+        if let Some(formats) = &self.formats
+            && !formats.contains(&options.output_format)
+        {
+            return Err(ErrorResponse {
+                code: None,
+                kind: ErrorKind::Forbidden,
+                message: format!(
+                    "upload token doesn't allow converting to {}",
+                    options.output_format.extension()
+                ),
+                backtrace: None,
+            });
+        }
+
+        let forbidden = |message: &str| ErrorResponse {
+            code: None,
+            kind: ErrorKind::Forbidden,
+            message: message.to_string(),
+            backtrace: None,
+        };
+
+        if options.priority > self.max_priority {
+            return Err(forbidden("upload token doesn't allow this priority"));
+        }
+
+        if !self.x2t_params && !options.x2t_params.is_empty() {
+            return Err(forbidden("upload token doesn't allow x2t_params"));
+        }
+
+        if !self.form_data && options.form_data.is_some() {
+            return Err(forbidden("upload token doesn't allow form_data"));
+        }
+
+        Ok(())
+    }
+}
+
+fn too_large(max_size: u64) -> ErrorResponse {
+    ErrorResponse {
+        code: None,
+        kind: ErrorKind::TooLarge,
+        message: format!("upload token only allows files up to {max_size} bytes"),
+        backtrace: None,
+    }
+}
+
+/// Middleware redeeming the upload token provided in the [UPLOAD_TOKEN_HEADER]
+/// header or [UPLOAD_TOKEN_PARAM] query parameter, the [UploadGrant] is added
+/// to the request for the handler to enforce. Bodies declared larger than
+/// the token allows are rejected before they are read, other bodies are
+/// rejected once more than the token allows has been read
+pub async fn require_upload_token(
+    State(upload_tokens): State<Arc<UploadTokens>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let token = request
+        .headers()
+        .get(UPLOAD_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| {
+            let query = request.uri().query()?;
+            serde_urlencoded::from_str::<Vec<(String, String)>>(query)
+                .ok()?
+                .into_iter()
+                .find(|(name, _)| name == UPLOAD_TOKEN_PARAM)
+                .map(|(_, value)| value)
+        });
+
+    let Some(token) = token else {
+        return ErrorResponse {
+            code: None,
+            kind: ErrorKind::Unauthorized,
+            message: "missing or invalid upload token".to_string(),
+            backtrace: None,
+        }
+        .into_response();
+    };
+
+    let grant = match upload_tokens.redeem(token.trim()) {
+        Ok(grant) => grant,
+        Err(err) => return err.into_response(),
+    };
+
+    let content_length = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+
+    let Some(max_size) = grant.max_size else {
+        request.extensions_mut().insert(grant);
+        return next.run(request).await;
+    };
+
+    // Multipart bodies are slightly larger than the file they contain
+    let max_body_size = max_size.saturating_add(MULTIPART_OVERHEAD);
+
+    if content_length.is_some_and(|content_length| content_length > max_body_size) {
+        return too_large(max_size).into_response();
+    }
+
+    request.extensions_mut().insert(grant);
+
+    // Bodies without a declared length (i.e chunked uploads) are counted
+    // while they are read and rejected as soon as they exceed the limit
+    DefaultBodyLimit::max(usize::try_from(max_body_size).unwrap_or(usize::MAX))
+        .layer(next)
+        .oneshot(request)
+        .await
+        .unwrap_or_else(|err| match err {})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::upload::ConvertFields;
+    use axum::{Router, body::Body, http::StatusCode, middleware, routing::post};
+    use jsonwebtoken::{EncodingKey, Header, encode};
+    use serde_json::{Value, json};
+
+    const SECRET: &str = "upload-token-secret";
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn token(claims: Value) -> String {
+        encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(SECRET.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    fn grant(tokens: &UploadTokens, claims: Value) -> UploadGrant {
+        tokens
+            .redeem(&token(claims))
+            .unwrap_or_else(|err| panic!("{}", err.message))
+    }
+
+    fn options(fields: ConvertFields) -> ConvertOptions {
+        fields
+            .into_options(false)
+            .unwrap_or_else(|err| panic!("{}", err.message))
+    }
+
+    fn error_message<T: std::fmt::Debug>(result: Result<T, ErrorResponse>) -> String {
+        result.unwrap_err().message
+    }
+
+    #[test]
+    fn test_redeem_valid_token() {
+        let tokens = UploadTokens::new(SECRET);
+        let grant = grant(&tokens, json!({ "jti": "a", "exp": now() + 60 }));
+
+        assert!(
+            grant
+                .authorize(1024, &options(ConvertFields::default()))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_replay_rejected() {
+        let tokens = UploadTokens::new(SECRET);
+        let token = token(json!({ "jti": "a", "exp": now() + 60 }));
+
+        assert!(tokens.redeem(&token).is_ok());
+        assert_eq!(
+            error_message(tokens.redeem(&token)),
+            "upload token has already been used"
+        );
+    }
+
+    #[test]
+    fn test_expired_token_rejected() {
+        let tokens = UploadTokens::new(SECRET);
+        let result = tokens.redeem(&token(json!({ "jti": "a", "exp": now() - 1 })));
+
+        assert_eq!(error_message(result), "missing or invalid upload token");
+    }
+
+    #[test]
+    fn test_wrong_secret_rejected() {
+        let tokens = UploadTokens::new("other-secret");
+        let result = tokens.redeem(&token(json!({ "jti": "a", "exp": now() + 60 })));
+
+        assert_eq!(error_message(result), "missing or invalid upload token");
+    }
+
+    #[test]
+    fn test_missing_jti_rejected() {
+        let tokens = UploadTokens::new(SECRET);
+        let result = tokens.redeem(&token(json!({ "exp": now() + 60 })));
+
+        assert_eq!(error_message(result), "missing or invalid upload token");
+    }
+
+    #[test]
+    fn test_long_lived_token_rejected() {
+        let tokens = UploadTokens::new(SECRET);
+        let result = tokens.redeem(&token(json!({
+            "jti": "a",
+            "exp": now() + MAX_TOKEN_LIFETIME + 60,
+        })));
+
+        assert_eq!(
+            error_message(result),
+            "upload token expires too far in the future"
+        );
+    }
+
+    #[test]
+    fn test_oversized_file_rejected() {
+        let tokens = UploadTokens::new(SECRET);
+        let grant = grant(
+            &tokens,
+            json!({ "jti": "a", "exp": now() + 60, "max_size": 100 }),
+        );
+
+        assert!(
+            grant
+                .authorize(100, &options(ConvertFields::default()))
+                .is_ok()
+        );
+
+        let err = grant
+            .authorize(101, &options(ConvertFields::default()))
+            .unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::TooLarge));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_body_rejected() {
+        let tokens = Arc::new(UploadTokens::new(SECRET));
+        let app = Router::new()
+            .route("/", post(|_body: String| async { StatusCode::OK }))
+            .layer(middleware::from_fn_with_state(tokens, require_upload_token));
+
+        let request = |jti: &str, size: u64| {
+            let token = token(json!({ "jti": jti, "exp": now() + 60, "max_size": 100 }));
+
+            Request::post("/")
+                .header(UPLOAD_TOKEN_HEADER, token)
+                .header(header::CONTENT_LENGTH, size)
+                .body(Body::from(vec![b'a'; size as usize]))
+                .unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(request("a", 100 + MULTIPART_OVERHEAD + 1))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let response = app.oneshot(request("b", 100)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_disallowed_format_rejected() {
+        let tokens = UploadTokens::new(SECRET);
+        let grant = grant(
+            &tokens,
+            json!({ "jti": "a", "exp": now() + 60, "formats": ["pdf"] }),
+        );
+
+        assert!(
+            grant
+                .authorize(0, &options(ConvertFields::default()))
+                .is_ok()
+        );
+
+        let docx = options(ConvertFields {
+            target_format: Some("docx".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(
+            error_message(grant.authorize(0, &docx)),
+            "upload token doesn't allow converting to docx"
+        );
+    }
+
+    #[test]
+    fn test_unknown_format_claim_rejected() {
+        let tokens = UploadTokens::new(SECRET);
+        let result = tokens.redeem(&token(json!({
+            "jti": "a",
+            "exp": now() + 60,
+            "formats": ["exe"],
+        })));
+
+        assert_eq!(
+            error_message(result),
+            "upload token allows an unknown format"
+        );
+    }
+
+    #[test]
+    fn test_priority_limited_by_token() {
+        let tokens = UploadTokens::new(SECRET);
+        let high = options(ConvertFields {
+            priority: Some("high".to_string()),
+            ..Default::default()
+        });
+
+        let default_grant = grant(&tokens, json!({ "jti": "a", "exp": now() + 60 }));
+        assert_eq!(
+            error_message(default_grant.authorize(0, &high)),
+            "upload token doesn't allow this priority"
+        );
+
+        let high_grant = grant(
+            &tokens,
+            json!({ "jti": "b", "exp": now() + 60, "max_priority": "high" }),
+        );
+        assert!(high_grant.authorize(0, &high).is_ok());
+    }
+
+    #[test]
+    fn test_x2t_params_limited_by_token() {
+        let tokens = UploadTokens::new(SECRET);
+        let params = options(ConvertFields {
+            x2t_params: Some(r#"{"m_sTitle": "title"}"#.to_string()),
+            ..Default::default()
+        });
+
+        let default_grant = grant(&tokens, json!({ "jti": "a", "exp": now() + 60 }));
+        assert_eq!(
+            error_message(default_grant.authorize(0, &params)),
+            "upload token doesn't allow x2t_params"
+        );
+
+        let params_grant = grant(
+            &tokens,
+            json!({ "jti": "b", "exp": now() + 60, "x2t_params": true }),
+        );
+        assert!(params_grant.authorize(0, &params).is_ok());
+    }
+
+    #[test]
+    fn test_form_data_limited_by_token() {
+        let tokens = UploadTokens::new(SECRET);
+        let form_data = options(ConvertFields {
+            form_data: Some(r#"{"name": "value"}"#.to_string()),
+            ..Default::default()
+        });
+
+        let default_grant = grant(&tokens, json!({ "jti": "a", "exp": now() + 60 }));
+        assert_eq!(
+            error_message(default_grant.authorize(0, &form_data)),
+            "upload token doesn't allow form_data"
+        );
+
+        let form_grant = grant(
+            &tokens,
+            json!({ "jti": "b", "exp": now() + 60, "form_data": true }),
+        );
+        assert!(form_grant.authorize(0, &form_data).is_ok());
+    }
+}
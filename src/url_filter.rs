@@ -0,0 +1,344 @@
+use ipnet::IpNet;
+use reqwest::{
+    Url,
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect,
+};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+
+use crate::client_ip::{contains_address, parse_networks};
+
+/// Maximum number of redirects followed when downloading a file
+const MAX_REDIRECTS: usize = 10;
+
+/// Restricts the URLs files are downloaded from to prevent callers from
+/// making the server request internal services. Only http and https URLs
+/// are allowed and hosts must resolve to public addresses, unless the
+/// address is within one of the explicitly allowed networks
+#[derive(Debug, Default)]
+pub struct UrlFilter {
+    allowed_networks: Vec<IpNet>,
+}
+
+impl UrlFilter {
+    /// Parse a comma separated list of additional networks to allow
+    /// downloading from (i.e "10.0.0.0/8")
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            allowed_networks: parse_networks(value)?,
+        })
+    }
+
+    pub fn is_allowed_address(&self, address: IpAddr) -> bool {
+        contains_address(&self.allowed_networks, address) || is_public_address(address)
+    }
+
+    /// Check the scheme of the URL and the address of hosts that are IP
+    /// literals, other hosts are checked once they are resolved
+    pub fn check_url(&self, url: &Url) -> Result<(), String> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err("url must be an absolute http or https url".to_string());
+        }
+
+        let Some(host) = url.host_str() else {
+            return Err("url must have a host".to_string());
+        };
+
+        // IPv6 hosts are enclosed in brackets
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let Ok(address) = host.parse::<IpAddr>() else {
+            return Ok(());
+        };
+
+        if !self.is_allowed_address(address) {
+            return Err(format!("requests to {address} are not allowed"));
+        }
+
+        Ok(())
+    }
+
+    /// Check the URL and that every address its host resolves to is allowed,
+    /// used to reject URLs upfront rather than when they are first requested
+    pub async fn check_resolved_url(&self, url: &Url) -> Result<(), String> {
+        self.check_url(url)?;
+
+        let Some(host) = url.host_str() else {
+            return Err("url must have a host".to_string());
+        };
+
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let port = url.port_or_known_default().unwrap_or_default();
+        let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|_| format!("{host} couldn't be resolved"))?
+            .collect();
+
+        if let Some(address) = addresses
+            .iter()
+            .find(|address| !self.is_allowed_address(address.ip()))
+        {
+            return Err(format!(
+                "{host} resolves to {} which is not allowed",
+                address.ip()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Configure an HTTP client to only connect to allowed addresses,
+    /// redirects are followed as long as they also point to allowed URLs
+    pub fn apply(self: Arc<Self>, builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        let redirect_filter = self.clone();
+        let redirect_policy = redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                return attempt.error("too many redirects");
+            }
+
+            match redirect_filter.check_url(attempt.url()) {
+                Ok(()) => attempt.follow(),
+                Err(message) => attempt.error(message),
+            }
+        });
+
+        builder
+            // Proxies resolve the host themselves which would bypass the filter
+            .no_proxy()
+            .redirect(redirect_policy)
+            .dns_resolver(Arc::new(FilteredResolver { filter: self }))
+    }
+}
+
+/// Resolves hosts using the system resolver, dropping addresses the filter
+/// doesn't allow. Checking the resolved addresses rather than the URL
+/// prevents hosts from resolving to internal addresses
+struct FilteredResolver {
+    filter: Arc<UrlFilter>,
+}
+
+impl Resolve for FilteredResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let filter = self.filter.clone();
+
+        Box::pin(async move {
+            let host = name.as_str();
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host, 0))
+                .await?
+                .filter(|address| filter.is_allowed_address(address.ip()))
+                .collect();
+
+            if addresses.is_empty() {
+                return Err(format!("{host} doesn't resolve to an allowed address").into());
+            }
+
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Whether the address is publicly routable, loopback, private, link-local,
+/// shared, multicast and other special purpose addresses are excluded. IPv6
+/// addresses embedding an IPv4 address are checked using the IPv4 address
+fn is_public_address(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => is_public_ipv4(address),
+        IpAddr::V6(address) => is_public_ipv6(address),
+    }
+}
+
+fn is_public_ipv4(address: Ipv4Addr) -> bool {
+    let [first, second, third, _] = address.octets();
+
+    !(address.is_unspecified()
+        || address.is_loopback()
+        || address.is_private()
+        || address.is_link_local()
+        || address.is_broadcast()
+        || address.is_multicast()
+        || address.is_documentation()
+        // Shared address space (100.64.0.0/10)
+        || (first == 100 && (second & 0b1100_0000) == 64)
+        // IETF protocol assignments (192.0.0.0/24)
+        || (first == 192 && second == 0 && third == 0)
+        // Benchmarking (198.18.0.0/15)
+        || (first == 198 && (second & 0b1111_1110) == 18)
+        // "This network" (0.0.0.0/8) and reserved (240.0.0.0/4)
+        || first == 0
+        || first >= 240)
+}
+
+fn is_public_ipv6(address: Ipv6Addr) -> bool {
+    if let Some(embedded) = embedded_ipv4(address) {
+        return is_public_ipv4(embedded);
+    }
+
+    let segments = address.segments();
+
+    !(address.is_unspecified()
+        || address.is_loopback()
+        || address.is_multicast()
+        || address.is_unique_local()
+        || address.is_unicast_link_local()
+        // Deprecated IPv4-compatible addresses (::/96)
+        || segments[..6].iter().all(|segment| *segment == 0)
+        // Local-use NAT64 (64:ff9b:1::/48)
+        || segments[..3] == [0x64, 0xff9b, 0x1]
+        // Teredo (2001::/32)
+        || segments[..2] == [0x2001, 0]
+        // Documentation (2001:db8::/32)
+        || segments[..2] == [0x2001, 0xdb8]
+        // Deprecated site-local (fec0::/10)
+        || (segments[0] & 0xffc0) == 0xfec0)
+}
+
+/// IPv4 address embedded within IPv4-mapped (::ffff:0:0/96), NAT64
+/// (64:ff9b::/96) and 6to4 (2002::/16) addresses, requests to these reach
+/// the embedded address
+fn embedded_ipv4(address: Ipv6Addr) -> Option<Ipv4Addr> {
+    if let Some(mapped) = address.to_ipv4_mapped() {
+        return Some(mapped);
+    }
+
+    let segments = address.segments();
+    let [_, _, _, _, _, _, _, _, _, _, _, _, a, b, c, d] = address.octets();
+
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        return Some(Ipv4Addr::new(a, b, c, d));
+    }
+
+    if segments[0] == 0x2002 {
+        let [a, b] = segments[1].to_be_bytes();
+        let [c, d] = segments[2].to_be_bytes();
+        return Some(Ipv4Addr::new(a, b, c, d));
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_public(address: &str) -> bool {
+        is_public_address(address.parse().unwrap())
+    }
+
+    #[test]
+    fn test_public_addresses() {
+        assert!(is_public("1.1.1.1"));
+        assert!(is_public("93.184.216.34"));
+        assert!(is_public("2606:4700:4700::1111"));
+    }
+
+    #[test]
+    fn test_private_ipv4_ranges() {
+        for address in [
+            "0.0.0.0",
+            "0.1.2.3",
+            "10.0.0.1",
+            "127.0.0.1",
+            "169.254.169.254",
+            "172.16.0.1",
+            "192.168.1.1",
+            "224.0.0.1",
+            "255.255.255.255",
+        ] {
+            assert!(!is_public(address), "{address}");
+        }
+    }
+
+    #[test]
+    fn test_shared_address_space() {
+        assert!(!is_public("100.64.0.1"));
+        assert!(!is_public("100.127.255.255"));
+        assert!(is_public("100.63.255.255"));
+        assert!(is_public("100.128.0.0"));
+    }
+
+    #[test]
+    fn test_reserved_ipv4() {
+        assert!(!is_public("240.0.0.1"));
+        assert!(!is_public("254.255.255.255"));
+        assert!(!is_public("239.255.255.255"));
+        assert!(is_public("223.255.255.255"));
+    }
+
+    #[test]
+    fn test_protocol_assignments() {
+        assert!(!is_public("192.0.0.1"));
+        assert!(!is_public("192.0.0.255"));
+        assert!(is_public("192.0.1.1"));
+    }
+
+    #[test]
+    fn test_benchmarking() {
+        assert!(!is_public("198.18.0.1"));
+        assert!(!is_public("198.19.255.255"));
+        assert!(is_public("198.17.255.255"));
+        assert!(is_public("198.20.0.0"));
+    }
+
+    #[test]
+    fn test_ipv4_documentation() {
+        assert!(!is_public("192.0.2.1"));
+        assert!(!is_public("198.51.100.1"));
+        assert!(!is_public("203.0.113.1"));
+    }
+
+    #[test]
+    fn test_private_ipv6_ranges() {
+        for address in [
+            "::", "::1", "fc00::1", "fd12::1", "fe80::1", "ff02::1", "fec0::1",
+        ] {
+            assert!(!is_public(address), "{address}");
+        }
+    }
+
+    #[test]
+    fn test_ipv6_documentation() {
+        assert!(!is_public("2001:db8::1"));
+        assert!(!is_public("2001:db8:ffff::1"));
+    }
+
+    #[test]
+    fn test_teredo() {
+        assert!(!is_public("2001::1"));
+        assert!(!is_public("2001:0:4136:e378::1"));
+    }
+
+    #[test]
+    fn test_ipv4_mapped() {
+        assert!(!is_public("::ffff:127.0.0.1"));
+        assert!(!is_public("::ffff:10.0.0.1"));
+        assert!(!is_public("::ffff:169.254.169.254"));
+        assert!(is_public("::ffff:1.1.1.1"));
+    }
+
+    #[test]
+    fn test_ipv4_compatible() {
+        assert!(!is_public("::127.0.0.1"));
+        assert!(!is_public("::10.0.0.1"));
+        assert!(!is_public("::1.1.1.1"));
+    }
+
+    #[test]
+    fn test_nat64() {
+        assert!(!is_public("64:ff9b::127.0.0.1"));
+        assert!(!is_public("64:ff9b::a9fe:a9fe"));
+        assert!(is_public("64:ff9b::1.1.1.1"));
+        assert!(!is_public("64:ff9b:1::1.1.1.1"));
+    }
+
+    #[test]
+    fn test_6to4() {
+        // 2002:7f00:1:: embeds 127.0.0.1
+        assert!(!is_public("2002:7f00:1::"));
+        // 2002:c0a8:101:: embeds 192.168.1.1
+        assert!(!is_public("2002:c0a8:101::1"));
+        // 2002:101:101:: embeds 1.1.1.1
+        assert!(is_public("2002:101:101::1"));
+    }
+}
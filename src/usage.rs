@@ -0,0 +1,186 @@
+use axum::http::{HeaderName, HeaderValue};
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+
+/// Response header with the wall time in milliseconds spent running x2t
+const WALL_TIME_HEADER: HeaderName = HeaderName::from_static("x-conversion-wall-time-ms");
+
+/// Response header with the CPU time in milliseconds used by x2t
+const CPU_TIME_HEADER: HeaderName = HeaderName::from_static("x-conversion-cpu-time-ms");
+
+/// Response header with the peak resident set size in bytes of x2t
+const PEAK_RSS_HEADER: HeaderName = HeaderName::from_static("x-conversion-peak-rss");
+
+/// Interval between samples of a running process
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Clock ticks per second used for CPU times in /proc (USER_HZ), fixed at
+/// 100 on Linux regardless of the kernel tick rate
+const CLOCK_TICKS_PER_SECOND: u64 = 100;
+
+/// Resource usage of the x2t processes of a conversion
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResourceUsage {
+    /// Time spent running x2t
+    pub wall_time: Duration,
+    /// User and system CPU time used by x2t
+    pub cpu_time: Duration,
+    /// Largest resident set size of any of the x2t processes in bytes
+    pub peak_rss: u64,
+}
+
+impl ResourceUsage {
+    /// Response headers describing the usage, included when diagnostics are requested
+    pub fn headers(&self) -> [(HeaderName, HeaderValue); 3] {
+        [
+            (
+                WALL_TIME_HEADER,
+                HeaderValue::from(self.wall_time.as_millis() as u64),
+            ),
+            (
+                CPU_TIME_HEADER,
+                HeaderValue::from(self.cpu_time.as_millis() as u64),
+            ),
+            (PEAK_RSS_HEADER, HeaderValue::from(self.peak_rss)),
+        ]
+    }
+}
+
+/// Resource usage of every x2t process run for a conversion, including
+/// retries and each step of the conversion
+#[derive(Default)]
+pub struct ConversionUsage {
+    usage: Mutex<ResourceUsage>,
+}
+
+impl ConversionUsage {
+    /// Add the usage of a finished x2t process
+    pub fn record(&self, usage: ResourceUsage) {
+        let mut total = self.usage.lock().expect("conversion usage lock poisoned");
+        total.wall_time += usage.wall_time;
+        total.cpu_time += usage.cpu_time;
+        total.peak_rss = total.peak_rss.max(usage.peak_rss);
+    }
+
+    /// Total usage of the conversion so far
+    pub fn total(&self) -> ResourceUsage {
+        *self.usage.lock().expect("conversion usage lock poisoned")
+    }
+}
+
+/// Samples the CPU time and peak memory of a running process from /proc, on
+/// platforms without /proc only the wall time is known
+pub struct ProcessMonitor {
+    /// CPU time of the process in clock ticks as of the last sample
+    cpu_ticks: Arc<AtomicU64>,
+    /// Peak resident set size of the process in bytes as of the last sample
+    peak_rss: Arc<AtomicU64>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl ProcessMonitor {
+    /// Start sampling the process with the provided ID
+    pub fn start(pid: Option<u32>) -> Self {
+        let cpu_ticks = Arc::new(AtomicU64::new(0));
+        let peak_rss = Arc::new(AtomicU64::new(0));
+
+        let task = pid.map(|pid| {
+            let cpu_ticks = cpu_ticks.clone();
+            let peak_rss = peak_rss.clone();
+
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+
+                loop {
+                    interval.tick().await;
+
+                    // Process has exited and been reaped
+                    let Some(ticks) = read_cpu_ticks(pid).await else {
+                        break;
+                    };
+                    cpu_ticks.fetch_max(ticks, Ordering::Relaxed);
+
+                    if let Some(rss) = read_peak_rss(pid).await {
+                        peak_rss.fetch_max(rss, Ordering::Relaxed);
+                    }
+                }
+            })
+        });
+
+        Self {
+            cpu_ticks,
+            peak_rss,
+            task,
+        }
+    }
+
+    /// Stop sampling once the process has exited, returns the usage as of
+    /// the last sample
+    ///
+    /// ## Arguments
+    /// * `wall_time` - Time the process ran for
+    pub fn finish(mut self, wall_time: Duration) -> ResourceUsage {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+
+        let cpu_ticks = self.cpu_ticks.load(Ordering::Relaxed);
+
+        ResourceUsage {
+            wall_time,
+            cpu_time: Duration::from_millis(cpu_ticks * 1000 / CLOCK_TICKS_PER_SECOND),
+            peak_rss: self.peak_rss.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Drop for ProcessMonitor {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+/// Read the user and system CPU time of a process and its waited for
+/// children in clock ticks from /proc/[pid]/stat
+async fn read_cpu_ticks(pid: u32) -> Option<u64> {
+    let stat = tokio::fs::read_to_string(format!("/proc/{pid}/stat"))
+        .await
+        .ok()?;
+
+    // Name of the process can contain spaces, fields are read after it
+    let (_, fields) = stat.rsplit_once(')')?;
+    let fields: Vec<&str> = fields.split_whitespace().collect();
+
+    // utime, stime, cutime and cstime (fields 14 to 17)
+    fields
+        .get(11..15)?
+        .iter()
+        .map(|value| value.parse::<u64>().ok())
+        .sum()
+}
+
+/// Read the peak resident set size of a process in bytes from /proc/[pid]/status
+async fn read_peak_rss(pid: u32) -> Option<u64> {
+    let status = tokio::fs::read_to_string(format!("/proc/{pid}/status"))
+        .await
+        .ok()?;
+
+    let kilobytes = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+
+    Some(kilobytes * 1024)
+}
@@ -0,0 +1,109 @@
+use serde_json::{Value, json};
+
+use crate::{ErrorKind, ErrorResponse};
+
+/// Default opacity of the watermark text
+const DEFAULT_OPACITY: f32 = 0.3;
+/// Default rotation of the watermark in degrees
+const DEFAULT_ANGLE: f32 = -45.0;
+/// Default font size of the watermark text in points
+const DEFAULT_FONT_SIZE: u32 = 48;
+
+/// Text watermark stamped onto every page of the converted document
+#[derive(Debug)]
+pub struct Watermark {
+    /// Text to display
+    pub text: String,
+    /// Opacity of the text from 0 (invisible) to 1 (opaque)
+    pub opacity: f32,
+    /// Rotation of the watermark in degrees
+    pub angle: f32,
+    /// Font size of the text in points
+    pub font_size: u32,
+}
+
+impl Watermark {
+    /// Create a watermark from the requested parameters, returns None when
+    /// no watermark text was provided
+    pub fn from_params(
+        text: Option<String>,
+        opacity: Option<f32>,
+        angle: Option<f32>,
+        font_size: Option<u32>,
+    ) -> Result<Option<Watermark>, ErrorResponse> {
+        let text = match text {
+            Some(text) if !text.trim().is_empty() => text,
+            _ if opacity.is_some() || angle.is_some() || font_size.is_some() => {
+                return Err(ErrorResponse {
+                    code: None,
+                    kind: ErrorKind::InvalidRequest,
+                    message: "watermark options require watermark_text".to_string(),
+                    backtrace: None,
+                });
+            }
+            _ => return Ok(None),
+        };
+
+        let opacity = opacity.unwrap_or(DEFAULT_OPACITY);
+        if !(0.0..=1.0).contains(&opacity) {
+            return Err(ErrorResponse {
+                code: None,
+                kind: ErrorKind::InvalidRequest,
+                message: "watermark_opacity must be between 0 and 1".to_string(),
+                backtrace: None,
+            });
+        }
+
+        let angle = angle.unwrap_or(DEFAULT_ANGLE);
+        if !angle.is_finite() {
+            return Err(ErrorResponse {
+                code: None,
+                kind: ErrorKind::InvalidRequest,
+                message: "watermark_angle must be a number".to_string(),
+                backtrace: None,
+            });
+        }
+
+        let font_size = font_size.unwrap_or(DEFAULT_FONT_SIZE);
+        if font_size == 0 {
+            return Err(ErrorResponse {
+                code: None,
+                kind: ErrorKind::InvalidRequest,
+                message: "watermark_font_size must be greater than 0".to_string(),
+                backtrace: None,
+            });
+        }
+
+        Ok(Some(Watermark {
+            text,
+            opacity,
+            angle,
+            font_size,
+        }))
+    }
+
+    /// Create the x2t "watermark_on_draw" JSON param drawing this watermark, uses
+    /// the same structure as the DocumentServer editors
+    pub fn json_param(&self) -> Value {
+        json!({
+            "transparent": self.opacity,
+            "type": "rect",
+            "width": 0,
+            "height": 0,
+            "rotate": self.angle,
+            "margins": [0, 0, 0, 0],
+            "align": 1,
+            "paragraphs": [{
+                "align": 2,
+                "linespacing": 1,
+                "runs": [{
+                    "text": self.text,
+                    "fill": [0, 0, 0],
+                    "font-family": "Arial",
+                    "font-size": self.font_size,
+                    "bold": true
+                }]
+            }]
+        })
+    }
+}
@@ -0,0 +1,156 @@
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::{sync::Arc, time::Duration};
+use uuid::Uuid;
+
+use crate::{ErrorResponse, jobs::JobStatus, url_filter::UrlFilter};
+
+/// Maximum number of attempts to deliver a webhook
+const WEBHOOK_MAX_ATTEMPTS: u32 = 5;
+
+/// Delay before the first retry, doubled on each following attempt
+const WEBHOOK_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Timeout for a single webhook delivery attempt
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Header containing the HMAC-SHA256 signature of the payload
+const SIGNATURE_HEADER: &str = "X-Signature-256";
+
+/// Delivers job completion notifications to caller provided callback URLs
+pub struct WebhookSender {
+    http: reqwest::Client,
+    /// Restricts the callback URLs webhooks can be sent to
+    url_filter: Arc<UrlFilter>,
+    /// Secret used to sign webhook payloads
+    secret: Option<String>,
+    /// Public base URL of the server used to build result URLs, result URLs
+    /// are omitted when not configured
+    public_url: Option<String>,
+}
+
+/// Payload sent to the callback URL
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    /// ID of the job that finished
+    id: Uuid,
+    /// Final status of the job
+    status: JobStatus,
+    /// URL the result can be downloaded from when completed, only present
+    /// when the public URL of the server is configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result_url: Option<String>,
+    /// Error that caused the job to fail
+    error: Option<&'a ErrorResponse>,
+}
+
+impl WebhookSender {
+    pub fn new(
+        secret: Option<String>,
+        public_url: Option<String>,
+        url_filter: UrlFilter,
+    ) -> reqwest::Result<Self> {
+        let url_filter = Arc::new(url_filter);
+        let http = url_filter
+            .clone()
+            .apply(reqwest::Client::builder())
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()?;
+
+        Ok(Self {
+            http,
+            url_filter,
+            secret,
+            public_url: public_url.map(|public_url| public_url.trim_end_matches('/').to_string()),
+        })
+    }
+
+    /// Check that a callback URL is an absolute HTTP(S) URL the server is
+    /// allowed to send webhooks to
+    pub async fn check_callback_url(&self, callback_url: &str) -> Result<(), String> {
+        let url = reqwest::Url::parse(callback_url)
+            .map_err(|_| "callback_url must be an absolute http or https url".to_string())?;
+
+        self.url_filter
+            .check_resolved_url(&url)
+            .await
+            .map_err(|message| format!("invalid callback_url: {message}"))
+    }
+
+    /// Notify the callback URL that a job has finished, retrying with a
+    /// backoff when delivery fails
+    pub async fn send(
+        &self,
+        callback_url: &str,
+        id: Uuid,
+        status: JobStatus,
+        error: Option<&ErrorResponse>,
+    ) {
+        let result_url = self
+            .public_url
+            .as_ref()
+            .filter(|_| status == JobStatus::Completed)
+            .map(|public_url| format!("{public_url}/jobs/{id}/result"));
+
+        let payload = WebhookPayload {
+            id,
+            status,
+            result_url,
+            error,
+        };
+
+        let body = match serde_json::to_vec(&payload) {
+            Ok(value) => value,
+            Err(err) => {
+                tracing::error!(?err, "failed to serialize webhook payload");
+                return;
+            }
+        };
+
+        let signature = self.secret.as_deref().map(|secret| sign(secret, &body));
+        let mut retry_delay = WEBHOOK_RETRY_DELAY;
+
+        for attempt in 1..=WEBHOOK_MAX_ATTEMPTS {
+            let mut request = self
+                .http
+                .post(callback_url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+
+            if let Some(signature) = &signature {
+                request = request.header(SIGNATURE_HEADER, signature);
+            }
+
+            match request.send().await {
+                Ok(response) if response.status().is_success() => return,
+                Ok(response) => {
+                    tracing::warn!(
+                        %id,
+                        attempt,
+                        status = %response.status(),
+                        "webhook rejected by callback url"
+                    );
+                }
+                Err(err) => {
+                    tracing::warn!(%id, attempt, ?err, "failed to deliver webhook");
+                }
+            }
+
+            if attempt < WEBHOOK_MAX_ATTEMPTS {
+                tokio::time::sleep(retry_delay).await;
+                retry_delay *= 2;
+            }
+        }
+
+        tracing::error!(%id, "giving up delivering webhook");
+    }
+}
+
+/// Sign the payload using HMAC-SHA256 producing a "sha256=<hex>" signature
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
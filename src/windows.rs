@@ -0,0 +1,86 @@
+use std::{ffi::c_void, io};
+use tokio::process::Child;
+use windows_sys::Win32::{
+    Foundation::{CloseHandle, HANDLE},
+    System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+        JOB_OBJECT_LIMIT_PROCESS_MEMORY, JOB_OBJECT_LIMIT_PROCESS_TIME,
+        JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JobObjectExtendedLimitInformation,
+        SetInformationJobObject,
+    },
+};
+
+use crate::limits::ProcessLimits;
+
+/// Job object a process is assigned to, takes the place of rlimits on
+/// Windows. Every process in the job, including any started by the assigned
+/// process, is killed when the job is dropped
+pub struct JobObject {
+    handle: HANDLE,
+}
+
+// SAFETY: Job object handles aren't tied to the thread that created them
+unsafe impl Send for JobObject {}
+unsafe impl Sync for JobObject {}
+
+impl JobObject {
+    /// Create a job enforcing the memory and CPU time limits and assign the
+    /// child process to it
+    ///
+    /// ## Arguments
+    /// * `child` - Process to assign to the job
+    /// * `limits` - Resource limits for the process
+    pub fn assign(child: &Child, limits: &ProcessLimits) -> io::Result<Self> {
+        let process = child
+            .raw_handle()
+            .ok_or_else(|| io::Error::other("process has already exited"))?;
+
+        let handle = unsafe { CreateJobObjectW(std::ptr::null(), std::ptr::null()) };
+        if handle.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+
+        let job = Self { handle };
+
+        let mut info = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+        info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+
+        if let Some(max_memory) = limits.max_memory {
+            info.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_PROCESS_MEMORY;
+            info.ProcessMemoryLimit = usize::try_from(max_memory).unwrap_or(usize::MAX);
+        }
+
+        if let Some(max_cpu_time) = limits.max_cpu_time {
+            // Time limit is measured in 100 nanosecond intervals
+            info.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_PROCESS_TIME;
+            info.BasicLimitInformation.PerProcessUserTimeLimit =
+                i64::try_from(max_cpu_time.saturating_mul(10_000_000)).unwrap_or(i64::MAX);
+        }
+
+        let result = unsafe {
+            SetInformationJobObject(
+                job.handle,
+                JobObjectExtendedLimitInformation,
+                (&info as *const JOBOBJECT_EXTENDED_LIMIT_INFORMATION).cast::<c_void>(),
+                size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+            )
+        };
+        if result == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if unsafe { AssignProcessToJobObject(job.handle, process) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(job)
+    }
+}
+
+impl Drop for JobObject {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.handle);
+        }
+    }
+}
@@ -0,0 +1,5 @@
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    onlyoffice_convert_test_utils::fake_x2t::run()
+}
@@ -0,0 +1,98 @@
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+
+/// Environment variable containing the exit code the fake x2t fails with,
+/// no output is written when set to a non zero value
+pub const EXIT_CODE_VAR: &str = "FAKE_X2T_EXIT_CODE";
+
+/// Environment variable containing the number of milliseconds the fake x2t
+/// waits before converting
+pub const DELAY_VAR: &str = "FAKE_X2T_DELAY_MS";
+
+/// Environment variable containing the path of a file the fake x2t copies
+/// as the output instead of the canned PDF
+pub const OUTPUT_VAR: &str = "FAKE_X2T_OUTPUT";
+
+/// Single page PDF written as the output of conversions to PDF
+pub const CANNED_PDF: &[u8] = include_bytes!("../assets/sample.pdf");
+
+/// Run the fake x2t with the config path provided as the first argument,
+/// mirrors the behavior of x2t closely enough for the server
+pub fn run() -> ExitCode {
+    let Some(config_path) = std::env::args_os().nth(1) else {
+        eprintln!("usage: x2t <config.xml>");
+        return ExitCode::FAILURE;
+    };
+
+    match convert(Path::new(&config_path)) {
+        Ok(exit_code) => exit_code,
+        Err(err) => {
+            eprintln!("fake x2t failed: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn convert(config_path: &Path) -> std::io::Result<ExitCode> {
+    if let Some(delay) = env_number(DELAY_VAR) {
+        std::thread::sleep(Duration::from_millis(delay));
+    }
+
+    if let Some(exit_code) = env_number(EXIT_CODE_VAR).filter(|exit_code| *exit_code != 0) {
+        eprintln!("simulated x2t failure");
+        return Ok(ExitCode::from(u8::try_from(exit_code).unwrap_or(u8::MAX)));
+    }
+
+    let config = std::fs::read_to_string(config_path)?;
+    let input_path = config_path_value(&config, "m_sFileFrom")?;
+    let output_path = config_path_value(&config, "m_sFileTo")?;
+
+    match std::env::var_os(OUTPUT_VAR) {
+        Some(canned_output) => {
+            std::fs::copy(canned_output, &output_path)?;
+        }
+        None if output_path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("pdf")) =>
+        {
+            std::fs::write(&output_path, CANNED_PDF)?;
+        }
+        None => {
+            std::fs::copy(&input_path, &output_path)?;
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Parse a numeric environment variable, None when missing or invalid
+fn env_number(name: &str) -> Option<u64> {
+    std::env::var(name).ok()?.trim().parse().ok()
+}
+
+/// Read the path from an element of the x2t config
+fn config_path_value(config: &str, name: &str) -> std::io::Result<PathBuf> {
+    let start_tag = format!("<{name}>");
+    let end_tag = format!("</{name}>");
+
+    let value = config
+        .split_once(&start_tag)
+        .and_then(|(_, rest)| rest.split_once(&end_tag))
+        .map(|(value, _)| value)
+        .ok_or_else(|| std::io::Error::other(format!("config is missing {name}")))?;
+
+    Ok(PathBuf::from(unescape_xml(value)))
+}
+
+/// Reverse the escaping the server applies to config values
+fn unescape_xml(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
@@ -0,0 +1,30 @@
+//! Utilities for writing integration tests against onlyoffice-convert-server
+//! without installing ONLYOFFICE.
+//!
+//! The `fake-x2t` binary stands in for x2t, it writes a canned PDF (or a copy
+//! of the input for other formats) and can simulate failures and slow
+//! conversions. [TestServer] starts the server on a random port using the
+//! fake x2t and stops it when dropped.
+//!
+//! Both binaries are found next to the test executable in the cargo target
+//! directory, `cargo test --workspace` builds both. Otherwise build them
+//! first (i.e `cargo build -p onlyoffice-convert-server -p
+//! onlyoffice-convert-test-utils`) or provide their paths with the
+//! `CONVERT_SERVER_BIN` and `FAKE_X2T_BIN` environment variables.
+//!
+//! ```no_run
+//! use onlyoffice_convert_test_utils::TestServer;
+//! use std::time::Duration;
+//!
+//! let server = TestServer::builder()
+//!     .delay(Duration::from_millis(500))
+//!     .start()
+//!     .unwrap();
+//!
+//! let convert_url = server.url("/convert");
+//! ```
+
+pub mod fake_x2t;
+mod server;
+
+pub use server::{TestServer, TestServerBuilder};
@@ -0,0 +1,246 @@
+use std::{
+    ffi::{OsStr, OsString},
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+use tempfile::TempDir;
+
+use crate::fake_x2t::{DELAY_VAR, EXIT_CODE_VAR, OUTPUT_VAR};
+
+/// Environment variable containing the path to the server binary
+const SERVER_BIN_VAR: &str = "CONVERT_SERVER_BIN";
+
+/// Environment variable containing the path to the fake x2t binary
+const FAKE_X2T_BIN_VAR: &str = "FAKE_X2T_BIN";
+
+/// Name of the server binary in the cargo target directory
+const SERVER_BIN_NAME: &str = "onlyoffice-convert-server";
+
+/// Name of the fake x2t binary in the cargo target directory
+const FAKE_X2T_BIN_NAME: &str = "fake-x2t";
+
+/// Name the server expects the x2t binary to have
+#[cfg(not(windows))]
+const X2T_BIN: &str = "x2t";
+#[cfg(windows)]
+const X2T_BIN: &str = "x2t.exe";
+
+/// Default time to wait for the server to start responding
+const DEFAULT_STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time between checks of whether the server has started
+const STARTUP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Server running with the fake x2t on a random local port, the server is
+/// stopped and its files removed when dropped
+pub struct TestServer {
+    process: Child,
+    address: SocketAddr,
+    _dir: TempDir,
+}
+
+/// Builder for configuring the server and fake x2t before starting it
+#[derive(Default)]
+pub struct TestServerBuilder {
+    server_bin: Option<PathBuf>,
+    fake_x2t_bin: Option<PathBuf>,
+    startup_timeout: Option<Duration>,
+    envs: Vec<(OsString, OsString)>,
+}
+
+impl TestServer {
+    /// Start a server with the default configuration
+    pub fn start() -> std::io::Result<Self> {
+        Self::builder().start()
+    }
+
+    pub fn builder() -> TestServerBuilder {
+        TestServerBuilder::default()
+    }
+
+    /// Address the server is listening on
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// URL of a path on the server (i.e "/convert")
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{path}", self.address)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        _ = self.process.kill();
+        _ = self.process.wait();
+    }
+}
+
+impl TestServerBuilder {
+    /// Path to the server binary, found in the cargo target directory when
+    /// not provided
+    pub fn server_bin(mut self, path: impl Into<PathBuf>) -> Self {
+        self.server_bin = Some(path.into());
+        self
+    }
+
+    /// Path to the fake x2t binary, found in the cargo target directory when
+    /// not provided
+    pub fn fake_x2t_bin(mut self, path: impl Into<PathBuf>) -> Self {
+        self.fake_x2t_bin = Some(path.into());
+        self
+    }
+
+    /// Maximum time to wait for the server to start, defaults to 10 seconds
+    pub fn startup_timeout(mut self, timeout: Duration) -> Self {
+        self.startup_timeout = Some(timeout);
+        self
+    }
+
+    /// Make every conversion fail with the x2t exit code
+    pub fn exit_code(self, exit_code: u8) -> Self {
+        self.env(EXIT_CODE_VAR, exit_code.to_string())
+    }
+
+    /// Make every conversion take at least the provided duration
+    pub fn delay(self, delay: Duration) -> Self {
+        self.env(DELAY_VAR, delay.as_millis().to_string())
+    }
+
+    /// Use a copy of the file as the output of every conversion
+    pub fn output(self, path: impl AsRef<Path>) -> Self {
+        self.env(OUTPUT_VAR, path.as_ref())
+    }
+
+    /// Set an environment variable for the server, used to configure the
+    /// server (i.e "MAX_CONCURRENT_CONVERSIONS"). The variables are also
+    /// visible to the fake x2t
+    pub fn env(mut self, name: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> Self {
+        self.envs
+            .push((name.as_ref().to_owned(), value.as_ref().to_owned()));
+        self
+    }
+
+    /// Start the server, returns once it responds to health checks
+    pub fn start(self) -> std::io::Result<TestServer> {
+        let server_bin = match self.server_bin {
+            Some(path) => path,
+            None => find_bin(SERVER_BIN_VAR, SERVER_BIN_NAME)?,
+        };
+        let fake_x2t_bin = match self.fake_x2t_bin {
+            Some(path) => path,
+            None => find_bin(FAKE_X2T_BIN_VAR, FAKE_X2T_BIN_NAME)?,
+        };
+
+        let dir = tempfile::tempdir()?;
+        let x2t_path = dir.path().join("x2t");
+        let fonts_path = dir.path().join("fonts");
+        let temp_path = dir.path().join("tmp");
+
+        for path in [&x2t_path, &fonts_path, &temp_path] {
+            std::fs::create_dir_all(path)?;
+        }
+
+        std::fs::copy(&fake_x2t_bin, x2t_path.join(X2T_BIN))?;
+
+        let address = unused_address()?;
+
+        // Running from the temporary directory keeps the server from loading
+        // a .env file from the working directory of the tests
+        let mut process = Command::new(&server_bin)
+            .current_dir(dir.path())
+            .env("SERVER_ADDRESS", address.to_string())
+            .env("X2T_PATH", &x2t_path)
+            .env("X2T_FONTS_PATH", &fonts_path)
+            .env("TMPDIR", &temp_path)
+            .env("TMP", &temp_path)
+            .env("TEMP", &temp_path)
+            .envs(self.envs)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|err| {
+                std::io::Error::new(
+                    err.kind(),
+                    format!("failed to start {}: {err}", server_bin.display()),
+                )
+            })?;
+
+        let timeout = self.startup_timeout.unwrap_or(DEFAULT_STARTUP_TIMEOUT);
+        let started = Instant::now();
+
+        while !is_healthy(address) {
+            if let Some(status) = process.try_wait()? {
+                return Err(std::io::Error::other(format!(
+                    "server exited during startup ({status})"
+                )));
+            }
+
+            if started.elapsed() > timeout {
+                _ = process.kill();
+                _ = process.wait();
+
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "server didn't start before the startup timeout",
+                ));
+            }
+
+            std::thread::sleep(STARTUP_POLL_INTERVAL);
+        }
+
+        Ok(TestServer {
+            process,
+            address,
+            _dir: dir,
+        })
+    }
+}
+
+/// Find a binary from its environment variable or in the cargo target
+/// directory the test executable was built in
+fn find_bin(var: &str, name: &str) -> std::io::Result<PathBuf> {
+    if let Some(path) = std::env::var_os(var) {
+        return Ok(PathBuf::from(path));
+    }
+
+    let file_name = format!("{name}{}", std::env::consts::EXE_SUFFIX);
+    let current_exe = std::env::current_exe()?;
+
+    // Test executables are built in target/{profile}/deps
+    current_exe
+        .ancestors()
+        .skip(1)
+        .take(2)
+        .map(|dir| dir.join(&file_name))
+        .find(|path| path.is_file())
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{file_name} not found in the target directory, build it or set {var}"),
+            )
+        })
+}
+
+/// Local address that nothing is currently listening on
+fn unused_address() -> std::io::Result<SocketAddr> {
+    TcpListener::bind("127.0.0.1:0")?.local_addr()
+}
+
+/// Whether the server responds to a health check with a success status
+fn is_healthy(address: SocketAddr) -> bool {
+    let Ok(mut stream) = TcpStream::connect_timeout(&address, STARTUP_POLL_INTERVAL) else {
+        return false;
+    };
+
+    let request = format!("GET /health HTTP/1.1\r\nHost: {address}\r\nConnection: close\r\n\r\n");
+    if stream.write_all(request.as_bytes()).is_err() {
+        return false;
+    }
+
+    let mut status_line = [0; 12];
+    stream.read_exact(&mut status_line).is_ok() && status_line.ends_with(b" 200")
+}
@@ -0,0 +1,99 @@
+use onlyoffice_convert_test_utils::fake_x2t::{CANNED_PDF, DELAY_VAR, EXIT_CODE_VAR, OUTPUT_VAR};
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+/// Write an x2t config converting the input to the output path
+fn write_config(dir: &Path, input_path: &Path, output_path: &Path) -> PathBuf {
+    let config_path = dir.join("config.xml");
+    let config = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?><TaskQueueDataConvert>\
+         <m_sFileFrom>{}</m_sFileFrom><m_sFileTo>{}</m_sFileTo>\
+         </TaskQueueDataConvert>",
+        input_path.display(),
+        output_path.display()
+    );
+    std::fs::write(&config_path, config).unwrap();
+    config_path
+}
+
+fn run(config_path: &Path, envs: &[(&str, &str)]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_fake-x2t"))
+        .arg(config_path)
+        .env_remove(EXIT_CODE_VAR)
+        .env_remove(DELAY_VAR)
+        .env_remove(OUTPUT_VAR)
+        .envs(envs.iter().copied())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_writes_canned_pdf() {
+    let dir = tempfile::tempdir().unwrap();
+    let input_path = dir.path().join("input.docx");
+    let output_path = dir.path().join("output.pdf");
+    std::fs::write(&input_path, b"document").unwrap();
+
+    let output = run(&write_config(dir.path(), &input_path, &output_path), &[]);
+
+    assert!(output.status.success());
+    assert_eq!(std::fs::read(&output_path).unwrap(), CANNED_PDF);
+}
+
+#[test]
+fn test_copies_input_for_other_formats() {
+    let dir = tempfile::tempdir().unwrap();
+    let input_path = dir.path().join("input.txt");
+    let output_path = dir.path().join("output.docx");
+    std::fs::write(&input_path, b"document").unwrap();
+
+    let output = run(&write_config(dir.path(), &input_path, &output_path), &[]);
+
+    assert!(output.status.success());
+    assert_eq!(std::fs::read(&output_path).unwrap(), b"document");
+}
+
+#[test]
+fn test_copies_canned_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let input_path = dir.path().join("input.txt");
+    let canned_path = dir.path().join("canned.bin");
+    let output_path = dir.path().join("output.pdf");
+    std::fs::write(&input_path, b"document").unwrap();
+    std::fs::write(&canned_path, b"canned").unwrap();
+
+    let output = run(
+        &write_config(dir.path(), &input_path, &output_path),
+        &[(OUTPUT_VAR, canned_path.to_str().unwrap())],
+    );
+
+    assert!(output.status.success());
+    assert_eq!(std::fs::read(&output_path).unwrap(), b"canned");
+}
+
+#[test]
+fn test_simulated_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let input_path = dir.path().join("input.txt");
+    let output_path = dir.path().join("output.pdf");
+    std::fs::write(&input_path, b"document").unwrap();
+
+    let output = run(
+        &write_config(dir.path(), &input_path, &output_path),
+        &[(EXIT_CODE_VAR, "89")],
+    );
+
+    assert_eq!(output.status.code(), Some(89));
+    assert!(!output_path.exists());
+}
+
+#[test]
+fn test_missing_config() {
+    let output = Command::new(env!("CARGO_BIN_EXE_fake-x2t"))
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+}
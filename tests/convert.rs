@@ -0,0 +1,357 @@
+//! End to end tests running the server against the fake x2t from the test
+//! utils, the fake x2t is built along with the workspace tests (i.e `cargo
+//! test --workspace`) or can be provided with the `FAKE_X2T_BIN` variable
+
+use onlyoffice_convert_test_utils::{TestServer, TestServerBuilder, fake_x2t::CANNED_PDF};
+use reqwest::{
+    Client, StatusCode,
+    multipart::{Form, Part},
+};
+use serde_json::Value;
+use std::{
+    io::{Cursor, Read, Write},
+    time::{Duration, Instant},
+};
+use zip::{ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+/// Maximum time to wait for a job to finish
+const JOB_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn server() -> TestServerBuilder {
+    TestServer::builder().server_bin(env!("CARGO_BIN_EXE_onlyoffice-convert-server"))
+}
+
+fn file_part(file_name: &str, contents: &[u8]) -> Part {
+    Part::bytes(contents.to_vec()).file_name(file_name.to_string())
+}
+
+fn zip_entries(bytes: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).unwrap();
+
+    (0..archive.len())
+        .map(|index| {
+            let mut file = archive.by_index(index).unwrap();
+            let mut contents = Vec::new();
+            file.read_to_end(&mut contents).unwrap();
+            (file.name().to_string(), contents)
+        })
+        .collect()
+}
+
+async fn convert(client: &Client, server: &TestServer, form: Form) -> reqwest::Response {
+    client
+        .post(server.url("/convert"))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_convert_to_pdf() {
+    let server = server().start().unwrap();
+    let client = Client::new();
+
+    let form = Form::new().part("file", file_part("document.txt", b"hello world"));
+    let response = convert(&client, &server, form).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["content-type"].to_str().unwrap(),
+        "application/pdf"
+    );
+    assert!(
+        response.headers()["content-disposition"]
+            .to_str()
+            .unwrap()
+            .contains("document.pdf")
+    );
+    assert_eq!(response.bytes().await.unwrap(), CANNED_PDF);
+}
+
+#[tokio::test]
+async fn test_convert_target_format() {
+    let server = server().start().unwrap();
+    let client = Client::new();
+
+    let form = Form::new()
+        .text("target_format", "docx")
+        .part("file", file_part("document.txt", b"hello world"));
+    let response = convert(&client, &server, form).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.bytes().await.unwrap(), &b"hello world"[..]);
+}
+
+#[tokio::test]
+async fn test_convert_missing_file() {
+    let server = server().start().unwrap();
+    let client = Client::new();
+
+    let response = convert(&client, &server, Form::new().text("target_format", "pdf")).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["message"], "missing file to convert");
+}
+
+#[tokio::test]
+async fn test_convert_failure() {
+    let server = server().exit_code(1).start().unwrap();
+    let client = Client::new();
+
+    let form = Form::new().part("file", file_part("document.txt", b"hello world"));
+    let response = convert(&client, &server, form).await;
+
+    assert!(!response.status().is_success());
+    let body: Value = response.json().await.unwrap();
+    assert!(body["kind"].is_string());
+}
+
+#[tokio::test]
+async fn test_convert_deadline() {
+    let server = server().delay(Duration::from_secs(5)).start().unwrap();
+    let client = Client::new();
+
+    let form = Form::new().part("file", file_part("document.txt", b"hello world"));
+    let started = Instant::now();
+    let response = client
+        .post(server.url("/convert"))
+        .header("x-deadline-ms", "500")
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    assert!(started.elapsed() < Duration::from_secs(4));
+}
+
+#[tokio::test]
+async fn test_batch() {
+    let server = server().start().unwrap();
+    let client = Client::new();
+
+    let form = Form::new()
+        .text("target_format", "docx")
+        .part("file", file_part("first.txt", b"first"))
+        .part("file", file_part("second.txt", b"second"));
+    let response = client
+        .post(server.url("/convert/batch"))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["content-type"].to_str().unwrap(),
+        "application/zip"
+    );
+
+    let entries = zip_entries(&response.bytes().await.unwrap());
+    assert!(entries.contains(&("first.docx".to_string(), b"first".to_vec())));
+    assert!(entries.contains(&("second.docx".to_string(), b"second".to_vec())));
+}
+
+#[tokio::test]
+async fn test_batch_failure_listed() {
+    let server = server().exit_code(1).start().unwrap();
+    let client = Client::new();
+
+    let form = Form::new().part("file", file_part("first.txt", b"first"));
+    let response = client
+        .post(server.url("/convert/batch"))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Failed files are listed in the error manifest instead of the archive
+    let entries = zip_entries(&response.bytes().await.unwrap());
+    assert!(entries.iter().all(|(name, _)| name != "first.pdf"));
+    assert!(
+        entries
+            .iter()
+            .any(|(_, contents)| String::from_utf8_lossy(contents).contains("first.txt"))
+    );
+}
+
+#[tokio::test]
+async fn test_archive() {
+    let server = server().start().unwrap();
+    let client = Client::new();
+
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, contents) in [("a.txt", "first"), ("nested/b.txt", "second")] {
+        writer
+            .start_file(name, SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(contents.as_bytes()).unwrap();
+    }
+    let archive = writer.finish().unwrap().into_inner();
+
+    let form = Form::new()
+        .text("target_format", "docx")
+        .part("file", file_part("documents.zip", &archive));
+    let response = client
+        .post(server.url("/convert/archive"))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let entries = zip_entries(&response.bytes().await.unwrap());
+    assert!(entries.contains(&("a.docx".to_string(), b"first".to_vec())));
+    assert!(entries.contains(&("nested/b.docx".to_string(), b"second".to_vec())));
+
+    let (_, manifest) = entries
+        .iter()
+        .find(|(name, _)| name == "manifest.json")
+        .expect("archive should contain a manifest");
+    let manifest: Value = serde_json::from_slice(manifest).unwrap();
+    assert!(manifest.to_string().contains("nested/b.txt"));
+}
+
+#[tokio::test]
+async fn test_job() {
+    let server = server().start().unwrap();
+    let client = Client::new();
+
+    let form = Form::new().part("file", file_part("document.txt", b"hello world"));
+    let response = client
+        .post(server.url("/jobs"))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let job: Value = response.json().await.unwrap();
+    let id = job["id"].as_str().unwrap().to_string();
+
+    let started = Instant::now();
+    let job = loop {
+        let job: Value = client
+            .get(server.url(&format!("/jobs/{id}")))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        if job["status"] != "queued" && job["status"] != "running" {
+            break job;
+        }
+
+        assert!(started.elapsed() < JOB_TIMEOUT, "job didn't finish");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    };
+
+    assert_eq!(job["status"], "completed");
+    assert!(job["output_checksum"].is_string());
+
+    let response = client
+        .get(server.url(&format!("/jobs/{id}/result")))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.bytes().await.unwrap(), CANNED_PDF);
+}
+
+#[tokio::test]
+async fn test_cancel_job() {
+    let server = server().delay(Duration::from_secs(5)).start().unwrap();
+    let client = Client::new();
+
+    let form = Form::new().part("file", file_part("document.txt", b"hello world"));
+    let job: Value = client
+        .post(server.url("/jobs"))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let id = job["id"].as_str().unwrap();
+
+    let response = client
+        .delete(server.url(&format!("/jobs/{id}")))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let job: Value = client
+        .get(server.url(&format!("/jobs/{id}")))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(job["status"], "cancelled");
+
+    let response = client
+        .get(server.url(&format!("/jobs/{id}/result")))
+        .send()
+        .await
+        .unwrap();
+    assert!(!response.status().is_success());
+}
+
+#[tokio::test]
+async fn test_identical_conversions_coalesced() {
+    let server = server()
+        .delay(Duration::from_secs(1))
+        .env("MAX_CONCURRENT_CONVERSIONS", "1")
+        .start()
+        .unwrap();
+    let client = Client::new();
+
+    let request = || {
+        let form = Form::new().part("file", file_part("document.txt", b"hello world"));
+        convert(&client, &server, form)
+    };
+
+    // Only one conversion slot, running both would take at least 2 seconds
+    let started = Instant::now();
+    let (first, second) = tokio::join!(request(), request());
+    let elapsed = started.elapsed();
+
+    assert_eq!(first.status(), StatusCode::OK);
+    assert_eq!(second.status(), StatusCode::OK);
+    assert_eq!(first.bytes().await.unwrap(), CANNED_PDF);
+    assert_eq!(second.bytes().await.unwrap(), CANNED_PDF);
+    assert!(elapsed < Duration::from_millis(1900), "took {elapsed:?}");
+}
+
+#[tokio::test]
+async fn test_different_conversions_not_coalesced() {
+    let server = server()
+        .delay(Duration::from_secs(1))
+        .env("MAX_CONCURRENT_CONVERSIONS", "1")
+        .start()
+        .unwrap();
+    let client = Client::new();
+
+    let request = |contents: &'static [u8]| {
+        let form = Form::new().part("file", file_part("document.txt", contents));
+        convert(&client, &server, form)
+    };
+
+    let started = Instant::now();
+    let (first, second) = tokio::join!(request(b"first"), request(b"second"));
+
+    assert_eq!(first.status(), StatusCode::OK);
+    assert_eq!(second.status(), StatusCode::OK);
+    assert!(started.elapsed() >= Duration::from_secs(2));
+}